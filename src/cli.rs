@@ -0,0 +1,119 @@
+//! Command line parsing, done by hand: the few flags don't warrant a dependency such as `clap`,
+//! and `+LINE`, which Vim users expect, is awkward to express with one.
+
+use crate::Theme;
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: hecto [OPTIONS] [+LINE] [FILE]
+
+Options:
+  -R, --readonly         Open the file without allowing changes
+      --tab-width <N>    Display tabs N columns wide
+      --no-line-numbers  Hide the line number gutter
+      --theme <NAME>     Use a built-in theme
+      --batch <SCRIPT>   Run the commands of SCRIPT on FILE, without a terminal
+      --log-file <PATH>  Write a debug log to PATH, see also HECTO_LOG
+  +LINE                  Put the cursor on line LINE
+  -h, --help             Print this help
+  -V, --version          Print the version";
+
+/// Command line options, overriding the config file.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub path: Option<PathBuf>,
+    pub read_only: bool,
+    pub tab_width: Option<usize>,
+    pub line_numbers: Option<bool>,
+    pub theme: Option<String>,
+    /// 1-based
+    pub line: Option<usize>,
+    /// Script to run in batch mode.
+    pub batch: Option<PathBuf>,
+    /// Where to write the debug log.
+    pub log_file: Option<PathBuf>,
+}
+
+pub enum Cli {
+    Run(Args),
+    Help,
+    Version,
+}
+
+/// Parses the arguments, without the program name.
+///
+/// # Errors
+/// On unknown flags, missing or invalid values, and extra arguments.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut result = Args::default();
+    let mut args = args.into_iter();
+    let mut only_paths = false;
+
+    while let Some(arg) = args.next() {
+        if only_paths || arg == "-" || !arg.starts_with(['-', '+']) {
+            if result.path.is_some() {
+                return Err(format!(
+                    "unexpected argument `{arg}`, only one file can be opened"
+                ));
+            }
+            result.path = Some(arg.into());
+            continue;
+        }
+
+        if let Some(line) = arg.strip_prefix('+') {
+            let line = line
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("invalid line number `{arg}`"))?;
+            result.line = Some(line);
+            continue;
+        }
+
+        // Allow both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("`{name}` requires a value"))
+        };
+
+        match flag {
+            "--" => only_paths = true,
+            "-h" | "--help" => return Ok(Cli::Help),
+            "-V" | "--version" => return Ok(Cli::Version),
+            "-R" | "--readonly" => result.read_only = true,
+            "--no-line-numbers" => result.line_numbers = Some(false),
+            "--tab-width" => {
+                let width = value(flag)?;
+                result.tab_width = Some(
+                    width
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| format!("invalid tab width `{width}`"))?,
+                );
+            }
+            "--batch" => result.batch = Some(value(flag)?.into()),
+            "--log-file" => result.log_file = Some(value(flag)?.into()),
+            "--theme" => {
+                let theme = value(flag)?;
+                if Theme::by_name(&theme).is_none() {
+                    return Err(format!(
+                        "unknown theme `{theme}` (available: {})",
+                        Theme::NAMES.join(", ")
+                    ));
+                }
+                result.theme = Some(theme);
+            }
+            _ => return Err(format!("unknown option `{flag}`")),
+        }
+    }
+
+    Ok(Cli::Run(result))
+}
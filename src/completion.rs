@@ -0,0 +1,119 @@
+//! Completing file names in prompts and words in the document, and listing files for the file
+//! finder.
+
+use crate::git;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Paths starting with `input`, sorted, directories ending with `/`. Hidden files are only
+/// offered once their leading dot is typed, and a leading `~/` stands for the home directory.
+#[must_use]
+pub fn paths(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(idx) => input.split_at(idx + 1),
+        None => ("", input),
+    };
+    let dir_path = match dir.strip_prefix("~/") {
+        Some(rest) => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(rest),
+            None => return Vec::new(),
+        },
+        None if dir.is_empty() => PathBuf::from("."),
+        None => PathBuf::from(dir),
+    };
+    let Ok(entries) = fs::read_dir(dir_path) else {
+        return Vec::new();
+    };
+
+    let mut completions: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Following symlinks, for links to directories to complete as directories
+            let is_dir = entry.path().is_dir();
+            Some(format!("{dir}{name}{}", if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    completions.sort();
+    completions
+}
+
+/// Files under `root`, relative to it and sorted, at most `max` of them. Hidden files and
+/// directories are skipped, as are those git ignores, such as build output, and symlinks to
+/// directories, which could loop.
+#[must_use]
+pub fn files(root: &Path, max: usize) -> Vec<String> {
+    if let Some(mut files) = git::files(root) {
+        files.retain(|file| !file.split('/').any(|name| name.starts_with('.')));
+        files.sort();
+        files.truncate(max);
+        return files;
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        if files.len() >= max {
+            break;
+        }
+        let Ok(entries) = fs::read_dir(root.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            if name.to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = dir.join(name);
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if files.len() < max && (!file_type.is_symlink() || entry.path().is_file()) {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Distinct words of `lines` starting with `prefix`, in order of appearance, but not `prefix`
+/// itself. Words are made of alphanumeric characters and underscores.
+#[must_use]
+pub fn words<'a>(prefix: &str, lines: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut seen = HashSet::new();
+    for line in lines {
+        for word in line.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+            if word.len() > prefix.len() && word.starts_with(prefix) && seen.insert(word) {
+                words.push(word.into());
+            }
+        }
+    }
+    words
+}
+
+/// Longest start shared by all of `candidates`, empty if there are none.
+#[must_use]
+pub fn common_prefix<T: AsRef<str>>(candidates: &[T]) -> &str {
+    let Some(first) = candidates.first().map(AsRef::as_ref) else {
+        return "";
+    };
+    let len = candidates[1..].iter().fold(first.len(), |len, candidate| {
+        first[..len]
+            .char_indices()
+            .zip(candidate.as_ref().chars())
+            .find(|&((_, a), b)| a != b)
+            .map_or(cmp::min(len, candidate.as_ref().len()), |((idx, _), _)| idx)
+    });
+    &first[..len]
+}
@@ -0,0 +1,481 @@
+use crate::input::{self, Chord};
+use crate::keymap::{Command, Keymap};
+use crate::modeline;
+use crate::status_line;
+use crate::terminal::RgbColor;
+use crate::toml::{self, Table, Value};
+use crate::{FileType, Theme};
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use termion::event::Key;
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+/// Cursor margin at top/bottom
+pub const DEFAULT_SCROLL_OFFSET: usize = 5;
+/// Cursor margin at left/right
+pub const DEFAULT_SIDE_SCROLL_OFFSET: usize = 0;
+/// How long to wait for the rest of a key sequence.
+pub const DEFAULT_KEY_TIMEOUT: Duration = Duration::from_millis(1000);
+/// `strftime` format of inserted dates.
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+/// Size from which opening a file is confirmed, in bytes.
+pub const DEFAULT_LARGE_FILE_SIZE: u64 = 64 << 20;
+
+/// Settings read from `config.toml`.
+pub struct EditorConfig {
+    pub tab_width: usize,
+    /// Insert spaces instead of tab characters.
+    pub expand_tab: bool,
+    pub scroll_offset: usize,
+    pub side_scroll_offset: usize,
+    /// Scroll by half a screen when the cursor reaches a margin, rather than line by line.
+    pub scroll_jump: bool,
+    pub line_numbers: bool,
+    pub theme: Theme,
+    /// `[colors]` overrides, applied on top of whichever theme is selected.
+    pub colors: Vec<(String, Option<RgbColor>)>,
+    /// Save modified files this often, `None` to disable.
+    pub autosave_interval: Option<Duration>,
+    /// Layout of the status bar, see `status_line::render`.
+    pub status_format: String,
+    pub keymap: Keymap,
+    /// Stands for `<Leader>` in key sequences.
+    pub leader: Chord,
+    /// How long to wait for the next key of a sequence, before running the bound prefix.
+    pub key_timeout: Duration,
+    /// Column lines shouldn't extend past, marked by a ruler.
+    pub text_width: Option<usize>,
+    /// Break lines at the last word boundary when typing past the text width.
+    pub auto_wrap: bool,
+    /// `strftime` format of the date `insert_date` inserts.
+    pub date_format: String,
+    /// `[snippets]`: text inserted by name.
+    pub snippets: HashMap<String, String>,
+    /// `[filetype.<name>]` overrides, by file type name.
+    pub file_types: HashMap<String, FileTypeConfig>,
+    /// Script to source at startup, see `init_script_path`.
+    pub init_script: Option<PathBuf>,
+    /// Where prompt history is kept across sessions, not kept if `None`.
+    pub history_file: Option<PathBuf>,
+    /// Vim-style normal/insert/visual modes.
+    pub modal: bool,
+    /// Word motions also stop inside identifiers, at `camelCase` humps and after underscores.
+    pub subword_motion: bool,
+    /// Highlight misspelled words.
+    pub spell: bool,
+    /// Word list to check spelling against, `spell::DEFAULT_DICTIONARY` if `None`.
+    pub spell_dictionary: Option<PathBuf>,
+    /// Files larger than this, in bytes, are opened after asking, possibly in degraded mode.
+    /// `None` to never ask.
+    pub large_file_size: Option<u64>,
+    /// Apply the settings of Vim and Emacs modelines, see `modeline::Modeline`. Worth disabling
+    /// when editing untrusted files.
+    pub modelines: bool,
+    /// Guess whether files are indented with tabs or spaces, and how wide, from their content.
+    pub detect_indent: bool,
+    /// Report mouse clicks and the wheel, which scrolls the view. Off by default, as terminals
+    /// then leave selecting text to hold Shift.
+    pub mouse: bool,
+    /// Tab width given on the command line, overriding file type settings as well as those
+    /// guessed or read from the file.
+    pub forced_tab_width: Option<usize>,
+}
+
+/// Settings of a `[filetype.<name>]` section, `None` where the global value applies.
+#[derive(Default)]
+pub struct FileTypeConfig {
+    pub tab_width: Option<usize>,
+    pub expand_tab: Option<bool>,
+    pub comment: Option<String>,
+    /// 0 disables the global text width.
+    pub text_width: Option<usize>,
+    /// Run in the background after saving, see `lint::run`.
+    pub lint: Option<String>,
+}
+
+/// Settings that depend on the file type of the document being edited.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferSettings {
+    pub tab_width: usize,
+    pub expand_tab: bool,
+    /// Line comment prefix.
+    pub comment: Option<String>,
+    pub text_width: Option<usize>,
+    pub lint: Option<String>,
+    /// Strip trailing whitespace when saving, only set by `.editorconfig` files.
+    pub trim_trailing_whitespace: bool,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            expand_tab: false,
+            scroll_offset: DEFAULT_SCROLL_OFFSET,
+            side_scroll_offset: DEFAULT_SIDE_SCROLL_OFFSET,
+            scroll_jump: false,
+            line_numbers: true,
+            theme: Theme::default(),
+            colors: Vec::new(),
+            autosave_interval: None,
+            status_format: status_line::DEFAULT_FORMAT.into(),
+            keymap: Keymap::default(),
+            leader: Key::Char('\\').into(),
+            key_timeout: DEFAULT_KEY_TIMEOUT,
+            text_width: None,
+            auto_wrap: false,
+            date_format: DEFAULT_DATE_FORMAT.into(),
+            snippets: HashMap::new(),
+            file_types: HashMap::new(),
+            init_script: None,
+            history_file: None,
+            modal: false,
+            subword_motion: false,
+            spell: false,
+            spell_dictionary: None,
+            large_file_size: Some(DEFAULT_LARGE_FILE_SIZE),
+            modelines: true,
+            detect_indent: true,
+            mouse: false,
+            forced_tab_width: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl EditorConfig {
+    /// `$XDG_CONFIG_HOME/hecto`, falling back to `~/.config/hecto`.
+    #[must_use]
+    pub fn dir() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            // Relative paths are invalid per the spec and must be ignored
+            .filter(|p| p.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_home.join("hecto"))
+    }
+
+    #[must_use]
+    pub fn path() -> Option<PathBuf> {
+        Self::dir().map(|dir| dir.join("config.toml"))
+    }
+
+    /// Where files the editor keeps for itself go: `$XDG_STATE_HOME/hecto`, falling back to
+    /// `~/.local/state/hecto`.
+    #[must_use]
+    pub fn state_dir() -> Option<PathBuf> {
+        let state_home = env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            // Relative paths are invalid per the spec and must be ignored
+            .filter(|p| p.is_absolute())
+            .or_else(|| {
+                env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+            })?;
+        Some(state_home.join("hecto"))
+    }
+
+    /// Script sourced at startup, see `script`.
+    #[must_use]
+    pub fn init_script_path() -> Option<PathBuf> {
+        Self::dir().map(|dir| dir.join("init.hct"))
+    }
+
+    /// Reads the configuration file, if there is one.
+    ///
+    /// # Errors
+    /// If the file can't be read or contains invalid settings.
+    pub fn load() -> Result<Self, ConfigError> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(ConfigError {
+                    path,
+                    message: e.to_string(),
+                })
+            }
+        };
+
+        Self::parse(&text).map_err(|message| ConfigError { path, message })
+    }
+
+    /// # Errors
+    /// On TOML syntax errors, unknown settings or invalid values.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let table = toml::parse(text).map_err(|e| e.to_string())?;
+        let mut config = Self::default();
+        config.apply(&table)?;
+        // Overrides apply whatever the order of `theme` and `[colors]` in the file
+        let theme = std::mem::take(&mut config.theme);
+        config.set_theme(theme);
+        Ok(config)
+    }
+
+    /// Switches to `theme`, keeping `[colors]` overrides.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        for (name, color) in &self.colors {
+            // Names were checked when parsing
+            let _ = self.theme.set_color(name, *color);
+        }
+    }
+
+    fn apply(&mut self, table: &Table) -> Result<(), String> {
+        // `[keys]` sequences may refer to it, wherever it is in the file
+        if let Some(value) = table.get("leader") {
+            self.leader = string("leader", value)?.parse()?;
+        }
+
+        for (key, value) in table {
+            match key.as_str() {
+                "leader" => {}
+                "key_timeout" => {
+                    let millis = positive(key, value)?;
+                    self.key_timeout = Duration::from_millis(millis as u64);
+                }
+                "tab_width" => self.tab_width = tab_width(key, value)?,
+                "expand_tab" => self.expand_tab = boolean(key, value)?,
+                "scroll_offset" => self.scroll_offset = non_negative(key, value)?,
+                "side_scroll_offset" => self.side_scroll_offset = non_negative(key, value)?,
+                "scroll_jump" => self.scroll_jump = boolean(key, value)?,
+                "line_numbers" => self.line_numbers = boolean(key, value)?,
+                "theme" => {
+                    let name = string(key, value)?;
+                    self.theme = Theme::by_name(name).ok_or_else(|| {
+                        format!(
+                            "unknown theme `{name}` (available: {})",
+                            Theme::NAMES.join(", ")
+                        )
+                    })?;
+                }
+                "autosave_interval" => {
+                    let secs = non_negative(key, value)?;
+                    self.autosave_interval = (secs > 0).then(|| Duration::from_secs(secs as u64));
+                }
+                "status_format" => self.status_format = string(key, value)?.into(),
+                "large_file_size" => {
+                    // In MiB
+                    let size = non_negative(key, value)?;
+                    let bytes = (size as u64)
+                        .checked_mul(1 << 20)
+                        .ok_or_else(|| format!("`{key}` is too large, got {size}"))?;
+                    self.large_file_size = (size > 0).then_some(bytes);
+                }
+                "text_width" => self.text_width = text_width(key, value)?,
+                "auto_wrap" => self.auto_wrap = boolean(key, value)?,
+                "date_format" => self.date_format = string(key, value)?.into(),
+                "snippets" => {
+                    for (name, value) in table_value(key, value)? {
+                        let text = string(&format!("snippets.{name}"), value)?;
+                        self.snippets.insert(name.clone(), text.into());
+                    }
+                }
+                "modal" => self.modal = boolean(key, value)?,
+                "modelines" => self.modelines = boolean(key, value)?,
+                "detect_indent" => self.detect_indent = boolean(key, value)?,
+                "mouse" => self.mouse = boolean(key, value)?,
+                "subword_motion" => self.subword_motion = boolean(key, value)?,
+                "spell" => self.spell = boolean(key, value)?,
+                "spell_dictionary" => self.spell_dictionary = Some(string(key, value)?.into()),
+                "colors" => {
+                    for (name, value) in table_value(key, value)? {
+                        let color = match string(name, value)? {
+                            "none" => None,
+                            hex => Some(RgbColor::from_hex(hex).ok_or_else(|| {
+                                format!("`{name}` should be a `#rrggbb` color or \"none\"")
+                            })?),
+                        };
+                        Theme::default()
+                            .set_color(name, color)
+                            .map_err(|e| format!("[colors]: {e}"))?;
+                        self.colors.push((name.clone(), color));
+                    }
+                }
+                "keys" => self.apply_keys(table_value(key, value)?)?,
+                "filetype" => {
+                    for (name, value) in table_value(key, value)? {
+                        let config = FileTypeConfig::parse(name, table_value(name, value)?)?;
+                        self.file_types.insert(name.clone(), config);
+                    }
+                }
+                _ => return Err(format!("unknown setting `{key}`")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Global settings, overridden by the `[filetype.<name>]` section for `file_type`.
+    #[must_use]
+    pub fn settings_for(&self, file_type: FileType) -> BufferSettings {
+        let default = FileTypeConfig::default();
+        let overrides = self.file_types.get(file_type.name()).unwrap_or(&default);
+
+        BufferSettings {
+            tab_width: overrides.tab_width.unwrap_or(self.tab_width),
+            expand_tab: overrides.expand_tab.unwrap_or(self.expand_tab),
+            comment: overrides
+                .comment
+                .clone()
+                .or_else(|| file_type.comment().map(String::from)),
+            text_width: overrides
+                .text_width
+                .map_or(self.text_width, |w| (w > 0).then_some(w)),
+            lint: overrides.lint.clone(),
+            trim_trailing_whitespace: false,
+        }
+    }
+
+    /// `[keys]` entries look like `"<C-s>" = "save"` or `"<Leader>fs" = "save"`, `"none"`
+    /// removes a default binding.
+    fn apply_keys(&mut self, table: &Table) -> Result<(), String> {
+        for (keys, command) in table {
+            let sequence =
+                input::parse_sequence(keys, self.leader).map_err(|e| format!("[keys]: {e}"))?;
+            let command = match string(&format!("keys.{keys}"), command)? {
+                "none" => None,
+                name => Some(
+                    name.parse::<Command>()
+                        .map_err(|e| format!("[keys]: {e}"))?,
+                ),
+            };
+            self.keymap.bind(sequence, command);
+        }
+        Ok(())
+    }
+}
+
+impl FileTypeConfig {
+    fn parse(name: &str, table: &Table) -> Result<Self, String> {
+        let mut config = Self::default();
+        for (key, value) in table {
+            let key = &format!("filetype.{name}.{key}");
+            match key.rsplit('.').next().unwrap_or_default() {
+                "tab_width" => config.tab_width = Some(tab_width(key, value)?),
+                "expand_tab" => config.expand_tab = Some(boolean(key, value)?),
+                "comment" => config.comment = Some(string(key, value)?.into()),
+                "text_width" => config.text_width = Some(non_negative(key, value)?),
+                "lint" => config.lint = Some(string(key, value)?.into()),
+                _ => return Err(format!("unknown setting `{key}`")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn mismatch(key: &str, expected: &str, value: &Value) -> String {
+    format!("`{key}` must be {expected}, not {}", value.type_name())
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(b) => Ok(*b),
+        _ => Err(mismatch(key, "true or false", value)),
+    }
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(mismatch(key, "a string", value)),
+    }
+}
+
+fn table_value<'a>(key: &str, value: &'a Value) -> Result<&'a Table, String> {
+    match value {
+        Value::Table(t) => Ok(t),
+        _ => Err(format!(
+            "`{key}` must be a [{key}] table, not {}",
+            value.type_name()
+        )),
+    }
+}
+
+fn non_negative(key: &str, value: &Value) -> Result<usize, String> {
+    match value {
+        Value::Integer(n) => {
+            usize::try_from(*n).map_err(|_| format!("`{key}` must not be negative, got {n}"))
+        }
+        _ => Err(mismatch(key, "an integer", value)),
+    }
+}
+
+/// 0 disables the text width.
+fn text_width(key: &str, value: &Value) -> Result<Option<usize>, String> {
+    non_negative(key, value).map(|n| (n > 0).then_some(n))
+}
+
+fn positive(key: &str, value: &Value) -> Result<usize, String> {
+    match non_negative(key, value)? {
+        0 => Err(format!("`{key}` must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+fn tab_width(key: &str, value: &Value) -> Result<usize, String> {
+    match positive(key, value)? {
+        n if n > modeline::MAX_TAB_WIDTH => Err(format!(
+            "`{key}` must be at most {}, got {n}",
+            modeline::MAX_TAB_WIDTH
+        )),
+        n => Ok(n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tab_width_is_bounded() {
+        assert_eq!(EditorConfig::parse("tab_width = 32").unwrap().tab_width, 32);
+        assert_eq!(
+            EditorConfig::parse("tab_width = 33").err().unwrap(),
+            "`tab_width` must be at most 32, got 33"
+        );
+        assert_eq!(
+            EditorConfig::parse("[filetype.rust]\ntab_width = 0")
+                .err()
+                .unwrap(),
+            "`filetype.rust.tab_width` must be at least 1"
+        );
+    }
+
+    #[test]
+    fn large_file_size_does_not_overflow() {
+        let config = EditorConfig::parse("large_file_size = 2").unwrap();
+        assert_eq!(config.large_file_size, Some(2 << 20));
+        assert_eq!(
+            EditorConfig::parse("large_file_size = 0")
+                .unwrap()
+                .large_file_size,
+            None
+        );
+        assert_eq!(
+            EditorConfig::parse("large_file_size = 0x7fff_ffff_ffff")
+                .err()
+                .unwrap(),
+            "`large_file_size` is too large, got 140737488355327"
+        );
+    }
+}
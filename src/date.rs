@@ -0,0 +1,33 @@
+//! The current local date and time, formatted with `strftime`.
+
+use std::ffi::CString;
+use std::ptr;
+
+/// Longest formatted date, in bytes.
+const MAX_LEN: usize = 256;
+
+/// The local time formatted according to `format`, e.g. `%Y-%m-%d`. `None` if the format
+/// contains a NUL or the result is too long.
+#[must_use]
+pub fn now(format: &str) -> Option<String> {
+    if format.is_empty() {
+        return Some(String::new());
+    }
+    let format = CString::new(format).ok()?;
+    let mut buf = [0u8; MAX_LEN];
+    // SAFETY: `tm` is fully initialized by `localtime_r` before use, and `strftime` writes at
+    // most `buf.len()` bytes.
+    let len = unsafe {
+        let time = libc::time(ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return None;
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    // 0 when the result doesn't fit, or is empty
+    if len == 0 {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
@@ -0,0 +1,82 @@
+/// A single step transforming the old sequence into the new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Computes a shortest edit script between `old` and `new` using Myers' algorithm.
+///
+/// This is O((N+M)D) in time and memory, D being the number of edits, which is cheap for the
+/// usual "a few lines changed" case.
+#[must_use]
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffOp> {
+    let (n, m) = (old.len(), new.len());
+    let max = n + m;
+    // Diagonal k is stored at index k + offset
+    let offset = max as isize + 1;
+    let mut v = vec![0usize; 2 * max + 3];
+    let mut trace = Vec::new();
+
+    'outer: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                // Move down (insertion)
+                v[idx + 1]
+            } else {
+                // Move right (deletion)
+                v[idx - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            while x < n && y < m && old[x] == new[y] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                trace.push(v.clone());
+                break 'outer;
+            }
+        }
+    }
+
+    // Walk the trace backwards to recover the path
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n as isize, m as isize);
+    for d in (1..trace.len() as isize - 1).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let prev_x = v[(prev_k + offset) as usize] as isize;
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(DiffOp::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if x == prev_x {
+            DiffOp::Insert
+        } else {
+            DiffOp::Delete
+        });
+        x = prev_x;
+        y = prev_y;
+    }
+    while x > 0 && y > 0 {
+        ops.push(DiffOp::Equal);
+        x -= 1;
+        y -= 1;
+    }
+
+    ops.reverse();
+    ops
+}
@@ -1,52 +1,487 @@
 use regex::Regex;
 
-use crate::{Position, Row, SearchDirection};
+use crate::logging::{Level, Span};
+use crate::row;
+use crate::{FileType, Position, Row, SearchDirection};
+use std::cmp;
+use std::fmt;
 use std::fs;
-use std::io::{self, BufRead, Seek, Write};
-use std::path::PathBuf;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufRead, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Bytes read or written between progress reports.
+const PROGRESS_CHUNK: usize = 1 << 20;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Written in place of characters the encoding can't represent.
+const REPLACEMENT: char = '?';
+/// Rows searched between looks at the clock.
+const SEARCH_CHECK_ROWS: usize = 256;
+
+/// A search gave up before its time budget ran out, without finding anything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 with a byte order mark, which is preserved when saving.
+    Utf8Bom,
+    /// With a byte order mark, which is how it's detected when opening.
+    Utf16Le,
+    /// With a byte order mark, which is how it's detected when opening.
+    Utf16Be,
+    /// ISO-8859-1, only used when asked for, as any file could be read in it.
+    Latin1,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] =
+        &["utf-8", "utf-8-bom", "utf-16le", "utf-16be", "latin1"];
+
+    /// The encoding called `name`, case-insensitively and with or without dashes.
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().replace('-', "").as_str() {
+            "utf8" => Self::Utf8,
+            "utf8bom" => Self::Utf8Bom,
+            "utf16le" => Self::Utf16Le,
+            "utf16be" => Self::Utf16Be,
+            "latin1" | "iso88591" => Self::Latin1,
+            _ => return None,
+        })
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Utf8 | Self::Latin1 => b"",
+            Self::Utf8Bom => UTF8_BOM,
+            Self::Utf16Le => UTF16LE_BOM,
+            Self::Utf16Be => UTF16BE_BOM,
+        }
+    }
+
+    #[must_use]
+    pub fn can_encode(self, c: char) -> bool {
+        self != Self::Latin1 || u32::from(c) <= 0xFF
+    }
+
+    /// Appends `text` in this encoding to `out`, with `REPLACEMENT` for what can't be encoded.
+    fn encode(self, text: &str, out: &mut Vec<u8>) {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => out.extend_from_slice(text.as_bytes()),
+            Self::Utf16Le => out.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Self::Utf16Be => out.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Self::Latin1 => out.extend(
+                text.chars()
+                    .map(|c| u8::try_from(c).unwrap_or(REPLACEMENT as u8)),
+            ),
+        }
+    }
+
+    /// Length of `text` in this encoding, in bytes.
+    fn encoded_len(self, text: &str) -> usize {
+        match self {
+            Self::Utf8 | Self::Utf8Bom => text.len(),
+            Self::Utf16Le | Self::Utf16Be => text.encode_utf16().count() * 2,
+            Self::Latin1 => text.chars().count(),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf8Bom => "utf-8-bom",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+            Self::Latin1 => "latin1",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lf => "LF",
+            Self::Crlf => "CRLF",
+        })
+    }
+}
+
+/// A snapshot of a document's content, which can be written while the document keeps changing.
+pub struct SaveJob {
+    path: PathBuf,
+    bytes: Vec<u8>,
+    content_hash: u64,
+}
+
+impl SaveJob {
+    /// Returns number of bytes written to disk.
+    /// # Errors
+    /// If file can't be opened or written.
+    pub fn write(&self) -> Result<u64, io::Error> {
+        self.write_with_progress(|_| ())
+    }
+
+    /// Like `write`, calling `progress` with the number of bytes written so far after each
+    /// chunk.
+    /// # Errors
+    /// If file can't be opened or written.
+    pub fn write_with_progress(&self, mut progress: impl FnMut(u64)) -> Result<u64, io::Error> {
+        let _span = Span::new(
+            Level::Debug,
+            format_args!("save {} ({} bytes)", self.path.display(), self.bytes.len()),
+        );
+        let mut file = fs::File::create(&self.path)?;
+        let mut written = 0;
+        for chunk in self.bytes.chunks(PROGRESS_CHUNK) {
+            file.write_all(chunk)?;
+            written += chunk.len() as u64;
+            progress(written);
+        }
+        Ok(written)
+    }
+
+    /// Size of the content, in bytes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Identifies the content, for `Document::saved`.
+    #[must_use]
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+}
+
+/// Rows replaced by edits, for undo, see `Document::take_change`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// First edited row.
+    pub start: usize,
+    /// Rows from `start` on that replaced `old`.
+    pub len: usize,
+    /// Rows from `start` on as they were before the edits.
+    pub old: Vec<String>,
+}
+
+/// A `Change` being recorded.
+struct Journal {
+    start: usize,
+    /// Rows after the edited ones, which don't move relative to the end of the document.
+    tail: usize,
+    old: Vec<String>,
+}
 
 #[derive(Default)]
 pub struct Document {
     rows: Vec<Row>,
     path: Option<PathBuf>,
+    file_type: FileType,
+    encoding: Encoding,
+    /// Detected from the first line of the file, and used for every line when saving.
+    line_ending: LineEnding,
+    /// Leave out the line ending after the last row when saving.
+    omit_final_newline: bool,
     /// Whether the document was modified since last save.
     dirty: bool,
+    /// Changes whenever the content does, see `revision`.
+    revision: u64,
+    /// Rows edited since the last `take_change`.
+    journal: Option<Journal>,
+    /// Hash of the content as last opened or saved, `None` for a new document.
+    saved_hash: Option<u64>,
+    /// Rows, as read, whose invalid UTF-8 was replaced with U+FFFD.
+    invalid_lines: Vec<usize>,
+    /// Whether only the start of the file was read, see `open_start`.
+    truncated: bool,
 }
 
 impl Document {
     /// # Errors
     /// If file can't be opened or line can't be read.
     pub fn open(path: PathBuf) -> Result<Self, io::Error> {
+        Self::open_with_progress(path, |_| Ok(()))
+    }
+
+    /// Like `open`, calling `progress` with the number of bytes read so far every so often. An
+    /// error from `progress`, e.g. when the load was cancelled, stops reading.
+    /// # Errors
+    /// If file can't be opened or line can't be read.
+    pub fn open_with_progress(
+        path: PathBuf,
+        progress: impl FnMut(u64) -> Result<(), io::Error>,
+    ) -> Result<Self, io::Error> {
+        Self::open_start(path, u64::MAX, progress)
+    }
+
+    /// Like `open_with_progress`, reading at most `limit` bytes, e.g. to look at the start of a
+    /// huge file. The last line read is dropped if the file is longer, as it may be cut.
+    /// # Errors
+    /// If file can't be opened or line can't be read.
+    pub fn open_start(
+        path: PathBuf,
+        limit: u64,
+        progress: impl FnMut(u64) -> Result<(), io::Error>,
+    ) -> Result<Self, io::Error> {
+        Self::open_as(path, Encoding::Utf8, limit, progress)
+    }
+
+    /// Like `open_start`, decoding the file in `encoding` unless it starts with a byte order
+    /// mark. Only Latin-1 is read differently from UTF-8.
+    /// # Errors
+    /// If file can't be opened or line can't be read.
+    pub fn open_as(
+        path: PathBuf,
+        encoding: Encoding,
+        limit: u64,
+        mut progress: impl FnMut(u64) -> Result<(), io::Error>,
+    ) -> Result<Self, io::Error> {
+        let _span = Span::new(Level::Debug, format_args!("open {}", path.display()));
         let file = fs::File::open(&path)?;
-        let lines = io::BufReader::new(file)
-            .lines()
-            .map(|res| Ok(Row::from(res?)))
-            .collect::<Result<Vec<_>, io::Error>>()?;
+        let truncated = file.metadata()?.len() > limit;
+        let mut reader = io::BufReader::new(file).take(limit);
+
+        let start = reader.fill_buf()?;
+        let utf16 = [Encoding::Utf16Le, Encoding::Utf16Be]
+            .into_iter()
+            .find(|encoding| start.starts_with(encoding.bom()));
+        if let Some(encoding) = utf16 {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes)?;
+            progress(bytes.len() as u64)?;
+            let mut bytes = &bytes[encoding.bom().len()..];
+            if truncated {
+                // The cut last line is dropped anyway, but must decode
+                bytes = whole_code_units(bytes, encoding);
+            }
+            let text = decode_utf16(bytes, encoding)?;
+            let document = Self::read(path, io::Cursor::new(text), encoding, &mut |_| Ok(()))?;
+            return Ok(document.truncate(truncated));
+        }
+        let encoding = match encoding {
+            Encoding::Latin1 => Encoding::Latin1,
+            _ => Encoding::Utf8,
+        };
+        let document = Self::read(path, reader, encoding, &mut progress)?;
+        Ok(document.truncate(truncated))
+    }
 
-        Ok(Self {
-            rows: lines,
+    /// Marks the document as `truncated`, dropping its last row which may be incomplete.
+    fn truncate(mut self, truncated: bool) -> Self {
+        if truncated && self.rows.len() > 1 {
+            self.rows.pop();
+            let len = self.rows.len();
+            self.invalid_lines.retain(|&y| y < len);
+            self.saved_hash = Some(self.content_hash());
+        }
+        self.truncated = truncated;
+        self
+    }
+
+    /// Whether only the start of the file was read, which must not be saved over it.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Reads the UTF-8 lines of `reader`, which came from the file at `path` in `encoding`,
+    /// calling `progress` with the number of bytes read after each chunk and stopping on its
+    /// errors.
+    fn read(
+        path: PathBuf,
+        mut reader: impl BufRead,
+        mut encoding: Encoding,
+        progress: &mut dyn FnMut(u64) -> Result<(), io::Error>,
+    ) -> Result<Self, io::Error> {
+        let mut rows = Vec::new();
+        let mut line_ending = None;
+        let mut buf = Vec::new();
+        let mut invalid_lines = Vec::new();
+        let mut read = 0;
+        let mut reported = 0;
+
+        while reader.read_until(b'\n', &mut buf)? > 0 {
+            read += buf.len() as u64;
+            if read - reported >= PROGRESS_CHUNK as u64 {
+                reported = read;
+                progress(read)?;
+            }
+            if rows.is_empty() && encoding == Encoding::Utf8 && buf.starts_with(UTF8_BOM) {
+                buf.drain(..UTF8_BOM.len());
+                encoding = Encoding::Utf8Bom;
+            }
+
+            if buf.last() == Some(&b'\n') {
+                buf.pop();
+                let crlf = buf.last() == Some(&b'\r');
+                if crlf {
+                    buf.pop();
+                }
+                line_ending.get_or_insert(if crlf {
+                    LineEnding::Crlf
+                } else {
+                    LineEnding::Lf
+                });
+            }
+
+            let line = if encoding == Encoding::Latin1 {
+                let line = buf.iter().copied().map(char::from).collect();
+                buf.clear();
+                line
+            } else {
+                String::from_utf8(std::mem::take(&mut buf)).unwrap_or_else(|e| {
+                    invalid_lines.push(rows.len());
+                    String::from_utf8_lossy(e.as_bytes()).into_owned()
+                })
+            };
+            rows.push(Row::from(line));
+        }
+
+        let mut document = Self {
+            rows,
+            file_type: FileType::detect(&path),
             path: Some(path),
+            encoding,
+            line_ending: line_ending.unwrap_or_default(),
+            omit_final_newline: false,
             dirty: false,
-        })
+            revision: row::next_revision(),
+            journal: None,
+            saved_hash: None,
+            invalid_lines,
+            truncated: false,
+        };
+        document.saved_hash = Some(document.content_hash());
+        Ok(document)
+    }
+
+    /// Rows, as read, where invalid UTF-8 was replaced with U+FFFD, which would be written back
+    /// when saving.
+    #[must_use]
+    pub fn invalid_lines(&self) -> &[usize] {
+        &self.invalid_lines
+    }
+
+    /// Accepts that the replacement characters are saved.
+    pub fn forget_invalid_lines(&mut self) {
+        self.invalid_lines.clear();
+    }
+
+    /// An untitled document holding `lines`, unmodified.
+    #[must_use]
+    pub fn scratch(lines: &[String]) -> Self {
+        let mut document = Self {
+            rows: lines.iter().map(|line| Row::from(line.as_str())).collect(),
+            revision: row::next_revision(),
+            ..Self::default()
+        };
+        document.saved_hash = Some(document.content_hash());
+        document
     }
 
     /// Returns number of bytes written to disk.
     /// # Errors
     /// If file can'be opened or line can't be written.
     pub fn save(&mut self) -> Result<u64, io::Error> {
-        let mut bytes_written = 0;
-        if let Some(ref path) = self.path {
-            let mut file = fs::File::create(path)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+        let Some(job) = self.save_job() else {
+            self.dirty = false;
+            return Ok(0);
+        };
+        let bytes_written = job.write()?;
+        self.saved(job.content_hash());
+        Ok(bytes_written)
+    }
+
+    /// The content to write to the document's path, `None` without one.
+    #[must_use]
+    pub fn save_job(&self) -> Option<SaveJob> {
+        Some(self.save_job_to(self.path.clone()?))
+    }
+
+    /// The content to write to `path`, for saving under another name.
+    #[must_use]
+    pub fn save_job_to(&self, path: PathBuf) -> SaveJob {
+        let mut bytes = self.encoding.bom().to_vec();
+        for (idx, row) in self.rows.iter().enumerate() {
+            self.encoding.encode(row.as_str(), &mut bytes);
+            if !(self.omit_final_newline && idx + 1 == self.rows.len()) {
+                self.encoding.encode(self.line_ending.as_str(), &mut bytes);
             }
+        }
+        SaveJob {
+            path,
+            bytes,
+            content_hash: self.content_hash(),
+        }
+    }
 
-            bytes_written = file.seek(io::SeekFrom::End(0))?;
+    /// Identifies what saving would write: the text, its line endings and its encoding.
+    fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for row in &self.rows {
+            row.as_str().hash(&mut hasher);
         }
+        self.line_ending.hash(&mut hasher);
+        self.encoding.hash(&mut hasher);
+        hasher.finish()
+    }
 
-        self.dirty = false;
-        Ok(bytes_written)
+    /// Records that the content identified by `hash` (see `SaveJob::content_hash`) was
+    /// written: the document stays dirty if it was modified since.
+    pub fn saved(&mut self, hash: u64) {
+        self.saved_hash = Some(hash);
+        self.dirty = self.content_hash() != hash;
+    }
+
+    /// Checks the consistency of every row, and that a document that isn't dirty has the
+    /// content it was opened or saved with.
+    ///
+    /// # Errors
+    /// Describing the first broken invariant.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        for (idx, row) in self.rows.iter().enumerate() {
+            row.check_invariants()
+                .map_err(|e| format!("row {}: {e}", idx + 1))?;
+        }
+        let modified = self
+            .saved_hash
+            .map_or(!self.rows.is_empty(), |hash| hash != self.content_hash());
+        if !self.dirty && modified {
+            return Err("modified but not dirty".into());
+        }
+        Ok(())
     }
 
     #[must_use]
@@ -54,6 +489,10 @@ impl Document {
         self.rows.get(index)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = &Row> {
+        self.rows.iter()
+    }
+
     #[must_use]
     pub fn find(
         &self,
@@ -61,8 +500,35 @@ impl Document {
         limit: Position,
         direction: SearchDirection,
     ) -> Option<Position> {
+        self.find_until(query, limit, direction, None)
+            .unwrap_or_default()
+    }
+
+    /// Like `find`, giving up after `budget`, e.g. to keep searching as you type responsive
+    /// on large files.
+    ///
+    /// # Errors
+    /// If nothing was found before the budget ran out.
+    pub fn find_within(
+        &self,
+        query: &Regex,
+        limit: Position,
+        direction: SearchDirection,
+        budget: Duration,
+    ) -> Result<Option<Position>, TimedOut> {
+        self.find_until(query, limit, direction, Some(Instant::now() + budget))
+    }
+
+    fn find_until(
+        &self,
+        query: &Regex,
+        limit: Position,
+        direction: SearchDirection,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Position>, TimedOut> {
+        let _span = Span::new(Level::Trace, format_args!("search /{query}/"));
         if limit.y > self.len() {
-            return None;
+            return Ok(None);
         };
 
         let mut pos = limit;
@@ -72,12 +538,19 @@ impl Document {
             SearchDirection::Backward => (0, limit.y + 1),
         };
 
-        for _ in start..end {
-            let row = self.rows.get(pos.y)?;
+        for searched in 0..end - start {
+            if searched % SEARCH_CHECK_ROWS == SEARCH_CHECK_ROWS - 1
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(TimedOut);
+            }
+            let Some(row) = self.rows.get(pos.y) else {
+                return Ok(None);
+            };
 
-            if let Some(x) = row.find(&query, pos.x, direction) {
+            if let Some(x) = row.find(query, pos.x, direction) {
                 pos.x = x;
-                return Some(pos);
+                return Ok(Some(pos));
             }
             match direction {
                 SearchDirection::Forward => {
@@ -91,7 +564,28 @@ impl Document {
             }
         }
 
-        None
+        Ok(None)
+    }
+
+    /// Returns the byte and character offsets of `pos` from the start of the file, as saved.
+    #[must_use]
+    pub fn offsets(&self, pos: Position) -> (usize, usize) {
+        let eol = self.line_ending.as_str();
+        let eol_bytes = self.encoding.encoded_len(eol);
+        let mut bytes = self.encoding.bom().len();
+        let mut chars = 0;
+
+        for row in self.rows.iter().take(pos.y) {
+            bytes += self.encoding.encoded_len(row.as_str()) + eol_bytes;
+            chars += row.as_str().chars().count() + eol.len();
+        }
+        if let Some(row) = self.rows.get(pos.y) {
+            let before = &row.as_str()[..row.byte_index(pos.x)];
+            bytes += self.encoding.encoded_len(before);
+            chars += before.chars().count();
+        }
+
+        (bytes, chars)
     }
 
     #[must_use]
@@ -109,13 +603,62 @@ impl Document {
         self.dirty
     }
 
+    /// Changes whenever the document is edited, for what is derived from its content to tell
+    /// whether it is still up to date.
+    #[must_use]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The rows edited since the last call, and what they were, `None` if nothing was. Edits
+    /// of rows far apart are recorded as one change spanning the rows between them.
+    pub fn take_change(&mut self) -> Option<Change> {
+        let journal = self.journal.take()?;
+        Some(Change {
+            start: journal.start,
+            len: self.rows.len() - journal.tail - journal.start,
+            old: journal.old,
+        })
+    }
+
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.revision = row::next_revision();
+    }
+
+    /// Records `rows`, about to be edited, for `take_change`. Empty for rows about to be
+    /// inserted there.
+    fn changing(&mut self, rows: Range<usize>) {
+        self.changed();
+        let len = self.rows.len();
+        let rows = cmp::min(rows.start, len)..cmp::min(rows.end, len);
+        let Some(journal) = &mut self.journal else {
+            self.journal = Some(Journal {
+                start: rows.start,
+                tail: len - rows.end,
+                old: lines(&self.rows[rows]),
+            });
+            return;
+        };
+        let end = len - journal.tail;
+        if rows.start < journal.start {
+            let before = lines(&self.rows[rows.start..journal.start]);
+            journal.old.splice(0..0, before);
+            journal.start = rows.start;
+        }
+        if rows.end > end {
+            journal.old.extend(lines(&self.rows[end..rows.end]));
+            journal.tail = len - rows.end;
+        }
+    }
+
     pub fn insert_or_append(&mut self, pos: Position, c: char) {
         if c == '\n' {
             self.insert_newline(pos);
             return;
         }
 
-        self.dirty = true;
+        self.changing(pos.y..pos.y + 1);
 
         if pos.y >= self.len() {
             self.rows.push(Row::from(String::from(c)));
@@ -124,6 +667,46 @@ impl Document {
         }
     }
 
+    /// Inserts `text` at `pos`, splitting rows on newlines, and returns the position after it.
+    /// `pos.y == len()` appends, noop if `pos.y` > `len()`.
+    pub fn insert_str(&mut self, pos: Position, text: &str) -> Position {
+        if pos.y > self.len() || text.is_empty() {
+            return pos;
+        }
+        self.changing(pos.y..pos.y + 1);
+
+        if pos.y == self.len() {
+            self.rows.push(Row::default());
+        }
+        let tail = self.rows[pos.y].split(pos.x);
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.rows[pos.y].push(Row::from(first));
+        }
+        let mut y = pos.y;
+        for line in lines {
+            y += 1;
+            self.rows.insert(y, Row::from(line));
+        }
+
+        let end = Position {
+            x: self.rows[y].len(),
+            y,
+        };
+        self.rows[y].push(tail);
+        end
+    }
+
+    /// Replaces the grapheme at `pos` with `c`, or inserts it if `pos` is at the end of the row.
+    /// Newlines are always inserted.
+    pub fn replace(&mut self, pos: Position, c: char) {
+        if c != '\n' && self.rows.get(pos.y).is_some_and(|row| pos.x < row.len()) {
+            self.changing(pos.y..pos.y + 1);
+            self.rows[pos.y].delete(pos.x);
+        }
+        self.insert_or_append(pos, c);
+    }
+
     /// Delete character at `pos`, if it exists.
     /// Joins current row with the next if `pos.x` is at end of Row.
     pub fn delete(&mut self, pos: Position) {
@@ -132,13 +715,13 @@ impl Document {
             return;
         }
 
-        self.dirty = true;
-
         if pos.x == self.rows[pos.y].len() && pos.y < len.saturating_sub(1) {
             // If at end of row, but not end of file
+            self.changing(pos.y..pos.y + 2);
             let next_row = self.rows.remove(pos.y.saturating_add(1));
             self.rows[pos.y].push(next_row);
         } else {
+            self.changing(pos.y..pos.y + 1);
             self.rows[pos.y].delete(pos.x);
         }
     }
@@ -155,22 +738,255 @@ impl Document {
         self.path.as_ref().map(|p| p.to_string_lossy().into())
     }
 
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     #[must_use]
     pub fn has_path(&self) -> bool {
         self.path.is_some()
     }
 
     pub fn set_path(&mut self, path: PathBuf) {
+        self.file_type = FileType::detect(&path);
+        // What was read can be saved elsewhere
+        if self.path.as_ref() != Some(&path) {
+            self.truncated = false;
+        }
         self.path = Some(path);
     }
 
+    #[must_use]
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Overrides the file type detected from the path, e.g. from a modeline.
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        self.file_type = file_type;
+    }
+
+    #[must_use]
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    #[must_use]
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Converts line endings when saving.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if line_ending != self.line_ending {
+            self.line_ending = line_ending;
+            self.changed();
+        }
+    }
+
+    /// How many characters can't be represented in the document's encoding.
+    #[must_use]
+    pub fn unencodable(&self) -> usize {
+        if self.encoding != Encoding::Latin1 {
+            // Unicode encodings can represent anything
+            return 0;
+        }
+        self.rows
+            .iter()
+            .map(|row| {
+                row.as_str()
+                    .chars()
+                    .filter(|&c| !self.encoding.can_encode(c))
+                    .count()
+            })
+            .sum()
+    }
+
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.changed();
+        }
+    }
+
+    pub fn set_final_newline(&mut self, final_newline: bool) {
+        self.omit_final_newline = !final_newline;
+    }
+
+    /// Text between `start` (inclusive) and `end` (exclusive), rows separated by `\n`.
+    #[must_use]
+    pub fn text_range(&self, start: Position, end: Position) -> String {
+        let mut text = String::new();
+        for y in start.y..=cmp::min(end.y, self.len().saturating_sub(1)) {
+            let Some(row) = self.rows.get(y) else {
+                break;
+            };
+            let from = if y == start.y {
+                row.byte_index(start.x)
+            } else {
+                0
+            };
+            let to = if y == end.y {
+                row.byte_index(end.x)
+            } else {
+                row.len_bytes()
+            };
+            text.push_str(&row.as_str()[from..cmp::max(from, to)]);
+            if y != end.y {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    /// Deletes the text between `start` (inclusive) and `end` (exclusive), joining their rows.
+    pub fn delete_range(&mut self, start: Position, end: Position) {
+        if start.y >= self.len() || (start.y, start.x) >= (end.y, end.x) {
+            return;
+        }
+        let end_y = cmp::min(end.y, self.len() - 1);
+        self.changing(start.y..end_y + 1);
+
+        let tail = {
+            let row = &self.rows[end_y];
+            // Past the last row, everything up to the end is deleted
+            let from = if end_y == end.y {
+                row.byte_index(end.x)
+            } else {
+                row.len_bytes()
+            };
+            row.as_str()[from..].to_string()
+        };
+
+        let row = &self.rows[start.y];
+        let mut content = row.as_str()[..row.byte_index(start.x)].to_string();
+        content.push_str(&tail);
+        self.rows[start.y] = Row::from(content);
+        self.rows.drain(start.y + 1..=end_y);
+    }
+
+    /// Removes whole rows, returning their content.
+    pub fn remove_rows(&mut self, range: Range<usize>) -> Vec<String> {
+        let range = cmp::min(range.start, self.len())..cmp::min(range.end, self.len());
+        if !range.is_empty() {
+            self.changing(range.clone());
+        }
+        self.rows
+            .drain(range)
+            .map(|row| row.as_str().to_string())
+            .collect()
+    }
+
+    /// Inserts whole rows before row `y`, `y == len()` appends.
+    pub fn insert_rows(&mut self, y: usize, lines: &[String]) {
+        let y = cmp::min(y, self.len());
+        self.changing(y..y);
+        self.rows
+            .splice(y..y, lines.iter().map(|line| Row::from(line.as_str())));
+    }
+
+    /// Replaces the first match of `regex` (or all of them if `all`) in each of `rows` with
+    /// `replacement`, where `$1` or `${name}` stand for groups. Returns the number of
+    /// replacements.
+    pub fn substitute(
+        &mut self,
+        rows: Range<usize>,
+        regex: &Regex,
+        replacement: &str,
+        all: bool,
+    ) -> usize {
+        let rows = cmp::min(rows.start, self.len())..cmp::min(rows.end, self.len());
+        let limit = usize::from(!all);
+        let matching = |row: &Row| regex.is_match(row.as_str());
+        let Some(first) = self.rows[rows.clone()].iter().position(matching) else {
+            return 0;
+        };
+        let last = self.rows[rows.clone()]
+            .iter()
+            .rposition(matching)
+            .unwrap_or(first);
+        let rows = rows.start + first..rows.start + last + 1;
+        self.changing(rows.clone());
+        let mut count = 0;
+        for row in &mut self.rows[rows] {
+            let matches = regex.find_iter(row.as_str()).count();
+            if matches == 0 {
+                continue;
+            }
+            count += if all { matches } else { 1 };
+            let replaced = regex.replacen(row.as_str(), limit, replacement);
+            *row = Row::from(replaced.as_ref());
+        }
+        count
+    }
+
+    /// Sorts rows lexicographically.
+    pub fn sort(&mut self, reverse: bool) {
+        self.changing(0..self.rows.len());
+        self.rows.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        if reverse {
+            self.rows.reverse();
+        }
+    }
+
+    /// Reverses the order of `rows`.
+    pub fn reverse(&mut self, rows: Range<usize>) {
+        let rows = cmp::min(rows.start, self.len())..cmp::min(rows.end, self.len());
+        if rows.len() > 1 {
+            self.changing(rows.clone());
+            self.rows[rows].reverse();
+        }
+    }
+
+    /// How many rows of `rows` are equal to the one before them, compared as by `dedup`.
+    #[must_use]
+    pub fn duplicates(&self, rows: Range<usize>, ignore_indent: bool, ignore_case: bool) -> usize {
+        let rows = cmp::min(rows.start, self.len())..cmp::min(rows.end, self.len());
+        let key = |row: &Row| dedup_key(row, ignore_indent, ignore_case);
+        self.rows[rows]
+            .windows(2)
+            .filter(|pair| key(&pair[0]) == key(&pair[1]))
+            .count()
+    }
+
+    /// Removes rows of `rows` equal to the one before them, compared without leading whitespace
+    /// if `ignore_indent` and regardless of case if `ignore_case`. Returns how many were removed.
+    pub fn dedup(&mut self, rows: Range<usize>, ignore_indent: bool, ignore_case: bool) -> usize {
+        let rows = cmp::min(rows.start, self.len())..cmp::min(rows.end, self.len());
+        if self.duplicates(rows.clone(), ignore_indent, ignore_case) == 0 {
+            return 0;
+        }
+        self.changing(rows.clone());
+        let key = |row: &Row| dedup_key(row, ignore_indent, ignore_case);
+        let mut segment: Vec<Row> = self.rows.drain(rows.clone()).collect();
+        let len = segment.len();
+        segment.dedup_by(|row, previous| key(row) == key(previous));
+        let removed = len - segment.len();
+        self.rows.splice(rows.start..rows.start, segment);
+        removed
+    }
+
+    /// Removes trailing whitespace from every row.
+    pub fn trim_trailing_whitespace(&mut self) {
+        let trailing = |row: &Row| row.as_str().ends_with(char::is_whitespace);
+        let Some(first) = self.rows.iter().position(trailing) else {
+            return;
+        };
+        let last = self.rows.iter().rposition(trailing).unwrap_or(first);
+        self.changing(first..last + 1);
+        for row in &mut self.rows[first..=last] {
+            row.trim_end();
+        }
+    }
+
     /// `pos.y == len()` is allowed, noop if `pos.y` > `len()`.
     fn insert_newline(&mut self, pos: Position) {
         if pos.y > self.len() {
             return;
         }
 
-        self.dirty = true;
+        self.changing(pos.y..pos.y + 1);
 
         let new_row = Row::default();
 
@@ -182,3 +998,196 @@ impl Document {
         self.rows.insert(pos.y.saturating_add(1), new_row);
     }
 }
+
+/// The content of `rows`.
+fn lines(rows: &[Row]) -> Vec<String> {
+    rows.iter().map(|row| row.as_str().to_string()).collect()
+}
+
+/// What `Document::dedup` compares rows by.
+fn dedup_key(row: &Row, ignore_indent: bool, ignore_case: bool) -> String {
+    let text = if ignore_indent {
+        row.as_str().trim_start()
+    } else {
+        row.as_str()
+    };
+    if ignore_case {
+        text.to_lowercase()
+    } else {
+        text.to_string()
+    }
+}
+
+/// Decodes UTF-16 without its byte order mark, little-endian if `encoding` is `Utf16Le`.
+/// `bytes` of UTF-16 without a trailing odd byte or unpaired high surrogate, as a read stopping
+/// at an arbitrary length may leave.
+fn whole_code_units(bytes: &[u8], encoding: Encoding) -> &[u8] {
+    let bytes = &bytes[..bytes.len() & !1];
+    let high_byte = match (bytes, encoding) {
+        ([.., _, high], Encoding::Utf16Le) | ([.., high, _], _) => Some(*high),
+        _ => None,
+    };
+    if high_byte.is_some_and(|byte| (0xD8..=0xDB).contains(&byte)) {
+        &bytes[..bytes.len() - 2]
+    } else {
+        bytes
+    }
+}
+
+fn decode_utf16(bytes: &[u8], encoding: Encoding) -> Result<String, io::Error> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let pairs = bytes.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(invalid("odd number of bytes in UTF-16".into()));
+    }
+    let units = pairs.map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if encoding == Encoding::Utf16Le {
+            u16::from_le_bytes(pair)
+        } else {
+            u16::from_be_bytes(pair)
+        }
+    });
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8], encoding: Encoding) -> Document {
+        Document::read(PathBuf::from("test.txt"), bytes, encoding, &mut |_| Ok(())).unwrap()
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        document.iter().map(Row::as_str).collect()
+    }
+
+    #[test]
+    fn encodings_are_named_loosely() {
+        assert_eq!(Encoding::by_name("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::by_name("utf16le"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::by_name("ISO-8859-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::by_name("cp1252"), None);
+    }
+
+    #[test]
+    fn utf8_boms_and_line_endings_are_detected() {
+        let document = read(b"\xEF\xBB\xBFa\r\nb\n", Encoding::Utf8);
+        assert_eq!(lines(&document), ["a", "b"]);
+        assert_eq!(document.encoding(), Encoding::Utf8Bom);
+        assert_eq!(document.line_ending(), LineEnding::Crlf);
+    }
+
+    #[test]
+    fn invalid_utf8_is_reported_by_line() {
+        let document = read(b"ok\nh\xE9\n", Encoding::Utf8);
+        assert_eq!(lines(&document), ["ok", "h\u{FFFD}"]);
+        assert_eq!(document.invalid_lines(), [1]);
+
+        let document = read(b"ok\nh\xE9\n", Encoding::Latin1);
+        assert_eq!(lines(&document), ["ok", "h\u{E9}"]);
+        assert!(document.invalid_lines().is_empty());
+    }
+
+    #[test]
+    fn utf16_is_decoded_in_either_byte_order() {
+        assert_eq!(
+            decode_utf16(b"h\0\xE9\0", Encoding::Utf16Le).unwrap(),
+            "h\u{E9}"
+        );
+        assert_eq!(
+            decode_utf16(b"\0h\0\xE9", Encoding::Utf16Be).unwrap(),
+            "h\u{E9}"
+        );
+        assert!(decode_utf16(b"h\0\xE9", Encoding::Utf16Le).is_err());
+        // Unpaired surrogate
+        assert!(decode_utf16(b"\0\xD8", Encoding::Utf16Le).is_err());
+    }
+
+    #[test]
+    fn changes_record_only_the_edited_rows() {
+        let text = ["a", "b", "c", "d", "e"].map(String::from);
+        let mut document = Document::scratch(&text);
+        assert_eq!(document.take_change(), None);
+
+        document.insert_or_append(Position { x: 1, y: 3 }, '!');
+        document.insert_rows(1, &["new".into()]);
+        document.delete(Position { x: 0, y: 2 });
+        assert_eq!(lines(&document), ["a", "new", "", "c", "d!", "e"]);
+        // From the first edited row to the last, as they were
+        let change = Change {
+            start: 1,
+            len: 4,
+            old: ["b", "c", "d"].map(String::from).to_vec(),
+        };
+        assert_eq!(document.take_change(), Some(change));
+        assert_eq!(document.take_change(), None);
+
+        document.remove_rows(5..6);
+        let change = Change {
+            start: 5,
+            len: 0,
+            old: vec!["e".into()],
+        };
+        assert_eq!(document.take_change(), Some(change));
+    }
+
+    #[test]
+    fn only_latin1_has_unencodable_characters() {
+        let mut document = Document::scratch(&["h\u{E9} \u{263A}".to_string()]);
+        assert_eq!(document.unencodable(), 0);
+        document.set_encoding(Encoding::Latin1);
+        assert_eq!(document.unencodable(), 1);
+    }
+
+    #[test]
+    fn saving_older_line_endings_or_encodings_leaves_the_document_dirty() {
+        let mut document = read(b"a\n", Encoding::Utf8);
+        let job = document.save_job_to(PathBuf::from("test.txt"));
+        document.set_line_ending(LineEnding::Crlf);
+        document.saved(job.content_hash());
+        assert!(document.is_dirty());
+
+        let job = document.save_job_to(PathBuf::from("test.txt"));
+        document.set_encoding(Encoding::Latin1);
+        document.saved(job.content_hash());
+        assert!(document.is_dirty());
+
+        document.saved(
+            document
+                .save_job_to(PathBuf::from("test.txt"))
+                .content_hash(),
+        );
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn utf16_reads_are_cut_between_characters() {
+        // "a😀" then an odd byte
+        let le = b"a\0\x3D\xD8\x00\xDE\x62";
+        assert_eq!(whole_code_units(le, Encoding::Utf16Le), &le[..6]);
+        assert_eq!(whole_code_units(&le[..5], Encoding::Utf16Le), &le[..2]);
+        assert_eq!(whole_code_units(&le[..4], Encoding::Utf16Le), &le[..2]);
+        let be = b"\0a\xD8\x3D\xDE\x00";
+        assert_eq!(whole_code_units(be, Encoding::Utf16Be), be);
+        assert_eq!(whole_code_units(&be[..4], Encoding::Utf16Be), &be[..2]);
+
+        let path = std::env::temp_dir().join(format!("hecto-utf16-{}", std::process::id()));
+        let mut bytes = Encoding::Utf16Le.bom().to_vec();
+        for _ in 0..3 {
+            Encoding::Utf16Le.encode("😀\n", &mut bytes);
+        }
+        fs::write(&path, &bytes).unwrap();
+        // Within the surrogate pair of the second line, then within its second unit
+        for limit in [10, 11, 13] {
+            let document = Document::open_as(path.clone(), Encoding::Utf8, limit, |_| Ok(()));
+            let document = document.unwrap();
+            assert!(document.is_truncated());
+            assert_eq!(lines(&document), ["😀"]);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
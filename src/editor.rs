@@ -1,25 +1,106 @@
-use crate::{terminal, Document, Row, Terminal, TruncateGraphemes};
+use crate::cli::Args;
+use crate::completion;
+use crate::config::{BufferSettings, EditorConfig};
+use crate::date;
+use crate::document::{Encoding, TimedOut};
+use crate::editorconfig::Properties;
+use crate::event::{Event, Events, Mouse};
+use crate::ex::{self, Ex, Invocation};
+use crate::frame::{Frame, Line, Style};
+use crate::fuzzy;
+use crate::git::{self, LineChange, RepoStatus};
+use crate::history::{History, PromptKind};
+use crate::hooks::{Handler, Hook, Hooks};
+use crate::indent::{self, Indentation};
+use crate::input::{self, Chord};
+use crate::jobs::Jobs;
+use crate::keymap::{self, Command};
+use crate::lint::{self, Diagnostic, Severity};
+use crate::logging::{self, Level, Span};
+use crate::markdown;
+use crate::memory_terminal::MemoryTerminal;
+use crate::modeline::{self, Modeline};
+use crate::number;
+use crate::options::{self, Assignment, Setting};
+use crate::panel::Panel;
+use crate::picker::{self, Input, Picker};
+use crate::popup::{Popup, Rect};
+use crate::reflow;
+use crate::row;
+use crate::script::{self, Statement};
+use crate::shell;
+use crate::spell::{self, Dictionary};
+use crate::status_line;
+use crate::terminal::{self, CursorShape, Size, TermionTerminal};
+use crate::text_object;
+use crate::url;
+use crate::vim::{self, Action, InsertAt, Motion, Operator, ViewAlign};
+use crate::word_count::Counts;
+use crate::{Document, Row, Terminal, Theme, TruncateGraphemes};
 
 use anyhow::Result;
-use regex::Regex;
-use std::cmp;
-use std::io;
-use std::path::PathBuf;
-use std::str::FromStr;
+use regex::{Regex, RegexBuilder};
+use std::cell::{Cell, Ref, RefCell};
+use std::cmp::{self, Ordering};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{self, BufRead};
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use termion::event::Key;
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const HELP_MESSAGE: &str =
-    "<C-Q>: quit (don't save); <C-S>: save; <C-W>: save as; <C-F>: search regex in line; <F1>: Display this help message";
 
-const STATUS_BG_COLOR: terminal::RgbColor = terminal::RgbColor(0, 128, 128);
-const LINE_NUM_BG_COLOR: terminal::RgbColor = terminal::RgbColor(255, 255, 255);
-const LINE_NUM_FG_COLOR: terminal::RgbColor = terminal::RgbColor(0, 0, 0);
-/// Cursor margin at top/bottom
-const SCROLL_OFFSET: usize = 5;
+/// How often the editor gets a chance to do periodic work, such as autosaving.
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a background job runs before the status bar names it.
+const JOB_INDICATOR_DELAY: Duration = Duration::from_millis(500);
+/// How long loading or saving runs before its progress is shown.
+const PROGRESS_DELAY: Duration = Duration::from_millis(200);
+/// Shortest time between two redraws, events arriving meanwhile are drawn at once (60 fps).
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+/// How long status messages stay, prompts excepted.
+const STATUS_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Status messages kept for the message log, older ones are dropped.
+const MAX_MESSAGES: usize = 1000;
+/// Screen size assumed by batch mode, for commands that depend on it.
+const HEADLESS_SIZE: Size = Size {
+    width: 80,
+    height: 24,
+};
+/// The terminal panel's separator and at least one line of output.
+const MIN_PANEL_HEIGHT: usize = 3;
+/// Lines scrolled per notch of the mouse wheel.
+const WHEEL_LINES: isize = 3;
+/// Width paragraphs are reflowed to when there is no text width, as in Vim.
+const DEFAULT_REFLOW_WIDTH: usize = 79;
+/// How deep user commands can call each other, to stop infinite recursion.
+const MAX_COMMAND_DEPTH: usize = 16;
+/// Documents larger than this, in bytes, are written in the background.
+const BACKGROUND_SAVE_LEN: usize = 1 << 20;
+/// Files from this size on are read on another thread, showing progress.
+const BACKGROUND_LOAD_LEN: u64 = 1 << 20;
+/// Rows substituted between checks for `Esc`, which cancels the rest.
+const SUBSTITUTE_CHUNK: usize = 10_000;
+/// Size of a compiled regex past which its pattern is refused, as it would be slow to build
+/// and run, e.g. `\w{1000}{1000}`.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// Time spent looking for the query after each key while searching, not to lag behind typing.
+const SEARCH_BUDGET: Duration = Duration::from_millis(50);
+/// Bytes a counted paste inserts at most.
+const MAX_PASTE_LEN: usize = 64 << 20;
+/// Height of the completion menu, border included.
+const MAX_COMPLETION_ROWS: usize = 10;
+/// Files listed by the file finder, which stops looking past them.
+const MAX_FOUND_FILES: usize = 10_000;
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -31,282 +112,4915 @@ pub enum SearchDirection {
     Backward,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Insert,
+    /// Typed characters replace the ones under the cursor.
+    Overwrite,
+    /// Input goes to the message bar prompt.
+    Prompt,
+    /// Modal editing: keys are commands.
+    Normal,
+    /// Modal editing with a selection from `Editor::anchor` to the cursor.
+    Visual {
+        linewise: bool,
+    },
+}
+
+/// Answer to a yes/no question, see `Editor::confirm`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confirm {
+    Yes,
+    No,
+    /// Escaped, or asked without a terminal to answer.
+    Cancel,
+}
+
+impl Mode {
+    fn cursor_shape(self) -> CursorShape {
+        match self {
+            Self::Insert => CursorShape::Bar,
+            Self::Overwrite | Self::Normal | Self::Visual { .. } => CursorShape::Block,
+            Self::Prompt => CursorShape::Underline,
+        }
+    }
+}
+
+/// Maximum number of undo steps kept.
+const MAX_UNDO_LEVELS: usize = 100;
+/// Memory the edits of an undo or redo history may take, past which the oldest ones are
+/// dropped. The newest one is always kept, however large.
+const MAX_UNDO_BYTES: usize = 64 << 20;
+
+/// Rows to put back to undo or redo a change, see `Document::take_change`.
+struct Edit {
+    /// First row of the change.
+    start: usize,
+    /// Rows from `start` on that `lines` replace.
+    len: usize,
+    lines: Vec<String>,
+    cursor_position: Position,
+}
+
+impl Edit {
+    /// Memory taken by the lines, roughly.
+    fn size(&self) -> usize {
+        self.lines
+            .iter()
+            .map(|line| line.len() + mem::size_of::<String>())
+            .sum()
+    }
+}
+
+/// Edits to undo or redo, newest last, within `MAX_UNDO_LEVELS` and `MAX_UNDO_BYTES`.
+#[derive(Default)]
+struct UndoStack {
+    edits: VecDeque<Edit>,
+    /// Total size of the edits.
+    size: usize,
+}
+
+impl UndoStack {
+    fn push(&mut self, edit: Edit) {
+        self.size += edit.size();
+        self.edits.push_back(edit);
+        while self.edits.len() > MAX_UNDO_LEVELS
+            || (self.size > MAX_UNDO_BYTES && self.edits.len() > 1)
+        {
+            if let Some(oldest) = self.edits.pop_front() {
+                self.size -= oldest.size();
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<Edit> {
+        let edit = self.edits.pop_back()?;
+        self.size -= edit.size();
+        Some(edit)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.edits.clear();
+        self.size = 0;
+    }
+}
+
+/// A save running on another thread, posting `Event::Saved` once done.
+struct BackgroundSave {
+    /// Of the content being written, for `Document::saved`.
+    content_hash: u64,
+    autosave: bool,
+    thread: JoinHandle<Result<u64, io::Error>>,
+}
+
+/// Display cells of a row, reused until the row is edited or the view scrolls sideways.
+struct RenderedRow {
+    revision: u64,
+    columns: Range<usize>,
+    tab_width: usize,
+    /// Grapheme index and text of each cell, see `Row::render`.
+    cells: Vec<(usize, String)>,
+    indent: usize,
+    /// Display width of the whole row, to tell whether it continues past `columns`.
+    width: usize,
+}
+
+/// Lines of the Markdown preview, reused until the document is edited or the preview resized.
+struct RenderedPreview {
+    revision: u64,
+    width: usize,
+    /// Source row and rendered line, see `markdown::render`.
+    lines: Vec<(usize, Line)>,
+}
+
+/// A document set aside while another one is shown, with the state that goes with it.
+struct Buffer {
+    document: Document,
+    read_only: bool,
+    degraded: bool,
+    settings: BufferSettings,
+    cursor_position: Position,
+    offset: Position,
+    git_changes: Vec<Option<LineChange>>,
+    diagnostics: Vec<Diagnostic>,
+    undo_stack: UndoStack,
+    redo_stack: UndoStack,
+}
+
+impl Buffer {
+    /// `document` with the cursor at its start and no history.
+    fn new(document: Document, settings: BufferSettings) -> Self {
+        Self {
+            document,
+            read_only: false,
+            degraded: false,
+            settings,
+            cursor_position: Position::default(),
+            offset: Position::default(),
+            git_changes: Vec::new(),
+            diagnostics: Vec::new(),
+            undo_stack: UndoStack::default(),
+            redo_stack: UndoStack::default(),
+        }
+    }
+}
+
+/// Words offered at the cursor while choosing one.
+struct CompletionMenu {
+    /// Words, and whether they are names of snippets.
+    candidates: Vec<(String, bool)>,
+    /// The one inserted, `None` while only their common start is.
+    selected: Option<usize>,
+    /// Start of the completed word.
+    start: Position,
+}
+
+/// Timings of the last frame, shown in a corner to find out what is slow.
+#[derive(Default)]
+struct FrameStats {
+    /// Rendering and drawing the whole frame.
+    frame: Duration,
+    /// Of which rendering rows that changed, accumulated while rendering.
+    rows: Cell<Duration>,
+    /// From the first event handled to the frame showing its effect.
+    latency: Duration,
+    /// When the first event not drawn yet arrived.
+    pending_since: Option<Instant>,
+}
+
+/// What a key sequence runs.
+enum Binding {
+    Command(Command),
+    /// Mapped by a script.
+    CommandLine(String),
+}
+
+/// Text yanked or deleted by modal operators.
+struct Register {
+    text: String,
+    /// Whether the text is whole lines, pasted as new rows.
+    linewise: bool,
+}
+
 pub struct Editor {
     should_quit: bool,
-    terminal: Terminal,
+    mode: Mode,
+    /// Refuse to modify the document.
+    read_only: bool,
+    /// The document is a large file, shown without git changes nor spell checking.
+    degraded: bool,
+    terminal: Box<dyn Terminal>,
+    events: Events,
+    /// Background work, such as lint commands and git diffs.
+    jobs: Jobs,
+    /// Encodings set with `:set fileencoding`, by path, for files saved in them to be read back
+    /// in them.
+    encodings: HashMap<PathBuf, Encoding>,
+    config: EditorConfig,
+    /// Settings for the document's file type.
+    settings: BufferSettings,
     document: Document,
     status_message: String,
+    /// The status message as last drawn, and since when, for it to expire.
+    status_shown: (String, Instant),
+    /// Shown after the input while prompting.
+    prompt_hint: String,
+    /// Shown over the text area while picking an entry.
+    picker: Option<Picker>,
+    /// Shown below the cursor while completing a word.
+    completion_menu: Option<CompletionMenu>,
+    /// Shown in the top right corner, `None` while hidden.
+    frame_stats: Option<FrameStats>,
+    /// A directory or large file from the command line, with the line to go to, opened once
+    /// running since the user has to be asked first.
+    deferred_open: Option<(PathBuf, Option<usize>)>,
+    /// Status messages shown so far, oldest first.
+    messages: VecDeque<String>,
+    /// The document, while the message log is shown in its place.
+    stashed: Option<Buffer>,
+    /// Documents open besides the current one, in order.
+    buffers: Vec<Buffer>,
+    /// Position of the current document in the order of `buffers`, before `buffers[buffer_idx]`.
+    buffer_idx: usize,
+    /// Position of the buffer shown before the current one, in the same order.
+    alternate: Option<usize>,
     cursor_position: Position,
+    /// Offset of the viewport; `x` is in display columns, not graphemes.
     offset: Position,
+    /// Last search query, whose matches stay highlighted until cleared.
+    search_highlight: Option<Regex>,
+    /// Row and graphemes of the match an interactive substitution asks about.
+    replace_candidate: Option<(usize, Range<usize>)>,
+    /// Per-row changes relative to the git index, shown in the gutter.
+    git_changes: Vec<Option<LineChange>>,
+    /// Reported by the file type's lint command when last saved, sorted by position.
+    diagnostics: Vec<Diagnostic>,
+    /// Branch of the document's repository, if it is in one.
+    repo_status: Option<RepoStatus>,
+    /// What is currently displayed, to only redraw lines that changed.
+    last_frame: Frame,
+    /// When the document was last written, for autosaving.
+    last_save: Instant,
+    /// Save running in the background, see `Event::Saved`.
+    saving: Option<BackgroundSave>,
+    /// What is being loaded or saved in the background and since when, to show its progress
+    /// once it takes long.
+    progress: Option<(String, Instant)>,
+    /// Keys typed while waiting for a file to load, handled once it is.
+    deferred_keys: VecDeque<Chord>,
+    /// Rows on screen as last rendered, by index.
+    rendered_rows: RefCell<HashMap<usize, RenderedRow>>,
+    /// Words of rows by revision, for `{words}` not to count the whole file on every frame.
+    row_words: RefCell<HashMap<u64, usize>>,
+    /// The Markdown preview as last rendered.
+    rendered_preview: RefCell<Option<RenderedPreview>>,
+    /// Command lines of script-defined commands, by name.
+    user_commands: HashMap<String, Vec<String>>,
+    /// Key sequences mapped to command lines by scripts, taking precedence over the keymap.
+    key_mappings: HashMap<Vec<Chord>, String>,
+    /// Typed start of a key sequence, waiting for the rest.
+    pending_keys: Vec<Chord>,
+    /// When the last pending key was typed.
+    pending_since: Instant,
+    hooks: Hooks<Self>,
+    /// Set while hooks run, so that they don't trigger each other.
+    in_hook: bool,
+    /// Pending keys of a modal command.
+    vim: vim::Parser,
+    /// Typed with Alt and digits in insert mode, repeating the next command.
+    count: Option<usize>,
+    /// Other end of the visual mode selection.
+    anchor: Position,
+    register: Option<Register>,
+    undo_stack: UndoStack,
+    redo_stack: UndoStack,
+    /// Where the cursor was when the change being made began, to go back there on undo.
+    edit_cursor: Position,
+    /// Whether the last edit was typing, which following typing is grouped with for undo.
+    typing: bool,
+    /// Set while a command makes several edits that undo as one.
+    grouping_edits: bool,
+    /// Whether the last command was a quit refused because of unsaved changes, which quitting
+    /// again discards.
+    quit_warned: bool,
+    /// Loaded the first time spell checking is enabled.
+    dictionary: Option<Dictionary>,
+    /// Shell shown below the document, until it exits.
+    panel: Option<Panel>,
+    /// Whether keys go to the panel's shell.
+    panel_focused: bool,
+    /// Whether the rendered Markdown is shown beside the document.
+    preview: bool,
+    /// Running a batch script: there is no one to answer prompts.
+    headless: bool,
+    /// Previous answers to prompts.
+    history: History,
 }
 
 #[allow(clippy::unused_self)]
 impl Editor {
-    pub fn default() -> Result<Self, std::io::Error> {
-        Self::common_init(Document::default(), "".into())
+    /// Creates an editor on the real terminal, with the user's configuration file overridden by
+    /// command line options.
+    pub fn from_args(args: Args) -> Result<Self, std::io::Error> {
+        let (mut config, config_error) = match EditorConfig::load() {
+            Ok(config) => (config, None),
+            Err(e) => (EditorConfig::default(), Some(e)),
+        };
+        Self::apply_args(&mut config, &args);
+        config.init_script = EditorConfig::init_script_path().filter(|p| p.exists());
+        config.history_file = History::path();
+
+        let terminal = Box::new(TermionTerminal::init(config.mouse)?);
+        let mut editor = Self::with_terminal(terminal, args.path, config)?;
+        editor.read_only |= args.read_only;
+        match &mut editor.deferred_open {
+            Some((_, line)) => *line = args.line,
+            None => {
+                if let Some(line) = args.line {
+                    editor.go_to(line, 1);
+                }
+            }
+        }
+        if let Some(e) = config_error {
+            logging::warn(format_args!("invalid config: {e}"));
+            editor.status_message = format!("Invalid config, using defaults. {e}");
+        }
+        Ok(editor)
+    }
+
+    /// Runs the command lines of `script` on the file given in `args`, without a terminal.
+    /// Prompts are cancelled, and messages are printed to stderr.
+    ///
+    /// # Errors
+    /// If the config, file or script can't be read, a command doesn't exist, or changes are
+    /// left unsaved.
+    pub fn batch(args: Args, script: &Path) -> Result<(), String> {
+        let mut config = EditorConfig::load().map_err(|e| e.to_string())?;
+        Self::apply_args(&mut config, &args);
+
+        let terminal = Box::new(MemoryTerminal::new(HEADLESS_SIZE, Vec::new()));
+        let opened = args.path.is_some();
+        let mut editor =
+            Self::with_terminal(terminal, args.path, config).map_err(|e| e.to_string())?;
+        editor.headless = true;
+        // Without anyone to ask, large files are opened fully
+        if let Some((path, _)) = editor
+            .deferred_open
+            .take()
+            .filter(|(path, _)| path.is_file())
+        {
+            editor.open(path).map_err(|e| e.to_string())?;
+        }
+        if opened && !editor.document.has_path() {
+            return Err(editor.status_message);
+        }
+        // Not the key help
+        editor.status_message.clear();
+        editor.read_only |= args.read_only;
+        if let Some(line) = args.line {
+            editor.go_to(line, 1);
+        }
+
+        editor.source(script, 0)?;
+        if editor.document.is_dirty() && !editor.should_quit {
+            return Err("changes not written, end the script with `write` or `quit!`".into());
+        }
+        Ok(())
+    }
+
+    /// Overrides `config` with command line options.
+    fn apply_args(config: &mut EditorConfig, args: &Args) {
+        if let Some(tab_width) = args.tab_width {
+            config.tab_width = tab_width;
+            config.forced_tab_width = Some(tab_width);
+        }
+        if let Some(line_numbers) = args.line_numbers {
+            config.line_numbers = line_numbers;
+        }
+        if let Some(theme) = args.theme.as_deref().and_then(Theme::by_name) {
+            config.set_theme(theme);
+        }
     }
 
-    pub fn from_file_path(path: PathBuf) -> Result<Self, std::io::Error> {
-        let doc = Document::open(path.clone());
-        let mess = match doc {
-            Ok(_) => HELP_MESSAGE.into(),
+    /// Creates an editor drawing to and reading from `terminal`, opening `path` if given.
+    pub fn with_terminal(
+        terminal: Box<dyn Terminal>,
+        path: Option<PathBuf>,
+        config: EditorConfig,
+    ) -> Result<Self, std::io::Error> {
+        let Some(path) = path else {
+            return Self::common_init(terminal, config, Document::default(), "".into());
+        };
+        let large = large_file_size(&path, &config).is_some();
+        if path.is_dir() || large {
+            let message = if large {
+                format!(r#""{}" is a large file"#, path.display())
+            } else {
+                format!(r#""{}" is a directory"#, path.display())
+            };
+            let mut editor = Self::common_init(terminal, config, Document::default(), message)?;
+            editor.deferred_open = Some((path, None));
+            return Ok(editor);
+        }
+
+        let encoding = Properties::for_path(&path).encoding.unwrap_or_default();
+        let doc = Document::open_as(path.clone(), encoding, u64::MAX, |_| Ok(()));
+        let mess = match &doc {
+            Ok(doc) => invalid_utf8_warning(doc).unwrap_or_else(|| config.keymap.help()),
             Err(_) => format!("Couldn't open file: \"{}\"", path.to_string_lossy()),
         };
-        Self::common_init(doc.unwrap_or_default(), mess)
+        Self::common_init(terminal, config, doc.unwrap_or_default(), mess)
     }
 
     #[inline(always)]
-    fn common_init(document: Document, status_message: String) -> Result<Self, std::io::Error> {
-        Ok(Self {
+    fn common_init(
+        mut terminal: Box<dyn Terminal>,
+        config: EditorConfig,
+        document: Document,
+        status_message: String,
+    ) -> Result<Self, std::io::Error> {
+        let events = Events::init()?;
+        terminal.start_input(events.sender());
+        events.start_timer(TICK_INTERVAL);
+
+        let jobs = Jobs::new(events.sender());
+
+        let mut editor = Self {
+            settings: config.settings_for(document.file_type()),
             should_quit: false,
-            terminal: Terminal::init()?,
+            read_only: false,
+            degraded: false,
+            mode: if config.modal {
+                Mode::Normal
+            } else {
+                Mode::Insert
+            },
+            terminal,
+            events,
+            jobs,
+            encodings: HashMap::new(),
+            config,
             document,
             status_message,
+            status_shown: (String::new(), Instant::now()),
+            prompt_hint: String::new(),
+            picker: None,
+            completion_menu: None,
+            frame_stats: None,
+            deferred_open: None,
+            messages: VecDeque::new(),
+            stashed: None,
+            buffers: Vec::new(),
+            buffer_idx: 0,
+            alternate: None,
             cursor_position: Position::default(),
             offset: Position::default(),
-        })
+            search_highlight: None,
+            replace_candidate: None,
+            git_changes: Vec::new(),
+            diagnostics: Vec::new(),
+            repo_status: None,
+            last_frame: Frame::new(),
+            last_save: Instant::now(),
+            saving: None,
+            progress: None,
+            deferred_keys: VecDeque::new(),
+            rendered_rows: RefCell::default(),
+            row_words: RefCell::default(),
+            rendered_preview: RefCell::default(),
+            user_commands: HashMap::new(),
+            key_mappings: HashMap::new(),
+            pending_keys: Vec::new(),
+            pending_since: Instant::now(),
+            hooks: Hooks::default(),
+            in_hook: false,
+            vim: vim::Parser::default(),
+            count: None,
+            anchor: Position::default(),
+            register: None,
+            undo_stack: UndoStack::default(),
+            redo_stack: UndoStack::default(),
+            edit_cursor: Position::default(),
+            typing: false,
+            grouping_edits: false,
+            quit_warned: false,
+            dictionary: None,
+            panel: None,
+            panel_focused: false,
+            preview: false,
+            headless: false,
+            history: History::default(),
+        };
+        if let Some(path) = &editor.config.history_file {
+            // Starting without history is better than not starting
+            editor.history = History::load(path).unwrap_or_default();
+        }
+        editor.remember_file();
+        if editor.config.spell {
+            editor.load_dictionary();
+        }
+        editor.update_settings();
+        editor.apply_modeline_read_only();
+        editor.register_builtin_hooks();
+        if let Some(path) = editor.config.init_script.clone() {
+            if let Err(e) = editor.source(&path, 0) {
+                editor.status_message = e;
+            }
+        }
+        // After sourcing, so that scripts see the initial document
+        editor.run_hooks(Hook::Open);
+        Ok(editor)
     }
 
     pub fn run(&mut self) -> Result<()> {
-        println!("<C-Q> to quit\r");
+        self.terminal.print("<C-Q> to quit\r\n");
+        if let Some((path, line)) = self.deferred_open.take() {
+            self.open(path)?;
+            if let Some(line) = line {
+                self.go_to(line, 1);
+            }
+        }
         loop {
+            let frame_start = Instant::now();
             self.refresh_screen()?;
 
             if self.should_quit {
+                // Don't leave the file half-written
+                self.wait_for_save();
                 return Ok(());
             }
 
-            self.process_keypress()?;
+            self.process_event(None)?;
+            // Handle the rest of a paste or key repeat before redrawing, until the next frame is
+            // due even if events keep coming
+            let frame_end = frame_start + FRAME_INTERVAL;
+            while !self.should_quit
+                && Instant::now() < frame_end
+                && self.process_event(Some(frame_end))?
+            {}
         }
     }
 
+    #[must_use]
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    #[must_use]
+    pub fn cursor_position(&self) -> Position {
+        self.cursor_position
+    }
+
+    /// Message shown at the bottom of the screen.
+    #[must_use]
+    pub fn status_message(&self) -> &str {
+        &self.status_message
+    }
+
+    /// Whether a quit command ran.
+    #[must_use]
+    pub fn should_quit(&self) -> bool {
+        self.should_quit
+    }
+
     fn save(&mut self, always_ask: bool) {
         if always_ask || !self.document.has_path() {
             let path = self
-                .prompt("Save as: ", self.document.get_path_string(), |_, _, _| {})
+                .prompt(
+                    "Save as: ",
+                    PromptKind::Path,
+                    self.document.get_path_string(),
+                    |_, _, _| {},
+                )
                 .unwrap_or(None);
 
             match path {
-                None => {
-                    self.status_message = "Save aborted".into();
-                    return;
+                None => self.status_message = "Save aborted".into(),
+                Some(path) => self.save_as(PathBuf::from(path)),
+            }
+            return;
+        }
+
+        if self.document.is_truncated() {
+            self.status_message =
+                "Only the start of the file was read, save it under another name".into();
+            return;
+        }
+        if !self.confirm_replacements() {
+            return;
+        }
+        self.run_hooks(Hook::BeforeSave);
+        self.write_document(false);
+    }
+
+    /// Saves the document to `path`, asking before overwriting another file. The document only
+    /// moves to `path` once written there.
+    fn save_as(&mut self, path: PathBuf) {
+        if self.document.path() == Some(path.as_path()) {
+            self.save(false);
+            return;
+        }
+        if path.exists()
+            && self.confirm(&format!("\"{}\" exists, overwrite it?", path.display()))
+                != Confirm::Yes
+        {
+            self.status_message = "Save aborted".into();
+            return;
+        }
+        if !self.confirm_replacements() {
+            return;
+        }
+
+        self.run_hooks(Hook::BeforeSave);
+        self.wait_for_save();
+        let job = self.document.save_job_to(path.clone());
+        match job.write() {
+            Ok(sz) => {
+                self.document.set_path(path);
+                self.update_settings();
+                self.finish_save(job.content_hash(), false, Ok(sz));
+            }
+            Err(e) => {
+                self.status_message = format!(r#""{}" Error writing to file: {e}"#, path.display());
+            }
+        }
+    }
+
+    /// Asks whether to save the replacements of invalid UTF-8 read from the file, if any.
+    fn confirm_replacements(&mut self) -> bool {
+        if self.document.invalid_lines().is_empty() {
+            return true;
+        }
+        let question = "Invalid UTF-8 was replaced with \u{fffd}, save the replacements?";
+        if self.confirm(question) != Confirm::Yes {
+            self.status_message = "Save aborted".into();
+            return false;
+        }
+        self.document.forget_invalid_lines();
+        true
+    }
+
+    /// Writes the document, on a background thread if it is large.
+    fn write_document(&mut self, autosave: bool) {
+        self.wait_for_save();
+        let Some(job) = self.document.save_job() else {
+            return;
+        };
+        if job.len() < BACKGROUND_SAVE_LEN || self.headless {
+            let result = job.write();
+            self.finish_save(job.content_hash(), autosave, result);
+            return;
+        }
+
+        if !autosave {
+            let label = format!(
+                r#""{}" Saving"#,
+                self.document.get_path_string().unwrap_or_default()
+            );
+            self.status_message = format!("{label}…");
+            self.progress = Some((label, Instant::now()));
+        }
+        let events = self.events.sender();
+        let content_hash = job.content_hash();
+        let total = job.len() as u64;
+        let thread = thread::spawn(move || {
+            let result = job.write_with_progress(|done| {
+                let _ = events.send(Event::Progress { done, total });
+            });
+            let _ = events.send(Event::Saved);
+            result
+        });
+        self.saving = Some(BackgroundSave {
+            content_hash,
+            autosave,
+            thread,
+        });
+    }
+
+    /// Waits for the background save, if any, to complete.
+    fn wait_for_save(&mut self) {
+        let Some(save) = self.saving.take() else {
+            return;
+        };
+        self.progress = None;
+        let result = save
+            .thread
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("saving thread panicked")));
+        self.finish_save(save.content_hash, save.autosave, result);
+    }
+
+    fn finish_save(&mut self, content_hash: u64, autosave: bool, result: Result<u64, io::Error>) {
+        let path = self.document.get_path_string().unwrap_or_default();
+        match result {
+            Ok(sz) => {
+                self.last_save = Instant::now();
+                self.document.saved(content_hash);
+                if !autosave {
+                    self.status_message =
+                        format!(r#""{path}" {}L, {sz}B written"#, self.document.len());
+                    let unencodable = self.document.unencodable();
+                    if unencodable > 0 {
+                        self.status_message += &format!(
+                            ", {unencodable} characters written as ? in {}",
+                            self.document.encoding()
+                        );
+                    }
+                }
+                if !self.document.is_dirty() {
+                    self.run_hooks(Hook::AfterSave);
                 }
-                Some(p) => self.document.set_path(p.into()),
             }
+            Err(e) if autosave => {
+                self.status_message = format!(r#""{path}" Error autosaving: {e}"#);
+            }
+            Err(e) => self.status_message = format!(r#""{path}" Error writing to file: {e}"#),
         }
+    }
 
-        self.status_message = match self.document.save() {
-            Ok(sz) => format!(
-                r#""{}" {}L, {sz}B written"#,
-                self.document.get_path_string().unwrap_or_default(),
-                self.document.len()
-            ),
-            Err(e) => format!(
-                r#""{}" Error writing to file: {}"#,
-                self.document.get_path_string().unwrap_or_default(),
-                e
-            ),
+    fn register_builtin_hooks(&mut self) {
+        self.hooks
+            .register(Hook::Open, Handler::Builtin(Self::refresh_git_changes));
+        self.hooks
+            .register(Hook::AfterSave, Handler::Builtin(Self::refresh_git_changes));
+        self.hooks
+            .register(Hook::Open, Handler::Builtin(Self::refresh_repo_status));
+        self.hooks
+            .register(Hook::AfterSave, Handler::Builtin(Self::refresh_repo_status));
+        self.hooks
+            .register(Hook::AfterSave, Handler::Builtin(Self::run_linter));
+        self.hooks
+            .register(Hook::BeforeSave, Handler::Builtin(Self::trim_on_save));
+    }
+
+    /// Runs the handlers of `hook`, unless a hook is already running.
+    fn run_hooks(&mut self, hook: Hook) {
+        if self.in_hook {
+            return;
+        }
+        self.in_hook = true;
+        for handler in self.hooks.handlers(hook) {
+            match handler {
+                Handler::Builtin(f) => f(self),
+                Handler::CommandLine(line) => {
+                    if let Err(e) = self.run_command_line(&line, 0) {
+                        self.status_message = format!("Error in {hook:?} hook: {e}");
+                    }
+                }
+            }
         }
+        self.in_hook = false;
     }
 
-    fn useful_text_width(&self) -> usize {
+    fn trim_on_save(&mut self) {
+        if self.settings.trim_trailing_whitespace {
+            self.document.trim_trailing_whitespace();
+            // Keep the cursor inside the (possibly shorter) row
+            self.move_cursor(Key::Null);
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.run_hooks(Hook::ModeChange);
+        }
+    }
+
+    /// Resolves the settings for the document's file type, then applies the indentation
+    /// detected in the document, the `.editorconfig` files of its directory and the document's
+    /// modeline.
+    fn update_settings(&mut self) {
+        let modeline = self.modeline().unwrap_or_default();
+        if let Some(file_type) = modeline.file_type {
+            self.document.set_file_type(file_type);
+        }
+        self.settings = self.config.settings_for(self.document.file_type());
+        if self.config.detect_indent && !self.degraded {
+            match indent::detect(self.document.iter().map(Row::as_str)) {
+                Some(Indentation::Tabs) => self.settings.expand_tab = false,
+                Some(Indentation::Spaces(width)) => {
+                    self.settings.expand_tab = true;
+                    self.settings.tab_width = width;
+                }
+                None => (),
+            }
+        }
+
+        if let Some(path) = self.document.path() {
+            let properties = Properties::for_path(path);
+            properties.apply(&mut self.settings);
+            // Existing files keep theirs, converting them being an edit of its own
+            if !path.exists() {
+                if let Some(line_ending) = properties.line_ending {
+                    self.document.set_line_ending(line_ending);
+                }
+                if let Some(encoding) = properties.encoding {
+                    self.document.set_encoding(encoding);
+                }
+            }
+            if let Some(final_newline) = properties.insert_final_newline {
+                self.document.set_final_newline(final_newline);
+            }
+        }
+
+        // More specific than the project's settings
+        if let Some(width) = modeline.tab_width {
+            self.settings.tab_width = width;
+        }
+        if let Some(expand) = modeline.expand_tab {
+            self.settings.expand_tab = expand;
+        }
+        if let Some(width) = self.config.forced_tab_width {
+            self.settings.tab_width = width;
+        }
+    }
+
+    /// Makes the document read-only if its modeline asks for it, when it is opened. Not in
+    /// `update_settings`, for saving under another name not to lock the document.
+    fn apply_modeline_read_only(&mut self) {
+        if self.modeline().is_some_and(|modeline| modeline.read_only) {
+            self.read_only = true;
+        }
+    }
+
+    /// The modeline among the first or last lines of the document, unless disabled.
+    fn modeline(&self) -> Option<Modeline> {
+        if !self.config.modelines {
+            return None;
+        }
+        let len = self.document.len();
+        let lines = (0..cmp::min(modeline::LINES, len))
+            .chain(cmp::max(modeline::LINES, len.saturating_sub(modeline::LINES))..len)
+            .filter_map(|y| self.document.get(y).map(Row::as_str));
+        Modeline::find(lines)
+    }
+
+    /// Starts diffing the document against the git index in the background.
+    fn refresh_git_changes(&mut self) {
+        if self.degraded {
+            return;
+        }
+        if let Some(path) = self.document.path() {
+            let lines: Vec<String> = self
+                .document
+                .iter()
+                .map(|row| row.as_str().into())
+                .collect();
+            let path = path.to_path_buf();
+            self.jobs.cancel("diff");
+            self.jobs.spawn("diff", move |token| {
+                let base = git::index_content(&path, token)?;
+                if token.is_cancelled() {
+                    return None;
+                }
+                Some(Event::GitChanges(git::line_changes(&base, &lines)))
+            });
+        }
+    }
+
+    /// Starts the file type's lint command in the background.
+    fn run_linter(&mut self) {
+        if let (Some(command), Some(path)) = (&self.settings.lint, self.document.path()) {
+            let (command, path) = (command.clone(), path.to_path_buf());
+            self.jobs.cancel("lint");
+            self.jobs.spawn("lint", move |token| {
+                lint::run(&command, &path, token).map(Event::Diagnostics)
+            });
+        }
+    }
+
+    /// Moves to the next (or previous) diagnostic, wrapping around, and shows its message.
+    fn go_to_diagnostic(&mut self, forward: bool) {
+        let Position { x, y } = self.cursor_position;
+        let column = |d: &Diagnostic| {
+            self.document.get(d.line).map_or(d.column, |row| {
+                // Columns are in characters
+                let byte_idx = row
+                    .as_str()
+                    .char_indices()
+                    .nth(d.column)
+                    .map_or(row.len_bytes(), |(idx, _)| idx);
+                Row::from(&row.as_str()[..byte_idx]).len()
+            })
+        };
+        let positions: Vec<(Position, &Diagnostic)> = self
+            .diagnostics
+            .iter()
+            .map(|d| {
+                let pos = Position {
+                    x: column(d),
+                    y: d.line,
+                };
+                (pos, d)
+            })
+            .collect();
+
+        let found = if forward {
+            positions
+                .iter()
+                .find(|(pos, _)| (pos.y, pos.x) > (y, x))
+                .or_else(|| positions.first())
+        } else {
+            positions
+                .iter()
+                .rev()
+                .find(|(pos, _)| (pos.y, pos.x) < (y, x))
+                .or_else(|| positions.last())
+        };
+        let Some(&(pos, diagnostic)) = found else {
+            self.status_message = "No diagnostics".into();
+            return;
+        };
+
+        self.status_message = format!("{}: {}", pos.y + 1, diagnostic.message);
+        self.cursor_position = pos;
+        self.move_cursor(Key::Null);
+    }
+
+    /// Starts reading the branch and status of the repository in the background.
+    fn refresh_repo_status(&mut self) {
+        if let Some(path) = self.document.path() {
+            let path = path.to_path_buf();
+            self.jobs.cancel("status");
+            self.jobs.spawn("status", move |token| {
+                Some(Event::GitStatus(git::repo_status(&path, token)))
+            });
+        }
+    }
+
+    /// Starts looking for the last commit that changed the current line, shown once found.
+    fn blame(&mut self) {
+        let Some(path) = self.document.path() else {
+            self.status_message = "Can't blame a file that was never saved".into();
+            return;
+        };
+        let path = path.to_path_buf();
+        let line = self.cursor_position.y + 1;
+        // Blame the buffer as edited, so that line numbers match
+        let mut content = String::new();
+        for row in self.document.iter() {
+            content.push_str(row.as_str());
+            content.push('\n');
+        }
+        self.jobs.cancel("blame");
+        self.jobs.spawn("blame", move |_| {
+            Some(Event::Blame(git::blame(&path, line, &content)))
+        });
+    }
+
+    /// Opens the URL under the cursor in the system browser.
+    fn open_url(&mut self) -> Result<(), io::Error> {
+        let url = self.document.get(self.cursor_position.y).and_then(|row| {
+            url::at(row.as_str(), row.byte_index(self.cursor_position.x)).map(String::from)
+        });
+        let Some(url) = url else {
+            self.status_message = "No URL under the cursor".into();
+            return Ok(());
+        };
+
+        // Openers may print to the terminal, e.g. when no browser is found
+        self.terminal.pause()?;
+        let status = url::opener(&url).stdin(Stdio::null()).status();
+        self.terminal.resume()?;
+        self.redraw_all()?;
+
+        self.status_message = match status {
+            Ok(status) if status.success() => format!("Opened {url}"),
+            Ok(status) => format!("Couldn't open {url}: {status}"),
+            Err(e) => format!("Couldn't open {url}: {e}"),
+        };
+        Ok(())
+    }
+
+    /// Saves the document if it has been modified for longer than the autosave interval.
+    fn autosave(&mut self) {
+        let Some(interval) = self.config.autosave_interval else {
+            return;
+        };
+        if !self.document.is_dirty() {
+            // Count from the first modification, not from whenever the file was last saved
+            self.last_save = Instant::now();
+            return;
+        }
+        // Writing replacement characters over invalid UTF-8 needs the user's consent
+        if !self.document.has_path()
+            || self.saving.is_some()
+            || !self.document.invalid_lines().is_empty()
+            || self.last_save.elapsed() < interval
+        {
+            return;
+        }
+
+        self.last_save = Instant::now();
+        self.write_document(true);
+    }
+
+    /// Columns left for the document by the Markdown preview.
+    fn text_width(&self) -> usize {
         let width: usize = self.terminal.size().width.into();
-        width.saturating_sub(self.num_col_width())
+        if self.preview {
+            width - width / 2
+        } else {
+            width
+        }
+    }
+
+    fn useful_text_width(&self) -> usize {
+        let width = self.text_width();
+        // Leave room for the space after the line numbers, and for the scrollbar in the rightmost
+        // column
+        width.saturating_sub(self.num_col_width()).saturating_sub(2)
     }
 
     fn num_col_width(&self) -> usize {
+        if !self.config.line_numbers {
+            // Only the git change marker
+            return 1;
+        }
         (self.document.len().checked_ilog10().unwrap_or(0) + 1 + 1) as _
     }
 
-    fn refresh_screen(&self) -> Result<(), io::Error> {
-        Terminal::cursor_position(Position::default());
-
+    /// Draws what changed since the last refresh.
+    ///
+    /// # Errors
+    /// If the terminal can't be written to.
+    pub fn refresh_screen(&mut self) -> Result<(), io::Error> {
         if self.should_quit {
-            Terminal::clear_screen();
-            println!("Goodbye!\r");
+            // The terminal restores itself when dropped
+            return Ok(());
+        }
+
+        let _span = Span::new(Level::Trace, "render");
+        let start = Instant::now();
+        if let Some(stats) = &self.frame_stats {
+            stats.rows.set(Duration::ZERO);
+        }
+        self.expire_status_message();
+        let frame = self.render();
+
+        // Only emit the lines that changed since the previous frame
+        for (y, line) in frame.iter().enumerate() {
+            if self.last_frame.get(y) != Some(line) {
+                self.terminal.cursor_position(Position { x: 0, y });
+                self.terminal.clear_current_line();
+                self.terminal.print_line(line);
+            }
+        }
+        self.last_frame = frame;
+
+        if let Some(position) = self.panel_cursor().filter(|_| self.panel_focused) {
+            self.terminal.set_cursor_shape(CursorShape::Block);
+            self.terminal.cursor_position(position);
         } else {
-            self.draw_rows();
-            self.draw_status_bar();
-            self.draw_message_bar();
-            Terminal::cursor_position(Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x) + self.num_col_width() + 1,
+            self.terminal.set_cursor_shape(self.mode.cursor_shape());
+            self.terminal.cursor_position(Position {
+                x: self.cursor_render_x().saturating_sub(self.offset.x) + self.num_col_width() + 1,
                 y: self.cursor_position.y.saturating_sub(self.offset.y),
             });
         }
 
-        Terminal::flush()
+        let result = self.terminal.flush();
+        if let Some(stats) = &mut self.frame_stats {
+            stats.frame = start.elapsed();
+            if let Some(since) = stats.pending_since.take() {
+                stats.latency = since.elapsed();
+            }
+        }
+        result
     }
 
-    fn draw_rows(&self) {
-        let (width, height): (usize, usize) = {
-            let s = self.terminal.size();
-            (s.width.into(), s.height.into())
-        };
+    /// The whole screen as `refresh_screen` would draw it, without touching the terminal.
+    #[must_use]
+    pub fn render(&self) -> Frame {
+        let mut frame = self.draw_rows();
+        self.draw_picker(&mut frame);
+        self.draw_completion_menu(&mut frame);
+        self.draw_frame_stats(&mut frame);
+        frame.push(self.draw_status_bar());
+        frame.push(self.draw_message_bar());
+        frame
+    }
 
-        // Terminal::size already takes care of leaving space for status bars
-        for rel_line_num in 0..height {
-            Terminal::clear_current_line();
-
-            let line_num = rel_line_num + self.offset.y;
-            if let Some(row) = self.document.get(line_num) {
-                self.draw_row(row, line_num + 1, self.num_col_width());
-            } else if self.document.is_empty() && rel_line_num == height / 3 {
-                self.draw_welcome_message(width);
-            } else {
-                println!("~\r");
+    /// Clears the status message once it has been shown for `STATUS_MESSAGE_TIMEOUT`. Ticks
+    /// refresh the screen, so it goes away even without keypresses.
+    fn expire_status_message(&mut self) {
+        if self.status_message != self.status_shown.0 {
+            self.status_shown = (self.status_message.clone(), Instant::now());
+            // While prompting, the message is the prompt itself
+            if !self.status_message.is_empty() && self.mode != Mode::Prompt {
+                if self.messages.len() == MAX_MESSAGES {
+                    self.messages.pop_front();
+                }
+                logging::info(format_args!("message: {}", self.status_message));
+                self.messages.push_back(self.status_message.clone());
             }
+        } else if self.mode != Mode::Prompt
+            && self.status_shown.1.elapsed() >= STATUS_MESSAGE_TIMEOUT
+        {
+            self.status_message.clear();
         }
     }
 
-    fn draw_row(&self, row: &Row, line_num: usize, num_width: usize) {
-        let width = self.useful_text_width();
+    /// Forces the next `refresh_screen` to redraw every line.
+    fn invalidate_screen(&mut self) {
+        self.last_frame.clear();
+    }
 
-        let start = self.offset.x;
-        let end = start + width;
+    /// Rows left for the document by the terminal panel.
+    fn text_height(&self) -> usize {
+        usize::from(self.terminal.size().height).saturating_sub(self.panel_height())
+    }
 
-        let row = row.render(start..end);
-        Terminal::set_bg_color(LINE_NUM_BG_COLOR);
-        Terminal::set_fg_color(LINE_NUM_FG_COLOR);
-        print!("{line_num:>num_width$}");
-        Terminal::reset_fg_color();
-        Terminal::reset_bg_color();
-        println!(" {row}\r");
-    }
-
-    fn draw_status_bar(&self) {
-        let file_name = match self.document.get_file_name() {
-            Some(name) => {
-                let mut name = name.clone();
-                if name.len() <= 30 {
-                    name
+    /// Rows of the terminal panel, including its separator.
+    fn panel_height(&self) -> usize {
+        if self.panel.is_none() {
+            return 0;
+        }
+        panel_height(self.terminal.size().height.into())
+    }
+
+    fn draw_rows(&self) -> Frame {
+        let width = self.text_width();
+        let height = self.text_height();
+
+        let scrollbar_thumb = self.scrollbar_thumb(height);
+        let mut preview = self.draw_preview(height).into_iter();
+
+        // Terminal::size already takes care of leaving space for status bars
+        let frame = (0..height)
+            .map(|rel_line_num| {
+                let line_num = rel_line_num + self.offset.y;
+                let mut line = if let Some(row) = self.document.get(line_num) {
+                    self.draw_row(row, line_num + 1, self.num_col_width())
+                } else if self.document.is_empty() && rel_line_num == height / 3 {
+                    self.draw_welcome_message(width.saturating_sub(1))
                 } else {
-                    name.truncate_graphemes(29);
-                    format!("<{name}")
+                    Line::from("~")
+                };
+
+                if let Some(thumb) = &scrollbar_thumb {
+                    line.pad(Style::default(), width.saturating_sub(1));
+                    line.push(
+                        Style::default().fg(self.config.theme.scrollbar_fg),
+                        if thumb.contains(&rel_line_num) {
+                            "┃"
+                        } else {
+                            "│"
+                        },
+                    );
+                }
+                if self.preview {
+                    line.pad(Style::default(), width);
+                    line.push(Style::default().fg(self.config.theme.scrollbar_fg), "│");
+                    line.append(&preview.next().unwrap_or_default());
+                }
+                line
+            })
+            .chain(self.draw_panel(self.terminal.size().width.into()))
+            .collect();
+
+        // Forget rows scrolled out of view
+        let visible = self.offset.y..self.offset.y + height;
+        self.rendered_rows
+            .borrow_mut()
+            .retain(|y, _| visible.contains(y));
+        frame
+    }
+
+    /// The cells of row `y` in `columns`, from the cache if the row is unchanged.
+    fn rendered_row(&self, y: usize, row: &Row, columns: Range<usize>) -> Ref<'_, RenderedRow> {
+        let tab_width = self.settings.tab_width;
+        let fresh = self.rendered_rows.borrow().get(&y).is_some_and(|rendered| {
+            rendered.revision == row.revision()
+                && rendered.columns == columns
+                && rendered.tab_width == tab_width
+        });
+        if !fresh {
+            let start = Instant::now();
+            let rendered = RenderedRow {
+                revision: row.revision(),
+                cells: row
+                    .render(columns.clone(), tab_width)
+                    .into_iter()
+                    .map(|(idx, cell)| (idx, cell.to_string()))
+                    .collect(),
+                columns,
+                tab_width,
+                indent: row.indent_width(tab_width),
+                width: row.render_x(row.len(), tab_width),
+            };
+            self.rendered_rows.borrow_mut().insert(y, rendered);
+            if let Some(stats) = &self.frame_stats {
+                stats.rows.set(stats.rows.get() + start.elapsed());
+            }
+        }
+        Ref::map(self.rendered_rows.borrow(), |rows| &rows[&y])
+    }
+
+    /// Rendered Markdown from the first row on screen, or nothing without a preview.
+    fn draw_preview(&self, height: usize) -> Vec<Line> {
+        if !self.preview {
+            return Vec::new();
+        }
+        // Right of the separator
+        let width = usize::from(self.terminal.size().width).saturating_sub(self.text_width() + 1);
+        let revision = self.document.revision();
+        let mut cache = self.rendered_preview.borrow_mut();
+        let fresh = cache
+            .as_ref()
+            .is_some_and(|preview| preview.revision == revision && preview.width == width);
+        if !fresh {
+            *cache = Some(RenderedPreview {
+                revision,
+                width,
+                lines: markdown::render(
+                    self.document.iter().map(Row::as_str),
+                    width,
+                    &self.config.theme,
+                ),
+            });
+        }
+        let rendered = cache.as_ref().map_or(&[][..], |preview| &preview.lines);
+        let start = rendered
+            .iter()
+            .position(|&(idx, _)| idx >= self.offset.y)
+            .unwrap_or(rendered.len());
+        rendered
+            .iter()
+            .skip(start)
+            .take(height)
+            .map(|(_, line)| line.clone())
+            .collect()
+    }
+
+    /// Shows or hides the Markdown preview.
+    fn toggle_preview(&mut self) {
+        if !self.preview && self.document.file_type().name() != "markdown" {
+            self.status_message = "Only Markdown documents can be previewed".into();
+            return;
+        }
+        self.preview = !self.preview;
+        // The document is narrower or wider
+        self.scroll();
+    }
+
+    /// Shows the status messages so far in place of the document, read-only, or goes back to the
+    /// document.
+    fn toggle_messages(&mut self) {
+        if let Some(mut buffer) = self.stashed.take() {
+            self.swap_buffer(&mut buffer);
+            return;
+        }
+        // The save would complete on the log
+        self.wait_for_save();
+        let lines: Vec<String> = self.messages.iter().cloned().collect();
+        let document = Document::scratch(&lines);
+        let settings = self.config.settings_for(document.file_type());
+        let mut buffer = Buffer {
+            read_only: true,
+            cursor_position: Position {
+                x: 0,
+                y: lines.len().saturating_sub(1),
+            },
+            ..Buffer::new(document, settings)
+        };
+        self.swap_buffer(&mut buffer);
+        self.stashed = Some(buffer);
+        self.preview = false;
+        if matches!(self.mode, Mode::Visual { .. }) {
+            self.set_mode(Mode::Normal);
+        }
+        self.scroll();
+    }
+
+    /// Asks for a file to open.
+    fn open_prompt(&mut self) -> Result<(), io::Error> {
+        match self.prompt("Open: ", PromptKind::Path, None, |_, _, _| {})? {
+            Some(path) => self.open(PathBuf::from(path)),
+            None => Ok(()),
+        }
+    }
+
+    /// The file at `path`, or an empty document to be saved there if it doesn't exist, with
+    /// whether it is new. Only its first `limit` bytes are read. `None` after reporting why it
+    /// can't be read.
+    fn load(&mut self, path: &Path, limit: u64) -> Option<(Document, bool)> {
+        let large = fs::metadata(path).is_ok_and(|m| m.len() >= BACKGROUND_LOAD_LEN);
+        let result = if large && !self.headless {
+            self.load_in_background(path, limit)
+        } else {
+            let encoding = self.file_encoding(path);
+            Document::open_as(path.to_path_buf(), encoding, limit, |_| Ok(()))
+        };
+        match result {
+            Ok(document) => Some((document, false)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut document = Document::default();
+                document.set_path(path.to_path_buf());
+                Some((document, true))
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                self.status_message = "Open cancelled".into();
+                None
+            }
+            Err(e) => {
+                self.status_message = format!("Couldn't open {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Reads the file at `path` on another thread, showing progress and handling other events
+    /// meanwhile. Keys are kept for later, except `Esc` or `Ctrl-C` which cancel the load.
+    fn load_in_background(&mut self, path: &Path, limit: u64) -> Result<Document, io::Error> {
+        let total = cmp::min(fs::metadata(path)?.len(), limit);
+        let events = self.events.sender();
+        let owned = path.to_path_buf();
+        let encoding = self.file_encoding(path);
+        self.jobs.spawn("open", move |token| {
+            let result = Document::open_as(owned, encoding, limit, |done| {
+                if token.is_cancelled() {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                let _ = events.send(Event::Progress { done, total });
+                Ok(())
+            });
+            Some(Event::Loaded(Box::new(result)))
+        });
+        self.progress = Some((format!(r#""{}" Opening"#, path.display()), Instant::now()));
+
+        let result = loop {
+            let Some(event) = self.next_new_event(None)? else {
+                continue;
+            };
+            match event {
+                Event::Key(chord) if is_interrupt(chord) => {
+                    self.jobs.cancel("open");
+                    break Err(io::ErrorKind::Interrupted.into());
+                }
+                Event::Key(chord) => self.deferred_keys.push_back(chord),
+                Event::Job(result) => match self.jobs.finish(result) {
+                    Some(Event::Loaded(result)) => break *result,
+                    Some(event) => {
+                        self.handle_event(event)?;
+                    }
+                    None => (),
+                },
+                event => {
+                    self.handle_event(event)?;
+                }
+            }
+            self.refresh_screen()?;
+        };
+        self.progress = None;
+        result
+    }
+
+    /// Shows the file at `path`, in a new buffer after the current one unless it is already
+    /// open. An untouched untitled document is replaced. For a directory, lets the user pick one
+    /// of its files.
+    fn open(&mut self, path: PathBuf) -> Result<(), io::Error> {
+        if path.is_dir() {
+            return self.open_directory(&path);
+        }
+        if self.stashed.is_some() {
+            self.toggle_messages();
+        }
+        let idx = (0..=self.buffers.len())
+            .find(|&idx| self.buffer_document(idx).path() == Some(path.as_path()));
+        if let Some(idx) = idx {
+            self.switch_buffer(idx);
+            return Ok(());
+        }
+        let mut degraded = false;
+        let mut limit = u64::MAX;
+        if let Some(size) = large_file_size(&path, &self.config).filter(|_| !self.headless) {
+            let start = self.config.large_file_size.unwrap_or(size);
+            let question = format!(
+                r#""{}" is {}, open its first {} [r]ead-only, [f]ully or [c]ancel?"#,
+                path.display(),
+                format_size(size),
+                format_size(start)
+            );
+            match self.choose(&question, &['r', 'f', 'c']) {
+                Some('r') => {
+                    degraded = true;
+                    limit = start;
+                }
+                Some('f') => (),
+                _ => {
+                    self.status_message = "Open cancelled".into();
+                    return Ok(());
+                }
+            }
+        }
+        let Some((document, new)) = self.load(&path, limit) else {
+            return Ok(());
+        };
+        // Not to complete on the new document
+        self.wait_for_save();
+
+        let settings = self.config.settings_for(document.file_type());
+        let mut buffer = Buffer {
+            read_only: self.read_only || degraded,
+            degraded,
+            ..Buffer::new(document, settings)
+        };
+        self.swap_buffer(&mut buffer);
+        let untouched = !buffer.document.has_path()
+            && buffer.document.is_empty()
+            && !buffer.document.is_dirty();
+        if !untouched {
+            self.buffers.insert(self.buffer_idx, buffer);
+            self.alternate = Some(self.buffer_idx);
+            self.buffer_idx += 1;
+        }
+        self.update_settings();
+        self.apply_modeline_read_only();
+        self.after_switch();
+        self.remember_file();
+        if new {
+            self.status_message = format!(r#""{}" [New]"#, path.display());
+        } else if degraded {
+            let first = if self.document.is_truncated() {
+                "first "
+            } else {
+                ""
+            };
+            self.status_message = format!(
+                r#""{}" {first}{}L, read-only without highlighting, git changes nor spell checking"#,
+                path.display(),
+                self.document.len()
+            );
+        }
+        self.run_hooks(Hook::Open);
+        Ok(())
+    }
+
+    /// Lists the files under `dir` to open one of them.
+    fn open_directory(&mut self, dir: &Path) -> Result<(), io::Error> {
+        let files = completion::files(dir, MAX_FOUND_FILES)
+            .into_iter()
+            .map(|file| dir.join(file).to_string_lossy().into_owned())
+            .collect();
+        self.pick_file(&format!("Open in {}", dir.display()), files)
+    }
+
+    /// The encoding to read `path` in: the one it was last saved in, or its `.editorconfig`
+    /// charset, UTF-8 by default.
+    fn file_encoding(&self, path: &Path) -> Encoding {
+        self.encodings
+            .get(path)
+            .copied()
+            .or_else(|| Properties::for_path(path).encoding)
+            .unwrap_or_default()
+    }
+
+    /// Reads the document's file again, losing unsaved changes but keeping the cursor's line.
+    fn reload(&mut self) {
+        let Some(path) = self.document.path().map(Path::to_path_buf) else {
+            self.status_message = "Usage: edit PATH".into();
+            return;
+        };
+        let limit = if self.document.is_truncated() {
+            self.config.large_file_size.unwrap_or(u64::MAX)
+        } else {
+            u64::MAX
+        };
+        let Some((document, _)) = self.load(&path, limit) else {
+            return;
+        };
+        self.wait_for_save();
+        let cursor_position = self.cursor_position;
+        let mut buffer = Buffer {
+            read_only: self.read_only,
+            degraded: self.degraded,
+            ..Buffer::new(document, self.settings.clone())
+        };
+        self.swap_buffer(&mut buffer);
+        self.update_settings();
+        self.cursor_position = cursor_position;
+        self.move_cursor(Key::Null);
+        self.after_switch();
+        self.run_hooks(Hook::Open);
+    }
+
+    /// The document of the buffer at `idx` in the list of all buffers, including the current
+    /// one.
+    fn buffer_document(&self, idx: usize) -> &Document {
+        match idx.cmp(&self.buffer_idx) {
+            Ordering::Less => &self.buffers[idx].document,
+            Ordering::Equal => match &self.stashed {
+                Some(buffer) => &buffer.document,
+                None => &self.document,
+            },
+            Ordering::Greater => &self.buffers[idx - 1].document,
+        }
+    }
+
+    /// Shows the buffer at `idx` in the list of all buffers.
+    fn switch_buffer(&mut self, idx: usize) {
+        if self.stashed.is_some() {
+            self.toggle_messages();
+        }
+        if idx == self.buffer_idx || idx > self.buffers.len() {
+            return;
+        }
+        // Its result applies to the current document
+        self.wait_for_save();
+        let mut buffer = self
+            .buffers
+            .remove(if idx < self.buffer_idx { idx } else { idx - 1 });
+        self.swap_buffer(&mut buffer);
+        // Back in its place, among the others
+        let slot = if idx < self.buffer_idx {
+            self.buffer_idx - 1
+        } else {
+            self.buffer_idx
+        };
+        self.buffers.insert(slot, buffer);
+        self.alternate = Some(self.buffer_idx);
+        self.buffer_idx = idx;
+        self.after_switch();
+        self.refresh_repo_status();
+    }
+
+    /// Shows the buffer shown before the current one.
+    fn alternate_buffer(&mut self) {
+        match self.alternate {
+            Some(idx) => self.switch_buffer(idx),
+            None => self.status_message = "No alternate file".into(),
+        }
+    }
+
+    /// Shows the next buffer, or the previous one if `forward` is false, wrapping around.
+    fn cycle_buffers(&mut self, forward: bool) {
+        if self.buffers.is_empty() {
+            self.status_message = "No other buffer".into();
+            return;
+        }
+        let count = self.buffers.len() + 1;
+        let idx = if forward {
+            (self.buffer_idx + 1) % count
+        } else {
+            (self.buffer_idx + count - 1) % count
+        };
+        self.switch_buffer(idx);
+    }
+
+    /// Lists the open buffers to switch to one of them.
+    fn pick_buffer(&mut self) -> Result<(), io::Error> {
+        let entries = (0..=self.buffers.len())
+            .map(|idx| {
+                let document = self.buffer_document(idx);
+                let name = buffer_name(document);
+                let line = format!(
+                    "{}{} {} ({}L)",
+                    if idx == self.buffer_idx { '%' } else { ' ' },
+                    if document.is_dirty() { '+' } else { ' ' },
+                    name,
+                    document.len(),
+                );
+                (name, line)
+            })
+            .collect();
+        let picked = self.pick(Picker::new("Buffer", entries), |editor, idx| {
+            editor
+                .buffer_document(idx)
+                .iter()
+                .take(picker::PREVIEW_LINES)
+                .map(|row| preview_line(row.as_str()))
+                .collect()
+        })?;
+        if let Some(idx) = picked {
+            self.switch_buffer(idx);
+        }
+        Ok(())
+    }
+
+    /// Lists the files under the working directory to open one of them.
+    fn find_file(&mut self) -> Result<(), io::Error> {
+        let files = completion::files(Path::new("."), MAX_FOUND_FILES);
+        self.pick_file("Find file", files)
+    }
+
+    /// Lists the files opened before, most recent first, to open one of them again.
+    fn recent_files(&mut self) -> Result<(), io::Error> {
+        let current = self
+            .document
+            .path()
+            .and_then(|path| fs::canonicalize(path).ok());
+        let files = self
+            .history
+            .entries(PromptKind::File)
+            .iter()
+            .rev()
+            .filter(|&file| current.as_deref() != Some(Path::new(file)))
+            .cloned()
+            .collect();
+        self.pick_file("Recent file", files)
+    }
+
+    /// Opens one of `files`, previewing their first lines.
+    fn pick_file(&mut self, title: &str, files: Vec<String>) -> Result<(), io::Error> {
+        if files.is_empty() {
+            self.status_message = "No files".into();
+            return Ok(());
+        }
+        let entries = files.iter().map(|f| (f.clone(), f.clone())).collect();
+        let picked = self.pick(Picker::new(title, entries), |_, idx| {
+            file_preview(Path::new(&files[idx]))
+        })?;
+        match picked {
+            Some(idx) => self.open(PathBuf::from(&files[idx])),
+            None => Ok(()),
+        }
+    }
+
+    /// Lists the commands of the command line with their descriptions and keys, to run one of
+    /// them, or type the arguments of those not bound to keys.
+    fn pick_command(&mut self) -> Result<()> {
+        let commands: Vec<_> = ex::palette()
+            .into_iter()
+            .filter(|&(_, command, _)| command != Some(Command::PickCommand))
+            .collect();
+        let entries = commands
+            .iter()
+            .map(|&(name, command, description)| {
+                let keys = command
+                    .map(|command| self.config.keymap.sequences(command).join(" "))
+                    .unwrap_or_default();
+                let key = format!("{name} {description}");
+                let line = match (description, keys.is_empty()) {
+                    ("", _) => format!("{name}  {keys}"),
+                    (_, true) => format!("{name}: {description}"),
+                    (_, false) => format!("{name}: {description}  {keys}"),
+                };
+                (key, line.trim_end().to_string())
+            })
+            .collect();
+        match self.pick(Picker::new("Command", entries), |_, _| Vec::new())? {
+            Some(idx) => match commands[idx] {
+                (_, Some(command), _) => self.execute(command),
+                (name, None, _) => self.command_line(Some(format!("{name} "))),
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Lists the themes to switch to one of them.
+    fn pick_theme(&mut self) -> Result<(), io::Error> {
+        let entries = Theme::NAMES
+            .iter()
+            .map(|&name| (name.to_string(), name.to_string()))
+            .collect();
+        if let Some(idx) = self.pick(Picker::new("Theme", entries), |_, _| Vec::new())? {
+            self.apply_setting(Setting::Theme(Theme::NAMES[idx].into()), true);
+        }
+        Ok(())
+    }
+
+    /// Records the document's file among the recently opened ones.
+    fn remember_file(&mut self) {
+        let Some(path) = self.document.path().and_then(|p| fs::canonicalize(p).ok()) else {
+            return;
+        };
+        self.history.add(PromptKind::File, &path.to_string_lossy());
+        self.save_history();
+    }
+
+    /// Writes the history of prompts, if it is kept in a file.
+    fn save_history(&mut self) {
+        if let Some(path) = &self.config.history_file {
+            if let Err(e) = self.history.save(path) {
+                self.status_message = format!("Couldn't save the prompt history: {e}");
+            }
+        }
+    }
+
+    /// Closes the document, after confirming that unsaved changes can be discarded unless
+    /// `discard`, and shows the next buffer, or an empty document if it was the last one.
+    fn close_buffer(&mut self, discard: bool) {
+        if self.stashed.is_some() {
+            self.toggle_messages();
+            return;
+        }
+        let name = buffer_name(&self.document);
+        if self.document.is_dirty()
+            && !discard
+            && self.confirm(&format!("Discard unsaved changes to {name}?")) != Confirm::Yes
+        {
+            self.status_message = "Close aborted".into();
+            return;
+        }
+        self.wait_for_save();
+
+        let closed = self.buffer_idx;
+        let mut buffer = if self.buffers.is_empty() {
+            let document = Document::default();
+            let settings = self.config.settings_for(document.file_type());
+            Buffer {
+                read_only: self.read_only,
+                ..Buffer::new(document, settings)
+            }
+        } else if self.buffer_idx < self.buffers.len() {
+            self.buffers.remove(self.buffer_idx)
+        } else {
+            self.buffer_idx -= 1;
+            self.buffers.remove(self.buffer_idx)
+        };
+        // Buffers after the closed one move up
+        self.alternate = self
+            .alternate
+            .map(|idx| if idx > closed { idx - 1 } else { idx })
+            .filter(|&idx| idx != self.buffer_idx);
+        self.swap_buffer(&mut buffer);
+        self.after_switch();
+        self.refresh_repo_status();
+        self.status_message = format!("Closed {name}");
+    }
+
+    /// Resets what belongs to the previous document, and tells which one is shown.
+    fn after_switch(&mut self) {
+        // Their results would be about the previous document
+        self.jobs.cancel("diff");
+        self.jobs.cancel("lint");
+        self.replace_candidate = None;
+        self.preview = false;
+        if matches!(self.mode, Mode::Visual { .. }) {
+            self.set_mode(Mode::Normal);
+        }
+        self.status_message = invalid_utf8_warning(&self.document).unwrap_or_else(|| {
+            format!(
+                r#""{}" {}L"#,
+                buffer_name(&self.document),
+                self.document.len()
+            )
+        });
+        self.scroll();
+    }
+
+    /// Name of the first document with unsaved changes, the current one first.
+    fn unsaved_buffer(&self) -> Option<String> {
+        let current = self
+            .stashed
+            .as_ref()
+            .map_or(&self.document, |b| &b.document);
+        iter::once(current)
+            .chain(self.buffers.iter().map(|buffer| &buffer.document))
+            .find(|document| document.is_dirty())
+            .map(buffer_name)
+    }
+
+    /// Quits, unless a document has unsaved changes and not `force`.
+    fn quit(&mut self, force: bool) {
+        match self.unsaved_buffer() {
+            Some(name) if !force => {
+                self.status_message =
+                    format!("No write since last change to {name} (add ! to override)");
+            }
+            _ => self.should_quit = true,
+        }
+    }
+
+    /// Shows `buffer`, leaving the current document and its state in it.
+    fn swap_buffer(&mut self, buffer: &mut Buffer) {
+        self.finish_edit();
+        mem::swap(&mut self.document, &mut buffer.document);
+        mem::swap(&mut self.read_only, &mut buffer.read_only);
+        mem::swap(&mut self.degraded, &mut buffer.degraded);
+        mem::swap(&mut self.settings, &mut buffer.settings);
+        mem::swap(&mut self.cursor_position, &mut buffer.cursor_position);
+        mem::swap(&mut self.offset, &mut buffer.offset);
+        mem::swap(&mut self.git_changes, &mut buffer.git_changes);
+        mem::swap(&mut self.diagnostics, &mut buffer.diagnostics);
+        mem::swap(&mut self.undo_stack, &mut buffer.undo_stack);
+        mem::swap(&mut self.redo_stack, &mut buffer.redo_stack);
+        self.typing = false;
+    }
+
+    /// The separator and the end of the shell output, or nothing without a panel.
+    fn draw_panel(&self, width: usize) -> Vec<Line> {
+        let Some(panel) = &self.panel else {
+            return Vec::new();
+        };
+        let height = self.panel_height().saturating_sub(1);
+
+        let mut title = String::from(" Terminal");
+        if panel.scroll > 0 {
+            title.push_str(&format!(" [-{}]", panel.scroll));
+        }
+        if !self.panel_focused {
+            if let Some(keys) = self
+                .config
+                .keymap
+                .sequences(Command::ToggleTerminal)
+                .first()
+            {
+                title.push_str(&format!(" ({keys} to focus)"));
+            }
+        }
+        title.truncate_graphemes(width);
+        let mut separator = Line::default();
+        let style = Style {
+            fg: self.config.theme.status_fg,
+            bg: Some(self.config.theme.status_bg),
+            underline: false,
+            bold: false,
+        };
+        separator.push(style, &title);
+        separator.pad(style, width);
+
+        let end = panel.scrollback.len().saturating_sub(panel.scroll);
+        let start = end.saturating_sub(height);
+        let mut lines = vec![separator];
+        lines.extend((start..start + height).map(|idx| {
+            let mut text = panel.scrollback.line(idx).unwrap_or_default();
+            text.truncate_graphemes(width);
+            Line::from(text.as_str())
+        }));
+        lines
+    }
+
+    /// Screen position of the cursor in the panel, if it is shown.
+    fn panel_cursor(&self) -> Option<Position> {
+        let panel = self.panel.as_ref()?;
+        let height = self.panel_height().saturating_sub(1);
+        let len = panel.scrollback.len();
+        // The cursor is on the last line, which is at the bottom unless the output is short
+        let row = cmp::min(len, height).checked_sub(1 + panel.scroll)?;
+        let width: usize = self.terminal.size().width.into();
+        Some(Position {
+            x: cmp::min(panel.scrollback.column(), width.saturating_sub(1)),
+            y: self.text_height() + 1 + row,
+        })
+    }
+
+    /// Display column of the cursor, taking tabs into account.
+    fn cursor_render_x(&self) -> usize {
+        self.document.get(self.cursor_position.y).map_or(0, |row| {
+            row.render_x(self.cursor_position.x, self.settings.tab_width)
+        })
+    }
+
+    /// Screen rows covered by the scrollbar thumb, `None` if the whole document fits on screen.
+    fn scrollbar_thumb(&self, height: usize) -> Option<Range<usize>> {
+        let len = self.document.len();
+        if len <= height || height == 0 {
+            return None;
+        }
+
+        let size = cmp::max(height.saturating_mul(height) / len, 1);
+        let start = cmp::min(
+            self.offset.y.saturating_mul(height) / len,
+            height.saturating_sub(size),
+        );
+        Some(start..start + size)
+    }
+
+    fn draw_row(&self, row: &Row, line_num: usize, num_width: usize) -> Line {
+        let width = self.useful_text_width();
+
+        let start = self.offset.x;
+        let end = start + width;
+
+        let rendered = self.rendered_row(line_num - 1, row, start..end);
+        let (cells, indent) = (&rendered.cells, rendered.indent);
+        // Markers in the first and last columns when the row continues off screen
+        let continues_left = start > 0 && rendered.width > 0;
+        let continues_right = rendered.width > end;
+        let matches = self
+            .search_highlight
+            .as_ref()
+            .filter(|_| !self.degraded)
+            .map(|query| row.find_all(query))
+            .unwrap_or_default();
+
+        let mut line = Line::default();
+
+        // line_num is 1-based
+        let gutter_style = Style::default()
+            .fg(self.config.theme.line_num_fg)
+            .bg(self.config.theme.line_num_bg);
+        let diagnostic = self
+            .diagnostics
+            .iter()
+            .filter(|d| d.line == line_num - 1)
+            .map(|d| d.severity)
+            .min();
+        let change = self.git_changes.get(line_num - 1).copied().flatten();
+        match (diagnostic, change) {
+            // Diagnostics are more urgent than changes
+            (Some(severity), _) => {
+                let color = match severity {
+                    Severity::Error => self.config.theme.diagnostic_error_fg,
+                    Severity::Warning => self.config.theme.diagnostic_warning_fg,
+                    Severity::Info => self.config.theme.line_num_fg,
+                };
+                line.push(gutter_style.fg(color), &severity.marker().to_string());
+            }
+            (None, Some(change)) => {
+                let color = match change {
+                    LineChange::Added => self.config.theme.diff_added_fg,
+                    LineChange::Modified => self.config.theme.diff_modified_fg,
+                    LineChange::Deleted => self.config.theme.diff_deleted_fg,
+                };
+                line.push(gutter_style.fg(color), &change.marker().to_string());
+            }
+            (None, None) => line.push(gutter_style, " "),
+        }
+        if self.config.line_numbers {
+            line.push(
+                gutter_style,
+                &format!("{line_num:>0$}", num_width.saturating_sub(1)),
+            );
+        }
+
+        let base_style = Style {
+            fg: None,
+            bg: self
+                .config
+                .theme
+                .current_line_bg
+                .filter(|_| line_num == self.cursor_position.y + 1),
+            underline: false,
+            bold: false,
+        };
+        let match_style = Style::default()
+            .fg(self.config.theme.search_match_fg)
+            .bg(self.config.theme.search_match_bg);
+
+        let ruler = self
+            .settings
+            .text_width
+            .filter(|col| (start..end).contains(col));
+        let ruler_style = base_style.bg(self.config.theme.ruler_bg);
+        let selection = self.selected_graphemes(line_num - 1, row);
+        let selection_style = base_style.bg(self.config.theme.selection_bg);
+        let misspelled = self
+            .dictionary()
+            .map(|dictionary| dictionary.misspelled(row.as_str()))
+            .unwrap_or_default();
+        let misspelled_style = base_style.fg(self.config.theme.spell_error_fg).underline();
+        let marker_style = base_style.fg(self.config.theme.scrollbar_fg);
+
+        line.push(base_style, " ");
+        if cells.is_empty() && selection.is_some() && start == 0 {
+            // Show empty selected rows
+            line.push(selection_style, " ");
+        }
+        if cells.is_empty() && continues_left {
+            line.push(marker_style, "<");
+        }
+        for (col, (idx, cell)) in (start..).zip(cells) {
+            if (col == start && continues_left) || (col + 1 == end && continues_right) {
+                line.push(marker_style, if col == start { "<" } else { ">" });
+                continue;
+            }
+            let idx = *idx;
+            let is_match = matches.iter().any(|m| m.contains(&idx));
+            let style = if selection.as_ref().is_some_and(|s| s.contains(&idx)) {
+                selection_style
+            } else if is_match {
+                match_style
+            } else if misspelled.iter().any(|m| m.contains(&idx)) {
+                misspelled_style
+            } else if ruler == Some(col) {
+                ruler_style
+            } else {
+                base_style
+            };
+
+            if col < indent && col % self.settings.tab_width.max(1) == 0 && !self.degraded {
+                // Indent guide, only drawn on leading whitespace
+                let style = if is_match {
+                    style
+                } else {
+                    style.fg(self.config.theme.indent_guide_fg)
+                };
+                line.push(style, "│");
+            } else {
+                line.push(style, cell);
+            }
+        }
+
+        if let Some(col) = ruler {
+            if start + cells.len() <= col {
+                line.pad(base_style, num_width + 1 + col - start);
+                line.push(ruler_style, " ");
+            }
+        }
+        if base_style.bg.is_some() {
+            // Pad to the full width so the highlight spans the whole line
+            line.pad(base_style, num_width + 1 + width);
+        }
+
+        line
+    }
+
+    /// Graphemes of row `y` inside the visual mode selection.
+    fn selected_graphemes(&self, y: usize, row: &Row) -> Option<Range<usize>> {
+        if let Some((candidate_y, graphemes)) = &self.replace_candidate {
+            return (*candidate_y == y).then(|| graphemes.clone());
+        }
+        let (start, end, linewise) = self.selection()?;
+        if !(start.y..=end.y).contains(&y) {
+            return None;
+        }
+        if linewise {
+            return Some(0..cmp::max(row.len(), 1));
+        }
+        let from = if y == start.y { start.x } else { 0 };
+        // Past the end of the row includes the line break
+        let to = if y == end.y { end.x + 1 } else { row.len() + 1 };
+        Some(from..to)
+    }
+
+    fn draw_status_bar(&self) -> Line {
+        let width: usize = self.terminal.size().width.into();
+        let status_line = status_line::render(&self.config.status_format, width, |name| {
+            self.status_field(name)
+        });
+
+        let mut line = Line::default();
+        let style = Style {
+            fg: self.config.theme.status_fg,
+            bg: Some(self.config.theme.status_bg),
+            underline: false,
+            bold: false,
+        };
+        line.push(style, &status_line);
+        line
+    }
+
+    /// Value of a `{name}` placeholder in the status bar format.
+    fn status_field(&self, name: &str) -> Option<String> {
+        let cursor_x = self.cursor_position.x;
+        let cursor_y = self.cursor_position.y;
+
+        Some(match name {
+            "file" | "path" if self.stashed.is_some() => "[messages]".into(),
+            "file" => match self.document.get_file_name() {
+                Some(mut name) => {
+                    if name.len() <= 30 {
+                        name
+                    } else {
+                        name.truncate_graphemes(29);
+                        format!("<{name}")
+                    }
+                }
+                None => "[Untitled]".into(),
+            },
+            "path" => self
+                .document
+                .get_path_string()
+                .unwrap_or_else(|| "[Untitled]".into()),
+            "modified" => if self.saving.is_some() {
+                " [saving…]"
+            } else if self.document.is_dirty() {
+                " [+]"
+            } else {
+                ""
+            }
+            .into(),
+            "mode" => match self.mode {
+                _ if self.panel_focused => " [TERM]",
+                _ if self.read_only => " [RO]",
+                Mode::Overwrite => " [OVR]",
+                Mode::Normal => " [NOR]",
+                Mode::Visual { linewise: false } => " [VIS]",
+                Mode::Visual { linewise: true } => " [V-LINE]",
+                Mode::Insert if self.config.modal => " [INS]",
+                Mode::Insert | Mode::Prompt => "",
+            }
+            .into(),
+            "keys" => {
+                let count = self
+                    .count
+                    .map_or_else(|| self.vim.typed().into(), |count| count.to_string());
+                let keys = count + &input::format_sequence(&self.pending_keys);
+                if keys.is_empty() {
+                    keys
+                } else {
+                    format!(" {keys}")
+                }
+            }
+            "jobs" => {
+                let names = self.jobs.names(JOB_INDICATOR_DELAY);
+                if names.is_empty() {
+                    String::new()
+                } else {
+                    format!("[{}] ", names.join(", "))
+                }
+            }
+            "branch" => self
+                .repo_status
+                .as_ref()
+                .map(|status| format!("{status} | "))
+                .unwrap_or_default(),
+            "filetype" => self.document.file_type().to_string(),
+            "encoding" => self.document.encoding().to_string(),
+            "eol" => self.document.line_ending().to_string(),
+            "line" => (cursor_y + 1).to_string(),
+            "col" => (cursor_x + 1).to_string(),
+            "lines" => self.document.len().to_string(),
+            // Too slow to count on every frame
+            "words" if self.degraded => String::new(),
+            "words" => format!("{} words", self.word_count()),
+            "percent" => {
+                let y_max = self.document.len().saturating_sub(1);
+
+                if cursor_y == 0 {
+                    "Top".into()
+                } else if cursor_y == y_max {
+                    "Bot".into()
+                } else {
+                    format!("{}%", cursor_y.saturating_mul(100) / y_max)
+                }
+            }
+            _ => return None,
+        })
+    }
+
+    /// Draws the timings of the last frame in the top right corner.
+    fn draw_frame_stats(&self, frame: &mut Frame) {
+        let Some(stats) = &self.frame_stats else {
+            return;
+        };
+        let style = Style {
+            fg: self.config.theme.status_fg,
+            bg: Some(self.config.theme.status_bg),
+            underline: false,
+            bold: false,
+        };
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let lines = [
+            ("frame", stats.frame),
+            ("rows", stats.rows.get()),
+            ("latency", stats.latency),
+        ]
+        .into_iter()
+        .map(|(name, duration)| {
+            let mut line = Line::default();
+            line.push(style, &format!(" {name:<7} {:>6.1}ms ", millis(duration)));
+            line
+        })
+        .collect();
+        let popup = Popup::new("", lines);
+
+        let screen_width: usize = self.terminal.size().width.into();
+        let (width, height) = popup.size(screen_width, frame.len());
+        let area = Rect {
+            x: screen_width - width,
+            y: 0,
+            width,
+            height,
+        };
+        popup.draw(frame, area, style);
+    }
+
+    /// Draws the picker over the bottom of the text area.
+    fn draw_picker(&self, frame: &mut Frame) {
+        let Some(picker) = &self.picker else {
+            return;
+        };
+        let style = Style {
+            fg: self.config.theme.status_fg,
+            bg: Some(self.config.theme.status_bg),
+            underline: false,
+            bold: false,
+        };
+        let selected_style = style.bg(self.config.theme.selection_bg);
+        picker.draw(
+            frame,
+            self.terminal.size().width.into(),
+            style,
+            selected_style,
+        );
+    }
+
+    /// Draws the completion menu below the start of the completed word, or above it if there
+    /// is no room below.
+    fn draw_completion_menu(&self, frame: &mut Frame) {
+        let Some(menu) = &self.completion_menu else {
+            return;
+        };
+        let style = Style {
+            fg: self.config.theme.status_fg,
+            bg: Some(self.config.theme.status_bg),
+            underline: false,
+            bold: false,
+        };
+        let lines = menu
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(idx, (word, snippet))| {
+                let style = if menu.selected == Some(idx) {
+                    style.bg(self.config.theme.selection_bg)
+                } else {
+                    style
+                };
+                let mut line = Line::default();
+                line.push(style, &format!(" {word} "));
+                if *snippet {
+                    line.push(style.fg(self.config.theme.line_num_fg), "snippet ");
+                }
+                line
+            })
+            .collect();
+        let mut popup = Popup::new("", lines);
+
+        let screen_width: usize = self.terminal.size().width.into();
+        let (width, height) = popup.size(screen_width, MAX_COMPLETION_ROWS);
+        popup.scroll_to(menu.selected.unwrap_or(0), height);
+        let render_x = self
+            .document
+            .get(menu.start.y)
+            .map_or(0, |row| row.render_x(menu.start.x, self.settings.tab_width));
+        let x = (render_x + self.num_col_width() + 1).saturating_sub(self.offset.x);
+        let y = menu.start.y.saturating_sub(self.offset.y);
+        let y = if y + 1 + height <= frame.len() || y < height {
+            y + 1
+        } else {
+            y - height
+        };
+        let area = Rect {
+            x: cmp::min(x, screen_width.saturating_sub(width)),
+            y,
+            width,
+            height,
+        };
+        popup.draw(frame, area, style);
+    }
+
+    fn draw_message_bar(&self) -> Line {
+        let mut mess = self.status_message.clone();
+        mess.truncate_graphemes(self.terminal.size().width.into());
+        Line::from(mess.as_str())
+    }
+
+    fn draw_welcome_message(&self, width: usize) -> Line {
+        let message = format!("{NAME} text editor version {VERSION}");
+        let len = std::cmp::min(message.len(), width);
+        let padding = width.saturating_sub(len) / 2;
+        let spaces = " ".repeat(padding.saturating_sub(1));
+
+        let mut message = format!("~{spaces}{message}");
+        message.truncate_graphemes(width);
+
+        Line::from(message.as_str())
+    }
+
+    /// Picks up the terminal's size and clears it, so the next refresh redraws everything.
+    fn redraw_all(&mut self) -> Result<(), io::Error> {
+        self.terminal.update_size()?;
+        self.redraw();
+        Ok(())
+    }
+
+    /// Clears the terminal, so the next refresh redraws everything at its current size.
+    fn redraw(&mut self) {
+        self.resize_panel();
+        self.terminal.clear_screen();
+        self.invalidate_screen();
+        // Keep the cursor in the (possibly smaller) viewport
+        self.move_cursor(Key::Null);
+    }
+
+    /// Waits for the next event, `None` if nothing happened by `deadline`.
+    fn next_event(&mut self, deadline: Option<Instant>) -> Result<Option<Event>, io::Error> {
+        if let Some(chord) = self.deferred_keys.pop_front() {
+            return Ok(Some(Event::Key(chord)));
+        }
+        self.next_new_event(deadline)
+    }
+
+    /// Like `next_event`, ignoring deferred keys.
+    fn next_new_event(&mut self, deadline: Option<Instant>) -> Result<Option<Event>, io::Error> {
+        if Events::take_resize() {
+            return Ok(Some(Event::Resize(self.terminal.update_size()?)));
+        }
+        self.events.next(deadline)
+    }
+
+    /// Whether `Esc` or `Ctrl-C` was pressed, to stop a long operation. Other events are
+    /// handled and other keys kept for later. Never in batch mode, which has no keyboard.
+    fn interrupted(&mut self) -> Result<bool, io::Error> {
+        if self.headless {
+            return Ok(false);
+        }
+        while let Some(event) = self.next_new_event(Some(Instant::now()))? {
+            match event {
+                Event::Key(chord) if is_interrupt(chord) => return Ok(true),
+                Event::Key(chord) => self.deferred_keys.push_back(chord),
+                event => {
+                    self.handle_event(event)?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Shows how far the background load or save got, once it has been running for a while.
+    fn show_progress(&mut self, done: u64, total: u64) {
+        let Some((label, since)) = &self.progress else {
+            return;
+        };
+        if since.elapsed() >= PROGRESS_DELAY {
+            self.status_message = format!("{label}… {}", progress_bar(done, total));
+        }
+    }
+
+    /// Waits for a key, handling other events in the meantime. Used while prompting, the main
+    /// loop being `run`.
+    fn read_key(&mut self) -> Result<Chord, io::Error> {
+        loop {
+            if let Some(event) = self.next_event(None)? {
+                if let Some(chord) = self.handle_event(event)? {
+                    return Ok(chord);
+                }
+                self.refresh_screen()?;
+            }
+        }
+    }
+
+    /// Reacts to an event other than a key, which is returned.
+    fn handle_event(&mut self, event: Event) -> Result<Option<Chord>, io::Error> {
+        match event {
+            Event::Key(key) => return Ok(Some(key)),
+            // `next_event` already picked up the size
+            Event::Resize(_) => self.redraw(),
+            Event::Tick => self.autosave(),
+            Event::Saved => self.wait_for_save(),
+            Event::Progress { done, total } => self.show_progress(done, total),
+            // Only waited for by `load_in_background`
+            Event::Loaded(_) => (),
+            Event::GitChanges(changes) => match &mut self.stashed {
+                Some(buffer) => buffer.git_changes = changes,
+                None => self.git_changes = changes,
+            },
+            Event::GitStatus(status) => self.repo_status = status,
+            Event::Blame(result) => {
+                self.status_message = match result {
+                    Ok(blame) => blame.to_string(),
+                    Err(e) => format!("Blame failed: {e}"),
+                };
+            }
+            Event::Diagnostics(diagnostics) => {
+                self.status_message = match diagnostics.len() {
+                    0 => "No problems found".into(),
+                    1 => "1 diagnostic".into(),
+                    n => format!("{n} diagnostics"),
+                };
+                match &mut self.stashed {
+                    Some(buffer) => buffer.diagnostics = diagnostics,
+                    None => self.diagnostics = diagnostics,
+                }
+            }
+            Event::PanelOutput(output) => {
+                if let Some(panel) = &mut self.panel {
+                    panel.scrollback.feed(&output);
+                }
+            }
+            Event::PanelClosed => {
+                self.panel = None;
+                self.panel_focused = false;
+                self.status_message = "Terminal closed".into();
+                // The document gets its rows back
+                self.move_cursor(Key::Null);
+            }
+            Event::Mouse(Mouse::Wheel { up }) => {
+                self.scroll_view(if up { -WHEEL_LINES } else { WHEEL_LINES });
+            }
+            Event::FocusGained => {
+                self.refresh_repo_status();
+                self.refresh_git_changes();
+            }
+            Event::Job(result) => {
+                if let Some(event) = self.jobs.finish(result) {
+                    return self.handle_event(event);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Waits for the next event, until `until` at most, and reacts to it. Returns whether
+    /// anything happened.
+    fn process_event(&mut self, until: Option<Instant>) -> Result<bool> {
+        let key_deadline =
+            (!self.pending_keys.is_empty()).then(|| self.pending_since + self.config.key_timeout);
+        let deadline = match (key_deadline, until) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (deadline, None) | (None, deadline) => deadline,
+        };
+        let Some(event) = self.next_event(deadline)? else {
+            if key_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                // Nothing more is coming, run what is bound
+                self.flush_pending_keys(true)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        };
+        if let Some(stats) = &mut self.frame_stats {
+            stats.pending_since.get_or_insert_with(Instant::now);
+        }
+        if let Some(chord) = self.handle_event(event)? {
+            self.feed_key(chord)?;
+        }
+        Ok(true)
+    }
+
+    /// Handles `chord` as if it was typed. Keys starting a sequence wait for the rest of it.
+    ///
+    /// # Errors
+    /// If the terminal can't be written to.
+    pub fn feed_key(&mut self, chord: Chord) -> Result<()> {
+        if self.panel_focused {
+            return self.panel_key(chord);
+        }
+
+        self.pending_keys.push(chord);
+        self.pending_since = Instant::now();
+        self.flush_pending_keys(false)
+    }
+
+    /// Runs a command line, as typed at the `:` prompt.
+    ///
+    /// # Errors
+    /// If the terminal can't be written to.
+    pub fn run_command(&mut self, line: &str) -> Result<()> {
+        self.run_command_line(line, 0)
+    }
+
+    /// Runs the bindings of pending keys, as long as they can't be the start of a longer
+    /// sequence (or regardless if `timed_out`). Keys that aren't part of a sequence are handled
+    /// on their own.
+    fn flush_pending_keys(&mut self, timed_out: bool) -> Result<()> {
+        while !self.pending_keys.is_empty()
+            && (timed_out || !self.is_sequence_prefix(&self.pending_keys))
+        {
+            // The longest bound sequence wins
+            let bound = (1..=self.pending_keys.len()).rev().find_map(|len| {
+                let keys = &self.pending_keys[..len];
+                if let Some(line) = self.key_mappings.get(keys) {
+                    return Some((len, Binding::CommandLine(line.clone())));
+                }
+                // Single chords are handled by `handle_key`, depending on the mode
+                let command = self.config.keymap.get(keys).filter(|_| len > 1)?;
+                Some((len, Binding::Command(command)))
+            });
+
+            match bound {
+                Some((len, binding)) => {
+                    self.pending_keys.drain(..len);
+                    match binding {
+                        Binding::Command(command) => self.execute(command)?,
+                        Binding::CommandLine(line) => self.run_command_line(&line, 0)?,
+                    }
+                }
+                None => {
+                    let chord = self.pending_keys.remove(0);
+                    self.handle_key(chord)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a key to the panel's shell, except for the one giving the focus back, and
+    /// Shift-PageUp/PageDown which scroll through its output.
+    fn panel_key(&mut self, chord: Chord) -> Result<()> {
+        if self.config.keymap.get(&[chord]) == Some(Command::ToggleTerminal) {
+            return self.execute(Command::ToggleTerminal);
+        }
+        let page = self.panel_height().saturating_sub(1);
+        let Some(panel) = &mut self.panel else {
+            return Ok(());
+        };
+        match (chord.key, chord.modifiers.shift) {
+            (Key::PageUp, true) => panel.scroll_by(page.try_into().unwrap_or(isize::MAX)),
+            (Key::PageDown, true) => panel.scroll_by(-page.try_into().unwrap_or(isize::MAX)),
+            _ => {
+                if let Err(e) = panel.send_key(chord) {
+                    self.status_message = format!("Couldn't write to the terminal: {e}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the terminal panel, or moves the focus between it and the document.
+    fn toggle_terminal(&mut self) {
+        if self.panel.is_some() {
+            self.panel_focused = !self.panel_focused;
+            return;
+        }
+
+        let size = self.terminal.size();
+        // Without the separator
+        let rows = panel_height(size.height.into()).saturating_sub(1);
+        let rows = u16::try_from(rows).unwrap_or(u16::MAX);
+        match Panel::spawn(rows, size.width, self.events.sender()) {
+            Ok(panel) => {
+                self.panel = Some(panel);
+                self.panel_focused = true;
+                // Keep the cursor above the panel
+                self.move_cursor(Key::Null);
+            }
+            Err(e) => self.status_message = format!("Couldn't start the terminal: {e}"),
+        }
+    }
+
+    /// Tells the panel's shell about a new terminal size.
+    fn resize_panel(&mut self) {
+        let rows = self.panel_height().saturating_sub(1);
+        let width = self.terminal.size().width;
+        if let Some(panel) = &mut self.panel {
+            panel.resize(u16::try_from(rows).unwrap_or(u16::MAX), width);
+        }
+    }
+
+    /// Whether `keys` start a longer mapped or bound sequence.
+    fn is_sequence_prefix(&self, keys: &[Chord]) -> bool {
+        keymap::is_prefix(self.key_mappings.keys(), keys) || self.config.keymap.is_prefix(keys)
+    }
+
+    /// Handles a key that isn't part of a multi-key sequence.
+    fn handle_key(&mut self, chord: Chord) -> Result<()> {
+        if matches!(self.mode, Mode::Normal | Mode::Visual { .. }) {
+            return self.modal_key(chord);
+        }
+        if self.config.modal && chord == Key::Esc.into() {
+            self.set_mode(Mode::Normal);
+            self.move_cursor(Key::Left);
+            self.clamp_normal_cursor();
+            return Ok(());
+        }
+
+        let command = self.config.keymap.get(&[chord]);
+        if let (None, Key::Alt(c @ '0'..='9')) = (command, chord.key) {
+            if c != '0' || self.count.is_some() {
+                self.count = Some(vim::append_digit(self.count, c));
+                return Ok(());
+            }
+        }
+        let count = self.count.take().unwrap_or(1);
+        let command = match (command, chord.key) {
+            (Some(command), _) => command,
+            (None, Key::Char(c)) if chord.modifiers.is_empty() => Command::Insert(c),
+            (None, _) => {
+                self.status_message = format!("{chord} is not bound");
+                return Ok(());
+            }
+        };
+        self.execute_counted(command, count)
+    }
+
+    /// Handles a key in normal or visual mode.
+    fn modal_key(&mut self, chord: Chord) -> Result<()> {
+        let visual = matches!(self.mode, Mode::Visual { .. });
+        let Some(action) = self.vim.feed(chord, visual) else {
+            return Ok(());
+        };
+
+        let modifies = match action {
+            Action::Apply(operator, _, _)
+            | Action::ApplyLines(operator, _)
+            | Action::ApplySelection(operator)
+            | Action::ApplyObject(operator, _, _) => operator != Operator::Yank,
+            Action::Insert(_) | Action::DeleteChars(_) | Action::Paste { .. } => true,
+            _ => false,
+        };
+        if self.read_only && modifies {
+            self.status_message = "Read-only, can't modify the document".into();
+            return Ok(());
+        }
+        if modifies {
+            self.begin_edit(false);
+            // What is typed next belongs to the same change
+            self.typing = matches!(
+                action,
+                Action::Insert(_)
+                    | Action::Apply(Operator::Change, _, _)
+                    | Action::ApplyLines(Operator::Change, _)
+                    | Action::ApplySelection(Operator::Change)
+                    | Action::ApplyObject(Operator::Change, _, _)
+            );
+        }
+
+        match action {
+            Action::Move(motion, count) => {
+                self.cursor_position = self.motion_target(motion, count);
+                self.scroll();
+            }
+            Action::Apply(operator, motion, count) => {
+                let pos = self.cursor_position;
+                let on_word = self
+                    .document
+                    .get(pos.y)
+                    .and_then(|row| row.grapheme(pos.x))
+                    .is_some_and(|g| !g.trim().is_empty());
+                // Like in vim, `cw` doesn't change the whitespace after the word
+                let motion = match motion {
+                    Motion::WordForward if operator == Operator::Change && on_word => {
+                        Motion::WordEnd
+                    }
+                    motion => motion,
+                };
+
+                let mut target = self.motion_target(motion, count);
+                if motion.is_linewise() {
+                    let (first, last) = (cmp::min(pos.y, target.y), cmp::max(pos.y, target.y));
+                    self.apply_to_lines(operator, first..last + 1);
+                    return Ok(());
+                }
+                if motion == Motion::WordForward && target.y > pos.y {
+                    // `dw` on the last word of a line stops at its end
+                    target = Position {
+                        x: self.document.get(pos.y).map_or(0, Row::len),
+                        y: pos.y,
+                    };
+                }
+                let (mut start, mut end) = if (target.y, target.x) < (pos.y, pos.x) {
+                    (target, pos)
+                } else {
+                    (pos, target)
+                };
+                if motion.is_inclusive() {
+                    end.x += 1;
+                }
+                if start == end {
+                    return Ok(());
+                }
+                start.x = cmp::min(start.x, self.document.get(start.y).map_or(0, Row::len));
+                self.apply_to_range(operator, start, end);
+            }
+            Action::ApplyLines(operator, count) => {
+                let y = self.cursor_position.y;
+                self.apply_to_lines(operator, y..y + count);
+            }
+            Action::ApplySelection(operator) => {
+                if let Some((start, mut end, linewise)) = self.selection() {
+                    self.set_mode(Mode::Normal);
+                    if linewise {
+                        self.apply_to_lines(operator, start.y..end.y + 1);
+                    } else {
+                        // The selection includes the grapheme under the cursor
+                        end.x += 1;
+                        self.apply_to_range(operator, start, end);
+                    }
+                }
+            }
+            Action::ApplyObject(operator, object, around) => {
+                let span = text_object::find(&self.document, self.cursor_position, object, around);
+                match span {
+                    Some(span) if span.linewise => {
+                        self.apply_to_lines(operator, span.start.y..span.end.y + 1);
+                    }
+                    Some(span) if span.start != span.end => {
+                        self.apply_to_range(operator, span.start, span.end);
+                    }
+                    _ => (),
+                }
+            }
+            Action::SelectObject(object, around) => {
+                if let Some(span) =
+                    text_object::find(&self.document, self.cursor_position, object, around)
+                {
+                    self.anchor = span.start;
+                    // The selection includes the grapheme under the cursor
+                    self.cursor_position = if span.linewise || span.end.x == 0 {
+                        Position {
+                            x: 0,
+                            y: span.end.y,
+                        }
+                    } else {
+                        Position {
+                            x: span.end.x - 1,
+                            y: span.end.y,
+                        }
+                    };
+                    self.set_mode(Mode::Visual {
+                        linewise: span.linewise,
+                    });
+                    self.scroll();
+                }
+            }
+            Action::Insert(at) => self.start_insert(at),
+            Action::DeleteChars(count) => {
+                let pos = self.cursor_position;
+                let len = self.document.get(pos.y).map_or(0, Row::len);
+                let end = Position {
+                    x: cmp::min(pos.x.saturating_add(count), len),
+                    y: pos.y,
+                };
+                self.apply_to_range(Operator::Delete, pos, end);
+            }
+            Action::Paste { after, count } => self.paste(after, count),
+            Action::Visual { linewise } => match self.mode {
+                Mode::Visual { linewise: current } if current == linewise => {
+                    self.set_mode(Mode::Normal);
+                }
+                Mode::Visual { .. } => self.set_mode(Mode::Visual { linewise }),
+                _ => {
+                    self.anchor = self.cursor_position;
+                    self.set_mode(Mode::Visual { linewise });
+                }
+            },
+            Action::Align(align) => self.align_view(align),
+            Action::CommandLine => self.command_line(None)?,
+            Action::Undo(count) => {
+                for _ in 0..count {
+                    self.undo();
+                    if self.undo_stack.is_empty() {
+                        break;
+                    }
+                }
+            }
+            Action::Cancel => {
+                if visual {
+                    self.set_mode(Mode::Normal);
+                }
+            }
+            Action::Other(chord, count) => match self.config.keymap.get(&[chord]) {
+                Some(command) => self.execute_counted(command, count)?,
+                None => self.status_message = format!("{chord} is not bound"),
+            },
+        }
+
+        self.clamp_normal_cursor();
+        Ok(())
+    }
+
+    /// The visual mode selection as ordered (inclusive) start and end, and whether it's linewise.
+    fn selection(&self) -> Option<(Position, Position, bool)> {
+        let Mode::Visual { linewise } = self.mode else {
+            return None;
+        };
+        let (a, b) = (self.anchor, self.cursor_position);
+        Some(if (a.y, a.x) <= (b.y, b.x) {
+            (a, b, linewise)
+        } else {
+            (b, a, linewise)
+        })
+    }
+
+    /// Counts of the selected text, or of the whole document with `\n` line endings.
+    fn counts(&self) -> Counts {
+        let text = match self.selection() {
+            Some((start, end, false)) => self.document.text_range(
+                start,
+                Position {
+                    x: end.x + 1,
+                    ..end
+                },
+            ),
+            selection => {
+                let rows =
+                    selection.map_or(0..self.document.len(), |(start, end, _)| start.y..end.y + 1);
+                let mut text = String::new();
+                for row in self.document.iter().skip(rows.start).take(rows.len()) {
+                    text.push_str(row.as_str());
+                    text.push('\n');
+                }
+                text
+            }
+        };
+        Counts::of(&text)
+    }
+
+    /// Words of the selection, or of the whole document counted row by row, only counting the
+    /// rows edited since last time.
+    fn word_count(&self) -> usize {
+        if self.selection().is_some() {
+            return self.counts().words;
+        }
+        let mut cache = self.row_words.borrow_mut();
+        let total = self
+            .document
+            .iter()
+            .map(|row| {
+                *cache
+                    .entry(row.revision())
+                    .or_insert_with(|| row.as_str().split_whitespace().count())
+            })
+            .sum();
+        // Forget edited and deleted rows once they outnumber the others
+        if cache.len() > 2 * self.document.len() {
+            let revisions: HashSet<u64> = self.document.iter().map(Row::revision).collect();
+            cache.retain(|revision, _| revisions.contains(revision));
+        }
+        total
+    }
+
+    /// In normal mode, the cursor is on a grapheme rather than between them.
+    fn clamp_normal_cursor(&mut self) {
+        if self.mode != Mode::Normal {
+            return;
+        }
+        let len = self
+            .document
+            .get(self.cursor_position.y)
+            .map_or(0, Row::len);
+        if self.cursor_position.x >= len {
+            self.cursor_position.x = len.saturating_sub(1);
+            self.scroll();
+        }
+    }
+
+    /// Where `motion` repeated `count` times lands, from the cursor.
+    fn motion_target(&self, motion: Motion, count: Option<usize>) -> Position {
+        let Position { x, y } = self.cursor_position;
+        let n = count.unwrap_or(1);
+        let y_max = self.document.len().saturating_sub(1);
+        let row_len = |y: usize| self.document.get(y).map_or(0, Row::len);
+        let first_non_blank = |y: usize| self.document.get(y).map_or(0, Row::indent_len);
+
+        let mut target = match motion {
+            Motion::Left => Position {
+                x: x.saturating_sub(n),
+                y,
+            },
+            Motion::Right => Position {
+                x: cmp::min(x.saturating_add(n), row_len(y)),
+                y,
+            },
+            Motion::Up => Position {
+                x,
+                y: y.saturating_sub(n),
+            },
+            Motion::Down => Position {
+                x,
+                y: cmp::min(y.saturating_add(n), y_max),
+            },
+            Motion::WordForward | Motion::WordBackward | Motion::WordEnd => {
+                let mut pos = self.cursor_position;
+                for _ in 0..n {
+                    pos = match motion {
+                        Motion::WordForward => self.next_word_start(pos),
+                        Motion::WordBackward => self.previous_word_start(pos),
+                        _ => self.next_word_end(pos),
+                    };
+                }
+                pos
+            }
+            Motion::LineStart => Position { x: 0, y },
+            Motion::LineEnd => {
+                let y = cmp::min(y.saturating_add(n - 1), y_max);
+                Position { x: row_len(y), y }
+            }
+            Motion::FirstLine | Motion::LastLine => {
+                let default = if motion == Motion::FirstLine {
+                    0
+                } else {
+                    y_max
+                };
+                let y = count.map_or(default, |line| cmp::min(line.saturating_sub(1), y_max));
+                Position {
+                    x: first_non_blank(y),
+                    y,
+                }
+            }
+        };
+        target.x = cmp::min(target.x, row_len(target.y));
+        target
+    }
+
+    /// Word class of the grapheme at `pos`: 0 for whitespace or end of line, 1 for word
+    /// characters, 2 for punctuation.
+    fn word_class(&self, pos: Position) -> u8 {
+        let grapheme = self
+            .document
+            .get(pos.y)
+            .and_then(|row| row.grapheme(pos.x))
+            .unwrap_or(" ");
+        text_object::word_class(grapheme)
+    }
+
+    /// Moves one grapheme forward, to the start of the next row at the end of a row. `None` at
+    /// the end of the document.
+    fn step_forward(&self, pos: Position) -> Option<Position> {
+        let len = self.document.get(pos.y)?.len();
+        if pos.x < len {
+            Some(Position {
+                x: pos.x + 1,
+                ..pos
+            })
+        } else if pos.y + 1 < self.document.len() {
+            Some(Position { x: 0, y: pos.y + 1 })
+        } else {
+            None
+        }
+    }
+
+    fn step_backward(&self, pos: Position) -> Option<Position> {
+        if pos.x > 0 {
+            Some(Position {
+                x: pos.x - 1,
+                ..pos
+            })
+        } else if pos.y > 0 {
+            let y = pos.y - 1;
+            Some(Position {
+                x: self.document.get(y).map_or(0, Row::len),
+                y,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn is_empty_row(&self, y: usize) -> bool {
+        self.document.get(y).is_some_and(Row::is_empty)
+    }
+
+    /// With `subword_motion`, whether a sub-word of an identifier starts at `pos`: after
+    /// underscores, at a hump as in `camelCase`, or at the last capital of an acronym as in
+    /// `HTMLParser`.
+    fn is_subword_start(&self, pos: Position) -> bool {
+        if !self.config.subword_motion || pos.x == 0 {
+            return false;
+        }
+        let Some(row) = self.document.get(pos.y) else {
+            return false;
+        };
+        let char_at = |x: usize| row.grapheme(x).and_then(|g| g.chars().next());
+        let (Some(previous), Some(current)) = (char_at(pos.x - 1), char_at(pos.x)) else {
+            return false;
+        };
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        if !is_word(previous) || !is_word(current) {
+            return false;
+        }
+        (previous == '_' && current != '_')
+            || ((previous.is_lowercase() || previous.is_numeric()) && current.is_uppercase())
+            || (previous.is_uppercase()
+                && current.is_uppercase()
+                && char_at(pos.x + 1).is_some_and(char::is_lowercase))
+    }
+
+    /// `w`: start of the next word, or the next empty row.
+    fn next_word_start(&self, pos: Position) -> Position {
+        let class = self.word_class(pos);
+        let mut pos = pos;
+        // Skip the rest of the current word
+        while class != 0 && self.word_class(pos) == class {
+            match self.step_forward(pos) {
+                Some(next) if self.is_subword_start(next) => return next,
+                Some(next) if next.y == pos.y => pos = next,
+                _ => break,
+            }
+        }
+        // Then whitespace and line breaks
+        while self.word_class(pos) == 0 {
+            match self.step_forward(pos) {
+                Some(next) => {
+                    pos = next;
+                    if next.x == 0 && self.is_empty_row(next.y) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        pos
+    }
+
+    /// `b`: start of the previous word, or the previous empty row.
+    fn previous_word_start(&self, pos: Position) -> Position {
+        let Some(mut pos) = self.step_backward(pos) else {
+            return pos;
+        };
+        while self.word_class(pos) == 0 && !self.is_empty_row(pos.y) {
+            match self.step_backward(pos) {
+                Some(previous) => pos = previous,
+                None => return pos,
+            }
+        }
+        let class = self.word_class(pos);
+        while pos.x > 0
+            && self.word_class(Position {
+                x: pos.x - 1,
+                ..pos
+            }) == class
+            && !self.is_subword_start(pos)
+        {
+            pos.x -= 1;
+        }
+        pos
+    }
+
+    /// `e`: last grapheme of the current or next word.
+    fn next_word_end(&self, pos: Position) -> Position {
+        let Some(mut pos) = self.step_forward(pos) else {
+            return pos;
+        };
+        while self.word_class(pos) == 0 {
+            match self.step_forward(pos) {
+                Some(next) => pos = next,
+                None => return pos,
+            }
+        }
+        let class = self.word_class(pos);
+        let next = |pos: Position| Position {
+            x: pos.x + 1,
+            ..pos
+        };
+        while self.word_class(next(pos)) == class
+            && pos.x + 1 < self.document.get(pos.y).map_or(0, Row::len)
+            && !self.is_subword_start(next(pos))
+        {
+            pos.x += 1;
+        }
+        pos
+    }
+
+    /// Applies an operator to the text from `start` (inclusive) to `end` (exclusive).
+    fn apply_to_range(&mut self, operator: Operator, start: Position, end: Position) {
+        self.register = Some(Register {
+            text: self.document.text_range(start, end),
+            linewise: false,
+        });
+        if operator != Operator::Yank {
+            self.document.delete_range(start, end);
+        }
+        self.cursor_position = start;
+        if operator == Operator::Change {
+            self.set_mode(Mode::Insert);
+        }
+        self.scroll();
+    }
+
+    /// Applies an operator to whole rows.
+    fn apply_to_lines(&mut self, operator: Operator, rows: Range<usize>) {
+        let rows = rows.start..cmp::min(rows.end, self.document.len());
+        if rows.is_empty() {
+            return;
+        }
+
+        let lines: Vec<String> = match operator {
+            Operator::Yank => self
+                .document
+                .iter()
+                .skip(rows.start)
+                .take(rows.len())
+                .map(|row| row.as_str().to_string())
+                .collect(),
+            Operator::Delete | Operator::Change => self.document.remove_rows(rows.clone()),
+        };
+        self.register = Some(Register {
+            text: lines.join("\n"),
+            linewise: true,
+        });
+
+        let y = cmp::min(rows.start, self.document.len().saturating_sub(1));
+        if operator == Operator::Change {
+            self.document.insert_rows(rows.start, &[String::new()]);
+            self.cursor_position = Position {
+                x: 0,
+                y: rows.start,
+            };
+            self.set_mode(Mode::Insert);
+        } else {
+            self.cursor_position = Position { x: 0, y };
+        }
+        self.scroll();
+    }
+
+    fn start_insert(&mut self, at: InsertAt) {
+        let Position { x, y } = self.cursor_position;
+        let len = self.document.get(y).map_or(0, Row::len);
+        self.cursor_position = match at {
+            InsertAt::Cursor => self.cursor_position,
+            InsertAt::AfterCursor => Position {
+                x: cmp::min(x + 1, len),
+                y,
+            },
+            InsertAt::LineStart => Position {
+                x: self.document.get(y).map_or(0, Row::indent_len),
+                y,
+            },
+            InsertAt::LineEnd => Position { x: len, y },
+            InsertAt::LineBelow | InsertAt::LineAbove => {
+                let y = if at == InsertAt::LineBelow && !self.document.is_empty() {
+                    y + 1
+                } else {
+                    y
+                };
+                self.document.insert_rows(y, &[String::new()]);
+                Position { x: 0, y }
+            }
+        };
+        self.set_mode(Mode::Insert);
+        self.scroll();
+    }
+
+    /// Pastes the register `count` times, after or before the cursor.
+    fn paste(&mut self, after: bool, count: usize) {
+        let Some(register) = &self.register else {
+            self.status_message = "Nothing to paste".into();
+            return;
+        };
+        // Not to run out of memory with a large count
+        let max = cmp::max(MAX_PASTE_LEN / cmp::max(register.text.len(), 1), 1);
+        let count = cmp::min(count, cmp::min(max, vim::MAX_COUNT));
+
+        if register.linewise {
+            let lines: Vec<String> = (0..count)
+                .flat_map(|_| register.text.split('\n').map(String::from))
+                .collect();
+            let y = if after && !self.document.is_empty() {
+                self.cursor_position.y + 1
+            } else {
+                self.cursor_position.y
+            };
+            self.document.insert_rows(y, &lines);
+            self.cursor_position = Position { x: 0, y };
+        } else {
+            let text = register.text.repeat(count);
+            let mut pos = self.cursor_position;
+            if after && self.document.get(pos.y).is_some_and(|row| !row.is_empty()) {
+                pos.x += 1;
+            }
+            let pos = self.document.insert_str(pos, &text);
+            // On the last pasted grapheme
+            self.cursor_position = Position {
+                x: pos.x.saturating_sub(1),
+                ..pos
+            };
+        }
+        self.scroll();
+    }
+
+    /// Runs `command` `count` times, or once with the count as its amount for the commands that
+    /// take one.
+    fn execute_counted(&mut self, command: Command, count: usize) -> Result<()> {
+        let amount = i64::try_from(count).unwrap_or(i64::MAX);
+        let delta = match command {
+            Command::Increment => amount,
+            Command::Decrement => -amount,
+            _ => {
+                let count = cmp::min(count, vim::MAX_COUNT);
+                // Undone at once
+                let grouping = self.grouping_edits;
+                if count > 1 && command.modifies_document() && !self.read_only {
+                    self.begin_edit(false);
+                    self.grouping_edits = true;
+                }
+                let mut result = Ok(());
+                for _ in 0..count {
+                    result = self.execute(command);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                self.grouping_edits = grouping;
+                return result;
+            }
+        };
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return Ok(());
+        }
+        self.add_to_number(delta);
+        Ok(())
+    }
+
+    /// Runs `command` as if its key was pressed.
+    ///
+    /// # Errors
+    /// If the terminal can't be written to.
+    pub fn execute(&mut self, command: Command) -> Result<()> {
+        let quit_again = mem::take(&mut self.quit_warned);
+        if self.read_only && command.modifies_document() {
+            self.status_message = "Read-only, can't modify the document".into();
+            return Ok(());
+        }
+        // Inserting dates and snippets, and adding to numbers, begin their edit once there is
+        // something to change
+        if command.modifies_document()
+            && !matches!(
+                command,
+                Command::InsertDate
+                    | Command::InsertSnippet
+                    | Command::Increment
+                    | Command::Decrement
+            )
+        {
+            // Deleting while typing is part of the same change
+            let typing = matches!(
+                command,
+                Command::Insert(_) | Command::DeleteForward | Command::DeleteBackward
+            );
+            self.begin_edit(typing);
+        } else {
+            self.typing = false;
+        }
+
+        match command {
+            Command::Quit => {
+                self.quit(quit_again);
+                if let Some(name) = self.unsaved_buffer().filter(|_| !self.should_quit) {
+                    self.status_message =
+                        format!("No write since last change to {name}, quit again to discard it");
+                    self.quit_warned = true;
+                }
+            }
+            Command::Save => self.save(false),
+            Command::SaveAs => self.save(true),
+            Command::Open => self.open_prompt()?,
+            Command::NextBuffer => self.cycle_buffers(true),
+            Command::PreviousBuffer => self.cycle_buffers(false),
+            Command::CloseBuffer => self.close_buffer(false),
+            Command::PickBuffer => self.pick_buffer()?,
+            Command::AlternateBuffer => self.alternate_buffer(),
+            Command::FindFile => self.find_file()?,
+            Command::RecentFiles => self.recent_files()?,
+            Command::PickCommand => self.pick_command()?,
+            Command::PickTheme => self.pick_theme()?,
+            Command::Complete => self.complete()?,
+            Command::Search => self.search(),
+            Command::Prompt => self.command_line(None)?,
+            Command::Help => self.status_message = self.config.keymap.help(),
+            Command::ClearHighlight => self.search_highlight = None,
+            Command::Redraw => self.invalidate_screen(),
+            Command::ShowCursorOffset => self.show_cursor_offset(),
+            Command::CopyLine => self.copy_line(),
+            Command::ToggleComment => self.toggle_comment(),
+            Command::NextMisspelling => self.next_misspelling(),
+            Command::SpellSuggest => self.spell_suggest()?,
+            Command::Blame => self.blame(),
+            Command::Undo => self.undo(),
+            Command::Redo => self.redo(),
+            Command::NextDiagnostic => self.go_to_diagnostic(true),
+            Command::PreviousDiagnostic => self.go_to_diagnostic(false),
+            Command::ToggleTerminal => self.toggle_terminal(),
+            Command::OpenUrl => self.open_url()?,
+            Command::TogglePreview => self.toggle_preview(),
+            Command::FrameStats => {
+                self.frame_stats = match self.frame_stats {
+                    Some(_) => None,
+                    None => Some(FrameStats::default()),
+                };
+            }
+            Command::GoToLine => self.go_to_prompt(),
+            Command::Messages => self.toggle_messages(),
+            Command::Increment => self.add_to_number(1),
+            Command::Decrement => self.add_to_number(-1),
+            Command::Reflow => self.reflow_paragraph(),
+            Command::InsertDate => self.insert_date(&self.config.date_format.clone()),
+            Command::InsertSnippet => self.insert_snippet(None)?,
+            Command::WordCount => {
+                let scope = if self.selection().is_some() {
+                    "Selection"
+                } else {
+                    "File"
+                };
+                self.status_message = format!("{scope}: {}", self.counts());
+            }
+            Command::Suspend => {
+                self.terminal.suspend()?;
+                self.redraw_all()?;
+            }
+
+            Command::ToggleOverwrite => {
+                self.set_mode(match self.mode {
+                    Mode::Overwrite => Mode::Insert,
+                    _ => Mode::Overwrite,
+                });
+            }
+
+            Command::DeleteForward => {
+                self.document.delete(self.cursor_position);
+                self.scroll();
+            }
+            Command::DeleteBackward => {
+                if (self.cursor_position.x > 0) || (self.cursor_position.y > 0) {
+                    self.move_cursor(Key::Left);
+                    self.document.delete(self.cursor_position);
+                    self.scroll();
+                }
+            }
+
+            Command::Up => self.move_cursor(Key::Up),
+            Command::Down => self.move_cursor(Key::Down),
+            Command::Left => self.move_cursor(Key::Left),
+            Command::Right => self.move_cursor(Key::Right),
+            Command::PageUp => self.move_cursor(Key::PageUp),
+            Command::PageDown => self.move_cursor(Key::PageDown),
+            Command::LineStart => self.move_cursor(Key::Home),
+            Command::LineEnd => self.move_cursor(Key::End),
+            Command::ScrollDown => self.scroll_view(1),
+            Command::ScrollUp => self.scroll_view(-1),
+            Command::HalfPageDown => self.scroll_half_page(true),
+            Command::HalfPageUp => self.scroll_half_page(false),
+            Command::LineToTop => self.align_view(ViewAlign::Top),
+            Command::CenterLine => self.align_view(ViewAlign::Center),
+            Command::LineToBottom => self.align_view(ViewAlign::Bottom),
+            Command::DocumentStart => {
+                self.cursor_position = Position::default();
+                self.scroll();
+            }
+            Command::DocumentEnd => {
+                let y = self.document.len().saturating_sub(1);
+                let x = self.document.get(y).map_or(0, Row::len);
+                self.cursor_position = Position { x, y };
+                self.scroll();
+            }
+            Command::Insert(c) => {
+                self.insert_char(c);
+                if !c.is_whitespace() {
+                    self.auto_wrap();
+                }
+                self.run_hooks(Hook::Insert);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn insert_char(&mut self, c: char) {
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+        if c == '\t' && self.settings.expand_tab && self.mode == Mode::Insert {
+            let width = row::tab_stop_width(self.cursor_render_x(), self.settings.tab_width);
+            for _ in 0..width {
+                self.document.insert_or_append(self.cursor_position, ' ');
+                self.move_cursor(Key::Right);
+            }
+            return;
+        }
+
+        if self.mode == Mode::Overwrite {
+            self.document.replace(self.cursor_position, c);
+        } else {
+            self.document.insert_or_append(self.cursor_position, c);
+        }
+        self.move_cursor(Key::Right);
+    }
+
+    /// With `auto_wrap`, breaks the line at the last blank that keeps the text before the cursor
+    /// within the text width, continuing on a new line with the same indentation and comment
+    /// prefix.
+    fn auto_wrap(&mut self) {
+        let Some(width) = self.settings.text_width.filter(|_| self.config.auto_wrap) else {
+            return;
+        };
+        if self.cursor_render_x() <= width {
+            return;
+        }
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.get(y) else {
+            return;
+        };
+        let prefix = reflow::prefix(row.as_str(), self.settings.comment.as_deref()).to_string();
+        let prefix_len = row.grapheme_index(prefix.len());
+        let is_blank = |x: usize| row.grapheme(x).is_some_and(|g| g.trim().is_empty());
+        let Some(blank) = (prefix_len..x)
+            .rev()
+            .find(|&x| is_blank(x) && row.render_x(x, self.settings.tab_width) <= width)
+        else {
+            // A single word wider than the text width stays on its line
+            return;
+        };
+        let mut start = blank;
+        while start > prefix_len && is_blank(start - 1) {
+            start -= 1;
+        }
+        let mut end = blank + 1;
+        while end < x && is_blank(end) {
+            end += 1;
+        }
+        if start == prefix_len {
+            return;
+        }
+
+        self.document
+            .delete_range(Position { x: start, y }, Position { x: end, y });
+        self.document
+            .insert_str(Position { x: start, y }, &format!("\n{prefix}"));
+        self.cursor_position = Position {
+            x: prefix_len + x - end,
+            y: y + 1,
+        };
+        self.scroll();
+    }
+
+    /// Inserts the lines of the file at `path` below the cursor, as a single change.
+    fn read_file(&mut self, path: &Path) {
+        if path.as_os_str().is_empty() {
+            self.status_message = "Usage: read PATH".into();
+            return;
+        }
+        let inserted = match Document::open(path.to_path_buf()) {
+            Ok(inserted) => inserted,
+            Err(e) => {
+                self.status_message = format!("Couldn't read {}: {e}", path.display());
+                return;
+            }
+        };
+        let lines: Vec<String> = inserted
+            .iter()
+            .map(|row| row.as_str().to_string())
+            .collect();
+
+        self.begin_edit(false);
+        let y = cmp::min(self.cursor_position.y + 1, self.document.len());
+        self.document.insert_rows(y, &lines);
+        self.cursor_position = Position { x: 0, y };
+        self.move_cursor(Key::Null);
+        self.status_message = format!("Read {} lines from {}", lines.len(), path.display());
+        self.scroll();
+    }
+
+    /// Inserts `text` at the cursor, leaving the cursor after it.
+    fn insert_text(&mut self, text: &str) {
+        self.cursor_position = self.document.insert_str(self.cursor_position, text);
+        self.scroll();
+    }
+
+    /// Inserts `text` at the cursor as a change of its own, unless it is empty.
+    fn insert_change(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.begin_edit(false);
+            self.insert_text(text);
+        }
+    }
+
+    /// Inserts the current date and time, formatted with `strftime`.
+    fn insert_date(&mut self, format: &str) {
+        match date::now(format) {
+            Some(date) => self.insert_change(&date),
+            None => self.status_message = format!("Invalid date format: {format}"),
+        }
+    }
+
+    /// Inserts the snippet called `name`, prompting for it if `None`.
+    fn insert_snippet(&mut self, name: Option<&str>) -> Result<(), io::Error> {
+        if self.config.snippets.is_empty() {
+            self.status_message = "No snippets, add them to [snippets] in the config".into();
+            return Ok(());
+        }
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => {
+                let name = self.prompt(
+                    "Snippet: ",
+                    PromptKind::Snippet,
+                    None,
+                    |editor, _, typed| {
+                        let mut names: Vec<&String> = editor.config.snippets.keys().collect();
+                        names.sort();
+                        let matches: Vec<&str> = if typed.is_empty() {
+                            names.iter().map(|name| name.as_str()).collect()
+                        } else {
+                            fuzzy::filter(typed, &names)
+                                .into_iter()
+                                .map(|name| name.as_str())
+                                .collect()
+                        };
+                        editor.prompt_hint = if matches.is_empty() {
+                            String::new()
+                        } else {
+                            format!("  [{}]", matches[..matches.len().min(5)].join(" | "))
+                        };
+                    },
+                )?;
+                let Some(name) = name else {
+                    return Ok(());
+                };
+                name
+            }
+        };
+
+        match self.config.snippets.get(&name).cloned() {
+            Some(text) => self.insert_change(&text),
+            None => self.status_message = format!("No snippet named {name}"),
+        }
+        Ok(())
+    }
+
+    /// Completes the word before the cursor with words of the document, nearest first, and
+    /// names of snippets. Their common start is inserted, then a menu lets Up, Down and Tab go
+    /// through them. Enter or any other key keeps the shown one, Esc goes back to the word as
+    /// typed.
+    fn complete(&mut self) -> Result<()> {
+        let Position { x, y } = self.cursor_position;
+        let Some(row) = self.document.get(y) else {
+            return Ok(());
+        };
+        let is_word = |g: &str| g.chars().all(|c| c.is_alphanumeric() || c == '_');
+        let start = (0..x)
+            .rev()
+            .take_while(|&idx| row.grapheme(idx).is_some_and(is_word))
+            .last()
+            .unwrap_or(x);
+        let typed: String = (start..x).filter_map(|idx| row.grapheme(idx)).collect();
+        if typed.is_empty() {
+            self.status_message = "No word before the cursor to complete".into();
+            return Ok(());
+        }
+
+        let mut rows: Vec<usize> = (0..self.document.len()).collect();
+        rows.sort_by_key(|&row| row.abs_diff(y));
+        let lines = rows
+            .into_iter()
+            .filter_map(|row| self.document.get(row))
+            .map(Row::as_str);
+        let mut candidates: Vec<(String, bool)> = completion::words(&typed, lines)
+            .into_iter()
+            .map(|word| (word, false))
+            .collect();
+        let mut snippets: Vec<&String> = self
+            .config
+            .snippets
+            .keys()
+            .filter(|name| name.starts_with(&typed))
+            .collect();
+        snippets.sort();
+        candidates.extend(snippets.into_iter().map(|name| (name.clone(), true)));
+        if candidates.is_empty() {
+            self.status_message = format!("No completions for {typed}");
+            return Ok(());
+        }
+
+        let start = Position { x: start, y };
+        let words: Vec<&str> = candidates.iter().map(|(word, _)| word.as_str()).collect();
+        let common = completion::common_prefix(&words).to_string();
+        self.replace_word(start, &common);
+        if candidates.len() == 1 {
+            return self.accept_completion(start, &candidates[0]);
+        }
+        // No one to choose
+        if self.headless {
+            return Ok(());
+        }
+
+        let mut selected = None;
+        let key = loop {
+            self.completion_menu = Some(CompletionMenu {
+                candidates: candidates.clone(),
+                selected,
+                start,
+            });
+            let key = self.refresh_screen().and_then(|()| self.read_key());
+            self.completion_menu = None;
+            let chord = key?;
+            let count = candidates.len();
+            selected = match chord.key {
+                Key::Down | Key::Char('\t') => Some(selected.map_or(0, |idx| (idx + 1) % count)),
+                Key::Up | Key::BackTab => {
+                    Some(selected.map_or(count - 1, |idx| (idx + count - 1) % count))
+                }
+                Key::Esc => {
+                    self.replace_word(start, &typed);
+                    return Ok(());
+                }
+                _ => break chord,
+            };
+            if let Some(idx) = selected {
+                self.replace_word(start, &candidates[idx].0);
+            }
+        };
+
+        if let Some(idx) = selected {
+            self.accept_completion(start, &candidates[idx])?;
+        }
+        if key.key != Key::Char('\n') {
+            self.feed_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the word from `start` to the cursor with `word`.
+    fn replace_word(&mut self, start: Position, word: &str) {
+        self.document.delete_range(start, self.cursor_position);
+        self.cursor_position = self.document.insert_str(start, word);
+        self.scroll();
+    }
+
+    /// Keeps the completion of the word at `start`, expanding it if it names a snippet.
+    fn accept_completion(
+        &mut self,
+        start: Position,
+        (word, snippet): &(String, bool),
+    ) -> Result<()> {
+        if *snippet {
+            self.replace_word(start, "");
+            self.insert_snippet(Some(word))?;
+        } else {
+            self.replace_word(start, word);
+        }
+        Ok(())
+    }
+
+    /// Prompts for a command line, starting with `already_filled`, and runs it, showing matching
+    /// commands while typing.
+    fn command_line(&mut self, already_filled: Option<String>) -> Result<()> {
+        let line = self.prompt(
+            ":",
+            PromptKind::Command,
+            already_filled,
+            |editor, _, line| {
+                let candidates = ex::candidates(line, 5);
+                editor.prompt_hint = if candidates.is_empty() {
+                    String::new()
+                } else {
+                    format!("  [{}]", candidates.join(" | "))
+                };
+            },
+        )?;
+        let Some(line) = line else {
+            return Ok(());
+        };
+
+        self.run_command_line(&line, 0)
+    }
+
+    /// Runs a command line, which may be a script-defined command. `depth` is how many user
+    /// commands are already running.
+    fn run_command_line(&mut self, line: &str, depth: usize) -> Result<()> {
+        let name = line.split_whitespace().next().unwrap_or_default();
+        if let Some(body) = self.user_commands.get(name).cloned() {
+            if depth >= MAX_COMMAND_DEPTH {
+                self.status_message = format!("Commands nested too deeply in {name}");
+                return Ok(());
+            }
+            for line in body {
+                self.run_command_line(&line, depth + 1)?;
+            }
+            return Ok(());
+        }
+
+        match ex::parse(line) {
+            Ok(invocation) => self.run_ex(invocation, depth)?,
+            // Batch scripts stop there
+            Err(e) if self.headless => anyhow::bail!(e),
+            Err(e) => self.status_message = e,
+        }
+        Ok(())
+    }
+
+    /// Runs the script at `path`, see `script`.
+    fn source(&mut self, path: &Path, depth: usize) -> Result<(), String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+        if depth >= MAX_COMMAND_DEPTH {
+            return Err(error(&"scripts nested too deeply"));
+        }
+
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        for statement in script::parse(&text, self.config.leader).map_err(|e| error(&e))? {
+            match statement {
+                Statement::Command { name, body } => {
+                    self.user_commands.insert(name, body);
+                }
+                Statement::Map { keys, line } => {
+                    self.key_mappings.insert(keys, line);
+                }
+                Statement::Hook { hook, line } => {
+                    self.hooks.register(hook, Handler::CommandLine(line));
+                }
+                Statement::Run(line) => {
+                    self.run_command_line(&line, depth + 1)
+                        .map_err(|e| error(&e))?;
+                    if self.headless && !self.status_message.is_empty() {
+                        eprintln!("{line}: {}", self.status_message);
+                        self.status_message.clear();
+                    }
+                }
+            }
+            if self.should_quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_ex(&mut self, invocation: Invocation, depth: usize) -> Result<()> {
+        let Invocation {
+            ex,
+            bang,
+            args,
+            range,
+        } = invocation;
+        let takes_range = matches!(
+            ex,
+            Ex::Substitute
+                | Ex::DeleteLines
+                | Ex::Indent
+                | Ex::Outdent
+                | Ex::Global
+                | Ex::InverseGlobal
+                | Ex::DeleteMatching
+                | Ex::KeepMatching
+                | Ex::Uniq
+                | Ex::Reverse
+                | Ex::Retab
+        );
+        if range.is_some() && !takes_range {
+            self.status_message = "This command doesn't take a line range".into();
+            return Ok(());
+        }
+        let rows = range.map(|range| range.rows(self.cursor_position.y, self.document.len()));
+        match ex {
+            Ex::Write if args.is_empty() => self.save(false),
+            Ex::Write => self.save_as(args.into()),
+            Ex::Quit => {
+                if self.stashed.is_some() {
+                    self.toggle_messages();
+                } else {
+                    self.quit(bang);
+                }
+            }
+            Ex::WriteQuit => {
+                self.save(false);
+                // Large files are saved in the background
+                self.wait_for_save();
+                if !self.document.is_dirty() {
+                    self.quit(false);
+                }
+            }
+            Ex::Edit => {
+                let current = args.is_empty() || self.document.path() == Some(Path::new(&args));
+                if !current {
+                    self.open(args.into())?;
+                } else if self.document.is_dirty() && !bang && self.stashed.is_none() {
+                    self.status_message = "No write since last change (add ! to override)".into();
+                } else {
+                    self.reload();
+                }
+            }
+            Ex::CloseBuffer => self.close_buffer(bang),
+            Ex::Goto => self.go_to_location(&args),
+            Ex::Sort => {
+                if self.read_only {
+                    self.status_message = "Read-only, can't modify the document".into();
+                } else {
+                    self.begin_edit(false);
+                    self.document.sort(bang);
+                    self.move_cursor(Key::Null);
+                }
+            }
+            Ex::Set => self.status_message = self.set_options(&args, false),
+            Ex::SetGlobal => self.status_message = self.set_options(&args, true),
+            Ex::Help => self.status_message = ex::help(),
+            Ex::Insert | Ex::Date | Ex::Snippet | Ex::Read if self.read_only => {
+                self.status_message = "Read-only, can't modify the document".into();
+            }
+            Ex::Insert => self.insert_change(&unescape(&args)),
+            Ex::Date => {
+                let format = if args.is_empty() {
+                    self.config.date_format.clone()
+                } else {
+                    args
+                };
+                self.insert_date(&format);
+            }
+            Ex::Snippet => {
+                self.insert_snippet(
+                    Some(&args)
+                        .filter(|name| !name.is_empty())
+                        .map(String::as_str),
+                )?;
+            }
+            Ex::Read => self.read_file(Path::new(&args)),
+            Ex::Source => {
+                if let Err(e) = self.source(Path::new(&args), depth) {
+                    self.status_message = e;
+                }
+            }
+            Ex::Shell => self.shell(&args)?,
+            Ex::Filter => self.filter(&args),
+            Ex::Substitute => {
+                let y = self.cursor_position.y;
+                self.substitute(&args, rows.unwrap_or(y..y + 1));
+            }
+            Ex::DeleteLines => {
+                let rows = rows.unwrap_or_else(|| {
+                    let count = args.parse().unwrap_or(1);
+                    let y = self.cursor_position.y;
+                    y..y.saturating_add(count)
+                });
+                if self.read_only {
+                    self.status_message = "Read-only, can't modify the document".into();
+                } else {
+                    self.begin_edit(false);
+                    self.apply_to_lines(Operator::Delete, rows);
+                }
+            }
+            Ex::Indent | Ex::Outdent => {
+                let y = self.cursor_position.y;
+                self.indent_lines(rows.unwrap_or(y..y + 1), ex == Ex::Indent);
+            }
+            Ex::Global | Ex::InverseGlobal => {
+                let rows = rows.unwrap_or(0..self.document.len());
+                self.global(&args, rows, ex == Ex::Global, depth)?;
+            }
+            Ex::DeleteMatching | Ex::KeepMatching => {
+                let rows = rows.unwrap_or(0..self.document.len());
+                self.delete_matching(&args, rows, ex == Ex::KeepMatching);
+            }
+            Ex::Uniq => {
+                let rows = rows.unwrap_or_else(|| self.selected_rows());
+                self.uniq(&args, rows);
+            }
+            Ex::Retab | Ex::Reverse if self.read_only => {
+                self.status_message = "Read-only, can't modify the document".into();
+            }
+            Ex::Retab => {
+                let rows = rows.unwrap_or(0..self.document.len());
+                self.retab(rows, bang);
+            }
+            Ex::Reverse => {
+                let rows = rows.unwrap_or_else(|| self.selected_rows());
+                self.begin_edit(false);
+                self.document.reverse(rows);
+                if matches!(self.mode, Mode::Visual { .. }) {
+                    self.set_mode(Mode::Normal);
+                }
+                self.move_cursor(Key::Null);
+            }
+            Ex::Action(command) => self.execute(command)?,
+        }
+        Ok(())
+    }
+
+    /// Converts the indentation of `rows` to spaces if `expandtab` is set, or to tabs and the
+    /// spaces left over otherwise. With `all_tabs`, tabs after the indentation are expanded too.
+    fn retab(&mut self, rows: Range<usize>, all_tabs: bool) {
+        let rows = rows.start..cmp::min(rows.end, self.document.len());
+        let (tab_width, expand) = (self.settings.tab_width, self.settings.expand_tab);
+        let lines: Vec<String> = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .map(|row| retab(row.as_str(), tab_width, expand, all_tabs && expand))
+            .collect();
+        let changed = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .zip(&lines)
+            .filter(|(row, line)| row.as_str() != line.as_str())
+            .count();
+        if changed > 0 {
+            self.begin_edit(false);
+            self.document.remove_rows(rows.clone());
+            self.document.insert_rows(rows.start, &lines);
+            self.move_cursor(Key::Null);
+        }
+        self.status_message = match changed {
+            1 => "Retabbed 1 line".into(),
+            n => format!("Retabbed {n} lines"),
+        };
+    }
+
+    /// Rows of the visual mode selection, or of the whole document.
+    fn selected_rows(&self) -> Range<usize> {
+        match self.selection() {
+            Some((start, end, _)) => start.y..end.y + 1,
+            None => 0..self.document.len(),
+        }
+    }
+
+    /// Removes consecutive duplicate lines of `rows`. `flags` can have `i` to ignore case and `w`
+    /// to ignore leading whitespace.
+    fn uniq(&mut self, flags: &str, rows: Range<usize>) {
+        if flags.chars().any(|c| !matches!(c, 'i' | 'w')) {
+            self.status_message = "usage: :uniq [i][w]".into();
+            return;
+        }
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+        let (ignore_indent, ignore_case) = (flags.contains('w'), flags.contains('i'));
+        // Nothing to undo otherwise
+        if self
+            .document
+            .duplicates(rows.clone(), ignore_indent, ignore_case)
+            > 0
+        {
+            self.begin_edit(false);
+        }
+        let removed = self.document.dedup(rows, ignore_indent, ignore_case);
+        if matches!(self.mode, Mode::Visual { .. }) {
+            self.set_mode(Mode::Normal);
+        }
+        self.move_cursor(Key::Null);
+        self.scroll();
+        self.status_message = match removed {
+            1 => "Removed 1 line".into(),
+            n => format!("Removed {n} lines"),
+        };
+    }
+
+    /// Deletes the lines of `rows` matching `pattern`, or those not matching it if `keep`.
+    fn delete_matching(&mut self, pattern: &str, rows: Range<usize>, keep: bool) {
+        if pattern.is_empty() {
+            let name = if keep {
+                "keep_matching"
+            } else {
+                "delete_matching"
+            };
+            self.status_message = format!("usage: :{name} REGEX");
+            return;
+        }
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+        let regex = match build_regex(pattern, false) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.status_message = format!("Invalid regex: {e}");
+                return;
+            }
+        };
+
+        let rows = rows.start..cmp::min(rows.end, self.document.len());
+        let kept: Vec<String> = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .filter(|row| regex.is_match(row.as_str()) == keep)
+            .map(|row| row.as_str().to_string())
+            .collect();
+        let deleted = rows.len() - kept.len();
+        if deleted > 0 {
+            self.begin_edit(false);
+            self.document.remove_rows(rows.clone());
+            self.document.insert_rows(rows.start, &kept);
+            self.move_cursor(Key::Null);
+            self.scroll();
+        }
+        self.status_message = format!("Deleted {deleted} of {} lines", rows.len());
+    }
+
+    /// Adds a level of indentation to the non-blank `rows`, or removes one if not `indent`.
+    fn indent_lines(&mut self, rows: Range<usize>, indent: bool) {
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+        let rows = rows.start..cmp::min(rows.end, self.document.len());
+        let tab_width = self.settings.tab_width;
+        let level = if self.settings.expand_tab {
+            " ".repeat(tab_width)
+        } else {
+            "\t".into()
+        };
+        let lines: Vec<String> = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .map(|row| {
+                let line = row.as_str();
+                if indent {
+                    return if line.trim().is_empty() {
+                        line.to_string()
+                    } else {
+                        format!("{level}{line}")
+                    };
+                }
+                let removed = if line.starts_with('\t') {
+                    1
+                } else {
+                    line.bytes()
+                        .take(tab_width)
+                        .take_while(|&b| b == b' ')
+                        .count()
+                };
+                line[removed..].to_string()
+            })
+            .collect();
+        let unchanged = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .map(Row::as_str)
+            .eq(lines.iter().map(String::as_str));
+        if unchanged {
+            return;
+        }
+
+        self.begin_edit(false);
+        self.document.remove_rows(rows.clone());
+        self.document.insert_rows(rows.start, &lines);
+        self.move_cursor(Key::Null);
+    }
+
+    /// `/REGEX/COMMAND`: runs the command line on each of `rows` that matches (or doesn't if not
+    /// `matching`), with the cursor on it. The lines are found before running anything, and the
+    /// whole run undoes as one change.
+    fn global(
+        &mut self,
+        args: &str,
+        rows: Range<usize>,
+        matching: bool,
+        depth: usize,
+    ) -> Result<()> {
+        let usage = "usage: :g/REGEX/COMMAND";
+        let Some(delimiter) = args.chars().next().filter(|c| !c.is_alphanumeric()) else {
+            self.status_message = usage.into();
+            return Ok(());
+        };
+        let mut parts = args[delimiter.len_utf8()..].splitn(2, delimiter);
+        let pattern = parts.next().unwrap_or_default();
+        let command = parts.next().unwrap_or_default().trim();
+        if pattern.is_empty() || command.is_empty() {
+            self.status_message = usage.into();
+            return Ok(());
+        }
+        let regex = match build_regex(pattern, false) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.status_message = format!("Invalid regex: {e}");
+                return Ok(());
+            }
+        };
+        let invocation = match ex::parse(command) {
+            Ok(invocation) if matches!(invocation.ex, Ex::Global | Ex::InverseGlobal) => {
+                self.status_message = "Can't nest :global".into();
+                return Ok(());
+            }
+            Ok(invocation) => invocation,
+            Err(e) => {
+                self.status_message = e;
+                return Ok(());
+            }
+        };
+
+        let marked: Vec<usize> = rows
+            .filter(|&y| {
+                self.document
+                    .get(y)
+                    .is_some_and(|row| regex.is_match(row.as_str()) == matching)
+            })
+            .collect();
+        if marked.is_empty() {
+            self.status_message = format!("Pattern not found: {pattern}");
+            return Ok(());
+        }
+        self.begin_edit(false);
+        let grouping = mem::replace(&mut self.grouping_edits, true);
+        // Rows added or removed by the command so far, which shift the following marked rows.
+        // Commands are assumed to change their row and the following ones, so those it deletes,
+        // up to `deleted_until`, are no longer there to run on.
+        let mut shift = 0;
+        let mut deleted_until = 0;
+        let mut result = Ok(());
+        for &marked_y in &marked {
+            if marked_y < deleted_until {
+                continue;
+            }
+            let Some(y) = marked_y
+                .checked_add_signed(shift)
+                .filter(|&y| y < self.document.len())
+            else {
+                break;
+            };
+            self.cursor_position = Position { x: 0, y };
+            let len = self.document.len();
+            result = self.run_ex(invocation.clone(), depth);
+            if result.is_err() {
+                break;
+            }
+            let delta = self.document.len() as isize - len as isize;
+            if delta < 0 {
+                deleted_until = marked_y + delta.unsigned_abs();
+            }
+            shift += delta;
+        }
+        self.grouping_edits = grouping;
+        result?;
+
+        self.move_cursor(Key::Null);
+        self.scroll();
+        self.status_message = format!("Ran {command} on {} lines", marked.len());
+        Ok(())
+    }
+
+    /// Runs `command` with the user's shell, inserting its output at the cursor.
+    ///
+    /// The terminal is handed over while it runs, so that it can still show errors or ask for
+    /// passwords.
+    fn shell(&mut self, command: &str) -> Result<(), io::Error> {
+        if command.is_empty() {
+            self.status_message = "usage: :!COMMAND".into();
+            return Ok(());
+        }
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return Ok(());
+        }
+
+        self.terminal.pause()?;
+        let output = shell::command(command)
+            // Input is read by the editor
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output();
+        self.terminal.resume()?;
+        self.redraw_all()?;
+
+        let output = match output {
+            Ok(output) => output,
+            Err(e) => {
+                self.status_message = format!("Can't run {command}: {e}");
+                return Ok(());
+            }
+        };
+        let text = String::from_utf8_lossy(&output.stdout);
+        // Most commands end their output with a newline, which would split the current row
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        self.begin_edit(false);
+        self.cursor_position = self.document.insert_str(self.cursor_position, text);
+        self.scroll();
+
+        self.status_message = if output.status.success() {
+            format!("Inserted {} lines", text.lines().count())
+        } else {
+            format!("{command} failed: {}", output.status)
+        };
+        Ok(())
+    }
+
+    /// `s/REGEX/TEXT/FLAGS` on `rows`. Any character can delimit the parts instead of `/`. The
+    /// `g` flag replaces every match instead of the first, `i` ignores case and `c` asks for
+    /// each match. `TEXT` can refer to groups as `\1` and to the whole match as `&`, `$` being
+    /// literal.
+    fn substitute(&mut self, args: &str, rows: Range<usize>) {
+        let usage = "usage: :[RANGE]s/REGEX/TEXT/[gic]";
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+        let Some(delimiter) = args.chars().next().filter(|c| !c.is_alphanumeric()) else {
+            self.status_message = usage.into();
+            return;
+        };
+        let mut parts = args[delimiter.len_utf8()..].splitn(3, delimiter);
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+        let flags = parts.next().unwrap_or_default();
+        if pattern.is_empty() || flags.chars().any(|c| !matches!(c, 'g' | 'i' | 'c')) {
+            self.status_message = usage.into();
+            return;
+        }
+        let replacement = &ex::replacement(replacement);
+        let regex = match build_regex(pattern, flags.contains('i')) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.status_message = format!("Invalid regex: {e}");
+                return;
+            }
+        };
+
+        if flags.contains('c') && self.headless {
+            self.status_message = "Batch scripts can't confirm substitutions".into();
+            return;
+        }
+        let all = flags.contains('g');
+        let (count, found) = if flags.contains('c') {
+            match self.substitute_interactively(rows, &regex, replacement, all) {
+                Some((count, offered)) => (count, offered > 0),
+                // Stopped by the user
+                None => return,
+            }
+        } else {
+            let found = self
+                .document
+                .iter()
+                .skip(rows.start)
+                .take(rows.len())
+                .any(|row| regex.is_match(row.as_str()));
+            let mut count = 0;
+            // Without a match, there's nothing to do nor undo
+            let mut start = if found {
+                self.begin_edit(false);
+                rows.start
+            } else {
+                rows.end
+            };
+            while start < rows.end {
+                let end = cmp::min(start.saturating_add(SUBSTITUTE_CHUNK), rows.end);
+                count += self
+                    .document
+                    .substitute(start..end, &regex, replacement, all);
+                start = end;
+                // Keeping what was done, which undo reverts at once
+                if start < rows.end
+                    && start < self.document.len()
+                    && self.interrupted().unwrap_or_default()
+                {
+                    self.status_message = format!(
+                        "Substitute cancelled after line {start}, {count} substitution(s) made"
+                    );
+                    self.move_cursor(Key::Null);
+                    return;
+                }
+            }
+            (count, found)
+        };
+        self.status_message = match count {
+            0 if !found => format!("Pattern not found: {pattern}"),
+            1 => "1 substitution".into(),
+            n => format!("{n} substitutions"),
+        };
+        // The row may be shorter
+        self.move_cursor(Key::Null);
+    }
+
+    /// Asks before replacing each match in `rows`, or only the first one of each row if not
+    /// `all`. Returns the number of replacements and of matches offered, `None` if the user quit.
+    fn substitute_interactively(
+        &mut self,
+        rows: Range<usize>,
+        regex: &Regex,
+        replacement: &str,
+        all: bool,
+    ) -> Option<(usize, usize)> {
+        let rows = rows.start..cmp::min(rows.end, self.document.len());
+        let total: usize = self
+            .document
+            .iter()
+            .skip(rows.start)
+            .take(rows.len())
+            .map(|row| {
+                cmp::min(
+                    regex.find_iter(row.as_str()).count(),
+                    if all { usize::MAX } else { 1 },
+                )
+            })
+            .sum();
+        let mut count = 0;
+        let mut asked = 0;
+        let mut replace_rest = false;
+        let mut quit = false;
+        'rows: for y in rows {
+            let mut from = 0;
+            while let Some((found, expanded)) = self.document.get(y).and_then(|row| {
+                let captures = regex.captures_at(row.as_str(), from)?;
+                let mut expanded = String::new();
+                captures.expand(replacement, &mut expanded);
+                Some((captures.get(0)?.range(), expanded))
+            }) {
+                asked += 1;
+                let replace = replace_rest
+                    || {
+                        let row = self.document.get(y)?;
+                        let grapheme = |byte: usize| row.grapheme_index(byte);
+                        let graphemes = grapheme(found.start)
+                            ..cmp::max(grapheme(found.end), grapheme(found.start) + 1);
+                        self.cursor_position = Position {
+                            x: graphemes.start,
+                            y,
+                        };
+                        self.replace_candidate = Some((y, graphemes));
+                        self.scroll();
+                        let question = format!(
+                        "Replace with {expanded:?}? [y/n/a/q] ({asked} of {total}, {count} replaced)"
+                    );
+                        let answer = self.choose(&question, &['y', 'n', 'a', 'q']);
+                        self.replace_candidate = None;
+                        match answer {
+                            Some('y') => true,
+                            Some('a') => {
+                                replace_rest = true;
+                                true
+                            }
+                            Some('n') => false,
+                            _ => {
+                                quit = true;
+                                break 'rows;
+                            }
+                        }
+                    };
+
+                let mut line = self.document.get(y)?.as_str().to_string();
+                let next = if replace {
+                    // Declining every match changes nothing to undo
+                    if count == 0 {
+                        self.begin_edit(false);
+                    }
+                    line.replace_range(found.clone(), &expanded);
+                    self.document.remove_rows(y..y + 1);
+                    self.document.insert_rows(y, &[line.clone()]);
+                    count += 1;
+                    found.start + expanded.len()
+                } else {
+                    found.end
+                };
+                // Step over empty matches
+                from = if found.is_empty() {
+                    line[next..]
+                        .chars()
+                        .next()
+                        .map_or(line.len() + 1, |c| next + c.len_utf8())
+                } else {
+                    next
+                };
+                if !all || from > line.len() {
+                    break;
+                }
+            }
+        }
+
+        if quit {
+            self.status_message = match count {
+                1 => "Stopped after 1 substitution".into(),
+                n => format!("Stopped after {n} substitutions"),
+            };
+            self.move_cursor(Key::Null);
+            return None;
+        }
+        Some((count, asked))
+    }
+
+    /// Replaces the selected lines, or the whole document, with the output of `command` given
+    /// them as input.
+    fn filter(&mut self, command: &str) {
+        if command.is_empty() {
+            self.status_message = "usage: :filter COMMAND".into();
+            return;
+        }
+        if self.read_only {
+            self.status_message = "Read-only, can't modify the document".into();
+            return;
+        }
+
+        let rows = self.selected_rows();
+        let mut input = String::new();
+        for row in self.document.iter().skip(rows.start).take(rows.len()) {
+            input.push_str(row.as_str());
+            input.push('\n');
+        }
+
+        let output = match shell::pipe_through(command, input) {
+            Ok(output) => output,
+            Err(e) => {
+                self.status_message = format!("Can't run {command}: {e}");
+                return;
+            }
+        };
+        let errors = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            self.status_message = match errors.lines().next() {
+                Some(error) => format!("{command} failed: {error}"),
+                None => format!("{command} failed: {}", output.status),
+            };
+            return;
+        }
+
+        let lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(String::from)
+            .collect();
+        self.begin_edit(false);
+        self.document.remove_rows(rows.clone());
+        self.document.insert_rows(rows.start, &lines);
+        if matches!(self.mode, Mode::Visual { .. }) {
+            self.set_mode(Mode::Normal);
+        }
+        self.cursor_position = Position {
+            x: 0,
+            y: rows.start,
+        };
+        self.move_cursor(Key::Null);
+
+        self.status_message = match errors.lines().next() {
+            Some(error) => error.into(),
+            None => format!("Filtered {} lines into {}", rows.len(), lines.len()),
+        };
+    }
+
+    /// Begins a change to undo at once, ending the previous one. Typing right after typing is
+    /// part of the same change.
+    fn begin_edit(&mut self, typing: bool) {
+        if self.grouping_edits || (typing && self.typing) {
+            return;
+        }
+        self.finish_edit();
+        self.typing = typing;
+        self.edit_cursor = self.cursor_position;
+    }
+
+    /// Records the rows changed since `begin_edit` as one step to undo, if any were.
+    fn finish_edit(&mut self) {
+        if let Some(change) = self.document.take_change() {
+            self.redo_stack.clear();
+            self.undo_stack.push(Edit {
+                start: change.start,
+                len: change.len,
+                lines: change.old,
+                cursor_position: self.edit_cursor,
+            });
+        }
+    }
+
+    /// Puts back the rows of `edit`, returning the edit that reverts it.
+    fn apply_edit(&mut self, edit: Edit) -> Edit {
+        let lines = self.document.remove_rows(edit.start..edit.start + edit.len);
+        self.document.insert_rows(edit.start, &edit.lines);
+        // Undoing isn't a change to undo
+        self.document.take_change();
+        let reverse = Edit {
+            start: edit.start,
+            len: edit.lines.len(),
+            lines,
+            cursor_position: self.cursor_position,
+        };
+        self.cursor_position = edit.cursor_position;
+        self.typing = false;
+        self.move_cursor(Key::Null);
+        self.clamp_normal_cursor();
+        reverse
+    }
+
+    fn undo(&mut self) {
+        self.finish_edit();
+        let Some(edit) = self.undo_stack.pop() else {
+            self.status_message = "Already at oldest change".into();
+            return;
+        };
+        let reverse = self.apply_edit(edit);
+        self.redo_stack.push(reverse);
+    }
+
+    fn redo(&mut self) {
+        self.finish_edit();
+        let Some(edit) = self.redo_stack.pop() else {
+            self.status_message = "Already at newest change".into();
+            return;
+        };
+        let reverse = self.apply_edit(edit);
+        self.undo_stack.push(reverse);
+    }
+
+    /// Applies `set` arguments to the current document, and to the global settings too if
+    /// `global`. Returns the message to show.
+    fn set_options(&mut self, args: &str, global: bool) -> String {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        if args.is_empty() {
+            let values: Vec<String> = options::NAMES
+                .iter()
+                .map(|&(name, _)| self.option_value(name))
+                .collect();
+            return values.join("  ");
+        }
+
+        let mut shown = Vec::new();
+        for arg in args {
+            match options::parse(arg) {
+                Ok(Assignment::Query(name)) => shown.push(self.option_value(name)),
+                Ok(Assignment::Set(setting)) => self.apply_setting(setting, global),
+                Err(e) => return e,
+            }
+        }
+        shown.join("  ")
+    }
+
+    fn apply_setting(&mut self, setting: Setting, global: bool) {
+        match setting {
+            Setting::FileFormat(_) | Setting::FileEncoding(_) if self.read_only => {
+                self.status_message = "Read-only, can't modify the document".into();
+            }
+            Setting::FileFormat(line_ending) => self.document.set_line_ending(line_ending),
+            Setting::FileEncoding(encoding) => {
+                self.document.set_encoding(encoding);
+                if let Some(path) = self.document.path() {
+                    self.encodings.insert(path.to_path_buf(), encoding);
+                }
+                let unencodable = self.document.unencodable();
+                if unencodable > 0 {
+                    self.status_message = format!(
+                        "{unencodable} characters can't be saved in {encoding}, they'll be written as ?"
+                    );
                 }
             }
+            Setting::TabWidth(width) => {
+                self.settings.tab_width = width;
+                if global {
+                    self.config.tab_width = width;
+                }
+            }
+            Setting::ExpandTab(expand) => {
+                self.settings.expand_tab = expand;
+                if global {
+                    self.config.expand_tab = expand;
+                }
+            }
+            Setting::TextWidth(width) => {
+                self.settings.text_width = width;
+                if global {
+                    self.config.text_width = width;
+                }
+            }
+            // Not tied to the document
+            Setting::LineNumbers(on) => self.config.line_numbers = on,
+            Setting::ScrollOffset(offset) => self.config.scroll_offset = offset,
+            Setting::SideScrollOffset(offset) => self.config.side_scroll_offset = offset,
+            Setting::ScrollJump(jump) => self.config.scroll_jump = jump,
+            Setting::AutoWrap(on) => self.config.auto_wrap = on,
+            Setting::Subword(on) => self.config.subword_motion = on,
+            Setting::Spell(spell) => {
+                self.config.spell = spell;
+                if spell {
+                    self.load_dictionary();
+                }
+            }
+            Setting::Theme(name) => {
+                if let Some(theme) = Theme::by_name(&name) {
+                    self.config.set_theme(theme);
+                    // Rendered in the old colors
+                    self.rendered_preview.take();
+                }
+            }
+        }
+        self.scroll();
+    }
+
+    /// `name=value`, or `name`/`noname` for flags.
+    fn option_value(&self, name: &str) -> String {
+        let flag = |on: bool| if on { name.into() } else { format!("no{name}") };
+        match name {
+            "tabwidth" => format!("{name}={}", self.settings.tab_width),
+            "expandtab" => flag(self.settings.expand_tab),
+            "textwidth" => format!("{name}={}", self.settings.text_width.unwrap_or(0)),
+            "number" => flag(self.config.line_numbers),
+            "scrolloff" => format!("{name}={}", self.config.scroll_offset),
+            "sidescrolloff" => format!("{name}={}", self.config.side_scroll_offset),
+            "jumpscroll" => flag(self.config.scroll_jump),
+            "autowrap" => flag(self.config.auto_wrap),
+            "subword" => flag(self.config.subword_motion),
+            "spell" => flag(self.config.spell),
+            "fileformat" => format!(
+                "{name}={}",
+                self.document.line_ending().to_string().to_lowercase()
+            ),
+            "fileencoding" => format!("{name}={}", self.document.encoding()),
+            "theme" => format!("{name}={}", self.config.theme.name),
+            _ => String::new(),
+        }
+    }
+
+    /// The dictionary, if spell checking is enabled.
+    fn dictionary(&self) -> Option<&Dictionary> {
+        self.dictionary
+            .as_ref()
+            .filter(|_| self.config.spell && !self.degraded)
+    }
+
+    /// Loads the configured word list, disabling spell checking if it can't be read.
+    fn load_dictionary(&mut self) {
+        if self.dictionary.is_some() {
+            return;
+        }
+        let path = self
+            .config
+            .spell_dictionary
+            .clone()
+            .unwrap_or_else(|| spell::DEFAULT_DICTIONARY.into());
+        match Dictionary::load(&path) {
+            Ok(dictionary) => {
+                self.status_message = format!("Loaded {} words", dictionary.len());
+                self.dictionary = Some(dictionary);
+            }
+            Err(e) => {
+                self.status_message = format!("Can't load {}: {e}", path.display());
+                self.config.spell = false;
+            }
+        }
+    }
 
-            None => "[Untitled]".into(),
+    /// Moves to the next misspelled word, wrapping around at the end of the document.
+    fn next_misspelling(&mut self) {
+        let Some(dictionary) = self.dictionary() else {
+            self.status_message = "Spell checking is off, enable it with `set spell`".into();
+            return;
         };
 
-        let modified = if self.document.is_dirty() { " [+]" } else { "" };
+        let Position { x, y } = self.cursor_position;
+        let len = self.document.len();
+        let found = (0..=len).find_map(|i| {
+            let row_y = (y + i) % len.max(1);
+            let row = self.document.get(row_y)?;
+            dictionary
+                .misspelled(row.as_str())
+                .into_iter()
+                // The start of the current row is searched last, after wrapping around
+                .find(|range| match i {
+                    0 => range.start > x,
+                    i if i == len => range.start <= x,
+                    _ => true,
+                })
+                .map(|range| Position {
+                    x: range.start,
+                    y: row_y,
+                })
+        });
+
+        match found {
+            Some(pos) => {
+                self.cursor_position = pos;
+                self.scroll();
+            }
+            None => self.status_message = "No misspelled words".into(),
+        }
+    }
+
+    /// Offers corrections for the word under the cursor, replacing it with the chosen one.
+    fn spell_suggest(&mut self) -> Result<(), io::Error> {
+        /// Suggestions are picked by their single digit number.
+        const MAX_SUGGESTIONS: usize = 9;
 
-        let progression = {
-            let cursor_x = self.cursor_position.x;
-            let cursor_y = self.cursor_position.y;
+        let Position { x, y } = self.cursor_position;
+        let word = self.document.get(y).and_then(|row| {
+            spell::words(row.as_str())
+                .into_iter()
+                .find(|(range, _)| range.start <= x && x <= range.end)
+        });
+        let Some((range, word)) = word else {
+            self.status_message = "No word under the cursor".into();
+            return Ok(());
+        };
 
-            let percent_done = {
-                let y_max = self.document.len().saturating_sub(1);
+        if self.dictionary.is_none() {
+            self.load_dictionary();
+        }
+        let suggestions = self
+            .dictionary
+            .as_ref()
+            .map(|dictionary| dictionary.suggestions(&word, MAX_SUGGESTIONS))
+            .unwrap_or_default();
+        if suggestions.is_empty() {
+            self.status_message = format!("No suggestions for {word}");
+            return Ok(());
+        }
 
-                if cursor_y == 0 {
-                    "Top".into()
-                } else if cursor_y == y_max {
-                    "Bot".into()
-                } else {
-                    format!("{}%", cursor_y.saturating_mul(100) / y_max)
+        self.prompt_hint = suggestions
+            .iter()
+            .enumerate()
+            .map(|(idx, suggestion)| format!(" {}:{suggestion}", idx + 1))
+            .collect();
+        let Some(answer) = self.prompt(
+            &format!("Replace {word} with: "),
+            PromptKind::Replacement,
+            None,
+            |_, _, _| {},
+        )?
+        else {
+            return Ok(());
+        };
+        let replacement = match answer.parse::<usize>() {
+            Ok(n) => match suggestions.get(n.wrapping_sub(1)) {
+                Some(suggestion) => suggestion.clone(),
+                None => {
+                    self.status_message = format!("No suggestion {n}");
+                    return Ok(());
                 }
-            };
-
-            format!("{percent_done} [{:>4}:{:<2}]", cursor_y + 1, cursor_x + 1)
+            },
+            Err(_) => answer,
         };
 
-        let width: usize = self.terminal.size().width.into();
+        let start = Position { x: range.start, y };
+        self.begin_edit(false);
+        self.document
+            .delete_range(start, Position { x: range.end, y });
+        self.document.insert_str(start, &replacement);
+        self.cursor_position = start;
+        self.scroll();
+        Ok(())
+    }
 
-        let padding = " ".repeat(
-            width
-                .saturating_sub(file_name.len())
-                .saturating_sub(modified.len())
-                .saturating_sub(progression.len()),
-        );
+    /// Comments out the current line, or uncomments it if it already is.
+    fn toggle_comment(&mut self) {
+        let Some(comment) = self.settings.comment.clone() else {
+            self.status_message = format!(
+                "No comment string for file type {}",
+                self.document.file_type()
+            );
+            return;
+        };
+        let Some(row) = self.document.get(self.cursor_position.y) else {
+            return;
+        };
 
-        let mut status_line = format!("{file_name}{modified}{padding}{progression}");
-        status_line.truncate_graphemes(width);
+        let content = row.as_str();
+        let indent_bytes = content.len() - content.trim_start().len();
+        // Indentation is whitespace, so bytes are graphemes
+        let pos = Position {
+            x: indent_bytes,
+            y: self.cursor_position.y,
+        };
 
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        println!("{status_line}\r");
-        Terminal::reset_bg_color();
+        let body = &content[indent_bytes..];
+        if let Some(rest) = body.strip_prefix(comment.as_str()) {
+            let len = comment.chars().count() + usize::from(rest.starts_with(' '));
+            for _ in 0..len {
+                self.document.delete(pos);
+            }
+            if self.cursor_position.x > pos.x {
+                self.cursor_position.x = cmp::max(self.cursor_position.x - len, pos.x);
+            }
+        } else {
+            let len = comment.chars().count() + 1;
+            self.document.insert_str(pos, &format!("{comment} "));
+            if self.cursor_position.x >= indent_bytes {
+                self.cursor_position.x += len;
+            }
+        }
+        self.scroll();
     }
-    fn draw_message_bar(&self) {
-        Terminal::clear_current_line();
-        let mut mess = self.status_message.clone();
-        mess.truncate_graphemes(self.terminal.size().width.into());
-        print!("{}", mess);
+
+    /// Adds `delta` to the number under or after the cursor, leaving the cursor on its last digit.
+    fn add_to_number(&mut self, delta: i64) {
+        let y = self.cursor_position.y;
+        let Some(row) = self.document.get(y) else {
+            return;
+        };
+        let idx = row.byte_index(self.cursor_position.x);
+        let Some((range, number)) = number::add(row.as_str(), idx, delta) else {
+            self.status_message = "No number under or after the cursor".into();
+            return;
+        };
+
+        let mut line = row.as_str().to_string();
+        line.replace_range(range.clone(), &number);
+        self.begin_edit(false);
+        self.document.remove_rows(y..y + 1);
+        self.document.insert_rows(y, &[line]);
+        // Numbers are ASCII, so bytes are graphemes from the start of one
+        let start = self
+            .document
+            .get(y)
+            .map_or(0, |row| row.grapheme_index(range.start));
+        self.cursor_position.x = start + number.len() - 1;
+        self.scroll();
     }
 
-    fn draw_welcome_message(&self, width: usize) {
-        let message = format!("{NAME} text editor version {VERSION}");
-        let len = std::cmp::min(message.len(), width);
-        let padding = width.saturating_sub(len) / 2;
-        let spaces = " ".repeat(padding.saturating_sub(1));
+    /// Rewraps the paragraph around the cursor to the text width, or to
+    /// `DEFAULT_REFLOW_WIDTH` columns if there is none.
+    fn reflow_paragraph(&mut self) {
+        let comment = self.settings.comment.as_deref();
+        let is_blank = |y: usize| {
+            self.document
+                .get(y)
+                .is_none_or(|row| reflow::is_blank(row.as_str(), comment))
+        };
+        let y = self.cursor_position.y;
+        if is_blank(y) {
+            self.status_message = "No paragraph under the cursor".into();
+            return;
+        }
+        let mut start = y;
+        while start > 0 && !is_blank(start - 1) {
+            start -= 1;
+        }
+        let mut end = y + 1;
+        while end < self.document.len() && !is_blank(end) {
+            end += 1;
+        }
 
-        let mut message = format!("~{spaces}{message}\r");
-        message.truncate_graphemes(width);
+        let width = self.settings.text_width.unwrap_or(DEFAULT_REFLOW_WIDTH);
+        let lines: Vec<&str> = (start..end)
+            .filter_map(|y| self.document.get(y).map(Row::as_str))
+            .collect();
+        let wrapped = reflow::reflow(&lines, width, self.settings.tab_width, comment);
+        self.document.remove_rows(start..end);
+        self.document.insert_rows(start, &wrapped);
 
-        println!("{message}\r");
+        let x = self.document.get(start).map_or(0, |row| {
+            row.grapheme_index(reflow::prefix(row.as_str(), comment).len())
+        });
+        self.cursor_position = Position { x, y: start };
+        self.status_message = format!("Reflowed {} lines into {}", end - start, wrapped.len());
+        self.scroll();
     }
 
-    fn process_keypress(&mut self) -> Result<()> {
-        let pressed_key = Terminal::read_key()?;
+    /// Copies the current line to the clipboard.
+    fn copy_line(&mut self) {
+        let Some(row) = self.document.get(self.cursor_position.y) else {
+            return;
+        };
+        let line = format!("{}\n", row.as_str());
 
-        #[allow(clippy::single_match)]
-        match pressed_key {
-            Key::Ctrl('q') => self.should_quit = true,
-            Key::Ctrl('s') => self.save(false),
-            Key::Ctrl('w') => self.save(true),
-            Key::Ctrl('f') => self.search(),
-            Key::F(1) => self.status_message = HELP_MESSAGE.into(),
+        self.status_message = if self.terminal.copy_to_clipboard(&line) {
+            format!("Copied {}B to clipboard", line.len())
+        } else {
+            format!(
+                "Line too large to copy through the terminal ({}B, limit is about {}B)",
+                line.len(),
+                terminal::CLIPBOARD_MAX_ENCODED_LEN / 4 * 3
+            )
+        };
+    }
 
-            Key::Char(c) => {
-                self.document.insert_or_append(self.cursor_position, c);
-                self.move_cursor(Key::Right);
-            }
+    /// Reports the cursor's byte and character offsets, and the code points under it.
+    fn show_cursor_offset(&mut self) {
+        let (bytes, chars) = self.document.offsets(self.cursor_position);
 
-            Key::Delete => {
-                self.document.delete(self.cursor_position);
-                self.scroll();
+        let grapheme = self
+            .document
+            .get(self.cursor_position.y)
+            .and_then(|row| row.grapheme(self.cursor_position.x));
+        let under_cursor = match grapheme {
+            Some(grapheme) => {
+                let code_points: Vec<String> = grapheme
+                    .chars()
+                    .map(|c| format!("U+{:04X}", u32::from(c)))
+                    .collect();
+                format!("{grapheme:?} {}", code_points.join(" "))
             }
-            Key::Backspace => {
-                if (self.cursor_position.x > 0) || (self.cursor_position.y > 0) {
-                    self.move_cursor(Key::Left);
-                    self.document.delete(self.cursor_position);
-                    self.scroll();
-                }
-            }
-
-            Key::Up
-            | Key::Down
-            | Key::Left
-            | Key::Right
-            | Key::PageUp
-            | Key::PageDown
-            | Key::Home
-            | Key::End => self.move_cursor(pressed_key),
-            _ => (),
-        }
+            None => "<EOL>".into(),
+        };
 
-        Ok(())
+        self.status_message = format!("Byte {bytes}, char {chars}: {under_cursor}");
     }
 
     fn prompt<C>(
         &mut self,
         prompt: &str,
+        kind: PromptKind,
         already_filled: Option<String>,
         callback: C,
     ) -> Result<Option<String>, io::Error>
     where
         C: Fn(&mut Self, Key, &String),
     {
+        if self.headless {
+            self.status_message = format!("{prompt}cancelled, batch scripts can't answer prompts");
+            return Ok(None);
+        }
+        let previous_mode = self.mode;
+        self.set_mode(Mode::Prompt);
+
         let mut result = already_filled.unwrap_or_default();
+        let history = self.history.entries(kind).to_vec();
+        // Entry shown with Up and Down, `history.len()` being what was typed
+        let mut history_idx = history.len();
+        let mut typed = String::new();
+        // File names cycled through with Tab, and the one shown
+        let mut completions: Vec<String> = Vec::new();
+        let mut completion_idx = 0;
         loop {
-            self.status_message = format!("{prompt}{result}\u{258f}");
+            self.status_message = format!("{prompt}{result}\u{258f}{}", self.prompt_hint);
             self.refresh_screen()?;
-            let key = Terminal::read_key()?;
+            // Modifiers termion can't express are irrelevant here
+            let key = self.read_key()?.key;
+            if kind == PromptKind::Path && key != Key::Char('\t') {
+                completions.clear();
+                self.prompt_hint.clear();
+            }
             match key {
                 Key::Char('\n') => break,
+                Key::Char('\t') if kind == PromptKind::Path => {
+                    if completions.is_empty() {
+                        completions = completion::paths(&result);
+                        completion_idx = 0;
+                        self.prompt_hint = match completions.len() {
+                            0 => "  [no match]".into(),
+                            1 => String::new(),
+                            n => format!("  [{n} matches, Tab for the next one]"),
+                        };
+                    } else {
+                        completion_idx = (completion_idx + 1) % completions.len();
+                    }
+                    if let Some(completion) = completions.get(completion_idx) {
+                        result.clone_from(completion);
+                    }
+                }
                 Key::Char(c) => result.push(c),
                 Key::Backspace => {
-                    if !result.is_empty() {
-                        result.pop();
+                    result.pop();
+                }
+                Key::Up if history_idx > 0 => {
+                    if history_idx == history.len() {
+                        typed = mem::take(&mut result);
                     }
+                    history_idx -= 1;
+                    result.clone_from(&history[history_idx]);
+                }
+                Key::Down if history_idx < history.len() => {
+                    history_idx += 1;
+                    result = history
+                        .get(history_idx)
+                        .cloned()
+                        .unwrap_or_else(|| typed.clone());
                 }
                 Key::Esc | Key::Ctrl('q') => {
                     result.clear();
@@ -317,47 +5031,207 @@ impl Editor {
             callback(self, key, &result);
         }
 
+        self.set_mode(previous_mode);
         self.status_message.clear();
+        self.prompt_hint.clear();
 
         if result.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(result))
+            return Ok(None);
+        }
+        self.history.add(kind, &result);
+        self.save_history();
+        Ok(Some(result))
+    }
+
+    /// Lets the user pick an entry of `picker`, previewing the selected one with `preview`.
+    /// Returns its index, or `None` if escaped or there is no one to answer.
+    fn pick<P>(&mut self, mut picker: Picker, preview: P) -> Result<Option<usize>, io::Error>
+    where
+        P: Fn(&Self, usize) -> Vec<String>,
+    {
+        if self.headless {
+            self.status_message = format!(
+                "{} cancelled, batch scripts can't answer prompts",
+                picker.prompt()
+            );
+            return Ok(None);
+        }
+        let previous_mode = self.mode;
+        self.set_mode(Mode::Prompt);
+
+        let answer = loop {
+            let lines = picker
+                .selected()
+                .map_or_else(Vec::new, |idx| preview(self, idx));
+            picker.set_preview(lines);
+            self.status_message = picker.prompt();
+            // Drawn while shown
+            self.picker = Some(picker);
+            let key = self.refresh_screen().and_then(|()| self.read_key());
+            let Some(shown) = self.picker.take() else {
+                break Ok(None);
+            };
+            picker = shown;
+            match key.map(|chord| picker.handle_key(chord.key)) {
+                Ok(Input::Continue) => (),
+                Ok(Input::Picked(idx)) => break Ok(Some(idx)),
+                Ok(Input::Cancelled) => break Ok(None),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.set_mode(previous_mode);
+        self.status_message.clear();
+        answer
+    }
+
+    /// Asks `question` in the message bar, answered with a single key: `y`, `n`, or Esc to
+    /// cancel. Other keys are ignored.
+    fn confirm(&mut self, question: &str) -> Confirm {
+        match self.choose(&format!("{question} [y/n]"), &['y', 'n']) {
+            Some('y') => Confirm::Yes,
+            Some(_) => Confirm::No,
+            None => Confirm::Cancel,
+        }
+    }
+
+    /// Asks `question` until one of the lowercase `choices` is typed, in either case. `None` if
+    /// escaped, or without a terminal to answer.
+    fn choose(&mut self, question: &str, choices: &[char]) -> Option<char> {
+        if self.headless {
+            self.status_message = format!("{question} cancelled, batch scripts can't answer");
+            return None;
         }
+        let previous_mode = self.mode;
+        self.set_mode(Mode::Prompt);
+        self.status_message = question.into();
+
+        let answer = loop {
+            if self.refresh_screen().is_err() {
+                break None;
+            }
+            match self.read_key().map(|chord| chord.key) {
+                Ok(Key::Char(c)) if choices.contains(&c.to_ascii_lowercase()) => {
+                    break Some(c.to_ascii_lowercase())
+                }
+                Ok(Key::Esc | Key::Ctrl('q')) | Err(_) => break None,
+                Ok(_) => (),
+            }
+        };
+
+        self.set_mode(previous_mode);
+        self.status_message.clear();
+        answer
     }
 
     fn search(&mut self) {
         let old_pos = self.cursor_position;
+        // Whether the last search as you type gave up, to search further once the query is
+        // entered
+        let timed_out = Cell::new(false);
 
         let query = self
-            .prompt("Search: ", None, |editor, key, query| {
-                let mut moved = false;
-                let direction = match key {
-                    Key::Right | Key::Down => {
-                        editor.move_cursor(Key::Right);
-                        moved = true;
-                        SearchDirection::Forward
+            .prompt(
+                "Search: ",
+                PromptKind::Search,
+                None,
+                |editor, key, query| {
+                    let mut moved = false;
+                    // Up and Down go through previous queries
+                    let direction = match key {
+                        Key::Right => {
+                            editor.move_cursor(Key::Right);
+                            moved = true;
+                            SearchDirection::Forward
+                        }
+                        Key::Left => SearchDirection::Backward,
+                        _ => SearchDirection::Forward,
+                    };
+
+                    let regex = build_regex(query, false);
+                    let found = regex.as_ref().ok().map(|r| {
+                        editor.document.find_within(
+                            r,
+                            editor.cursor_position,
+                            direction,
+                            SEARCH_BUDGET,
+                        )
+                    });
+                    timed_out.set(found == Some(Err(TimedOut)));
+                    editor.prompt_hint = match (&regex, timed_out.get()) {
+                        (Err(regex::Error::CompiledTooBig(_)), _) => "  [too complex]".into(),
+                        (_, true) => "  [search too slow, Enter to keep looking]".into(),
+                        _ => String::new(),
+                    };
+                    let found = found.and_then(Result::ok).flatten();
+                    // Keep the previous highlight while the query doesn't compile (e.g. unclosed group)
+                    if let Ok(regex) = regex {
+                        editor.search_highlight = Some(regex);
                     }
-                    Key::Left | Key::Up => SearchDirection::Backward,
-                    _ => SearchDirection::Forward,
-                };
 
-                if let Ok(Some(pos)) = Regex::from_str(query)
-                    .map(|r| editor.document.find(&r, editor.cursor_position, direction))
-                {
-                    editor.cursor_position = pos;
-                    editor.scroll()
-                }
-                // Not found, move back
-                else if moved {
-                    editor.move_cursor(Key::Left);
-                }
-            })
+                    if let Some(pos) = found {
+                        editor.cursor_position = pos;
+                        editor.scroll()
+                    }
+                    // Not found, move back
+                    else if moved {
+                        editor.move_cursor(Key::Left);
+                    }
+                },
+            )
             .unwrap_or(None);
 
-        if query.is_none() {
+        let Some(query) = query else {
+            self.search_highlight = None;
             self.cursor_position = old_pos;
             self.scroll();
+            return;
+        };
+        if timed_out.get() {
+            let found = self.search_highlight.as_ref().and_then(|regex| {
+                self.document
+                    .find(regex, self.cursor_position, SearchDirection::Forward)
+            });
+            match found {
+                Some(pos) => {
+                    self.cursor_position = pos;
+                    self.scroll();
+                }
+                None => self.status_message = format!("Pattern not found: {query}"),
+            }
+        }
+    }
+
+    /// Moves the cursor to the start of `line` (1-based), or to the last line if it's past the
+    /// end.
+    /// Moves to the 1-based `line` and `col`, clamped to the document, with the line in the
+    /// middle of the screen.
+    fn go_to(&mut self, line: usize, col: usize) {
+        let y = cmp::min(
+            line.saturating_sub(1),
+            self.document.len().saturating_sub(1),
+        );
+        let len = self.document.get(y).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: cmp::min(col.saturating_sub(1), len),
+            y,
+        };
+        self.align_view(ViewAlign::Center);
+    }
+
+    fn go_to_prompt(&mut self) {
+        let input = self
+            .prompt("Go to line: ", PromptKind::Line, None, |_, _, _| {})
+            .unwrap_or(None);
+        if let Some(input) = input {
+            self.go_to_location(&input);
+        }
+    }
+
+    fn go_to_location(&mut self, location: &str) {
+        match parse_location(location, self.document.len()) {
+            Some((line, col)) => self.go_to(line, col),
+            None => self.status_message = format!("Not a line number: {location}"),
         }
     }
 
@@ -369,7 +5243,7 @@ impl Editor {
         };
         let y_max = self.document.len().saturating_sub(1);
 
-        let height: usize = self.terminal.size().height.into();
+        let height = self.text_height();
 
         match k {
             Key::Up => y = y.saturating_sub(1),
@@ -413,42 +5287,574 @@ impl Editor {
         self.scroll();
     }
 
-    fn scroll(&mut self) {
-        let Position { x, y } = self.cursor_position;
+    /// Scrolls the view by `lines`, up if negative, moving the cursor only as much as needed to
+    /// keep it on screen.
+    fn scroll_view(&mut self, lines: isize) {
+        let height = self.text_height();
+        let len = self.document.len();
+        let max_offset = cmp::max(len.saturating_sub(height), self.offset.y);
+        self.offset.y = cmp::min(self.offset.y.saturating_add_signed(lines), max_offset);
+
+        // The range `scroll` leaves the cursor in without scrolling back
+        let scroll_offset = cmp::min(self.config.scroll_offset, height.saturating_sub(1) / 2);
+        let top = match self.offset.y {
+            0 => 0,
+            y => y + scroll_offset,
+        };
+        let bottom = if self.offset.y >= len.saturating_sub(height) {
+            len.saturating_sub(1)
+        } else {
+            (self.offset.y + height).saturating_sub(scroll_offset + 1)
+        };
+        let y = self.cursor_position.y.clamp(top, cmp::max(top, bottom));
+        if y != self.cursor_position.y {
+            let len = self.document.get(y).map_or(0, Row::len);
+            self.cursor_position = Position {
+                x: cmp::min(self.cursor_position.x, len),
+                y,
+            };
+        }
+        self.scroll();
+    }
+
+    /// Moves the cursor and the view by half a screen.
+    fn scroll_half_page(&mut self, down: bool) {
+        let lines = cmp::max(self.text_height() / 2, 1);
+        let y = if down {
+            cmp::min(
+                self.cursor_position.y.saturating_add(lines),
+                self.document.len().saturating_sub(1),
+            )
+        } else {
+            self.cursor_position.y.saturating_sub(lines)
+        };
+        let len = self.document.get(y).map_or(0, Row::len);
+        self.cursor_position = Position {
+            x: cmp::min(self.cursor_position.x, len),
+            y,
+        };
+        let lines = isize::try_from(lines).unwrap_or(isize::MAX);
+        self.scroll_view(if down { lines } else { -lines });
+    }
+
+    /// Scrolls for the cursor line to be at the top, center or bottom of the screen, as far as
+    /// `scroll_offset` allows.
+    fn align_view(&mut self, align: ViewAlign) {
+        let y = self.cursor_position.y;
+        let height = self.text_height();
+        let scroll_offset = cmp::min(self.config.scroll_offset, height.saturating_sub(1) / 2);
+        self.offset.y = match align {
+            ViewAlign::Top => y.saturating_sub(scroll_offset),
+            ViewAlign::Center => y.saturating_sub(height / 2),
+            ViewAlign::Bottom => (y + scroll_offset + 1).saturating_sub(height),
+        };
+        self.scroll();
+    }
 
-        let (width, height) = {
-            let s = self.terminal.size();
-            (s.width.into(), s.height.into())
+    fn scroll(&mut self) {
+        let y = self.cursor_position.y;
+        let width = self.useful_text_width();
+        let height = self.text_height();
+        // Margins can't exceed half of the screen, or the cursor would never be in range
+        let scroll_offset = cmp::min(self.config.scroll_offset, height.saturating_sub(1) / 2);
+        let side_offset = cmp::min(self.config.side_scroll_offset, width.saturating_sub(1) / 2);
+        // Scrolling by half a screen at once, at least as far as needed
+        let (jump_y, jump_x) = if self.config.scroll_jump {
+            (height / 2, width / 2)
+        } else {
+            (0, 0)
         };
 
-        if y < self.offset.y.saturating_add(SCROLL_OFFSET) {
+        if y < self.offset.y.saturating_add(scroll_offset) {
             // If cursor has left top of viewport, scroll and cap offset
-            self.offset.y = y.saturating_sub(SCROLL_OFFSET);
+            self.offset.y = cmp::min(
+                y.saturating_sub(scroll_offset),
+                self.offset.y.saturating_sub(jump_y),
+            );
         } else if y
             >= self
                 .offset
                 .y
                 .saturating_add(height)
-                .saturating_sub(SCROLL_OFFSET)
+                .saturating_sub(scroll_offset)
         {
             // If cursor has left bottom of viewport
             self.offset.y = cmp::min(
-                y
-                    // These operations need to be in this order for saturating arithmetic to work
-                    // properly.
-                    .saturating_add(SCROLL_OFFSET)
-                    .saturating_sub(height)
-                    .saturating_add(1),
+                cmp::max(
+                    y
+                        // These operations need to be in this order for saturating arithmetic to
+                        // work properly.
+                        .saturating_add(scroll_offset)
+                        .saturating_sub(height)
+                        .saturating_add(1),
+                    self.offset.y.saturating_add(jump_y),
+                ),
                 self.document.len().saturating_sub(height),
             );
         }
 
-        if x < self.offset.x {
-            // If cursor has left top of viewport
-            self.offset.x = x
-        } else if x >= self.offset.x.saturating_add(width) {
-            // If cursor has left bottom of viewport
-            self.offset.x = x.saturating_sub(width).saturating_add(1);
+        let x = self.cursor_render_x();
+        if x < self.offset.x.saturating_add(side_offset) {
+            // If cursor has left the left of viewport
+            self.offset.x = cmp::min(
+                x.saturating_sub(side_offset),
+                self.offset.x.saturating_sub(jump_x),
+            );
+        } else if x
+            >= self
+                .offset
+                .x
+                .saturating_add(width)
+                .saturating_sub(side_offset)
+        {
+            // If cursor has left the right of viewport
+            self.offset.x = cmp::max(
+                x.saturating_add(side_offset)
+                    .saturating_sub(width)
+                    .saturating_add(1),
+                self.offset.x.saturating_add(jump_x),
+            );
+        }
+    }
+}
+
+/// `line` as shown in a preview, tabs expanded to single spaces and control characters
+/// dropped, as previews have no tab stops.
+fn preview_line(line: &str) -> String {
+    line.chars()
+        .filter_map(|c| match c {
+            '\t' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// The first lines of the file at `path`, nothing if it can't be read as text.
+fn file_preview(path: &Path) -> Vec<String> {
+    let Ok(file) = fs::File::open(path) else {
+        return Vec::new();
+    };
+    io::BufReader::new(file)
+        .lines()
+        .take(picker::PREVIEW_LINES)
+        .map_while(Result::ok)
+        .map(|line| preview_line(&line))
+        .collect()
+}
+
+/// Path of `document` as shown in messages.
+fn buffer_name(document: &Document) -> String {
+    document
+        .get_path_string()
+        .unwrap_or_else(|| "[Untitled]".into())
+}
+
+/// Size of the file at `path` in bytes, if it is larger than `config` allows without asking.
+fn large_file_size(path: &Path, config: &EditorConfig) -> Option<u64> {
+    let max = config.large_file_size?;
+    let metadata = fs::metadata(path).ok().filter(fs::Metadata::is_file)?;
+    (metadata.len() > max).then_some(metadata.len())
+}
+
+/// Compiles `pattern`, refusing those too large to search quickly.
+fn build_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// Whether `chord` asks to cancel a long operation.
+fn is_interrupt(chord: Chord) -> bool {
+    chord == Key::Esc.into() || chord == Key::Ctrl('c').into()
+}
+
+/// `[####      ] 40%`.
+fn progress_bar(done: u64, total: u64) -> String {
+    const WIDTH: u64 = 20;
+
+    let done = cmp::min(done, total);
+    let total = cmp::max(total, 1);
+    let filled = usize::try_from(done * WIDTH / total).unwrap_or_default();
+    let empty = usize::try_from(WIDTH).unwrap_or_default() - filled;
+    format!(
+        "[{}{}] {}%",
+        "#".repeat(filled),
+        " ".repeat(empty),
+        done * 100 / total
+    )
+}
+
+/// `size` bytes in the largest unit it makes at least one of.
+fn format_size(size: u64) -> String {
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = size as f64 / 1024.0;
+    for unit in ["KiB", "MiB"] {
+        if value < 1024.0 {
+            return format!("{value:.1} {unit}");
+        }
+        value /= 1024.0;
+    }
+    format!("{value:.1} GiB")
+}
+
+/// Tells which lines of `document` had invalid UTF-8, if any.
+fn invalid_utf8_warning(document: &Document) -> Option<String> {
+    const MAX_LISTED: usize = 5;
+
+    let lines = document.invalid_lines();
+    if lines.is_empty() {
+        return None;
+    }
+    let mut listed: Vec<String> = lines
+        .iter()
+        .take(MAX_LISTED)
+        .map(|y| (y + 1).to_string())
+        .collect();
+    if lines.len() > MAX_LISTED {
+        listed.push("…".into());
+    }
+    let plural = if lines.len() == 1 { "" } else { "s" };
+    Some(format!(
+        "Invalid UTF-8 replaced with \u{fffd} on line{plural} {}",
+        listed.join(", ")
+    ))
+}
+
+/// Rows of the terminal panel, with its separator, out of `height`: a third, leaving at least
+/// one for the document.
+fn panel_height(height: usize) -> usize {
+    cmp::min(
+        cmp::max(height / 3, MIN_PANEL_HEIGHT),
+        height.saturating_sub(1),
+    )
+}
+
+/// Parses `line`, `line:col` or `percent%` into a 1-based line and column, percentages being of
+/// `lines`.
+fn parse_location(location: &str, lines: usize) -> Option<(usize, usize)> {
+    let location = location.trim();
+    if let Some(percent) = location.strip_suffix('%') {
+        let percent = cmp::min(percent.trim().parse::<usize>().ok()?, 100);
+        return Some(((percent * lines).div_ceil(100), 1));
+    }
+    match location.split_once(':') {
+        Some((line, col)) => Some((line.trim().parse().ok()?, col.trim().parse().ok()?)),
+        None => Some((location.parse().ok()?, 1)),
+    }
+}
+
+/// Expands the `\n`, `\t` and `\\` escapes of `:insert`.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match (c, chars.clone().next()) {
+            ('\\', Some(escaped @ ('n' | 't' | '\\'))) => {
+                chars.next();
+                match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    _ => '\\',
+                }
+            }
+            (c, _) => c,
+        });
+    }
+    unescaped
+}
+
+/// `line` with its indentation made of spaces if `expand`, or of tabs then spaces, and with
+/// the tabs after it expanded too if `all_tabs`.
+fn retab(line: &str, tab_width: usize, expand: bool, all_tabs: bool) -> String {
+    let tab_width = tab_width.max(1);
+    let body = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - body.len()];
+    let width = indent.chars().fold(0, |col, c| {
+        col + if c == '\t' {
+            row::tab_stop_width(col, tab_width)
+        } else {
+            1
+        }
+    });
+
+    let mut retabbed = if expand {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width)
+    };
+    if !all_tabs {
+        retabbed.push_str(body);
+        return retabbed;
+    }
+    let mut col = width;
+    for c in body.chars() {
+        if c == '\t' {
+            let spaces = row::tab_stop_width(col, tab_width);
+            retabbed.push_str(&" ".repeat(spaces));
+            col += spaces;
+        } else {
+            retabbed.push(c);
+            col += 1;
+        }
+    }
+    retabbed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs;
+    use crate::memory_terminal::{MemoryTerminal, Screen};
+    use crate::terminal::Size;
+
+    use std::cell::RefCell;
+    use std::process::Command;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+
+    /// Creates an editor on an empty document, with `keys` as pending input.
+    fn editor_with_keys(keys: &[Key]) -> (Editor, Rc<RefCell<Screen>>) {
+        let terminal = MemoryTerminal::new(
+            Size {
+                width: 40,
+                height: 10,
+            },
+            keys.iter().copied().map(Chord::from).collect(),
+        );
+        let screen = terminal.screen();
+        let editor =
+            Editor::with_terminal(Box::new(terminal), None, EditorConfig::default()).unwrap();
+        (editor, screen)
+    }
+
+    /// Runs an editor on an empty document, typing `keys` then quitting.
+    fn run_keys(keys: &[Key]) -> Editor {
+        let mut keys = keys.to_vec();
+        // Again, to discard the changes
+        keys.extend([Key::Ctrl('q'), Key::Ctrl('q')]);
+
+        let (mut editor, _) = editor_with_keys(&keys);
+        editor.run().unwrap();
+        editor
+    }
+
+    fn type_str(s: &str) -> Vec<Key> {
+        s.chars().map(Key::Char).collect()
+    }
+
+    fn content(editor: &Editor) -> Vec<&str> {
+        editor.document.iter().map(Row::as_str).collect()
+    }
+
+    #[test]
+    fn typing_inserts_text() {
+        let editor = run_keys(&type_str("hello\nworld"));
+        assert_eq!(content(&editor), ["hello", "world"]);
+        assert_eq!(editor.cursor_position, Position { x: 5, y: 1 });
+    }
+
+    #[test]
+    fn backspace_at_line_start_joins_lines() {
+        let mut keys = type_str("ab\ncd");
+        keys.extend([Key::Home, Key::Backspace]);
+        let editor = run_keys(&keys);
+        assert_eq!(content(&editor), ["abcd"]);
+        assert_eq!(editor.cursor_position, Position { x: 2, y: 0 });
+    }
+
+    #[test]
+    fn draws_rows_and_status_bar() {
+        let keys = type_str("hello");
+        let (mut editor, screen) = editor_with_keys(&keys);
+        for _ in &keys {
+            editor.process_event(None).unwrap();
+        }
+        editor.refresh_screen().unwrap();
+
+        let lines = screen.borrow().lines();
+        assert_eq!(lines[0], " 1 hello");
+        assert!(lines[8].starts_with("[Untitled] [+]"));
+    }
+
+    #[test]
+    fn progress_bars_fill_up() {
+        assert_eq!(progress_bar(0, 0), "[                    ] 0%");
+        assert_eq!(progress_bar(1, 4), "[#####               ] 25%");
+        assert_eq!(progress_bar(9, 4), "[####################] 100%");
+    }
+
+    #[test]
+    fn ticks_arrive_without_input() {
+        let (editor, _) = editor_with_keys(&[]);
+        let deadline = Instant::now() + TICK_INTERVAL * 4;
+        let event = editor.events.next(Some(deadline)).unwrap();
+        assert!(matches!(event, Some(Event::Tick)));
+    }
+
+    #[test]
+    fn superseded_jobs_are_dropped() {
+        let (mut editor, _) = editor_with_keys(&[]);
+        editor.jobs.spawn("lint", |_| {
+            thread::sleep(Duration::from_millis(50));
+            Some(Event::Diagnostics(vec![Diagnostic {
+                line: 0,
+                column: 0,
+                severity: Severity::Error,
+                message: "stale".into(),
+            }]))
+        });
+        editor
+            .jobs
+            .spawn("lint", |_| Some(Event::Diagnostics(Vec::new())));
+        editor.jobs.cancel("lint");
+        editor
+            .jobs
+            .spawn("lint", |_| Some(Event::Diagnostics(Vec::new())));
+        assert_eq!(editor.jobs.names(Duration::ZERO), ["lint"]);
+
+        // Only the last job posts, but leave time for the stale one to finish
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while let Some(event) = editor.events.next(Some(deadline)).unwrap() {
+            editor.handle_event(event).unwrap();
+        }
+        assert!(editor.diagnostics.is_empty());
+        assert!(editor.jobs.names(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn undo_history_is_bounded_in_size() {
+        let edit = |len| Edit {
+            start: 0,
+            len: 1,
+            lines: vec!["x".repeat(len)],
+            cursor_position: Position::default(),
+        };
+        let mut stack = UndoStack::default();
+        for _ in 0..4 {
+            stack.push(edit(MAX_UNDO_BYTES / 3));
+        }
+        assert_eq!(stack.edits.len(), 2);
+        // The newest is kept, however large
+        stack.push(edit(MAX_UNDO_BYTES * 2));
+        assert_eq!(stack.edits.len(), 1);
+        assert!(stack.pop().is_some());
+        assert_eq!(stack.size, 0);
+    }
+
+    #[test]
+    fn preview_is_rendered_again_only_once_edited() {
+        let (mut editor, _) = editor_with_keys(&[]);
+        editor
+            .document
+            .set_file_type(crate::FileType::by_name("markdown").unwrap());
+        editor
+            .document
+            .insert_str(Position::default(), "# Title\ntext");
+        editor.toggle_preview();
+        editor.refresh_screen().unwrap();
+        let rendered = |editor: &Editor| {
+            editor
+                .rendered_preview
+                .borrow()
+                .as_ref()
+                .map_or(0, |preview| preview.lines.len())
+        };
+        assert!(rendered(&editor) > 0);
+
+        // Forgetting the lines shows whether they are rendered again
+        if let Some(preview) = editor.rendered_preview.borrow_mut().as_mut() {
+            preview.lines.clear();
+        }
+        editor.refresh_screen().unwrap();
+        assert_eq!(rendered(&editor), 0);
+        editor
+            .document
+            .insert_or_append(Position { x: 4, y: 1 }, '!');
+        editor.refresh_screen().unwrap();
+        assert!(rendered(&editor) > 0);
+    }
+
+    #[test]
+    fn undo_keeps_only_the_changed_rows() {
+        let (mut editor, _) = editor_with_keys(&[]);
+        let lines = vec!["x".repeat(100); 10_000];
+        editor.document.insert_rows(0, &lines);
+        editor.begin_edit(false);
+        editor
+            .document
+            .insert_or_append(Position { x: 0, y: 5000 }, 'y');
+        editor.undo();
+        assert_eq!(editor.redo_stack.size, 101 + mem::size_of::<String>());
+        assert_eq!(editor.document.get(5000).map(Row::len), Some(100));
+    }
+
+    #[test]
+    fn blame_runs_in_the_background() {
+        let dir = std::env::temp_dir().join(format!("hecto-blame-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "one\ntwo\n").unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args([
+                    "-c",
+                    "user.name=Jane Doe",
+                    "-c",
+                    "user.email=jane@example.com",
+                ])
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "-q"]);
+        git(&["add", "a.txt"]);
+        git(&["commit", "-q", "-m", "Add a"]);
+
+        let (mut editor, _) = editor_with_keys(&[]);
+        editor.open(dir.join("a.txt")).unwrap();
+        // Only the first line is still as committed
+        editor
+            .document
+            .insert_or_append(Position { x: 0, y: 1 }, 'x');
+        editor.status_message.clear();
+        editor.blame();
+        assert_eq!(editor.status_message, "");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while editor.status_message.is_empty() && editor.process_event(Some(deadline)).unwrap() {}
+        let message = &editor.status_message;
+        assert!(
+            message.contains(" Jane Doe ") && message.ends_with(": Add a"),
+            "{message}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cancelled_jobs_are_killed_and_post_nothing() {
+        let (mut editor, _) = editor_with_keys(&[]);
+        let (done, finished) = mpsc::channel();
+        editor.jobs.spawn("lint", move |token| {
+            let output = jobs::output(Command::new("sleep").arg("10"), token);
+            let _ = done.send(output.is_none());
+            Some(Event::Diagnostics(Vec::new()))
+        });
+        thread::sleep(Duration::from_millis(50));
+        editor.jobs.cancel("lint");
+
+        // Killed rather than waited for
+        assert_eq!(finished.recv_timeout(Duration::from_secs(5)), Ok(true));
+        let deadline = Instant::now() + Duration::from_millis(200);
+        while let Some(event) = editor.events.next(Some(deadline)).unwrap() {
+            assert!(!matches!(event, Event::Job(_)));
         }
     }
 }
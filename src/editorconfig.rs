@@ -0,0 +1,250 @@
+//! Support for `.editorconfig` files, see <https://editorconfig.org>.
+
+use crate::config::BufferSettings;
+use crate::document::{Encoding, LineEnding};
+
+use regex::Regex;
+use std::fs;
+use std::path::{self, Path};
+
+/// Properties that apply to a file, `None` where no `.editorconfig` section sets them.
+#[derive(Debug, Default)]
+pub struct Properties {
+    /// `true` for spaces.
+    pub expand_tab: Option<bool>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub line_ending: Option<LineEnding>,
+    pub encoding: Option<Encoding>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+impl Properties {
+    /// Collects the properties for `path` from every `.editorconfig` above it, up to the first
+    /// one marked `root = true`. Unreadable files and invalid sections are ignored.
+    #[must_use]
+    pub fn for_path(path: &Path) -> Self {
+        let mut properties = Self::default();
+        let Ok(path) = path::absolute(path) else {
+            return properties;
+        };
+
+        let mut files = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) else {
+                continue;
+            };
+            let file = File::parse(&text);
+            let root = file.root;
+            files.push((dir, file));
+            if root {
+                break;
+            }
+        }
+
+        // Closer files take precedence
+        for (dir, file) in files.iter().rev() {
+            let Ok(relative) = path.strip_prefix(dir) else {
+                continue;
+            };
+            let relative = relative
+                .to_string_lossy()
+                .replace(path::MAIN_SEPARATOR, "/");
+            for section in file
+                .sections
+                .iter()
+                .filter(|s| s.pattern.is_match(&relative))
+            {
+                for (key, value) in &section.properties {
+                    properties.set(key, value);
+                }
+            }
+        }
+
+        properties
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let flag = match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match key {
+            "indent_style" => {
+                self.expand_tab = match value {
+                    "space" => Some(true),
+                    "tab" => Some(false),
+                    _ => None,
+                }
+            }
+            // `tab` means the tab width, which is the default anyway
+            "indent_size" => self.indent_size = value.parse().ok().filter(|&n| n > 0),
+            "tab_width" => self.tab_width = value.parse().ok().filter(|&n| n > 0),
+            "end_of_line" => {
+                self.line_ending = match value {
+                    "lf" => Some(LineEnding::Lf),
+                    "crlf" => Some(LineEnding::Crlf),
+                    // Lone CR isn't supported
+                    _ => None,
+                }
+            }
+            "charset" => {
+                self.encoding = match value {
+                    "utf-8" => Some(Encoding::Utf8),
+                    "utf-8-bom" => Some(Encoding::Utf8Bom),
+                    "latin1" => Some(Encoding::Latin1),
+                    _ => None,
+                }
+            }
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag,
+            "insert_final_newline" => self.insert_final_newline = flag,
+            _ => (),
+        }
+    }
+
+    /// Overrides the settings from the user's config with the project's.
+    pub fn apply(&self, settings: &mut BufferSettings) {
+        if let Some(expand_tab) = self.expand_tab {
+            settings.expand_tab = expand_tab;
+        }
+
+        let width = if settings.expand_tab {
+            self.indent_size.or(self.tab_width)
+        } else {
+            self.tab_width.or(self.indent_size)
+        };
+        if let Some(width) = width {
+            settings.tab_width = width;
+        }
+
+        if let Some(trim) = self.trim_trailing_whitespace {
+            settings.trim_trailing_whitespace = trim;
+        }
+    }
+}
+
+struct Section {
+    pattern: Regex,
+    /// Keys and values are lowercased, as the spec makes them case insensitive.
+    properties: Vec<(String, String)>,
+}
+
+struct File {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+impl File {
+    fn parse(text: &str) -> Self {
+        let mut file = Self {
+            root: false,
+            sections: Vec::new(),
+        };
+        // Properties of sections whose glob is invalid are dropped
+        let mut current: Option<&mut Section> = None;
+        let mut in_preamble = true;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_preamble = false;
+                current = None;
+                if let Some(pattern) = glob_to_regex(glob) {
+                    file.sections.push(Section {
+                        pattern,
+                        properties: Vec::new(),
+                    });
+                    current = file.sections.last_mut();
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_lowercase();
+
+            if in_preamble {
+                if key == "root" {
+                    file.root = value == "true";
+                }
+            } else if let Some(section) = current.as_mut() {
+                section.properties.push((key, value));
+            }
+        }
+
+        file
+    }
+}
+
+/// Translates an EditorConfig glob into a regex matching paths relative to the directory of the
+/// `.editorconfig` file.
+fn glob_to_regex(glob: &str) -> Option<Regex> {
+    let (prefix, glob) = match glob.strip_prefix('/') {
+        Some(glob) => ("", glob),
+        // Globs without a slash match in any subdirectory
+        None if !glob.contains('/') => ("(?:.*/)?", glob),
+        None => ("", glob),
+    };
+
+    let mut regex = format!("^{prefix}");
+    let mut chars = glob.chars().peekable();
+    let mut braces = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let class: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let class = match class.strip_prefix('!') {
+                    Some(rest) => format!("^{rest}"),
+                    None => class,
+                };
+                regex.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+            }
+            '{' => {
+                // Numeric ranges, e.g. {1..3}
+                let rest: String = chars.clone().take_while(|&c| c != '}').collect();
+                if let Some((start, end)) = rest.split_once("..") {
+                    let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) else {
+                        return None;
+                    };
+                    if end.saturating_sub(start) > 1000 {
+                        return None;
+                    }
+                    let numbers: Vec<String> = (start..=end).map(|n| n.to_string()).collect();
+                    regex.push_str(&format!("(?:{})", numbers.join("|")));
+                    chars.by_ref().take_while(|&c| c != '}').for_each(drop);
+                } else {
+                    braces += 1;
+                    regex.push_str("(?:");
+                }
+            }
+            ',' if braces > 0 => regex.push('|'),
+            '}' if braces > 0 => {
+                braces -= 1;
+                regex.push(')');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    regex.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+
+    Regex::new(&regex).ok()
+}
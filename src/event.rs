@@ -0,0 +1,140 @@
+use crate::git::{Blame, LineChange, RepoStatus};
+use crate::input::Chord;
+use crate::lint::Diagnostic;
+use crate::terminal::Size;
+use crate::Document;
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Set by the SIGWINCH and SIGCONT handlers: in both cases the screen needs a full redraw.
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_redraw_signal(_signal: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+pub enum Event {
+    Key(Chord),
+    /// The terminal was resized (or the process resumed), to this size.
+    Resize(Size),
+    /// Posted periodically, see `Events::start_timer`.
+    Tick,
+    /// A background save completed.
+    Saved,
+    /// A background load or save got `done` bytes through, out of `total`.
+    Progress {
+        done: u64,
+        total: u64,
+    },
+    /// A file was read in the background.
+    Loaded(Box<Result<Document, io::Error>>),
+    /// A background git diff completed.
+    GitChanges(Vec<Option<LineChange>>),
+    /// The git status of the document's repository was read, `None` outside repositories.
+    GitStatus(Option<RepoStatus>),
+    /// `git blame` of the current line completed.
+    Blame(Result<Blame, String>),
+    /// A background lint command completed.
+    Diagnostics(Vec<Diagnostic>),
+    /// The shell of the terminal panel printed something.
+    PanelOutput(Vec<u8>),
+    /// The shell of the terminal panel exited.
+    PanelClosed,
+    Mouse(Mouse),
+    /// The terminal window was focused, other programs may have changed files meanwhile.
+    FocusGained,
+    /// A background job finished, see `Jobs::spawn`.
+    Job(JobResult),
+}
+
+/// What the mouse did. Only wheel turns are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mouse {
+    Wheel { up: bool },
+}
+
+/// Posted by a background job when done.
+pub struct JobResult {
+    pub id: u64,
+    /// What the job found, `None` if it failed or was cancelled.
+    pub event: Option<Box<Event>>,
+}
+
+/// Queue of everything the editor reacts to: the terminal and background jobs post events
+/// through `sender`.
+pub struct Events {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+impl Events {
+    /// Installs the signal handlers.
+    pub fn init() -> Result<Self, io::Error> {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_redraw_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            for signal in [libc::SIGWINCH, libc::SIGCONT] {
+                if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        Ok(Self { sender, receiver })
+    }
+
+    /// For the terminal and background jobs to post events.
+    #[must_use]
+    pub fn sender(&self) -> Sender<Event> {
+        self.sender.clone()
+    }
+
+    /// Posts an `Event::Tick` every `interval`, for periodic work such as autosaving.
+    pub fn start_timer(&self, interval: Duration) {
+        let sender = self.sender();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if sender.send(Event::Tick).is_err() {
+                // The editor is gone
+                return;
+            }
+        });
+    }
+
+    /// Whether the terminal was resized (or the process resumed) since last asked. Signal
+    /// handlers can't query the new size, so they can't post an `Event::Resize` themselves.
+    #[must_use]
+    pub fn take_resize() -> bool {
+        RESIZED.swap(false, Ordering::Relaxed)
+    }
+
+    /// Waits for the next event, returning `None` if nothing happened by `deadline`.
+    /// # Errors
+    /// Never, while `self` holds a sender.
+    pub fn next(&self, deadline: Option<Instant>) -> Result<Option<Event>, io::Error> {
+        let received = match deadline {
+            Some(deadline) => self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => self
+                .receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input closed"))
+            }
+        }
+    }
+}
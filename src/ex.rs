@@ -0,0 +1,502 @@
+//! The `:` command line: a registry of named commands, and parsing of what's typed.
+
+use crate::fuzzy;
+use crate::keymap::Command;
+
+use std::cmp;
+use std::ops::Range;
+
+/// Commands that only exist on the command line, as opposed to keymap `Command`s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ex {
+    Write,
+    Quit,
+    WriteQuit,
+    Edit,
+    CloseBuffer,
+    Goto,
+    Sort,
+    Set,
+    SetGlobal,
+    Help,
+    Insert,
+    Date,
+    Snippet,
+    Read,
+    Source,
+    /// `:!command`, inserting the output of a shell command.
+    Shell,
+    Filter,
+    Substitute,
+    DeleteLines,
+    Indent,
+    Outdent,
+    /// `:g/REGEX/COMMAND`, running a command on matching lines.
+    Global,
+    /// `:v/REGEX/COMMAND`, running a command on lines that don't match.
+    InverseGlobal,
+    DeleteMatching,
+    KeepMatching,
+    Uniq,
+    Reverse,
+    Retab,
+    /// Any keymap command, by name.
+    Action(Command),
+}
+
+/// Name, accepted aliases, and summary of every command-line only command. Abbreviations resolve
+/// to the first command in this order with that prefix.
+const REGISTRY: &[(&str, &[&str], Ex, &str)] = &[
+    (
+        "write",
+        &["w"],
+        Ex::Write,
+        "save, optionally to another path",
+    ),
+    ("quit", &["q"], Ex::Quit, "quit, `!` to discard changes"),
+    ("wq", &["x", "exit"], Ex::WriteQuit, "save and quit"),
+    (
+        "edit",
+        &["e"],
+        Ex::Edit,
+        "open a file, or reload this one, `!` to discard changes",
+    ),
+    (
+        "bdelete",
+        &["bd"],
+        Ex::CloseBuffer,
+        "close the file, `!` to discard changes",
+    ),
+    (
+        "goto",
+        &[],
+        Ex::Goto,
+        "go to LINE[:COL] or PERCENT%, also `:<line>`",
+    ),
+    ("set", &["se"], Ex::Set, "change options for this file"),
+    (
+        "setglobal",
+        &["setg"],
+        Ex::SetGlobal,
+        "change options for all files",
+    ),
+    ("sort", &[], Ex::Sort, "sort lines, `!` to reverse"),
+    (
+        "retab",
+        &[],
+        Ex::Retab,
+        "redo indentation with tabs or spaces as `expandtab` says, `!` for all tabs",
+    ),
+    (
+        "reverse",
+        &[],
+        Ex::Reverse,
+        "reverse the order of lines (of the selection, a range or the file)",
+    ),
+    (
+        "uniq",
+        &[],
+        Ex::Uniq,
+        "remove repeated lines (of the selection, a range or the file), `i` ignores case and `w` indentation",
+    ),
+    (
+        "substitute",
+        &["s"],
+        Ex::Substitute,
+        "replace in the line, a range or `%` for the file: s/REGEX/TEXT/[gic]",
+    ),
+    (
+        "delete_lines",
+        &["d"],
+        Ex::DeleteLines,
+        "delete lines in a range, or a count of them from the cursor",
+    ),
+    (
+        "indent",
+        &[],
+        Ex::Indent,
+        "indent the line or a range by a level",
+    ),
+    (
+        "outdent",
+        &[],
+        Ex::Outdent,
+        "unindent the line or a range by a level",
+    ),
+    (
+        "global",
+        &["g"],
+        Ex::Global,
+        "run a command on lines matching a regex: g/REGEX/COMMAND",
+    ),
+    (
+        "vglobal",
+        &["v"],
+        Ex::InverseGlobal,
+        "run a command on lines not matching a regex: v/REGEX/COMMAND",
+    ),
+    (
+        "delete_matching",
+        &[],
+        Ex::DeleteMatching,
+        "delete the lines (of the file or a range) matching REGEX",
+    ),
+    (
+        "keep_matching",
+        &[],
+        Ex::KeepMatching,
+        "delete the lines (of the file or a range) not matching REGEX",
+    ),
+    (
+        "insert",
+        &[],
+        Ex::Insert,
+        r"insert text at the cursor, `\n` for newlines",
+    ),
+    (
+        "date",
+        &[],
+        Ex::Date,
+        "insert the date, optionally in a strftime FORMAT",
+    ),
+    (
+        "snippet",
+        &[],
+        Ex::Snippet,
+        "insert a snippet from the config's [snippets], by name",
+    ),
+    (
+        "read",
+        &["r"],
+        Ex::Read,
+        "insert the lines of a file below the cursor",
+    ),
+    ("source", &["so"], Ex::Source, "run a script"),
+    (
+        "!",
+        &[],
+        Ex::Shell,
+        "run a shell command, inserting its output",
+    ),
+    (
+        "filter",
+        &[],
+        Ex::Filter,
+        "pipe the selected lines or the file through a command",
+    ),
+    ("help", &["h"], Ex::Help, "list commands"),
+];
+
+/// A line number in a range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Address {
+    /// 1-based, as typed.
+    Line(usize),
+    /// `.`, the cursor's line.
+    Current,
+    /// `$`, the last line.
+    Last,
+}
+
+/// Lines typed before a command's name, for it to apply to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineRange {
+    /// `%`
+    WholeFile,
+    /// `N,M`, or a single address.
+    Lines(Address, Address),
+}
+
+impl LineRange {
+    /// Rows covered in a document of `len` rows with the cursor on row `current`. Reversed
+    /// ranges are swapped, and lines past the end are ignored.
+    #[must_use]
+    pub fn rows(self, current: usize, len: usize) -> Range<usize> {
+        let row = |address: Address| match address {
+            Address::Line(n) => n.saturating_sub(1),
+            Address::Current => current,
+            Address::Last => len.saturating_sub(1),
+        };
+        match self {
+            Self::WholeFile => 0..len,
+            Self::Lines(start, end) => {
+                let (start, end) = (row(start), row(end));
+                cmp::min(cmp::min(start, end), len)..cmp::min(cmp::max(start, end) + 1, len)
+            }
+        }
+    }
+}
+
+/// A parsed command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invocation {
+    pub ex: Ex,
+    /// Whether the name was followed by `!`.
+    pub bang: bool,
+    pub args: String,
+    /// Lines typed before the name, as in `%s/a/b/` or `2,5d`.
+    pub range: Option<LineRange>,
+}
+
+/// Every name the command line accepts, registry commands first.
+#[must_use]
+pub fn names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = REGISTRY.iter().map(|&(name, _, _, _)| name).collect();
+    for &(_, name) in Command::ALL {
+        // e.g. `quit`, which the registry overrides to check for changes
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Every name of `names` with what it does, for the command palette. Only those bound to keys
+/// come with their `Command`, the others often taking arguments.
+#[must_use]
+pub fn palette() -> Vec<(&'static str, Option<Command>, &'static str)> {
+    names()
+        .into_iter()
+        .map(|name| match REGISTRY.iter().find(|entry| entry.0 == name) {
+            Some(&(_, _, _, summary)) => (name, None, summary),
+            None => {
+                let command = name.parse::<Command>().ok();
+                let description = command.and_then(Command::description);
+                (name, command, description.unwrap_or_default())
+            }
+        })
+        .collect()
+}
+
+/// Summary of the command-line only commands.
+#[must_use]
+pub fn help() -> String {
+    REGISTRY
+        .iter()
+        .map(|(name, _, _, summary)| format!(":{name}: {summary}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Best matching names for a partially typed command line, for display while typing.
+#[must_use]
+pub fn candidates(line: &str, max: usize) -> Vec<&'static str> {
+    let name = split(line).0;
+    if name.is_empty() {
+        return Vec::new();
+    }
+    fuzzy::filter(name, &names())
+        .into_iter()
+        .take(max)
+        .copied()
+        .collect()
+}
+
+/// # Errors
+/// If no command matches the name, or the range is invalid.
+pub fn parse(line: &str) -> Result<Invocation, String> {
+    let line = line.trim_start();
+    let (range, line) = parse_range(line)?;
+    let (name, bang, args) = split(line);
+
+    if name.is_empty() && bang {
+        return Ok(Invocation {
+            ex: Ex::Shell,
+            bang: false,
+            args: args.into(),
+            range,
+        });
+    }
+
+    if name.is_empty() {
+        // `:<line>` and `:$` go to the line
+        let location = match range {
+            Some(LineRange::Lines(Address::Line(n), end)) if end == Address::Line(n) => {
+                n.to_string()
+            }
+            Some(LineRange::Lines(Address::Last, Address::Last)) => "100%".into(),
+            _ => return Err("Missing command after the range".into()),
+        };
+        return Ok(Invocation {
+            ex: Ex::Goto,
+            bang,
+            args: location,
+            range: None,
+        });
+    }
+
+    let ex = resolve(name).ok_or_else(|| unknown(name))?;
+    Ok(Invocation {
+        ex,
+        bang,
+        args: args.into(),
+        range,
+    })
+}
+
+/// Converts a substitution's replacement to the syntax of the regex crate: Vim-style `\1` group
+/// references and `&` for the whole match are expanded, `\&`, `\\` and `$` are literal.
+#[must_use]
+pub fn replacement(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&digit)) if digit.is_ascii_digit() => {
+                chars.next();
+                converted.push_str(&format!("${{{digit}}}"));
+            }
+            ('\\', Some(&escaped @ ('\\' | '&'))) => {
+                chars.next();
+                converted.push(escaped);
+            }
+            ('&', _) => converted.push_str("${0}"),
+            ('$', _) => converted.push_str("$$"),
+            _ => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Splits `%`, `N,M` or a single address off the start of a command line.
+fn parse_range(line: &str) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = line.strip_prefix('%') {
+        return Ok((Some(LineRange::WholeFile), rest));
+    }
+    let Some((start, rest)) = address(line) else {
+        return Ok((None, line));
+    };
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => address(rest).ok_or_else(|| format!("Invalid range: {line}"))?,
+        None => (start, rest),
+    };
+    Ok((Some(LineRange::Lines(start, end)), rest))
+}
+
+/// Splits a line number, `.` or `$` off the start of `text`.
+fn address(text: &str) -> Option<(Address, &str)> {
+    if let Some(rest) = text.strip_prefix('.') {
+        return Some((Address::Current, rest));
+    }
+    if let Some(rest) = text.strip_prefix('$') {
+        return Some((Address::Last, rest));
+    }
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let line = text[..end].parse().ok()?;
+    Some((Address::Line(line), &text[end..]))
+}
+
+/// Splits a command line into name, bang and arguments.
+fn split(line: &str) -> (&str, bool, &str) {
+    let line = line.trim_start();
+    let end = line
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    let (bang, rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    (name, bang, rest.trim())
+}
+
+/// Exact names and aliases first, then abbreviations of a single command.
+fn resolve(name: &str) -> Option<Ex> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let registry = REGISTRY
+        .iter()
+        .find(|(full, aliases, _, _)| *full == name || aliases.contains(&name))
+        .map(|&(_, _, ex, _)| ex);
+    let action = || name.parse().ok().map(Ex::Action);
+    let prefix = || match prefixed(name).as_slice() {
+        [full] => resolve(full),
+        _ => None,
+    };
+
+    registry.or_else(action).or_else(prefix)
+}
+
+/// Names starting with `prefix`.
+fn prefixed(prefix: &str) -> Vec<&'static str> {
+    names()
+        .into_iter()
+        .filter(|full| full.starts_with(prefix))
+        .collect()
+}
+
+/// The error for `name`, which `resolve` doesn't know, suggesting what may have been meant.
+fn unknown(name: &str) -> String {
+    let candidates = prefixed(name);
+    if candidates.len() > 1 {
+        return format!("Ambiguous command: {name} ({})", candidates.join(", "));
+    }
+    match fuzzy::filter(name, &names()).first() {
+        Some(suggestion) => format!("Not a command: {name}, did you mean {suggestion}?"),
+        None => format!("Not a command: {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_aliases_and_unique_abbreviations_resolve() {
+        assert_eq!(resolve("quit"), Some(Ex::Quit));
+        assert_eq!(resolve("q"), Some(Ex::Quit));
+        assert_eq!(resolve("sor"), Some(Ex::Sort));
+        assert_eq!(resolve("sourc"), Some(Ex::Source));
+    }
+
+    #[test]
+    fn replacements_expand_groups_and_keep_dollars() {
+        assert_eq!(replacement(r"\2, \1"), "${2}, ${1}");
+        assert_eq!(replacement("[&]"), "[${0}]");
+        assert_eq!(replacement(r"\& \\ $1"), r"& \ $$1");
+    }
+
+    #[test]
+    fn ranges_are_split_off_the_command() {
+        use Address::{Current, Last, Line};
+        assert_eq!(
+            parse_range("%s/a/b/"),
+            Ok((Some(LineRange::WholeFile), "s/a/b/"))
+        );
+        assert_eq!(
+            parse_range("2,$d"),
+            Ok((Some(LineRange::Lines(Line(2), Last)), "d"))
+        );
+        assert_eq!(
+            parse_range(".sort"),
+            Ok((Some(LineRange::Lines(Current, Current)), "sort"))
+        );
+        assert_eq!(parse_range("sort"), Ok((None, "sort")));
+        assert!(parse_range("2,x").is_err());
+    }
+
+    #[test]
+    fn ranges_cover_rows_in_the_document() {
+        use Address::{Current, Last, Line};
+        assert_eq!(LineRange::WholeFile.rows(3, 10), 0..10);
+        assert_eq!(LineRange::Lines(Line(2), Last).rows(0, 10), 1..10);
+        // Reversed, and past the end
+        assert_eq!(LineRange::Lines(Line(5), Current).rows(1, 10), 1..5);
+        assert_eq!(LineRange::Lines(Line(8), Line(20)).rows(0, 10), 7..10);
+        assert_eq!(LineRange::Lines(Line(0), Line(0)).rows(0, 0), 0..0);
+    }
+
+    #[test]
+    fn typos_are_only_suggested() {
+        assert_eq!(resolve("srt"), None);
+        assert_eq!(
+            parse("srt").unwrap_err(),
+            "Not a command: srt, did you mean sort?"
+        );
+        assert_eq!(parse("zzz").unwrap_err(), "Not a command: zzz");
+    }
+}
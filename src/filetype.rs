@@ -0,0 +1,113 @@
+use std::fmt;
+use std::path::Path;
+
+/// Name, extensions and exact file names that identify the file type, and line comment prefix.
+type Definition = (
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+    Option<&'static str>,
+);
+
+/// Known file types.
+const FILE_TYPES: &[Definition] = &[
+    ("rust", &["rs"], &[], Some("//")),
+    ("python", &["py", "pyw"], &[], Some("#")),
+    ("c", &["c", "h"], &[], Some("//")),
+    (
+        "cpp",
+        &["cpp", "cc", "cxx", "hpp", "hh", "hxx"],
+        &[],
+        Some("//"),
+    ),
+    ("go", &["go"], &[], Some("//")),
+    ("java", &["java"], &[], Some("//")),
+    ("javascript", &["js", "mjs", "cjs", "jsx"], &[], Some("//")),
+    ("typescript", &["ts", "tsx"], &[], Some("//")),
+    ("html", &["html", "htm"], &[], None),
+    ("css", &["css"], &[], None),
+    ("json", &["json"], &[], None),
+    ("toml", &["toml"], &["Cargo.lock"], Some("#")),
+    ("yaml", &["yml", "yaml"], &[], Some("#")),
+    ("markdown", &["md", "markdown"], &[], None),
+    (
+        "shell",
+        &["sh", "bash", "zsh"],
+        &[".bashrc", ".zshrc", ".profile"],
+        Some("#"),
+    ),
+    (
+        "make",
+        &["mk"],
+        &["Makefile", "makefile", "GNUmakefile"],
+        Some("#"),
+    ),
+    ("dockerfile", &[], &["Dockerfile"], Some("#")),
+    ("gitcommit", &[], &["COMMIT_EDITMSG"], Some("#")),
+];
+
+/// The kind of content of a document, as detected from its path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileType {
+    name: &'static str,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self { name: "text" }
+    }
+}
+
+impl FileType {
+    /// Detects the file type from the file name or extension, defaulting to plain text.
+    #[must_use]
+    pub fn detect(path: &Path) -> Self {
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+
+        FILE_TYPES
+            .iter()
+            .find(|(_, extensions, file_names, _)| {
+                file_names.contains(&file_name) || extensions.contains(&extension)
+            })
+            .map_or_else(Self::default, |&(name, _, _, _)| Self { name })
+    }
+
+    /// The known file type called `name`, e.g. `rust`.
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<Self> {
+        if name == Self::default().name {
+            return Some(Self::default());
+        }
+        FILE_TYPES
+            .iter()
+            .find(|&&(known, _, _, _)| known == name)
+            .map(|&(name, _, _, _)| Self { name })
+    }
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    /// Prefix of line comments, if the language has them.
+    #[must_use]
+    pub fn comment(self) -> Option<&'static str> {
+        FILE_TYPES
+            .iter()
+            .find(|&&(name, _, _, _)| name == self.name)
+            .and_then(|&(_, _, _, comment)| comment)
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
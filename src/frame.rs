@@ -0,0 +1,133 @@
+use crate::terminal::RgbColor;
+
+use std::fmt;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Colors of a span of text, `None` meaning the terminal's default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<RgbColor>,
+    pub bg: Option<RgbColor>,
+    pub underline: bool,
+    pub bold: bool,
+}
+
+impl Style {
+    #[must_use]
+    pub fn fg(mut self, color: RgbColor) -> Self {
+        self.fg = Some(color);
+        self
+    }
+
+    #[must_use]
+    pub fn bg(mut self, color: RgbColor) -> Self {
+        self.bg = Some(color);
+        self
+    }
+
+    #[must_use]
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    #[must_use]
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+}
+
+/// A styled line of the screen.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Line {
+    spans: Vec<(Style, String)>,
+    /// Length in graphemes, assuming every grapheme takes up one column.
+    len: usize,
+}
+
+impl Line {
+    /// Appends `text`, merging it with the previous span if the style is the same.
+    pub fn push(&mut self, style: Style, text: &str) {
+        self.len += text.graphemes(true).count();
+        match self.spans.last_mut() {
+            Some((last_style, last_text)) if *last_style == style => last_text.push_str(text),
+            _ => self.spans.push((style, text.into())),
+        }
+    }
+
+    /// Appends the spans of `other`.
+    pub fn append(&mut self, other: &Self) {
+        for (style, text) in other.spans() {
+            self.push(style, text);
+        }
+    }
+
+    /// The columns of `range` that the line covers, with their styles.
+    #[must_use]
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        let mut line = Self::default();
+        let graphemes = self
+            .spans()
+            .flat_map(|(style, text)| text.graphemes(true).map(move |g| (style, g)));
+        for (style, grapheme) in graphemes.skip(range.start).take(range.len()) {
+            line.push(style, grapheme);
+        }
+        line
+    }
+
+    /// Replaces the columns from `x` on with `other`, padding the line with spaces first if it
+    /// is shorter.
+    pub fn overlay(&mut self, x: usize, other: &Self) {
+        let mut line = self.slice(0..x);
+        line.pad(Style::default(), x);
+        line.append(other);
+        line.append(&self.slice(x + other.len()..self.len));
+        *self = line;
+    }
+
+    /// Pads the line with spaces up to `width` columns.
+    pub fn pad(&mut self, style: Style, width: usize) {
+        if width > self.len {
+            self.push(style, &" ".repeat(width - self.len));
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn spans(&self) -> impl Iterator<Item = (Style, &str)> {
+        self.spans
+            .iter()
+            .map(|(style, text)| (*style, text.as_str()))
+    }
+}
+
+/// The text of the line, without styles.
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.spans
+            .iter()
+            .try_for_each(|(_, text)| f.write_str(text))
+    }
+}
+
+impl From<&str> for Line {
+    fn from(text: &str) -> Self {
+        let mut line = Self::default();
+        line.push(Style::default(), text);
+        line
+    }
+}
+
+/// The whole screen, top to bottom.
+pub type Frame = Vec<Line>;
@@ -0,0 +1,48 @@
+/// Scores how well `pattern` matches `candidate` as a case-insensitive subsequence, `None` if it
+/// doesn't. Consecutive matches and matches at word starts score higher, so `sa` prefers
+/// `save_as` over `search`.
+#[must_use]
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut candidate_chars = candidate.chars().enumerate().peekable();
+    let mut previous: Option<(usize, char)> = None;
+
+    for p in pattern.chars().map(|c| c.to_ascii_lowercase()) {
+        let (idx, c) = loop {
+            let (idx, c) = candidate_chars.next()?;
+            if c.to_ascii_lowercase() == p {
+                break (idx, c);
+            }
+        };
+
+        score += 1;
+        match previous {
+            Some((prev_idx, _)) if prev_idx + 1 == idx => score += 5,
+            _ => (),
+        }
+        let word_start = idx == 0
+            || candidate
+                .chars()
+                .nth(idx - 1)
+                .is_some_and(|b| !b.is_alphanumeric() || (b.is_lowercase() && c.is_uppercase()));
+        if word_start {
+            score += 8;
+        }
+        previous = Some((idx, c));
+    }
+
+    // Prefer shorter candidates among equal matches
+    Some(score * 100 - candidate.chars().count() as i64)
+}
+
+/// Sorts `candidates` matching `pattern` from best to worst, dropping those that don't match.
+#[must_use]
+pub fn filter<'a, T: AsRef<str>>(pattern: &str, candidates: &'a [T]) -> Vec<&'a T> {
+    let mut scored: Vec<(i64, &T)> = candidates
+        .iter()
+        .filter_map(|c| Some((score(pattern, c.as_ref())?, c)))
+        .collect();
+    // Stable, so ties keep their original order
+    scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    scored.into_iter().map(|(_, c)| c).collect()
+}
@@ -0,0 +1,323 @@
+use crate::diff::{self, DiffOp};
+use crate::jobs::{self, CancelToken};
+
+use std::cmp;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// How a line of the buffer differs from the version in the git index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// One or more lines were deleted right before this one.
+    Deleted,
+}
+
+impl LineChange {
+    #[must_use]
+    pub fn marker(self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Modified => '~',
+            Self::Deleted => '-',
+        }
+    }
+}
+
+/// Branch and working tree state of a repository.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoStatus {
+    /// Branch name, or abbreviated commit hash if `HEAD` is detached.
+    pub head: String,
+    /// Commits ahead of and behind the upstream branch.
+    pub ahead: usize,
+    pub behind: usize,
+    /// Whether tracked files have uncommitted changes.
+    pub dirty: bool,
+}
+
+impl RepoStatus {
+    /// Parses the output of `git status --porcelain=v2 --branch`.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut status = Self::default();
+        let mut oid = None;
+        for line in output.lines() {
+            match line.strip_prefix("# ") {
+                Some(header) => {
+                    let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+                    match key {
+                        "branch.oid" => oid = Some(value),
+                        "branch.head" => status.head = value.into(),
+                        "branch.ab" => {
+                            for count in value.split(' ') {
+                                if let Some(n) = count.strip_prefix('+') {
+                                    status.ahead = n.parse().ok()?;
+                                } else if let Some(n) = count.strip_prefix('-') {
+                                    status.behind = n.parse().ok()?;
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                None => status.dirty |= !line.is_empty(),
+            }
+        }
+
+        if status.head == "(detached)" {
+            status.head = oid?.chars().take(7).collect();
+        }
+        (!status.head.is_empty()).then_some(status)
+    }
+}
+
+impl fmt::Display for RepoStatus {
+    /// E.g. `main↑1↓2*`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.head)?;
+        if self.ahead > 0 {
+            write!(f, "↑{}", self.ahead)?;
+        }
+        if self.behind > 0 {
+            write!(f, "↓{}", self.behind)?;
+        }
+        if self.dirty {
+            f.write_str("*")?;
+        }
+        Ok(())
+    }
+}
+
+/// `git -C <directory of path>`, with no input or error output. Without optional locks, for
+/// the commands run in the background not to get in the way of the user's, nor leave the index
+/// locked when killed.
+fn git_command(path: &Path) -> Command {
+    git_command_in(path.parent().unwrap_or(Path::new("")))
+}
+
+/// `git -C dir`, like `git_command`.
+fn git_command_in(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("--no-optional-locks");
+    if !dir.as_os_str().is_empty() {
+        command.arg("-C").arg(dir);
+    }
+    command.stdin(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+/// Files under the directory `dir` that git doesn't ignore, tracked or not, relative to it and
+/// unsorted. `None` if `dir` isn't in a repository.
+#[must_use]
+pub fn files(dir: &Path) -> Option<Vec<String>> {
+    let output = git_command_in(dir)
+        .args([
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let files = String::from_utf8_lossy(&output.stdout)
+        .split_terminator('\0')
+        // Tracked files deleted from the working tree are still listed
+        .filter(|file| dir.join(file).is_file())
+        .map(String::from)
+        .collect();
+    Some(files)
+}
+
+/// Status of the repository containing `path`, `None` if it isn't in one or `token` is
+/// cancelled.
+#[must_use]
+pub fn repo_status(path: &Path, token: &CancelToken) -> Option<RepoStatus> {
+    let output = jobs::output(
+        git_command(path).args([
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "--untracked-files=no",
+        ]),
+        token,
+    )?;
+    if !output.status.success() {
+        return None;
+    }
+    RepoStatus::parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Last commit that changed a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Blame {
+    /// Abbreviated hash, `None` if the line isn't committed yet.
+    pub commit: Option<String>,
+    pub author: String,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    pub summary: String,
+}
+
+impl Blame {
+    /// Parses the output of `git blame --porcelain` for a single line.
+    #[must_use]
+    pub fn parse(output: &str) -> Option<Self> {
+        let mut lines = output.lines();
+        let hash = lines.next()?.split(' ').next()?;
+        let mut blame = Self {
+            commit: (!hash.chars().all(|c| c == '0')).then(|| hash.chars().take(7).collect()),
+            author: String::new(),
+            time: 0,
+            summary: String::new(),
+        };
+        for line in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "author" => blame.author = value.into(),
+                "author-time" => blame.time = value.parse().ok()?,
+                "summary" => blame.summary = value.into(),
+                _ => (),
+            }
+        }
+        Some(blame)
+    }
+}
+
+impl fmt::Display for Blame {
+    /// E.g. `1a2b3c4 Jane Doe 2024-05-17: Fix typo`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.commit {
+            Some(commit) => {
+                let (year, month, day) = civil_date(self.time);
+                write!(
+                    f,
+                    "{commit} {} {year}-{month:02}-{day:02}: {}",
+                    self.author, self.summary
+                )
+            }
+            None => f.write_str("Not committed yet"),
+        }
+    }
+}
+
+/// Converts a Unix timestamp to a UTC (year, month, day), see
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_date(timestamp: i64) -> (i64, u32, u32) {
+    let days = timestamp.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Blames line `line` (1-based) of `path`, whose current content is `content`.
+///
+/// # Errors
+/// If git can't be run, or fails (e.g. if the file isn't tracked), with its error message.
+pub fn blame(path: &Path, line: usize, content: &str) -> Result<Blame, String> {
+    let file_name = path.file_name().ok_or("no file name")?;
+    let mut child = git_command(path)
+        .arg("blame")
+        .arg("--porcelain")
+        .arg(format!("-L{line},{line}"))
+        .args(["--contents", "-", "--"])
+        .arg(file_name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("can't run git: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // git exits early on errors, which it reports below
+        let _ = stdin.write_all(content.as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(error.lines().next().unwrap_or("git blame failed").into());
+    }
+    Blame::parse(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| "unexpected git output".into())
+}
+
+/// Reads the content of `path` as staged in the git index.
+///
+/// Returns `None` if the file isn't in a repository or isn't tracked, or if `token` is cancelled.
+#[must_use]
+pub fn index_content(path: &Path, token: &CancelToken) -> Option<String> {
+    let file_name = path.file_name()?;
+    let output = jobs::output(
+        git_command(path)
+            .arg("show")
+            .arg(format!(":./{}", file_name.to_string_lossy())),
+        token,
+    )?;
+
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        None
+    }
+}
+
+/// Computes per-line change markers for `lines` relative to `base`.
+#[must_use]
+pub fn line_changes(base: &str, lines: &[String]) -> Vec<Option<LineChange>> {
+    let base: Vec<&str> = base.lines().collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+
+    let mut changes = vec![None; lines.len()];
+    let (mut deleted, mut inserted) = (0, 0);
+    let mut y = 0;
+
+    let mut flush_hunk = |y: usize, deleted: usize, inserted: usize| {
+        let hunk_start = y - inserted;
+        for (i, change) in changes[hunk_start..y].iter_mut().enumerate() {
+            *change = Some(if i < deleted {
+                LineChange::Modified
+            } else {
+                LineChange::Added
+            });
+        }
+        if inserted == 0 && deleted > 0 {
+            // Pure deletion: mark the following line, or the last one at end of file
+            let idx = cmp::min(y, changes.len().saturating_sub(1));
+            if let Some(change) = changes.get_mut(idx) {
+                *change = Some(LineChange::Deleted);
+            }
+        }
+    };
+
+    for op in diff::diff(&base, &lines) {
+        match op {
+            DiffOp::Equal => {
+                flush_hunk(y, deleted, inserted);
+                (deleted, inserted) = (0, 0);
+                y += 1;
+            }
+            DiffOp::Delete => deleted += 1,
+            DiffOp::Insert => {
+                inserted += 1;
+                y += 1;
+            }
+        }
+    }
+    flush_hunk(y, deleted, inserted);
+
+    changes
+}
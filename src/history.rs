@@ -0,0 +1,131 @@
+//! Previous answers to prompts, one list per kind of prompt, kept across sessions.
+//!
+//! The file has one entry per line, prefixed by the kind of prompt and a tab, oldest first.
+
+use crate::EditorConfig;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Entries kept per kind of prompt, older ones are dropped.
+pub const MAX_ENTRIES: usize = 100;
+
+/// What a prompt asks for. Each kind has its own history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PromptKind {
+    /// Command lines, including shell commands.
+    Command,
+    Search,
+    /// File names, as in "Save as".
+    Path,
+    /// Replacement words for spelling suggestions.
+    Replacement,
+    /// Line numbers to go to.
+    Line,
+    /// Names of snippets to insert.
+    Snippet,
+    /// Files opened, listed by the recent files picker.
+    File,
+}
+
+impl PromptKind {
+    const ALL: [Self; 7] = [
+        Self::Command,
+        Self::Search,
+        Self::Path,
+        Self::Replacement,
+        Self::Line,
+        Self::Snippet,
+        Self::File,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Search => "search",
+            Self::Path => "path",
+            Self::Replacement => "replacement",
+            Self::Line => "line",
+            Self::Snippet => "snippet",
+            Self::File => "file",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    entries: HashMap<PromptKind, Vec<String>>,
+}
+
+impl History {
+    /// `$XDG_STATE_HOME/hecto/history`, falling back to `~/.local/state/hecto/history`.
+    #[must_use]
+    pub fn path() -> Option<PathBuf> {
+        EditorConfig::state_dir().map(|dir| dir.join("history"))
+    }
+
+    /// Reads the history saved at `path`, empty if there is none yet. Lines of unknown kinds
+    /// are skipped.
+    ///
+    /// # Errors
+    /// If the file exists but can't be read.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let mut history = Self::default();
+        for line in text.lines() {
+            let Some((name, entry)) = line.split_once('\t') else {
+                continue;
+            };
+            if let Some(kind) = PromptKind::ALL.into_iter().find(|k| k.name() == name) {
+                history.add(kind, entry);
+            }
+        }
+        Ok(history)
+    }
+
+    /// Writes the history to `path`, creating its directory if needed.
+    ///
+    /// # Errors
+    /// If the directory can't be created or the file can't be written.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        for kind in PromptKind::ALL {
+            for entry in self.entries(kind) {
+                text.push_str(kind.name());
+                text.push('\t');
+                text.push_str(entry);
+                text.push('\n');
+            }
+        }
+        fs::write(path, text)
+    }
+
+    /// Records `entry` as the most recent one of `kind`, moving it there if it was already
+    /// recorded.
+    pub fn add(&mut self, kind: PromptKind, entry: &str) {
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        let entries = self.entries.entry(kind).or_default();
+        entries.retain(|e| e != entry);
+        entries.push(entry.into());
+        if entries.len() > MAX_ENTRIES {
+            entries.remove(0);
+        }
+    }
+
+    /// Entries of `kind`, oldest first.
+    #[must_use]
+    pub fn entries(&self, kind: PromptKind) -> &[String] {
+        self.entries.get(&kind).map_or(&[], Vec::as_slice)
+    }
+}
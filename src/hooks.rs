@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Points in the editor's lifecycle that handlers can subscribe to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// A document was opened, or the editor started on an empty one.
+    Open,
+    BeforeSave,
+    AfterSave,
+    /// A character was typed.
+    Insert,
+    ModeChange,
+}
+
+impl Hook {
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::Open, "on_open"),
+        (Self::BeforeSave, "before_save"),
+        (Self::AfterSave, "after_save"),
+        (Self::Insert, "on_insert"),
+        (Self::ModeChange, "on_mode_change"),
+    ];
+}
+
+impl FromStr for Hook {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|&&(_, name)| name == s)
+            .map(|&(hook, _)| hook)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::NAMES.iter().map(|&(_, name)| name).collect();
+                format!("unknown hook `{s}` (available: {})", names.join(", "))
+            })
+    }
+}
+
+/// Something to run when a hook fires, on a `T` (the editor).
+pub enum Handler<T> {
+    /// Part of the editor itself.
+    Builtin(fn(&mut T)),
+    /// Registered by a script.
+    CommandLine(String),
+}
+
+// Derives would require `T: Clone`
+impl<T> Clone for Handler<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Builtin(f) => Self::Builtin(*f),
+            Self::CommandLine(line) => Self::CommandLine(line.clone()),
+        }
+    }
+}
+
+/// Handlers by hook, run in registration order.
+pub struct Hooks<T> {
+    handlers: HashMap<Hook, Vec<Handler<T>>>,
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Hooks<T> {
+    pub fn register(&mut self, hook: Hook, handler: Handler<T>) {
+        self.handlers.entry(hook).or_default().push(handler);
+    }
+
+    /// A copy of the handlers for `hook`, so they can be run on the value owning `self`.
+    #[must_use]
+    pub fn handlers(&self, hook: Hook) -> Vec<Handler<T>> {
+        self.handlers.get(&hook).cloned().unwrap_or_default()
+    }
+}
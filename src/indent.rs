@@ -0,0 +1,102 @@
+//! Guessing the indentation style of a file from its leading whitespace, for edits to match it.
+
+/// Lines looked at, enough to make up one's mind without slowing down opening large files.
+const MAX_LINES: usize = 10_000;
+/// Widths a space indent is guessed among.
+const WIDTHS: [usize; 4] = [2, 3, 4, 8];
+
+/// How a file is indented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indentation {
+    Tabs,
+    /// By this many spaces per level.
+    Spaces(usize),
+}
+
+/// Guesses the indentation of `lines`, `None` if too few of them are indented to tell.
+#[must_use]
+pub fn detect<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Indentation> {
+    let mut tabs = 0;
+    let mut spaces = 0;
+    // Lines indented by one more level than the previous one, for each width
+    let mut steps = [0; WIDTHS.len()];
+    let mut previous = 0;
+
+    for line in lines.into_iter().take(MAX_LINES) {
+        let rest = line.trim_start_matches(' ');
+        let width = line.len() - rest.len();
+        if rest.trim().is_empty() {
+            // Blank lines don't tell anything, nor break a nesting
+            continue;
+        }
+        if rest.starts_with('\t') {
+            if width == 0 {
+                tabs += 1;
+            }
+            previous = 0;
+            continue;
+        }
+        // Continuation of a block comment, aligned on its `/*`
+        if rest.starts_with('*') && width % 2 == 1 {
+            continue;
+        }
+        if width > 0 {
+            spaces += 1;
+        }
+        if width > previous {
+            if let Some(idx) = WIDTHS.iter().position(|&w| w == width - previous) {
+                steps[idx] += 1;
+            }
+        }
+        previous = width;
+    }
+
+    if tabs == 0 && spaces == 0 {
+        return None;
+    }
+    if tabs >= spaces {
+        return Some(Indentation::Tabs);
+    }
+    // The smallest width at least half as common as the most common one, for files mostly
+    // nested by two levels at once not to be taken for a wider indent
+    let max = steps.iter().copied().max().unwrap_or_default();
+    if max == 0 {
+        return None;
+    }
+    WIDTHS
+        .iter()
+        .zip(steps)
+        .find(|&(_, count)| count * 2 >= max)
+        .map(|(&width, _)| Indentation::Spaces(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_in(text: &str) -> Option<Indentation> {
+        detect(text.lines())
+    }
+
+    #[test]
+    fn block_comment_continuations_are_skipped() {
+        let text = "/*\n * a\n * b\n * c\n */\nfn f() {\n    x();\n}\n";
+        assert_eq!(detect_in(text), Some(Indentation::Spaces(4)));
+    }
+
+    #[test]
+    fn ties_go_to_tabs() {
+        assert_eq!(detect_in("a\n\tb\nc\n    d\n"), Some(Indentation::Tabs));
+        assert_eq!(detect_in("a\nb\n"), None);
+    }
+
+    #[test]
+    fn narrower_widths_half_as_common_win() {
+        // Nested by 4 twice as often as by 2, which is still the indent
+        let text = "a\n  b\nc\n    d\ne\n    f\n";
+        assert_eq!(detect_in(text), Some(Indentation::Spaces(2)));
+        // Less than half as common
+        let text = "a\n  b\nc\n    d\ne\n    f\ng\n    h\n";
+        assert_eq!(detect_in(text), Some(Indentation::Spaces(4)));
+    }
+}
@@ -0,0 +1,536 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use termion::event::{self, Key};
+
+/// Modifier keys held along with a key, as reported by extended keyboard protocols.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl Modifiers {
+    #[must_use]
+    pub fn is_empty(self) -> bool {
+        self == Self::default()
+    }
+
+    /// Decodes the `1 + bitmask` modifier parameter of xterm and kitty sequences.
+    fn from_param(param: u32) -> Self {
+        let mask = param.saturating_sub(1);
+        Self {
+            shift: mask & 1 != 0,
+            alt: mask & 2 != 0,
+            ctrl: mask & 4 != 0,
+        }
+    }
+}
+
+/// A key press, along with modifiers `Key` itself can't express (e.g. `Ctrl-Shift-S`,
+/// `Alt-Up`).
+///
+/// Chords that termion can represent are normalized to their `Key` with no modifiers, so
+/// `Ctrl-Q` is always `Key::Ctrl('q')`, whichever protocol reported it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+impl From<Key> for Chord {
+    fn from(key: Key) -> Self {
+        Self {
+            key,
+            modifiers: Modifiers::default(),
+        }
+    }
+}
+
+impl Chord {
+    /// Builds a chord, folding modifiers into `key` where termion has an equivalent.
+    #[must_use]
+    pub fn new(key: Key, modifiers: Modifiers) -> Self {
+        let Modifiers { shift, alt, ctrl } = modifiers;
+        let key = match (key, shift, alt, ctrl) {
+            (key, false, false, false) => key,
+            (Key::Char(c), true, false, false) if c.is_ascii_alphabetic() => {
+                Key::Char(c.to_ascii_uppercase())
+            }
+            (Key::Char(c), false, false, true) if c.is_ascii_alphabetic() => {
+                Key::Ctrl(c.to_ascii_lowercase())
+            }
+            (Key::Char(c), false, true, false) => Key::Alt(c),
+            (Key::Char(c), true, true, false) if c.is_ascii_alphabetic() => {
+                Key::Alt(c.to_ascii_uppercase())
+            }
+            (key, _, _, _) => {
+                let key = match key {
+                    Key::Char(c) => Key::Char(c.to_ascii_lowercase()),
+                    key => key,
+                };
+                return Self { key, modifiers };
+            }
+        };
+        Self::from(key)
+    }
+}
+
+impl fmt::Display for Chord {
+    /// Vim-like notation, e.g. `<C-S-s>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut prefix = String::new();
+        if self.modifiers.ctrl {
+            prefix.push_str("C-");
+        }
+        if self.modifiers.alt {
+            prefix.push_str("A-");
+        }
+        if self.modifiers.shift {
+            prefix.push_str("S-");
+        }
+
+        let name = match self.key {
+            Key::Char('\n') => "CR".into(),
+            Key::Char('\t') => "Tab".into(),
+            Key::Char(' ') => "Space".into(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => return write!(f, "<{prefix}C-{c}>"),
+            Key::Alt(c) => return write!(f, "<{prefix}A-{c}>"),
+            Key::F(n) => format!("F{n}"),
+            key => format!("{key:?}"),
+        };
+
+        if prefix.is_empty() && name.chars().count() == 1 {
+            write!(f, "{name}")
+        } else {
+            write!(f, "<{prefix}{name}>")
+        }
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    /// Parses the notation used by `Display`, e.g. `x`, `<C-s>`, `<A-S-Up>` or `<F1>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key `{s}`");
+
+        let mut chars = s.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Key::Char(c).into());
+        }
+
+        let mut name = s
+            .strip_prefix('<')
+            .and_then(|s| s.strip_suffix('>'))
+            .ok_or_else(invalid)?;
+        let mut modifiers = Modifiers::default();
+        // The last dash may be the key itself, as in `<C-->`
+        while name.len() > 2 && name.as_bytes()[1] == b'-' {
+            match name.as_bytes()[0].to_ascii_uppercase() {
+                b'C' => modifiers.ctrl = true,
+                b'A' | b'M' => modifiers.alt = true,
+                b'S' => modifiers.shift = true,
+                _ => return Err(invalid()),
+            }
+            name = &name[2..];
+        }
+
+        let mut chars = name.chars();
+        let key = match (chars.next(), chars.next()) {
+            (Some(c), None) => {
+                // Legacy terminals report Ctrl-4..7 as control codes, which termion names this way
+                if modifiers
+                    == (Modifiers {
+                        ctrl: true,
+                        ..Modifiers::default()
+                    })
+                    && ('4'..='7').contains(&c)
+                {
+                    return Ok(Key::Ctrl(c).into());
+                }
+                Key::Char(c)
+            }
+            _ => match name.to_ascii_lowercase().as_str() {
+                "cr" | "enter" | "return" => Key::Char('\n'),
+                "tab" => Key::Char('\t'),
+                "space" => Key::Char(' '),
+                "esc" => Key::Esc,
+                "backspace" | "bs" => Key::Backspace,
+                "backtab" => Key::BackTab,
+                "delete" | "del" => Key::Delete,
+                "insert" | "ins" => Key::Insert,
+                "up" => Key::Up,
+                "down" => Key::Down,
+                "left" => Key::Left,
+                "right" => Key::Right,
+                "home" => Key::Home,
+                "end" => Key::End,
+                "pageup" => Key::PageUp,
+                "pagedown" => Key::PageDown,
+                f => match f.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=24) => Key::F(n),
+                    _ => return Err(invalid()),
+                },
+            },
+        };
+
+        Ok(Self::new(key, modifiers))
+    }
+}
+
+/// Parses a key sequence such as `gg`, `<Leader>fs` or `Space f s`, `<Leader>` standing for
+/// `leader`.
+///
+/// Whitespace separates chords, and is otherwise ignored: `<Space>` is a literal space. Capitalized
+/// words that name a key, like `Space`, `Leader` or `C-s`, are that key rather than a sequence of
+/// letters.
+///
+/// # Errors
+/// If a chord is invalid, or the sequence is empty.
+pub fn parse_sequence(s: &str, leader: Chord) -> Result<Vec<Chord>, String> {
+    let mut keys = Vec::new();
+    for word in s.split_whitespace() {
+        if word.len() > 1 && word.starts_with(|c: char| c.is_ascii_uppercase()) {
+            if word == "Leader" {
+                keys.push(leader);
+                continue;
+            }
+            if let Ok(chord) = format!("<{word}>").parse() {
+                keys.push(chord);
+                continue;
+            }
+        }
+
+        let mut rest = word;
+        while let Some(c) = rest.chars().next() {
+            let len = match rest.find('>') {
+                Some(end) if c == '<' && end > 1 => end + 1,
+                _ => c.len_utf8(),
+            };
+            let token = &rest[..len];
+            keys.push(if token.eq_ignore_ascii_case("<leader>") {
+                leader
+            } else {
+                token.parse()?
+            });
+            rest = &rest[len..];
+        }
+    }
+
+    if keys.is_empty() {
+        return Err("empty key sequence".into());
+    }
+    Ok(keys)
+}
+
+/// Formats a key sequence in the notation `parse_sequence` reads, e.g. `<Space>fs`.
+#[must_use]
+pub fn format_sequence(keys: &[Chord]) -> String {
+    keys.iter().map(Chord::to_string).collect()
+}
+
+/// Escape sequences enabling the kitty keyboard protocol (disambiguate escape codes) and xterm's
+/// modifyOtherKeys, so that chords like `Ctrl-Enter` are reported.
+pub const ENABLE_EXTENDED_KEYS: &str = "\x1b[>1u\x1b[>4;2m";
+pub const DISABLE_EXTENDED_KEYS: &str = "\x1b[<u\x1b[>4m";
+/// Escape sequences making the terminal report when it gains or loses focus.
+pub const ENABLE_FOCUS_EVENTS: &str = "\x1b[?1004h";
+pub const DISABLE_FOCUS_EVENTS: &str = "\x1b[?1004l";
+/// Escape sequences making the terminal report mouse buttons, in SGR encoding, for the wheel.
+/// Most terminals still select text with Shift held.
+pub const ENABLE_MOUSE_EVENTS: &str = "\x1b[?1000h\x1b[?1006h";
+pub const DISABLE_MOUSE_EVENTS: &str = "\x1b[?1006l\x1b[?1000l";
+
+/// What a `Decoder` reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Key(Chord),
+    /// The terminal window gained (`true`) or lost focus.
+    Focus(bool),
+    /// The mouse wheel was turned up (`true`) or down.
+    Wheel(bool),
+}
+
+/// Turns raw input bytes into chords, understanding both legacy escape sequences and the kitty
+/// keyboard protocol/modifyOtherKeys.
+#[derive(Default)]
+pub struct Decoder {
+    /// Bytes of an escape sequence split across reads.
+    pending: Vec<u8>,
+    inputs: VecDeque<Input>,
+}
+
+impl Decoder {
+    /// Decodes a chunk of input, as returned by a single `read`.
+    ///
+    /// A lone trailing escape byte is taken to be the Esc key, since escape sequences are sent in
+    /// one go by terminals.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let input = std::mem::take(&mut self.pending);
+
+        let mut i = 0;
+        while i < input.len() {
+            let Some(len) = sequence_len(&input[i..]) else {
+                // Incomplete sequence, wait for the rest
+                self.pending = input[i..].to_vec();
+                return;
+            };
+            if let Some(input) = parse(&input[i..i + len]) {
+                self.inputs.push_back(input);
+            }
+            i += len;
+        }
+    }
+
+    pub fn next_input(&mut self) -> Option<Input> {
+        self.inputs.pop_front()
+    }
+}
+
+/// Length of the sequence at the start of `bytes`, `None` if it is incomplete.
+fn sequence_len(bytes: &[u8]) -> Option<usize> {
+    match bytes {
+        [b'\x1b'] => Some(1),
+        [b'\x1b', b'[', b'[', ..] => (bytes.len() >= 4).then_some(4),
+        [b'\x1b', b'[', b'M', ..] => (bytes.len() >= 6).then_some(6),
+        [b'\x1b', b'[', rest @ ..] => rest
+            .iter()
+            .position(|b| (0x40..=0x7E).contains(b))
+            .map(|idx| idx + 3),
+        [b'\x1b', b'O', ..] => (bytes.len() >= 3).then_some(3),
+        [b'\x1b', rest @ ..] => utf8_len(rest).map(|len| len + 1),
+        _ => utf8_len(bytes),
+    }
+}
+
+fn utf8_len(bytes: &[u8]) -> Option<usize> {
+    let len = match bytes.first()? {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        // Invalid leading byte, skip it
+        _ => 1,
+    };
+    (bytes.len() >= len).then_some(len)
+}
+
+fn parse(seq: &[u8]) -> Option<Input> {
+    let chord = match seq {
+        [b'\x1b'] => Key::Esc.into(),
+        [b'\x1b', b'[', b'I'] => return Some(Input::Focus(true)),
+        [b'\x1b', b'[', b'O'] => return Some(Input::Focus(false)),
+        [b'\x1b', b'[', b'<', params @ .., b'M' | b'm'] => {
+            let params = std::str::from_utf8(params).ok()?;
+            return wheel(params.split(';').next()?.parse().ok()?);
+        }
+        // Legacy encoding, for terminals without SGR reports: the button is offset by 32
+        [b'\x1b', b'[', b'M', button, _, _] => return wheel(u32::from(*button).checked_sub(32)?),
+        [b'\x1b', b'[', params @ .., last] if params.first() != Some(&b'[') => {
+            parse_csi(params, *last)?
+        }
+        _ => {
+            let mut rest = seq[1..].iter().map(|&b| Ok(b));
+            match event::parse_event(seq[0], &mut rest).ok()? {
+                event::Event::Key(key) => key.into(),
+                _ => return None,
+            }
+        }
+    };
+    Some(Input::Key(chord))
+}
+
+/// Parses a CSI sequence, given its parameter bytes and final byte.
+fn parse_csi(params: &[u8], last: u8) -> Option<Chord> {
+    let params = std::str::from_utf8(params).ok()?;
+    // Kitty may add sub-parameters after colons, only the first one matters
+    let mut numbers = params
+        .split(';')
+        .map(|p| p.split(':').next().unwrap_or_default().parse::<u32>().ok());
+    let first = numbers.next().flatten();
+    let modifiers = Modifiers::from_param(numbers.next().flatten().unwrap_or(1));
+
+    let key = match last {
+        b'A' => Key::Up,
+        b'B' => Key::Down,
+        b'C' => Key::Right,
+        b'D' => Key::Left,
+        b'H' => Key::Home,
+        b'F' => Key::End,
+        b'Z' => Key::BackTab,
+        b'P'..=b'S' => Key::F(1 + last - b'P'),
+        // Kitty: CSI code ; modifiers u
+        b'u' => code_point_key(first?)?,
+        b'~' => match first? {
+            // modifyOtherKeys: CSI 27 ; modifiers ; code ~
+            27 => code_point_key(numbers.next().flatten()?)?,
+            1 | 7 => Key::Home,
+            2 => Key::Insert,
+            3 => Key::Delete,
+            4 | 8 => Key::End,
+            5 => Key::PageUp,
+            6 => Key::PageDown,
+            n @ 11..=15 => Key::F((n - 10) as u8),
+            n @ 17..=21 => Key::F((n - 11) as u8),
+            n @ 23..=24 => Key::F((n - 12) as u8),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(Chord::new(key, modifiers))
+}
+
+/// The wheel turn a mouse report's button code stands for, other buttons are ignored.
+fn wheel(button: u32) -> Option<Input> {
+    // Modifiers are added as bits 2 to 4
+    match button & !0b1_1100 {
+        64 => Some(Input::Wheel(true)),
+        65 => Some(Input::Wheel(false)),
+        _ => None,
+    }
+}
+
+fn code_point_key(code: u32) -> Option<Key> {
+    Some(match code {
+        9 => Key::Char('\t'),
+        13 => Key::Char('\n'),
+        27 => Key::Esc,
+        127 => Key::Backspace,
+        // Kitty's private use area codes for functional keys aren't supported
+        0xE000..=0xF8FF => return None,
+        code => Key::Char(char::from_u32(code)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs decoded from `chunks`, each one fed as a separate read.
+    fn decode(chunks: &[&[u8]]) -> Vec<Input> {
+        let mut decoder = Decoder::default();
+        let mut inputs = Vec::new();
+        for chunk in chunks {
+            decoder.feed(chunk);
+            inputs.extend(std::iter::from_fn(|| decoder.next_input()));
+        }
+        inputs
+    }
+
+    fn key(key: Key, shift: bool, alt: bool, ctrl: bool) -> Input {
+        Input::Key(Chord {
+            key,
+            modifiers: Modifiers { shift, alt, ctrl },
+        })
+    }
+
+    fn plain(k: Key) -> Input {
+        Input::Key(k.into())
+    }
+
+    #[test]
+    fn modifiers_are_decoded() {
+        // Kitty and modifyOtherKeys encodings of Ctrl-Shift-S
+        let ctrl_shift_s = key(Key::Char('s'), true, false, true);
+        assert_eq!(decode(&[b"\x1b[115;6u"]), [ctrl_shift_s]);
+        assert_eq!(decode(&[b"\x1b[27;6;115~"]), [ctrl_shift_s]);
+
+        let ctrl_enter = key(Key::Char('\n'), false, false, true);
+        assert_eq!(decode(&[b"\x1b[13;5u"]), [ctrl_enter]);
+        assert_eq!(decode(&[b"\x1b[27;5;13~"]), [ctrl_enter]);
+
+        assert_eq!(decode(&[b"\x1b[1;3A"]), [key(Key::Up, false, true, false)]);
+        assert_eq!(
+            decode(&[b"\x1b[1;3D"]),
+            [key(Key::Left, false, true, false)]
+        );
+        assert_eq!(
+            decode(&[b"\x1b[5;7~"]),
+            [key(Key::PageUp, false, true, true)]
+        );
+    }
+
+    #[test]
+    fn chords_termion_knows_are_normalized() {
+        assert_eq!(decode(&[b"\x1b[113;5u"]), [plain(Key::Ctrl('q'))]);
+        assert_eq!(decode(&[b"\x1b[97;3u"]), [plain(Key::Alt('a'))]);
+        assert_eq!(decode(&[b"\x1b[97;2u"]), [plain(Key::Char('A'))]);
+        // Kitty sub-parameters are ignored
+        assert_eq!(decode(&[b"\x1b[97:65;2u"]), [plain(Key::Char('A'))]);
+        assert_eq!(decode(&[b"\x1b[1;1B"]), [plain(Key::Down)]);
+    }
+
+    #[test]
+    fn legacy_sequences() {
+        assert_eq!(
+            decode(&[b"a\x1bb\x1bOP\x1b[3~\x1b[Z"]),
+            [
+                plain(Key::Char('a')),
+                plain(Key::Alt('b')),
+                plain(Key::F(1)),
+                plain(Key::Delete),
+                plain(Key::BackTab),
+            ]
+        );
+        assert_eq!(decode(&["é".as_bytes()]), [plain(Key::Char('é'))]);
+        assert_eq!(decode(&[b"\x1b"]), [plain(Key::Esc)]);
+        assert_eq!(
+            decode(&[b"\x1b[I\x1b[O"]),
+            [Input::Focus(true), Input::Focus(false)]
+        );
+    }
+
+    #[test]
+    fn sequences_split_across_reads() {
+        assert_eq!(
+            decode(&[b"x\x1b[1;", b"5", b"A"]),
+            [plain(Key::Char('x')), key(Key::Up, false, false, true)]
+        );
+        assert_eq!(
+            decode(&[b"\x1b[115;6", b"u"]),
+            [key(Key::Char('s'), true, false, true)]
+        );
+        assert_eq!(decode(&[&[0xC3], &[0xA9]]), [plain(Key::Char('é'))]);
+        assert_eq!(decode(&[b"\x1b[<65;1", b";1M"]), [Input::Wheel(false)]);
+        // Nothing comes out until the sequence is complete
+        assert_eq!(decode(&[b"\x1b[1;5"]), []);
+    }
+
+    #[test]
+    fn unknown_sequences_are_skipped() {
+        assert_eq!(decode(&[b"\x1b[99~a"]), [plain(Key::Char('a'))]);
+        assert_eq!(decode(&[b"\x1b[5Xa"]), [plain(Key::Char('a'))]);
+        // Kitty's functional keys in the private use area
+        assert_eq!(decode(&[b"\x1b[57399ua"]), [plain(Key::Char('a'))]);
+        // Invalid UTF-8 leading and continuation bytes
+        assert_eq!(decode(&[&[0xFF, b'a']]), [plain(Key::Char('a'))]);
+        assert_eq!(decode(&[&[0x80, b'a']]), [plain(Key::Char('a'))]);
+    }
+
+    #[test]
+    fn mouse_reports() {
+        assert_eq!(
+            decode(&[b"\x1b[<64;10;5M\x1b[<65;10;5M"]),
+            [Input::Wheel(true), Input::Wheel(false)]
+        );
+        // With Ctrl held
+        assert_eq!(decode(&[b"\x1b[<80;1;1M"]), [Input::Wheel(true)]);
+        // Clicks and releases are ignored
+        assert_eq!(
+            decode(&[b"\x1b[<0;1;1M\x1b[<0;1;1ma"]),
+            [plain(Key::Char('a'))]
+        );
+        // Legacy encoding, offset by 32
+        assert_eq!(
+            decode(&[b"\x1b[M`!!\x1b[Ma!!"]),
+            [Input::Wheel(true), Input::Wheel(false)]
+        );
+        assert_eq!(decode(&[b"\x1b[M !!a"]), [plain(Key::Char('a'))]);
+    }
+}
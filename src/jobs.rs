@@ -0,0 +1,194 @@
+//! Work done on background threads, such as linting or diffing against git, so that the
+//! editor stays responsive.
+//!
+//! A job posts its result as an `Event::Job`, which the editor unwraps with `Jobs::finish`
+//! unless the job was cancelled meanwhile. Cancelled jobs may keep running until they check
+//! their `CancelToken`, but post nothing.
+
+use crate::event::{Event, JobResult};
+use crate::logging;
+
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Tells a job that its result is no longer wanted.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A job that hasn't posted its result yet.
+struct Running {
+    name: &'static str,
+    token: CancelToken,
+    started: Instant,
+}
+
+/// The running jobs, by id.
+pub struct Jobs {
+    events: Sender<Event>,
+    next_id: u64,
+    running: BTreeMap<u64, Running>,
+}
+
+impl Jobs {
+    #[must_use]
+    pub fn new(events: Sender<Event>) -> Self {
+        Self {
+            events,
+            next_id: 0,
+            running: BTreeMap::new(),
+        }
+    }
+
+    /// Runs `work` on a new thread, named `name` in the status bar. The event it returns, if
+    /// any, is posted when it is done.
+    pub fn spawn<F>(&mut self, name: &'static str, work: F)
+    where
+        F: FnOnce(&CancelToken) -> Option<Event> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        logging::debug(format_args!("job {id} ({name}) started"));
+        let token = CancelToken::default();
+        self.running.insert(
+            id,
+            Running {
+                name,
+                token: token.clone(),
+                started: Instant::now(),
+            },
+        );
+
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let event = work(&token);
+            if token.is_cancelled() {
+                // Already forgotten by `cancel`
+                return;
+            }
+            // The editor may have quit, in which case nobody cares about the result
+            let _ = events.send(Event::Job(JobResult {
+                id,
+                event: event.map(Box::new),
+            }));
+        });
+    }
+
+    /// Forgets the job that posted `result`, returning its event unless it was cancelled.
+    pub fn finish(&mut self, result: JobResult) -> Option<Event> {
+        let job = self.running.remove(&result.id)?;
+        logging::debug(format_args!(
+            "job {} ({}) done in {:.1?}",
+            result.id,
+            job.name,
+            job.started.elapsed()
+        ));
+        result.event.map(|event| *event)
+    }
+
+    /// Cancels the jobs named `name`, e.g. when a newer one supersedes them.
+    pub fn cancel(&mut self, name: &str) {
+        self.running.retain(|_, job| {
+            if job.name == name {
+                job.token.cancel();
+            }
+            job.name != name
+        });
+    }
+
+    /// Names of the jobs running for at least `min_age`, once each, oldest first.
+    #[must_use]
+    pub fn names(&self, min_age: Duration) -> Vec<&'static str> {
+        let mut names: Vec<&str> = Vec::new();
+        for job in self.running.values() {
+            if job.started.elapsed() >= min_age && !names.contains(&job.name) {
+                names.push(job.name);
+            }
+        }
+        names
+    }
+}
+
+/// How often `output` checks whether it was cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a cancelled command has to exit after SIGTERM before being killed.
+const KILL_GRACE: Duration = Duration::from_millis(200);
+
+/// Runs `command` like `Command::output`, but kills it as soon as `token` is cancelled, in which
+/// case it returns `None`, as it does if the command can't be run.
+#[must_use]
+pub fn output(command: &mut Command, token: &CancelToken) -> Option<Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+    // Read from other threads, or the command could block on a full pipe
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let status = loop {
+        if token.is_cancelled() {
+            kill(&mut child);
+            return None;
+        }
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(_) => {
+                kill(&mut child);
+                return None;
+            }
+        }
+    };
+    Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        bytes
+    })
+}
+
+/// Asks `child` to terminate, giving it `KILL_GRACE` to clean up before killing it, and reaps
+/// it for it not to linger as a zombie.
+fn kill(child: &mut Child) {
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: sending a signal has no memory safety implications.
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+        }
+    }
+    let deadline = Instant::now() + KILL_GRACE;
+    while matches!(child.try_wait(), Ok(None)) && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    if let Err(e) = child.kill().and_then(|()| child.wait().map(drop)) {
+        if e.kind() != io::ErrorKind::InvalidInput {
+            logging::debug(format_args!("couldn't kill job command: {e}"));
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use crate::input::{self, Chord, Modifiers};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use termion::event::Key;
+
+/// Something the editor can do in response to a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    Quit,
+    Save,
+    SaveAs,
+    Open,
+    NextBuffer,
+    PreviousBuffer,
+    CloseBuffer,
+    PickBuffer,
+    AlternateBuffer,
+    FindFile,
+    RecentFiles,
+    PickCommand,
+    PickTheme,
+    Complete,
+    Search,
+    GoToLine,
+    Prompt,
+    Help,
+    ClearHighlight,
+    Redraw,
+    ShowCursorOffset,
+    CopyLine,
+    ToggleComment,
+    Suspend,
+    ToggleOverwrite,
+    NextMisspelling,
+    SpellSuggest,
+    Blame,
+    Undo,
+    Redo,
+    NextDiagnostic,
+    PreviousDiagnostic,
+    ToggleTerminal,
+    OpenUrl,
+    TogglePreview,
+    FrameStats,
+    Messages,
+    Increment,
+    Decrement,
+    Reflow,
+    InsertDate,
+    InsertSnippet,
+    WordCount,
+    DeleteForward,
+    DeleteBackward,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    LineStart,
+    LineEnd,
+    DocumentStart,
+    DocumentEnd,
+    ScrollDown,
+    ScrollUp,
+    HalfPageDown,
+    HalfPageUp,
+    LineToTop,
+    CenterLine,
+    LineToBottom,
+    /// Typing a character that isn't bound, which has no name.
+    Insert(char),
+}
+
+impl Command {
+    /// Every command with its name in the config file, in the order of the help message.
+    pub const ALL: &'static [(Self, &'static str)] = &[
+        (Self::Quit, "quit"),
+        (Self::Save, "save"),
+        (Self::SaveAs, "save_as"),
+        (Self::Open, "open"),
+        (Self::NextBuffer, "next_buffer"),
+        (Self::PreviousBuffer, "previous_buffer"),
+        (Self::CloseBuffer, "close_buffer"),
+        (Self::PickBuffer, "pick_buffer"),
+        (Self::AlternateBuffer, "alternate_buffer"),
+        (Self::FindFile, "find_file"),
+        (Self::RecentFiles, "recent_files"),
+        (Self::PickCommand, "pick_command"),
+        (Self::PickTheme, "pick_theme"),
+        (Self::Complete, "complete"),
+        (Self::Search, "search"),
+        (Self::GoToLine, "go_to_line"),
+        (Self::Prompt, "command_line"),
+        (Self::ToggleOverwrite, "toggle_overwrite"),
+        (Self::ShowCursorOffset, "show_cursor_offset"),
+        (Self::Suspend, "suspend"),
+        (Self::CopyLine, "copy_line"),
+        (Self::ToggleComment, "toggle_comment"),
+        (Self::NextMisspelling, "next_misspelling"),
+        (Self::SpellSuggest, "spell_suggest"),
+        (Self::Blame, "blame"),
+        (Self::Undo, "undo"),
+        (Self::Redo, "redo"),
+        (Self::NextDiagnostic, "next_diagnostic"),
+        (Self::PreviousDiagnostic, "previous_diagnostic"),
+        (Self::ToggleTerminal, "toggle_terminal"),
+        (Self::OpenUrl, "open_url"),
+        (Self::TogglePreview, "toggle_preview"),
+        (Self::FrameStats, "frame_stats"),
+        (Self::Messages, "messages"),
+        (Self::Increment, "increment"),
+        (Self::Decrement, "decrement"),
+        (Self::Reflow, "reflow"),
+        (Self::InsertDate, "insert_date"),
+        (Self::InsertSnippet, "insert_snippet"),
+        (Self::WordCount, "word_count"),
+        (Self::ClearHighlight, "clear_highlight"),
+        (Self::Redraw, "redraw"),
+        (Self::Help, "help"),
+        (Self::DeleteForward, "delete"),
+        (Self::DeleteBackward, "backspace"),
+        (Self::Up, "up"),
+        (Self::Down, "down"),
+        (Self::Left, "left"),
+        (Self::Right, "right"),
+        (Self::PageUp, "page_up"),
+        (Self::PageDown, "page_down"),
+        (Self::LineStart, "line_start"),
+        (Self::LineEnd, "line_end"),
+        (Self::DocumentStart, "document_start"),
+        (Self::DocumentEnd, "document_end"),
+        (Self::ScrollDown, "scroll_down"),
+        (Self::ScrollUp, "scroll_up"),
+        (Self::HalfPageDown, "half_page_down"),
+        (Self::HalfPageUp, "half_page_up"),
+        (Self::LineToTop, "line_to_top"),
+        (Self::CenterLine, "center_line"),
+        (Self::LineToBottom, "line_to_bottom"),
+    ];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|&&(command, _)| command == self)
+            .map_or("", |&(_, name)| name)
+    }
+
+    /// Whether the command changes the document's content.
+    #[must_use]
+    pub fn modifies_document(self) -> bool {
+        matches!(
+            self,
+            Self::DeleteForward
+                | Self::DeleteBackward
+                | Self::ToggleComment
+                | Self::SpellSuggest
+                | Self::Increment
+                | Self::Decrement
+                | Self::Reflow
+                | Self::InsertDate
+                | Self::InsertSnippet
+                | Self::Complete
+                | Self::Insert(_)
+        )
+    }
+
+    /// Short description for the help message, `None` for self-explanatory keys.
+    #[must_use]
+    pub fn description(self) -> Option<&'static str> {
+        Some(match self {
+            Self::Quit => "quit (don't save)",
+            Self::Save => "save",
+            Self::SaveAs => "save as",
+            Self::Open => "open file",
+            Self::NextBuffer => "next buffer",
+            Self::PreviousBuffer => "previous buffer",
+            Self::CloseBuffer => "close buffer",
+            Self::PickBuffer => "switch buffer",
+            Self::AlternateBuffer => "alternate file",
+            Self::FindFile => "find file",
+            Self::RecentFiles => "recent files",
+            Self::PickCommand => "run a command",
+            Self::PickTheme => "choose theme",
+            Self::Complete => "complete word",
+            Self::Search => "search regex in line",
+            Self::GoToLine => "go to line",
+            Self::Prompt => "command palette",
+            Self::ToggleOverwrite => "toggle overwrite",
+            Self::ShowCursorOffset => "show cursor offset",
+            Self::Suspend => "suspend",
+            Self::CopyLine => "copy line",
+            Self::ToggleComment => "toggle comment",
+            Self::NextMisspelling => "next misspelling",
+            Self::SpellSuggest => "fix spelling",
+            Self::Blame => "blame line",
+            Self::Undo => "undo",
+            Self::Redo => "redo",
+            Self::NextDiagnostic => "next diagnostic",
+            Self::PreviousDiagnostic => "previous diagnostic",
+            Self::ToggleTerminal => "terminal",
+            Self::OpenUrl => "open URL",
+            Self::TogglePreview => "Markdown preview",
+            Self::FrameStats => "frame timings",
+            Self::Messages => "message log",
+            Self::Increment => "increment number",
+            Self::Decrement => "decrement number",
+            Self::Reflow => "reformat paragraph",
+            Self::InsertDate => "insert date",
+            Self::InsertSnippet => "insert snippet",
+            Self::WordCount => "count words",
+            Self::ClearHighlight => "clear search highlight",
+            Self::Redraw => "redraw",
+            Self::Help => "display this help message",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|&&(_, name)| name == s)
+            .map(|&(command, _)| command)
+            .ok_or_else(|| format!("unknown command `{s}`"))
+    }
+}
+
+/// Bindings from chords to commands. Unbound characters are inserted.
+pub struct Keymap {
+    /// Bound key sequences, most of them single chords.
+    bindings: HashMap<Vec<Chord>, Command>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let ctrl = Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        };
+        let ctrl_shift = Modifiers {
+            shift: true,
+            ..ctrl
+        };
+        let shift = Modifiers {
+            shift: true,
+            ..Modifiers::default()
+        };
+
+        let bindings = [
+            (Key::Ctrl('q').into(), Command::Quit),
+            (Key::Ctrl('s').into(), Command::Save),
+            (Key::Ctrl('w').into(), Command::SaveAs),
+            (Chord::new(Key::Char('s'), ctrl_shift), Command::SaveAs),
+            (Key::Ctrl('o').into(), Command::Open),
+            (Key::Alt('.').into(), Command::NextBuffer),
+            (Key::Alt(',').into(), Command::PreviousBuffer),
+            (Key::Alt('w').into(), Command::CloseBuffer),
+            (Key::Alt('l').into(), Command::PickBuffer),
+            // Ctrl-^ and Ctrl-6 send the same byte, which termion reports as Ctrl-6
+            (Key::Ctrl('6').into(), Command::AlternateBuffer),
+            (Key::Alt('f').into(), Command::FindFile),
+            (Key::Alt('r').into(), Command::RecentFiles),
+            (Key::Alt('x').into(), Command::PickCommand),
+            (Key::Ctrl('n').into(), Command::Complete),
+            (Key::Ctrl('f').into(), Command::Search),
+            (Key::Ctrl('g').into(), Command::GoToLine),
+            (Key::Ctrl('p').into(), Command::Prompt),
+            (Chord::new(Key::Char('p'), ctrl_shift), Command::Prompt),
+            (Key::F(1).into(), Command::Help),
+            (Key::Esc.into(), Command::ClearHighlight),
+            (Key::Ctrl('l').into(), Command::Redraw),
+            (Key::Ctrl('b').into(), Command::ShowCursorOffset),
+            (Key::Ctrl('c').into(), Command::CopyLine),
+            (Key::Ctrl('z').into(), Command::Suspend),
+            // Legacy terminals send Ctrl-/ as Ctrl-_, which termion reports as Ctrl-7
+            (Chord::new(Key::Char('/'), ctrl), Command::ToggleComment),
+            (Key::Ctrl('7').into(), Command::ToggleComment),
+            (Key::F(7).into(), Command::NextMisspelling),
+            (Chord::new(Key::F(7), shift), Command::SpellSuggest),
+            (Key::Alt('b').into(), Command::Blame),
+            (Key::Alt('u').into(), Command::Undo),
+            (Key::Ctrl('r').into(), Command::Redo),
+            (Key::F(8).into(), Command::NextDiagnostic),
+            (Chord::new(Key::F(8), shift), Command::PreviousDiagnostic),
+            (Key::Alt('t').into(), Command::ToggleTerminal),
+            (Key::Alt('o').into(), Command::OpenUrl),
+            (Key::Alt('m').into(), Command::TogglePreview),
+            (Key::F(12).into(), Command::FrameStats),
+            (Key::Ctrl('a').into(), Command::Increment),
+            (Key::Ctrl('x').into(), Command::Decrement),
+            (Key::Alt('q').into(), Command::Reflow),
+            (Key::Insert.into(), Command::ToggleOverwrite),
+            (Key::Delete.into(), Command::DeleteForward),
+            (Key::Backspace.into(), Command::DeleteBackward),
+            (Key::Up.into(), Command::Up),
+            (Key::Down.into(), Command::Down),
+            (Key::Left.into(), Command::Left),
+            (Key::Right.into(), Command::Right),
+            (Key::PageUp.into(), Command::PageUp),
+            (Key::PageDown.into(), Command::PageDown),
+            (Key::Home.into(), Command::LineStart),
+            (Key::End.into(), Command::LineEnd),
+            (Chord::new(Key::Home, ctrl), Command::DocumentStart),
+            (Chord::new(Key::End, ctrl), Command::DocumentEnd),
+            (Key::Ctrl('e').into(), Command::ScrollDown),
+            (Key::Ctrl('y').into(), Command::ScrollUp),
+            (Key::Ctrl('d').into(), Command::HalfPageDown),
+            (Key::Ctrl('u').into(), Command::HalfPageUp),
+        ];
+
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(chord, command)| (vec![chord], command))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    #[must_use]
+    pub fn get(&self, keys: &[Chord]) -> Option<Command> {
+        self.bindings.get(keys).copied()
+    }
+
+    /// Whether `keys` are the start of a longer bound sequence.
+    #[must_use]
+    pub fn is_prefix(&self, keys: &[Chord]) -> bool {
+        is_prefix(self.bindings.keys(), keys)
+    }
+
+    /// Binds `keys` to `command`, or unbinds them if `command` is `None`.
+    pub fn bind(&mut self, keys: Vec<Chord>, command: Option<Command>) {
+        match command {
+            Some(command) => self.bindings.insert(keys, command),
+            None => self.bindings.remove(&keys),
+        };
+    }
+
+    /// Key sequences bound to `command`, formatted and sorted.
+    #[must_use]
+    pub fn sequences(&self, command: Command) -> Vec<String> {
+        let mut sequences: Vec<String> = self
+            .bindings
+            .iter()
+            .filter(|&(_, &c)| c == command)
+            .map(|(keys, _)| input::format_sequence(keys))
+            .collect();
+        sequences.sort();
+        sequences
+    }
+
+    /// Lists the bindings of documented commands, e.g. `<C-q>: quit (don't save); …`.
+    #[must_use]
+    pub fn help(&self) -> String {
+        Command::ALL
+            .iter()
+            .filter_map(|&(command, _)| {
+                let description = command.description()?;
+                let chords = self.sequences(command);
+                if chords.is_empty() {
+                    return None;
+                }
+                Some(format!("{}: {description}", chords.join("/")))
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+/// Whether one of `sequences` is longer than `keys` and starts with them.
+pub fn is_prefix<'a>(sequences: impl IntoIterator<Item = &'a Vec<Chord>>, keys: &[Chord]) -> bool {
+    sequences
+        .into_iter()
+        .any(|sequence| sequence.len() > keys.len() && sequence.starts_with(keys))
+}
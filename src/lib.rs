@@ -0,0 +1,62 @@
+//! A small terminal text editor.
+//!
+//! The binary is a thin wrapper around `Editor`, which can also be driven programmatically:
+//! give it a `Terminal` (a `MemoryTerminal` for headless use), then feed it keys or commands and
+//! inspect its `Document`.
+
+pub mod cli;
+mod completion;
+pub mod config;
+mod date;
+mod diff;
+pub mod document;
+pub mod editor;
+mod editorconfig;
+pub mod event;
+mod ex;
+pub mod filetype;
+pub mod frame;
+mod fuzzy;
+mod git;
+mod history;
+mod hooks;
+mod indent;
+pub mod input;
+mod jobs;
+pub mod keymap;
+mod lint;
+pub mod logging;
+mod markdown;
+pub mod memory_terminal;
+mod modeline;
+mod number;
+mod options;
+mod panel;
+mod picker;
+pub mod popup;
+mod reflow;
+pub mod row;
+mod script;
+mod shell;
+mod spell;
+mod status_line;
+pub mod terminal;
+mod text_object;
+pub mod theme;
+mod toml;
+mod truncate_graphemes;
+mod url;
+mod vim;
+mod word_count;
+
+pub use config::EditorConfig;
+pub use document::Document;
+pub use editor::{Editor, Position, SearchDirection};
+pub use filetype::FileType;
+pub use input::Chord;
+pub use keymap::Command;
+pub use memory_terminal::MemoryTerminal;
+pub use row::Row;
+pub use terminal::Terminal;
+pub use theme::Theme;
+pub use truncate_graphemes::TruncateGraphemes;
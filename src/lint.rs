@@ -0,0 +1,166 @@
+//! Running a lint command, and reading its diagnostics.
+//!
+//! Output lines like `path:line:column: message` or `path:line: message`, which most tools print
+//! in their short or GCC-like formats (`cargo check --message-format=short`, `shellcheck -f gcc`,
+//! `flake8`…), are diagnostics. Those for other files are ignored.
+
+use crate::jobs::{self, CancelToken};
+use crate::shell;
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    /// Guessed from the start of the message, as in `warning: unused variable`.
+    fn of(message: &str) -> Self {
+        let message = message.trim_start().to_ascii_lowercase();
+        if message.starts_with("warning") {
+            Self::Warning
+        } else if ["note", "help", "info", "hint"]
+            .iter()
+            .any(|prefix| message.starts_with(prefix))
+        {
+            Self::Info
+        } else {
+            Self::Error
+        }
+    }
+
+    #[must_use]
+    pub fn marker(self) -> char {
+        match self {
+            Self::Error => 'E',
+            Self::Warning => 'W',
+            Self::Info => 'I',
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 0-based row.
+    pub line: usize,
+    /// 0-based, in characters.
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Extracts the diagnostics about `path` from the output of a lint command that ran in `dir`.
+#[must_use]
+pub fn parse(output: &str, path: &Path, dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(4, ':');
+            let file = PathBuf::from(parts.next()?.trim());
+            let line_num: usize = parts.next()?.trim().parse().ok()?;
+            let rest: Vec<&str> = parts.collect();
+            // The column is optional
+            let (column, message): (usize, String) = match rest.as_slice() {
+                [column, message] if column.trim().parse::<usize>().is_ok() => {
+                    (column.trim().parse().unwrap_or(1), (*message).to_string())
+                }
+                parts => (1, parts.join(":")),
+            };
+
+            if !is_same_file(&file, path, dir) || line_num == 0 {
+                return None;
+            }
+            let message = message.trim().to_string();
+            Some(Diagnostic {
+                line: line_num - 1,
+                column: column.saturating_sub(1),
+                severity: Severity::of(&message),
+                message,
+            })
+        })
+        .collect();
+    diagnostics.sort_by_key(|d| (d.line, d.column));
+    diagnostics.dedup();
+    diagnostics
+}
+
+/// Whether `reported`, as printed by a tool running in `dir`, is `path`.
+fn is_same_file(reported: &Path, path: &Path, dir: &Path) -> bool {
+    if reported.as_os_str().is_empty() {
+        return false;
+    }
+    match (dir.join(reported).canonicalize(), path.canonicalize()) {
+        (Ok(a), Ok(b)) if a == b => true,
+        // Relative to a project root, e.g. `src/main.rs`
+        _ => reported.is_relative() && path.ends_with(reported),
+    }
+}
+
+/// Runs `command` in the directory of `path`, `%f` standing for its file name there, and reads
+/// its diagnostics. Blocks, so it is meant to run as a job, and is killed once `token` is cancelled.
+#[must_use]
+pub fn run(command: &str, path: &Path, token: &CancelToken) -> Option<Vec<Diagnostic>> {
+    let name = path.file_name().map_or(path.as_os_str(), |name| name);
+    let command = command.replace("%f", &shell::quote(&name.to_string_lossy()));
+    let dir = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let output = jobs::output(
+        shell::command(&command)
+            .current_dir(&dir)
+            .stdin(Stdio::null()),
+        token,
+    )?;
+    // Tools disagree on which stream diagnostics go to
+    let text = format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Some(parse(&text, path, &dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_diagnostics_about_the_file() {
+        let output = "main.rs:3:5: warning: unused variable\n\
+                      main.rs:1: error: oops\n\
+                      other.rs:2:1: error: elsewhere\n\
+                      src/main.rs:7:2: note: relative to the root\n\
+                      not a diagnostic\n";
+        let diagnostics = parse(output, Path::new("/p/src/main.rs"), Path::new("/p/src"));
+        let summary: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.column, d.severity, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, 0, Severity::Error, "error: oops"),
+                (2, 4, Severity::Warning, "warning: unused variable"),
+                (6, 1, Severity::Info, "note: relative to the root"),
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_run_next_to_the_file() {
+        // Relative to the current directory, which isn't the command's
+        let path = Path::new("src/lint.rs");
+        let diagnostics = run(
+            "test -f %f && echo %f:1: error: found",
+            path,
+            &CancelToken::default(),
+        )
+        .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! Opt-in debug log, written to a file since the terminal is taken by the editor.
+//!
+//! Enabled by `--log-file <PATH>` or the `HECTO_LOG` environment variable, which holds the most
+//! verbose level to record (`error`, `warn`, `info`, `debug` or `trace`). Slow operations such as
+//! opening, saving, rendering and searching are wrapped in `Span`s, which log how long they took.
+
+use crate::EditorConfig;
+
+use std::fmt::{self, Display};
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// The environment variable selecting the level.
+pub const ENV_VAR: &str = "HECTO_LOG";
+/// Name of the log file in the state directory, when only `HECTO_LOG` is given.
+const DEFAULT_FILE_NAME: &str = "hecto.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const NAMES: [&'static str; 5] = ["error", "warn", "info", "debug", "trace"];
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "error" => Self::Error,
+            "warn" | "warning" => Self::Warn,
+            "info" => Self::Info,
+            "debug" => Self::Debug,
+            "trace" => Self::Trace,
+            _ => {
+                return Err(format!(
+                    "unknown log level `{s}` (available: {})",
+                    Self::NAMES.join(", ")
+                ))
+            }
+        })
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded, for the messages to line up
+        f.pad(match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        })
+    }
+}
+
+struct Logger {
+    level: Level,
+    file: Mutex<File>,
+    start: Instant,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Starts logging to `path`, at the level named by `filter`. Either may be missing: the log
+/// then goes to `hecto.log` in `EditorConfig::state_dir`, or records `debug` messages. Does
+/// nothing if neither is given. The log, which may quote the files edited, is only readable by
+/// the user.
+///
+/// # Errors
+/// If the level is unknown or the file can't be opened.
+pub fn init(path: Option<&Path>, filter: Option<&str>) -> Result<(), String> {
+    if path.is_none() && filter.is_none() {
+        return Ok(());
+    }
+    let level = filter.map_or(Ok(Level::Debug), Level::from_str)?;
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let dir = EditorConfig::state_dir()
+                .ok_or("no state directory for the log file, pass --log-file")?;
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .map_err(|e| format!("can't create {}: {e}", dir.display()))?;
+            dir.join(DEFAULT_FILE_NAME)
+        }
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|e| format!("can't open log file {}: {e}", path.display()))?;
+
+    let _ = LOGGER.set(Logger {
+        level,
+        file: Mutex::new(file),
+        start: Instant::now(),
+    });
+    log(
+        Level::Info,
+        format_args!(
+            "{} {} started, logging at level {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            level
+        ),
+    );
+    Ok(())
+}
+
+/// Whether messages of `level` are recorded, to skip work preparing them otherwise.
+#[must_use]
+pub fn enabled(level: Level) -> bool {
+    LOGGER.get().is_some_and(|logger| level <= logger.level)
+}
+
+/// Records `message`, prefixed by the time since startup and the level.
+pub fn log(level: Level, message: impl Display) {
+    let Some(logger) = LOGGER.get().filter(|logger| level <= logger.level) else {
+        return;
+    };
+    let elapsed = logger.start.elapsed();
+    if let Ok(mut file) = logger.file.lock() {
+        // Losing a log line is better than disturbing the editor
+        let _ = writeln!(
+            file,
+            "{:>5}.{:03} {level:<5} {message}",
+            elapsed.as_secs(),
+            elapsed.subsec_millis()
+        );
+    }
+}
+
+pub fn error(message: impl Display) {
+    log(Level::Error, message);
+}
+
+pub fn warn(message: impl Display) {
+    log(Level::Warn, message);
+}
+
+pub fn info(message: impl Display) {
+    log(Level::Info, message);
+}
+
+pub fn debug(message: impl Display) {
+    log(Level::Debug, message);
+}
+
+/// An operation being timed, logged with its duration when dropped. Those happening on every
+/// keypress, such as rendering, belong at `trace` level.
+pub struct Span {
+    level: Level,
+    name: String,
+    start: Instant,
+}
+
+impl Span {
+    /// Starts timing `name`, e.g. `save notes.txt`.
+    #[must_use]
+    pub fn new(level: Level, name: impl Display) -> Self {
+        Self {
+            level,
+            name: if enabled(level) {
+                name.to_string()
+            } else {
+                String::new()
+            },
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if enabled(self.level) {
+            log(
+                self.level,
+                format_args!(
+                    "{}: {:.1}ms",
+                    self.name,
+                    self.start.elapsed().as_secs_f64() * 1000.0
+                ),
+            );
+        }
+    }
+}
@@ -1,28 +1,53 @@
-mod document;
-mod editor;
-mod row;
-mod terminal;
-mod truncate_graphemes;
-
-pub use document::Document;
-use editor::Editor;
-pub use editor::{Position, SearchDirection};
-pub use row::Row;
-pub use terminal::Terminal;
-pub use truncate_graphemes::TruncateGraphemes;
-
 use anyhow::Result;
+use hecto::cli::{self, Cli};
+use hecto::logging;
+use hecto::Editor;
 use std::env;
+use std::process;
 
 fn main() -> Result<()> {
-    let mut editor = match env::args().nth(1) {
-        Some(p) => Editor::from_file_path(p.into()),
-        None => Editor::default(),
-    }?;
+    let mut args = match cli::parse(env::args().skip(1)) {
+        Ok(Cli::Run(args)) => args,
+        Ok(Cli::Help) => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Ok(Cli::Version) => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(e) => {
+            let usage = cli::USAGE.lines().next().unwrap_or_default();
+            eprintln!("error: {e}\n\n{usage}\n\nFor more information, try `--help`.");
+            process::exit(2);
+        }
+    };
+
+    let log_filter = env::var(logging::ENV_VAR).ok();
+    if let Err(e) = logging::init(args.log_file.as_deref(), log_filter.as_deref()) {
+        eprintln!("error: {e}");
+        process::exit(2);
+    }
+
+    if let Some(script) = args.batch.take() {
+        if let Err(e) = Editor::batch(args, &script) {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let mut editor = Editor::from_args(args)?;
+
+    let result = editor.run();
+    // Restore the terminal before printing anything
+    drop(editor);
 
-    if let Err(e) = editor.run() {
+    if let Err(e) = result {
+        logging::error(&e);
         eprintln!("{}", e);
         return Err(e);
     }
+    println!("Goodbye!");
     Ok(())
 }
@@ -0,0 +1,182 @@
+//! Rendering Markdown for the preview, with the little formatting a terminal can show:
+//! headings, bold and inline code, links, lists, quotes, rules and code blocks.
+
+use crate::frame::{Line, Style};
+use crate::theme::Theme;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Renders `lines` wrapped to `width` columns. Each rendered line comes with the index of the
+/// source line it is from, for the preview to scroll along with the document.
+#[must_use]
+pub fn render<'a>(
+    lines: impl IntoIterator<Item = &'a str>,
+    width: usize,
+    theme: &Theme,
+) -> Vec<(usize, Line)> {
+    let code = Style::default().fg(theme.markdown_code_fg);
+    let mut rendered = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for (idx, line) in lines.into_iter().enumerate() {
+        let trimmed = line.trim_start();
+        let mut push = |prefix: (Style, &str), segments: Vec<(Style, String)>| {
+            for wrapped in wrap(prefix, &segments, width) {
+                rendered.push((idx, wrapped));
+            }
+        };
+
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            } else {
+                push((code, "  "), vec![(code, line.into())]);
+            }
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            fence = Some(marker);
+            continue;
+        }
+
+        if let Some((level, text)) = heading(trimmed) {
+            let mut style = Style::default().fg(theme.markdown_heading_fg).bold();
+            if level == 1 {
+                style = style.underline();
+            }
+            push((style, ""), inline(text, style, theme));
+        } else if is_rule(trimmed) {
+            let rule = "─".repeat(width);
+            push(
+                (Style::default().fg(theme.indent_guide_fg), &rule),
+                Vec::new(),
+            );
+        } else if let Some(text) = trimmed.strip_prefix('>') {
+            let text = text.strip_prefix(' ').unwrap_or(text);
+            let bar = Style::default().fg(theme.indent_guide_fg);
+            push((bar, "│ "), inline(text, Style::default(), theme));
+        } else if let Some((marker, text)) = list_item(trimmed) {
+            let indent = &line[..line.len() - trimmed.len()];
+            let prefix = format!("{indent}{marker} ");
+            push(
+                (Style::default(), &prefix),
+                inline(text, Style::default(), theme),
+            );
+        } else {
+            push(
+                (Style::default(), ""),
+                inline(line, Style::default(), theme),
+            );
+        }
+    }
+    rendered
+}
+
+/// Level and text of an ATX heading, like `## Usage`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    // Closing hashes are optional
+    let text = text.trim_end().trim_end_matches('#').trim_end();
+    (1..=6).contains(&level).then_some((level, text))
+}
+
+/// Whether `line` is a thematic break, like `---` or `* * *`.
+fn is_rule(line: &str) -> bool {
+    let Some(c) = line.chars().next().filter(|c| ['-', '*', '_'].contains(c)) else {
+        return false;
+    };
+    line.chars().all(|d| d == c || d == ' ') && line.matches(c).count() >= 3
+}
+
+/// Marker to show and text of a list item: bullets become `•`, numbers are kept.
+fn list_item(line: &str) -> Option<(String, &str)> {
+    if let Some(text) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| line.strip_prefix(bullet))
+    {
+        return Some(("•".into(), text));
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    let text = rest
+        .strip_prefix(". ")
+        .or_else(|| rest.strip_prefix(") "))?;
+    (digits > 0).then(|| (line[..digits + 1].to_string(), text))
+}
+
+/// Splits `text` into styled segments, removing the markup of bold text, inline code and
+/// links.
+fn inline(text: &str, base: Style, theme: &Theme) -> Vec<(Style, String)> {
+    let mut segments: Vec<(Style, String)> = Vec::new();
+    let mut push = |style: Style, text: &str| match segments.last_mut() {
+        Some((last, last_text)) if *last == style => last_text.push_str(text),
+        _ => segments.push((style, text.into())),
+    };
+
+    let mut bold = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let style = if bold { base.bold() } else { base };
+
+        if c == '\\' {
+            // Escaped punctuation
+            let escaped = rest[1..].chars().next().filter(char::is_ascii_punctuation);
+            if let Some(escaped) = escaped {
+                push(style, &escaped.to_string());
+                rest = &rest[1 + escaped.len_utf8()..];
+                continue;
+            }
+        } else if rest.starts_with("**") || rest.starts_with("__") {
+            bold = !bold;
+            rest = &rest[2..];
+            continue;
+        } else if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                push(Style::default().fg(theme.markdown_code_fg), &rest[1..=end]);
+                rest = &rest[end + 2..];
+                continue;
+            }
+        } else if c == '[' {
+            if let Some((label, after)) = link(rest) {
+                push(style.fg(theme.markdown_link_fg).underline(), label);
+                rest = after;
+                continue;
+            }
+        }
+
+        push(style, &rest[..c.len_utf8()]);
+        rest = &rest[c.len_utf8()..];
+    }
+    segments
+}
+
+/// Label of the `[label](target)` link `text` starts with, and the text after it.
+fn link(text: &str) -> Option<(&str, &str)> {
+    let label_end = text.find("](")?;
+    let target_len = text[label_end + 2..].find(')')?;
+    Some((&text[1..label_end], &text[label_end + 2 + target_len + 1..]))
+}
+
+/// Lays out `segments` after `prefix` on lines of `width` columns, continuation lines being
+/// indented by the width of the prefix.
+fn wrap(prefix: (Style, &str), segments: &[(Style, String)], width: usize) -> Vec<Line> {
+    let indent = " ".repeat(prefix.1.graphemes(true).count());
+    let mut lines = Vec::new();
+    let mut line = Line::default();
+    if !prefix.1.is_empty() {
+        line.push(prefix.0, prefix.1);
+    }
+
+    for (style, text) in segments {
+        for grapheme in text.graphemes(true) {
+            if line.len() >= width {
+                lines.push(std::mem::take(&mut line));
+                line.push(Style::default(), &indent);
+            }
+            line.push(*style, grapheme);
+        }
+    }
+    lines.push(line);
+    lines
+}
@@ -0,0 +1,174 @@
+use crate::event::Event;
+use crate::frame::Line;
+use crate::input::Chord;
+use crate::terminal::{CursorShape, Size, Terminal};
+use crate::Position;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// What a `MemoryTerminal` displays, as a grid of graphemes. Colors are discarded.
+#[derive(Debug, Default)]
+pub struct Screen {
+    cells: Vec<Vec<String>>,
+    pub cursor: Position,
+    pub cursor_shape: Option<CursorShape>,
+    pub clipboard: Option<String>,
+    /// How many times the screen was flushed, i.e. frames drawn.
+    pub flushes: usize,
+}
+
+impl Screen {
+    /// Every line of the screen, including the status and message bars, without trailing
+    /// spaces.
+    #[must_use]
+    pub fn lines(&self) -> Vec<String> {
+        self.cells
+            .iter()
+            .map(|row| row.concat().trim_end().to_string())
+            .collect()
+    }
+
+    fn put(&mut self, text: &str) {
+        for grapheme in text.graphemes(true) {
+            match grapheme {
+                "\r" => self.cursor.x = 0,
+                "\n" | "\r\n" => {
+                    self.cursor.x = 0;
+                    self.cursor.y += 1;
+                }
+                g => {
+                    if let Some(cell) = self
+                        .cells
+                        .get_mut(self.cursor.y)
+                        .and_then(|row| row.get_mut(self.cursor.x))
+                    {
+                        *cell = g.into();
+                    }
+                    self.cursor.x += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The screen as text, one line per row, for snapshot tests.
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.lines().join("\n"))
+    }
+}
+
+/// An in-memory terminal recording what is drawn, and feeding scripted input. For tests and
+/// headless use.
+pub struct MemoryTerminal {
+    size: Size,
+    screen: Rc<RefCell<Screen>>,
+    keys: Vec<Chord>,
+}
+
+impl MemoryTerminal {
+    /// `size` is the full terminal size, including the status and message bars. `keys` are sent
+    /// as soon as input starts.
+    #[must_use]
+    pub fn new(size: Size, keys: Vec<Chord>) -> Self {
+        let screen = Screen {
+            cells: vec![vec![" ".into(); size.width.into()]; size.height.into()],
+            ..Screen::default()
+        };
+        Self {
+            size: Size {
+                width: size.width,
+                height: size.height.saturating_sub(2),
+            },
+            screen: Rc::new(RefCell::new(screen)),
+            keys,
+        }
+    }
+
+    /// A handle to the screen, which stays valid once the terminal is moved into an `Editor`.
+    #[must_use]
+    pub fn screen(&self) -> Rc<RefCell<Screen>> {
+        Rc::clone(&self.screen)
+    }
+}
+
+impl Terminal for MemoryTerminal {
+    fn size(&self) -> Size {
+        self.size
+    }
+
+    fn update_size(&mut self) -> Result<Size, io::Error> {
+        Ok(self.size)
+    }
+
+    fn start_input(&mut self, events: Sender<Event>) {
+        for key in self.keys.drain(..) {
+            let _ = events.send(Event::Key(key));
+        }
+    }
+
+    fn clear_screen(&mut self) {
+        for row in &mut self.screen.borrow_mut().cells {
+            row.fill(" ".into());
+        }
+    }
+
+    fn clear_current_line(&mut self) {
+        let mut screen = self.screen.borrow_mut();
+        let y = screen.cursor.y;
+        if let Some(row) = screen.cells.get_mut(y) {
+            row.fill(" ".into());
+        }
+    }
+
+    fn cursor_position(&mut self, pos: Position) {
+        self.screen.borrow_mut().cursor = pos;
+    }
+
+    fn print(&mut self, text: &str) {
+        self.screen.borrow_mut().put(text);
+    }
+
+    fn print_line(&mut self, line: &Line) {
+        let mut screen = self.screen.borrow_mut();
+        for (_style, text) in line.spans() {
+            screen.put(text);
+        }
+    }
+
+    fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.screen.borrow_mut().cursor_shape = Some(shape);
+    }
+
+    fn reset_cursor_shape(&mut self) {
+        self.screen.borrow_mut().cursor_shape = None;
+    }
+
+    fn copy_to_clipboard(&mut self, text: &str) -> bool {
+        self.screen.borrow_mut().clipboard = Some(text.into());
+        true
+    }
+
+    fn pause(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.screen.borrow_mut().flushes += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+//! Settings embedded in files, as Vim modelines (`vim: set ts=4 et:`) or Emacs file variables
+//! (`-*- mode: python; tab-width: 4 -*-`).
+
+use crate::FileType;
+
+/// Lines at the start and end of a file searched for a modeline, as in Vim.
+pub const LINES: usize = 5;
+/// Widest tab a modeline or the configuration may ask for, larger values being likely mistakes
+/// or abuse.
+pub const MAX_TAB_WIDTH: usize = 32;
+
+/// Settings a modeline asks for, `None` where it doesn't mention them.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Modeline {
+    pub tab_width: Option<usize>,
+    /// `true` for spaces.
+    pub expand_tab: Option<bool>,
+    pub file_type: Option<FileType>,
+    pub read_only: bool,
+}
+
+impl Modeline {
+    /// Parses the first modeline found among `lines`, which should be the first and last
+    /// `LINES` lines of the file. Unknown settings are ignored.
+    #[must_use]
+    pub fn find<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        lines
+            .into_iter()
+            .find_map(|line| Self::parse_vim(line).or_else(|| Self::parse_emacs(line)))
+    }
+
+    /// `[text] vim: ts=4 et` or `[text] vim: set ts=4 et: [text]`, also after `vi:` or `ex:`.
+    /// `None` without any known setting, for prose such as `vi: the editor` not to be taken for
+    /// a modeline.
+    fn parse_vim(line: &str) -> Option<Self> {
+        let start = ["vim:", "Vim:", "vi:", "ex:"].iter().find_map(|marker| {
+            line.match_indices(marker)
+                // The marker must start the line or follow a blank
+                .find(|&(idx, _)| idx == 0 || line[..idx].ends_with(char::is_whitespace))
+                .map(|(idx, _)| idx + marker.len())
+        })?;
+        let rest = line[start..].trim_start();
+
+        let options = match rest
+            .strip_prefix("set ")
+            .or_else(|| rest.strip_prefix("se "))
+        {
+            // Ends at the next colon, the text after it being e.g. the end of a comment
+            Some(options) => options.split(':').next().unwrap_or_default(),
+            None => rest,
+        };
+        let mut modeline = Self::default();
+        let mut known = false;
+        for option in options.split([' ', '\t', ':']).filter(|o| !o.is_empty()) {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+            match name {
+                "ts" | "tabstop" => modeline.tab_width = tab_width(value),
+                "et" | "expandtab" => modeline.expand_tab = Some(true),
+                "noet" | "noexpandtab" => modeline.expand_tab = Some(false),
+                "ft" | "filetype" | "syn" | "syntax" => modeline.file_type = file_type(value),
+                "ro" | "readonly" => modeline.read_only = true,
+                "noro" | "noreadonly" => modeline.read_only = false,
+                _ => continue,
+            }
+            known = true;
+        }
+        known.then_some(modeline)
+    }
+
+    /// `-*- mode: rust; tab-width: 4; indent-tabs-mode: nil -*-`, or just `-*- rust -*-`.
+    fn parse_emacs(line: &str) -> Option<Self> {
+        let (_, rest) = line.split_once("-*-")?;
+        let (variables, _) = rest.split_once("-*-")?;
+
+        let mut modeline = Self::default();
+        if !variables.contains(':') {
+            modeline.file_type = file_type(variables.trim());
+            return Some(modeline);
+        }
+        for variable in variables.split(';') {
+            let Some((name, value)) = variable.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "mode" => modeline.file_type = file_type(value),
+                "tab-width" => modeline.tab_width = tab_width(value),
+                "indent-tabs-mode" => modeline.expand_tab = Some(value == "nil"),
+                "buffer-read-only" => modeline.read_only = value != "nil",
+                _ => (),
+            }
+        }
+        Some(modeline)
+    }
+}
+
+/// A tab width between 1 and `MAX_TAB_WIDTH`.
+fn tab_width(value: &str) -> Option<usize> {
+    value
+        .parse()
+        .ok()
+        .filter(|n| (1..=MAX_TAB_WIDTH).contains(n))
+}
+
+/// The file type named `name` in Vim or Emacs, e.g. `sh`, `c++` or `python-mode`.
+fn file_type(name: &str) -> Option<FileType> {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_suffix("-mode").unwrap_or(&name);
+    let name = match name {
+        "c++" => "cpp",
+        "js" => "javascript",
+        "sh" | "bash" | "zsh" | "shell-script" => "shell",
+        "makefile" => "make",
+        "md" => "markdown",
+        "conf-toml" => "toml",
+        name => name,
+    };
+    FileType::by_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vim_modelines() {
+        let modeline = Modeline::parse_vim("# vim: set ts=4 et ft=python: end").unwrap();
+        assert_eq!(modeline.tab_width, Some(4));
+        assert_eq!(modeline.expand_tab, Some(true));
+        assert_eq!(modeline.file_type.unwrap().name(), "python");
+
+        let modeline = Modeline::parse_vim("/* vi:noet:ro */").unwrap();
+        assert_eq!(modeline.expand_tab, Some(false));
+        assert!(modeline.read_only);
+
+        // Not after a word, nor without any known setting
+        assert_eq!(Modeline::parse_vim("email:vim:ts=4"), None);
+        assert_eq!(Modeline::parse_vim("See vi: the editor"), None);
+    }
+
+    #[test]
+    fn emacs_file_variables() {
+        let modeline =
+            Modeline::parse_emacs("# -*- mode: python; tab-width: 4; indent-tabs-mode: nil -*-")
+                .unwrap();
+        assert_eq!(modeline.tab_width, Some(4));
+        assert_eq!(modeline.expand_tab, Some(true));
+        assert_eq!(modeline.file_type.unwrap().name(), "python");
+
+        let modeline = Modeline::parse_emacs("// -*- c++ -*-").unwrap();
+        assert_eq!(modeline.file_type.unwrap().name(), "cpp");
+        assert_eq!(Modeline::parse_emacs("no -*- end"), None);
+    }
+
+    #[test]
+    fn huge_tab_widths_are_ignored() {
+        assert_eq!(
+            Modeline::parse_vim("vim: ts=32").unwrap().tab_width,
+            Some(32)
+        );
+        assert_eq!(
+            Modeline::parse_vim("vim: ts=100000").unwrap().tab_width,
+            None
+        );
+        assert_eq!(Modeline::parse_vim("vim: ts=0").unwrap().tab_width, None);
+        let modeline = Modeline::parse_emacs("-*- tab-width: 1000 -*-").unwrap();
+        assert_eq!(modeline.tab_width, None);
+    }
+}
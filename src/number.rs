@@ -0,0 +1,61 @@
+//! Adding to the number under the cursor, as `Ctrl-A` and `Ctrl-X` do in Vim.
+
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Hexadecimal with its prefix, or decimal with an optional minus sign.
+const NUMBER_PATTERN: &str = r"0[xX][0-9a-fA-F]+|-?[0-9]+";
+
+/// Adds `delta` to the number spanning byte `idx` of `line`, or to the first one after it.
+/// Returns the byte range of the number and its replacement, which keeps the `0x` prefix, the
+/// case of hexadecimal digits and the width of zero-padded numbers.
+#[must_use]
+pub fn add(line: &str, idx: usize, delta: i64) -> Option<(Range<usize>, String)> {
+    static PATTERN: OnceLock<Option<Regex>> = OnceLock::new();
+    // The pattern is valid
+    let pattern = PATTERN
+        .get_or_init(|| Regex::new(NUMBER_PATTERN).ok())
+        .as_ref()?;
+    let found = pattern.find_iter(line).find(|m| m.end() > idx)?;
+    let mut range = found.range();
+    let mut text = found.as_str();
+    // A dash after a word is a separator, as in `item-3`, not a sign
+    let after_word = line[..range.start]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_');
+    if after_word && text.starts_with('-') {
+        range.start += 1;
+        text = &text[1..];
+    }
+
+    let replacement = match text.get(..2) {
+        Some(prefix @ ("0x" | "0X")) => {
+            let digits = &text[2..];
+            let value = u64::from_str_radix(digits, 16).ok()?;
+            let sum = value.wrapping_add_signed(delta);
+            let width = digits.len();
+            let sum = if digits.bytes().any(|b| b.is_ascii_uppercase()) {
+                format!("{sum:0width$X}")
+            } else {
+                format!("{sum:0width$x}")
+            };
+            format!("{prefix}{sum}")
+        }
+        _ => {
+            let value: i64 = text.parse().ok()?;
+            let sum = value.saturating_add(delta);
+            let digits = text.trim_start_matches('-');
+            let width = if digits.len() > 1 && digits.starts_with('0') {
+                digits.len()
+            } else {
+                1
+            };
+            let sign = if sum < 0 { "-" } else { "" };
+            format!("{sign}{:0width$}", sum.unsigned_abs())
+        }
+    };
+    Some((range, replacement))
+}
@@ -0,0 +1,138 @@
+//! Parsing of `set` arguments, e.g. `tabwidth=2`, `noexpandtab` or `textwidth?`.
+
+use crate::document::{Encoding, LineEnding};
+use crate::Theme;
+
+/// An option name with its abbreviation, in the order `set all` lists them.
+pub const NAMES: &[(&str, &str)] = &[
+    ("tabwidth", "ts"),
+    ("expandtab", "et"),
+    ("textwidth", "tw"),
+    ("autowrap", "autowrap"),
+    ("number", "nu"),
+    ("scrolloff", "so"),
+    ("sidescrolloff", "siso"),
+    ("jumpscroll", "js"),
+    ("subword", "subword"),
+    ("spell", "spell"),
+    ("fileformat", "ff"),
+    ("fileencoding", "fenc"),
+    ("theme", "theme"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Setting {
+    TabWidth(usize),
+    ExpandTab(bool),
+    TextWidth(Option<usize>),
+    AutoWrap(bool),
+    LineNumbers(bool),
+    ScrollOffset(usize),
+    SideScrollOffset(usize),
+    ScrollJump(bool),
+    Subword(bool),
+    Spell(bool),
+    /// Line endings the document is saved with.
+    FileFormat(LineEnding),
+    /// Encoding the document is saved in.
+    FileEncoding(Encoding),
+    Theme(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Assignment {
+    Set(Setting),
+    /// Show the current value of the option with this full name.
+    Query(&'static str),
+}
+
+/// Parses a single `set` argument.
+///
+/// # Errors
+/// If the option is unknown or its value is invalid.
+pub fn parse(arg: &str) -> Result<Assignment, String> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg, None),
+    };
+    let (name, query) = match name.strip_suffix('?') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let (name, negated) = match full_name(name) {
+        Some(full) => (full, false),
+        None => match name.strip_prefix("no").and_then(full_name) {
+            Some(full) => (full, true),
+            None if name == "wrap" || name == "nowrap" => {
+                return Err("Line wrapping isn't supported".into())
+            }
+            None => return Err(format!("Unknown option: {name}")),
+        },
+    };
+
+    if query {
+        return Ok(Assignment::Query(name));
+    }
+
+    let number = |min: usize| -> Result<usize, String> {
+        value
+            .and_then(|v| v.parse().ok())
+            .filter(|&n| n >= min)
+            .ok_or_else(|| format!("{name} needs a number of at least {min}"))
+    };
+
+    let setting = match (name, value, negated) {
+        ("expandtab", None, negated) => Setting::ExpandTab(!negated),
+        ("autowrap", None, negated) => Setting::AutoWrap(!negated),
+        ("number", None, negated) => Setting::LineNumbers(!negated),
+        ("jumpscroll", None, negated) => Setting::ScrollJump(!negated),
+        ("subword", None, negated) => Setting::Subword(!negated),
+        ("spell", None, negated) => Setting::Spell(!negated),
+        (_, _, true) => return Err(format!("{name} isn't a flag")),
+        ("expandtab" | "autowrap" | "number" | "jumpscroll" | "subword" | "spell", Some(_), _) => {
+            return Err(format!("{name} doesn't take a value"))
+        }
+        // Without a value, numbers and strings are shown
+        (_, None, false) => return Ok(Assignment::Query(name)),
+        ("tabwidth", _, _) => Setting::TabWidth(number(1)?),
+        ("textwidth", _, _) => Setting::TextWidth(Some(number(0)?).filter(|&n| n > 0)),
+        ("scrolloff", _, _) => Setting::ScrollOffset(number(0)?),
+        ("sidescrolloff", _, _) => Setting::SideScrollOffset(number(0)?),
+        ("fileformat", Some(format), _) => match format.to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Setting::FileFormat(LineEnding::Lf),
+            "crlf" | "dos" => Setting::FileFormat(LineEnding::Crlf),
+            _ => {
+                return Err(format!(
+                    "Unknown file format: {format} (available: lf, crlf)"
+                ))
+            }
+        },
+        ("fileencoding", Some(name), _) => match Encoding::by_name(name) {
+            Some(encoding) => Setting::FileEncoding(encoding),
+            None => {
+                return Err(format!(
+                    "Unknown encoding: {name} (available: {})",
+                    Encoding::NAMES.join(", ")
+                ))
+            }
+        },
+        ("theme", Some(theme), _) => {
+            if Theme::by_name(theme).is_none() {
+                return Err(format!(
+                    "Unknown theme: {theme} (available: {})",
+                    Theme::NAMES.join(", ")
+                ));
+            }
+            Setting::Theme(theme.into())
+        }
+        _ => return Err(format!("Unknown option: {name}")),
+    };
+    Ok(Assignment::Set(setting))
+}
+
+fn full_name(name: &str) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|&&(full, short)| name == full || name == short)
+        .map(|&(full, _)| full)
+}
@@ -0,0 +1,432 @@
+//! A shell running in a pseudo-terminal, shown in a panel below the document.
+//!
+//! Its output is kept as lines of plain text: carriage returns, backspaces and line erasing
+//! are honoured, so prompts and progress bars look right, but other escape sequences (colors,
+//! cursor addressing) are skipped. Full-screen programs won't display properly.
+
+use crate::event::Event;
+use crate::input::Chord;
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termion::event::Key;
+
+/// Lines of output kept, older ones are dropped.
+pub const MAX_SCROLLBACK: usize = 10_000;
+const TAB_WIDTH: usize = 8;
+/// How long the shell has to exit after SIGHUP before being killed.
+const HANGUP_GRACE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// After ESC.
+    Start,
+    /// Control sequence (`ESC [`), with its parameters so far.
+    Csi,
+    /// Operating system command (`ESC ]`), up to BEL or `ESC \`.
+    Osc,
+}
+
+/// Output of the shell, as lines of text.
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<Vec<char>>,
+    /// Column of the cursor on the last line.
+    column: usize,
+    /// Of the panel, which cursor movements stay within.
+    width: usize,
+    escape: Escape,
+    params: String,
+    /// Start of a UTF-8 sequence split between reads.
+    partial: Vec<u8>,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self {
+            lines: VecDeque::from([Vec::new()]),
+            column: 0,
+            width: usize::from(u16::MAX),
+            escape: Escape::None,
+            params: String::new(),
+            partial: Vec::new(),
+        }
+    }
+}
+
+impl Scrollback {
+    /// Appends raw output.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut bytes = [std::mem::take(&mut self.partial).as_slice(), bytes].concat();
+        loop {
+            match std::str::from_utf8(&bytes) {
+                Ok(text) => {
+                    text.chars().for_each(|c| self.feed_char(c));
+                    return;
+                }
+                Err(e) => {
+                    let (valid, rest) = bytes.split_at(e.valid_up_to());
+                    // SAFETY: `valid_up_to` bytes are valid UTF-8.
+                    let valid = unsafe { std::str::from_utf8_unchecked(valid) };
+                    valid.chars().for_each(|c| self.feed_char(c));
+                    match e.error_len() {
+                        // Incomplete, the rest comes with the next read
+                        None => {
+                            self.partial = rest.to_vec();
+                            return;
+                        }
+                        Some(len) => {
+                            self.feed_char(char::REPLACEMENT_CHARACTER);
+                            bytes = rest[len..].to_vec();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn feed_char(&mut self, c: char) {
+        match self.escape {
+            Escape::None => (),
+            Escape::Start => {
+                self.escape = match c {
+                    '[' => Escape::Csi,
+                    ']' => Escape::Osc,
+                    // Two-character sequences, and the end of `ESC \`
+                    _ => Escape::None,
+                };
+                self.params.clear();
+                return;
+            }
+            Escape::Csi => {
+                if ('\x40'..='\x7e').contains(&c) {
+                    self.escape = Escape::None;
+                    self.control_sequence(c);
+                } else {
+                    self.params.push(c);
+                }
+                return;
+            }
+            Escape::Osc => {
+                match c {
+                    '\x07' => self.escape = Escape::None,
+                    '\x1b' => self.escape = Escape::Start,
+                    _ => (),
+                }
+                return;
+            }
+        }
+
+        match c {
+            '\x1b' => self.escape = Escape::Start,
+            '\r' => self.column = 0,
+            '\n' => {
+                self.lines.push_back(Vec::new());
+                if self.lines.len() > MAX_SCROLLBACK {
+                    self.lines.pop_front();
+                }
+                self.column = 0;
+            }
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => {
+                let stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < stop {
+                    self.put(' ');
+                }
+            }
+            c if c.is_control() => (),
+            c => self.put(c),
+        }
+    }
+
+    /// Handles the few sequences that matter to line-oriented output.
+    fn control_sequence(&mut self, final_char: char) {
+        let n = self.params.parse::<usize>().unwrap_or(0);
+        let column = self.column;
+        // Movements stop at the edge, as in a terminal
+        let last_column = self.width.saturating_sub(1);
+        let line = self.current_line();
+        match final_char {
+            // Erase in line: to the end, to the start, or all of it
+            'K' => match n {
+                0 => line.truncate(column),
+                1 => line.iter_mut().take(column + 1).for_each(|c| *c = ' '),
+                _ => line.clear(),
+            },
+            'C' => self.column = column.saturating_add(n.max(1)).min(last_column),
+            'D' => self.column = column.saturating_sub(n.max(1)),
+            'G' => self.column = n.saturating_sub(1).min(last_column),
+            _ => (),
+        }
+    }
+
+    /// Sets the width of the panel.
+    pub fn set_width(&mut self, width: u16) {
+        self.width = usize::from(width.max(1));
+    }
+
+    fn current_line(&mut self) -> &mut Vec<char> {
+        if self.lines.is_empty() {
+            self.lines.push_back(Vec::new());
+        }
+        let last = self.lines.len() - 1;
+        &mut self.lines[last]
+    }
+
+    /// Writes `c` at the cursor, overwriting what was there.
+    fn put(&mut self, c: char) {
+        let column = self.column;
+        let line = self.current_line();
+        if column < line.len() {
+            line[column] = c;
+        } else {
+            line.resize(column, ' ');
+            line.push(c);
+        }
+        self.column += 1;
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The line at `idx`, from the oldest one kept.
+    #[must_use]
+    pub fn line(&self, idx: usize) -> Option<String> {
+        self.lines.get(idx).map(|line| line.iter().collect())
+    }
+
+    /// Column of the cursor, which is always on the last line.
+    #[must_use]
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+/// The shell and what it printed.
+pub struct Panel {
+    master: File,
+    child: Child,
+    pub scrollback: Scrollback,
+    /// How many lines the view is scrolled back from the end of the output.
+    pub scroll: usize,
+}
+
+impl Panel {
+    /// Starts `$SHELL` (or `sh`) in a pseudo-terminal of the given size. Its output is posted
+    /// as `Event::PanelOutput`, until `Event::PanelClosed`.
+    ///
+    /// # Errors
+    /// If the pseudo-terminal can't be opened or the shell can't be started.
+    pub fn spawn(rows: u16, cols: u16, events: Sender<Event>) -> Result<Self, io::Error> {
+        let (mut master, mut slave) = (0, 0);
+        let size = window_size(rows, cols);
+        // SAFETY: the pointers are valid for the duration of the call.
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `openpty` succeeded, so both are open file descriptors owned by no one else.
+        let (master, slave) =
+            unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        let shell = env::var_os("SHELL").unwrap_or_else(|| "sh".into());
+        let mut command = Command::new(shell);
+        command
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave)
+            // Escape sequences are mostly skipped, ask programs not to use them
+            .env("TERM", "dumb");
+        // SAFETY: only async-signal-safe functions are called between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                // Make the pseudo-terminal the controlling terminal of a new session, for job
+                // control and Ctrl-C to work
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // Close our copies of the slave side, or reading wouldn't fail once the shell exits
+        drop(command);
+
+        let master = File::from(master);
+        let mut reader = master.try_clone()?;
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(len) if len > 0 => {
+                        if events
+                            .send(Event::PanelOutput(buf[..len].to_vec()))
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    // EIO once the shell exited
+                    _ => {
+                        let _ = events.send(Event::PanelClosed);
+                        return;
+                    }
+                }
+            }
+        });
+
+        let mut scrollback = Scrollback::default();
+        scrollback.set_width(cols);
+        Ok(Self {
+            master,
+            child,
+            scrollback,
+            scroll: 0,
+        })
+    }
+
+    /// Sends `chord` to the shell, as a terminal would encode it.
+    ///
+    /// # Errors
+    /// If the shell can't be written to.
+    pub fn send_key(&mut self, chord: Chord) -> Result<(), io::Error> {
+        self.scroll = 0;
+        self.master.write_all(&encode_key(chord.key))
+    }
+
+    /// Tells the shell the panel's new size.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.scrollback.set_width(cols);
+        let size = window_size(rows, cols);
+        // SAFETY: `size` is valid for the duration of the call.
+        unsafe {
+            libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size);
+        }
+    }
+
+    /// Scrolls back by `lines` (or forward if negative), within the scrollback.
+    pub fn scroll_by(&mut self, lines: isize) {
+        let max = self.scrollback.len().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+}
+
+/// Stops the shell along with the editor, as closing a terminal window would.
+impl Drop for Panel {
+    fn drop(&mut self) {
+        if let Ok(pid) = libc::pid_t::try_from(self.child.id()) {
+            // SAFETY: sending a signal has no memory safety implications.
+            unsafe {
+                libc::kill(pid, libc::SIGHUP);
+            }
+        }
+        // Give it a moment to exit cleanly, then make sure it does, as shells may ignore SIGHUP
+        let deadline = Instant::now() + HANGUP_GRACE;
+        while matches!(self.child.try_wait(), Ok(None)) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn window_size(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Bytes a VT100-like terminal sends for `key`.
+fn encode_key(key: Key) -> Vec<u8> {
+    let sequence = |s: &str| s.as_bytes().to_vec();
+    match key {
+        // termion reports Enter as a newline
+        Key::Char('\n') => vec![b'\r'],
+        Key::Char(c) => c.to_string().into_bytes(),
+        Key::Alt(c) => format!("\x1b{c}").into_bytes(),
+        Key::Ctrl(c) => match c {
+            'a'..='z' => vec![c as u8 - b'a' + 1],
+            ' ' | '@' | '2' => vec![0],
+            '[' | '3' => vec![0x1b],
+            '\\' | '4' => vec![0x1c],
+            ']' | '5' => vec![0x1d],
+            '^' | '6' => vec![0x1e],
+            '_' | '7' | '/' => vec![0x1f],
+            c => c.to_string().into_bytes(),
+        },
+        Key::Backspace => vec![0x7f],
+        Key::Esc => vec![0x1b],
+        Key::Up => sequence("\x1b[A"),
+        Key::Down => sequence("\x1b[B"),
+        Key::Right => sequence("\x1b[C"),
+        Key::Left => sequence("\x1b[D"),
+        Key::Home => sequence("\x1b[H"),
+        Key::End => sequence("\x1b[F"),
+        Key::BackTab => sequence("\x1b[Z"),
+        Key::Insert => sequence("\x1b[2~"),
+        Key::Delete => sequence("\x1b[3~"),
+        Key::PageUp => sequence("\x1b[5~"),
+        Key::PageDown => sequence("\x1b[6~"),
+        Key::F(n @ 1..=4) => format!("\x1bO{}", char::from(b'P' + n - 1)).into_bytes(),
+        Key::F(n) => {
+            let code = match n {
+                5 => 15,
+                6..=10 => u16::from(n) + 11,
+                _ => u16::from(n) + 12,
+            };
+            format!("\x1b[{code}~").into_bytes()
+        }
+        Key::Null => vec![0],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carriage_returns_and_erasing_overwrite_lines() {
+        let mut scrollback = Scrollback::default();
+        scrollback.feed(b"50%\r100%\nab\x08c\x1b[Kd\n\x1b[31mred\x1b[0m");
+        let lines: Vec<_> = (0..scrollback.len())
+            .filter_map(|y| scrollback.line(y))
+            .collect();
+        assert_eq!(lines, ["100%", "acd", "red"]);
+    }
+
+    #[test]
+    fn cursor_movements_stay_within_the_panel() {
+        let mut scrollback = Scrollback::default();
+        scrollback.set_width(10);
+        scrollback.feed(b"\x1b[1000000000Cx");
+        assert_eq!(scrollback.line(0).unwrap(), format!("{}x", " ".repeat(9)));
+        scrollback.feed(b"\n\x1b[99999Gy");
+        assert_eq!(scrollback.line(1).unwrap().len(), 10);
+    }
+}
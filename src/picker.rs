@@ -0,0 +1,176 @@
+//! Picking one entry of a list by typing part of it, as in the buffer switcher or the file
+//! finder. The list is filtered with `fuzzy` and drawn in a popup, beside a preview of the
+//! selected entry.
+
+use crate::frame::{Frame, Line, Style};
+use crate::fuzzy;
+use crate::popup::{Popup, Rect};
+
+use std::cmp;
+
+use termion::event::Key;
+
+/// Height of the popups, border included.
+const MAX_HEIGHT: usize = 12;
+/// Lines of the selected entry worth previewing.
+pub const PREVIEW_LINES: usize = MAX_HEIGHT - 2;
+
+/// What a key did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    /// Changed the query or the selection.
+    Continue,
+    /// Enter, on the entry at this index.
+    Picked(usize),
+    Cancelled,
+}
+
+pub struct Picker {
+    title: String,
+    /// Text matched against the query, and line listed, of each entry.
+    entries: Vec<(String, String)>,
+    query: String,
+    /// Indices of the entries matching the query, best first.
+    matches: Vec<usize>,
+    /// Index in `matches`.
+    selected: usize,
+    /// Lines shown beside the list, for the selected entry.
+    preview: Vec<String>,
+}
+
+impl Picker {
+    #[must_use]
+    pub fn new(title: &str, entries: Vec<(String, String)>) -> Self {
+        let mut picker = Self {
+            title: title.into(),
+            entries,
+            matches: Vec::new(),
+            query: String::new(),
+            selected: 0,
+            preview: Vec::new(),
+        };
+        picker.filter();
+        picker
+    }
+
+    /// Index of the selected entry, if any matches.
+    #[must_use]
+    pub fn selected(&self) -> Option<usize> {
+        self.matches.get(self.selected).copied()
+    }
+
+    /// The prompt and query, for the message bar.
+    #[must_use]
+    pub fn prompt(&self) -> String {
+        format!("{}: {}\u{258f}", self.title, self.query)
+    }
+
+    pub fn set_preview(&mut self, preview: Vec<String>) {
+        self.preview = preview;
+    }
+
+    /// Edits the query or moves the selection.
+    pub fn handle_key(&mut self, key: Key) -> Input {
+        match key {
+            Key::Char('\n') => return self.selected().map_or(Input::Cancelled, Input::Picked),
+            Key::Esc | Key::Ctrl('q') => return Input::Cancelled,
+            Key::Up | Key::BackTab => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('\t') => {
+                self.selected = cmp::min(self.selected + 1, self.matches.len().saturating_sub(1));
+            }
+            Key::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            Key::Char(c) => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => (),
+        }
+        Input::Continue
+    }
+
+    /// Lists the entries matching the query, selecting the best one.
+    fn filter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (key, _))| Some((fuzzy::score(&self.query, key)?, idx)))
+            .collect();
+        // Stable, so ties keep their order. Without a query, all entries are listed in order
+        if !self.query.is_empty() {
+            scored.sort_by_key(|&(score, _)| cmp::Reverse(score));
+        }
+        self.matches = scored.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = 0;
+    }
+
+    /// Draws the list at the bottom of `frame`, `width` columns wide, with the preview on its
+    /// right if there is one.
+    pub fn draw(&self, frame: &mut Frame, width: usize, style: Style, selected_style: Style) {
+        let lines = self
+            .matches
+            .iter()
+            .enumerate()
+            .map(|(idx, &entry)| {
+                let style = if idx == self.selected {
+                    selected_style
+                } else {
+                    style
+                };
+                let mut line = Line::default();
+                line.push(style, &format!(" {} ", self.entries[entry].1));
+                line
+            })
+            .collect();
+        let mut list = Popup::new(&self.title, lines);
+
+        let rows = cmp::max(self.matches.len(), self.preview.len());
+        let height = cmp::min(cmp::min(rows + 2, MAX_HEIGHT), frame.len());
+        let y = frame.len() - height;
+        list.scroll_to(self.selected, height);
+        if self.preview.is_empty() {
+            let (list_width, _) = list.size(width, height);
+            let x = (width - list_width) / 2;
+            list.draw(
+                frame,
+                Rect {
+                    x,
+                    y,
+                    width: list_width,
+                    height,
+                },
+                style,
+            );
+            return;
+        }
+
+        let (list_width, _) = list.size(width / 2, height);
+        list.draw(
+            frame,
+            Rect {
+                x: 0,
+                y,
+                width: list_width,
+                height,
+            },
+            style,
+        );
+        let title = self
+            .selected()
+            .map_or("", |idx| self.entries[idx].0.as_str());
+        let preview = Popup::new(
+            title,
+            self.preview.iter().map(|l| l.as_str().into()).collect(),
+        );
+        let area = Rect {
+            x: list_width,
+            y,
+            width: width - list_width,
+            height,
+        };
+        preview.draw(frame, area, style);
+    }
+}
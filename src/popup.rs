@@ -0,0 +1,119 @@
+//! Bordered boxes drawn over the screen, for pickers, menus and dialogs.
+//!
+//! Frames are drawn from scratch, so whatever a popup covered reappears in the next frame
+//! without it.
+
+use crate::frame::{Frame, Line, Style};
+use crate::TruncateGraphemes;
+
+use std::cmp;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Part of the screen, in columns and rows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Lines in a box with a title, scrolled when they don't all fit.
+#[derive(Clone, Debug, Default)]
+pub struct Popup {
+    title: String,
+    lines: Vec<Line>,
+    /// First line shown.
+    scroll: usize,
+}
+
+impl Popup {
+    #[must_use]
+    pub fn new(title: &str, lines: Vec<Line>) -> Self {
+        Self {
+            title: title.into(),
+            lines,
+            scroll: 0,
+        }
+    }
+
+    /// Width and height of the box fitting the title and lines, border included, at most
+    /// `max_width` by `max_height`.
+    #[must_use]
+    pub fn size(&self, max_width: usize, max_height: usize) -> (usize, usize) {
+        let title_width = if self.title.is_empty() {
+            0
+        } else {
+            self.title.graphemes(true).count() + 2
+        };
+        let content_width = self.lines.iter().map(Line::len).max().unwrap_or(0);
+        (
+            cmp::min(cmp::max(title_width, content_width) + 2, max_width),
+            cmp::min(self.lines.len() + 2, max_height),
+        )
+    }
+
+    /// Scrolls as little as possible for line `idx` to show in a box `height` rows high.
+    pub fn scroll_to(&mut self, idx: usize, height: usize) {
+        let rows = height.saturating_sub(2);
+        if idx < self.scroll {
+            self.scroll = idx;
+        } else if rows > 0 && idx >= self.scroll + rows {
+            self.scroll = idx + 1 - rows;
+        }
+    }
+
+    /// Draws the box over `area` of `frame`, lines being padded or cut to its width. The
+    /// bottom border tells which lines are shown when they don't all fit.
+    pub fn draw(&self, frame: &mut Frame, area: Rect, style: Style) {
+        let height = cmp::min(area.height, frame.len().saturating_sub(area.y));
+        if area.width < 2 || height < 2 {
+            return;
+        }
+        let inner_width = area.width - 2;
+        let rows = height - 2;
+
+        let border = |left: &str, label: &str, right: &str| {
+            let mut label = label.to_string();
+            label.truncate_graphemes(inner_width);
+            let mut line = Line::default();
+            line.push(style, left);
+            line.push(style, &label);
+            line.push(
+                style,
+                &"─".repeat(inner_width - label.graphemes(true).count()),
+            );
+            line.push(style, right);
+            line
+        };
+
+        let title = if self.title.is_empty() {
+            String::new()
+        } else {
+            format!(" {} ", self.title)
+        };
+        frame[area.y].overlay(area.x, &border("┌", &title, "┐"));
+        for row in 0..rows {
+            let mut line = Line::default();
+            line.push(style, "│");
+            if let Some(content) = self.lines.get(self.scroll + row) {
+                line.append(&content.slice(0..inner_width));
+            }
+            line.pad(style, inner_width + 1);
+            line.push(style, "│");
+            frame[area.y + 1 + row].overlay(area.x, &line);
+        }
+        let position = if self.lines.len() > rows {
+            format!(
+                " {}-{}/{} ",
+                self.scroll + 1,
+                cmp::min(self.scroll + rows, self.lines.len()),
+                self.lines.len()
+            )
+        } else {
+            String::new()
+        };
+        frame[area.y + height - 1].overlay(area.x, &border("└", &position, "┘"));
+    }
+}
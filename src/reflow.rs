@@ -0,0 +1,72 @@
+//! Rewrapping paragraphs of prose to a text width, keeping their indentation and comment prefix.
+
+use crate::row;
+
+/// Leading indentation and comment marker of `line`, with the spaces after them. Repeated or
+/// `!` marker characters belong to the prefix, as in `///` or `//!` for `//`.
+#[must_use]
+pub fn prefix<'a>(line: &'a str, comment: Option<&str>) -> &'a str {
+    let indent = line.len() - line.trim_start().len();
+    let Some(comment) = comment.filter(|c| line[indent..].starts_with(c)) else {
+        return &line[..indent];
+    };
+    let mut end = indent + comment.len();
+    end += line[end..]
+        .chars()
+        .take_while(|&c| c == '!' || (!c.is_alphanumeric() && comment.contains(c)))
+        .map(char::len_utf8)
+        .sum::<usize>();
+    end += line[end..].len() - line[end..].trim_start().len();
+    &line[..end]
+}
+
+/// Whether `line` has nothing but its prefix, so it separates paragraphs.
+#[must_use]
+pub fn is_blank(line: &str, comment: Option<&str>) -> bool {
+    line.len() == prefix(line, comment).len()
+}
+
+/// Joins the words of `lines` and wraps them so that lines fit in `width` columns where possible,
+/// each starting with the first line's prefix.
+#[must_use]
+pub fn reflow(
+    lines: &[&str],
+    width: usize,
+    tab_width: usize,
+    comment: Option<&str>,
+) -> Vec<String> {
+    let Some(first) = lines.first() else {
+        return Vec::new();
+    };
+    let lead = prefix(first, comment);
+    let lead_width = lead.chars().fold(0, |col, c| {
+        col + if c == '\t' {
+            row::tab_stop_width(col, tab_width)
+        } else {
+            1
+        }
+    });
+    let words = lines
+        .iter()
+        .flat_map(|line| line[prefix(line, comment).len()..].split_whitespace());
+
+    let mut wrapped = Vec::new();
+    let mut current = String::from(lead);
+    let mut current_width = lead_width;
+    for word in words {
+        let word_width = word.chars().count();
+        if current_width > lead_width && current_width + 1 + word_width > width {
+            wrapped.push(current);
+            current = String::from(lead);
+            current_width = lead_width;
+        }
+        if current_width > lead_width {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(word);
+        current_width += word_width;
+    }
+    wrapped.push(current);
+    wrapped
+}
@@ -2,26 +2,37 @@ use crate::SearchDirection;
 
 use std::cmp;
 use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use regex::Regex;
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Source of row revisions, shared by all rows so that a revision identifies one content.
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+
 /// A grapheme-based string.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Row {
     content: String,
-    grapheme_count: usize,
+    /// Byte index at which each grapheme starts, kept up to date on edits so that indexing by
+    /// grapheme doesn't segment the whole string.
+    boundaries: Vec<usize>,
+    revision: u64,
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self::from(String::new())
+    }
 }
 
 impl From<String> for Row {
     fn from(string: String) -> Self {
-        let mut row = Self {
+        Self {
+            boundaries: boundaries(&string, 0).collect(),
             content: string,
-            grapheme_count: 0,
-        };
-
-        row.update_grapheme_count();
-        row
+            revision: next_revision(),
+        }
     }
 }
 
@@ -32,63 +43,103 @@ impl From<&str> for Row {
 }
 
 impl Row {
+    /// Returns the display cells for the columns in `range`, along with the index of the
+    /// grapheme each cell belongs to. Tabs are expanded up to the next multiple of `tab_width`.
     #[must_use]
-    pub fn render(&self, range: Range<usize>) -> String {
-        let end = cmp::min(range.end, self.content.len());
-        let start = cmp::min(range.start, end);
+    pub fn render(&self, range: Range<usize>, tab_width: usize) -> Vec<(usize, &str)> {
+        let mut result = Vec::new();
+        let mut col = 0;
 
-        let mut result = String::new();
+        for (idx, grapheme) in self.graphemes().enumerate() {
+            if col >= range.end {
+                break;
+            }
 
-        for grapheme in self.content.graphemes(true).skip(start).take(end - start) {
-            result.push_str(match grapheme {
-                "\t" => " ",
-                g => g,
-            });
+            let (cell, width) = match grapheme {
+                "\t" => (" ", tab_stop_width(col, tab_width)),
+                g => (g, 1),
+            };
+            for c in col..col + width {
+                if range.contains(&c) {
+                    result.push((idx, cell));
+                }
+            }
+            col += width;
         }
 
         result
     }
 
+    /// Returns the display column of the grapheme at `idx`, taking tabs into account.
+    #[must_use]
+    pub fn render_x(&self, idx: usize, tab_width: usize) -> usize {
+        self.graphemes()
+            .take(idx)
+            .fold(0, |col, grapheme| match grapheme {
+                "\t" => col + tab_stop_width(col, tab_width),
+                _ => col + 1,
+            })
+    }
+
+    /// Returns the display width of the leading whitespace.
+    #[must_use]
+    pub fn indent_width(&self, tab_width: usize) -> usize {
+        self.render_x(self.indent_len(), tab_width)
+    }
+
+    /// Returns the number of leading whitespace graphemes.
+    #[must_use]
+    pub fn indent_len(&self) -> usize {
+        self.graphemes()
+            .take_while(|g| matches!(*g, " " | "\t"))
+            .count()
+    }
+
     #[must_use]
     pub fn find(&self, query: &Regex, limit: usize, direction: SearchDirection) -> Option<usize> {
-        if limit > self.grapheme_count {
+        if limit > self.len() {
             return None;
         }
 
         let (start, end) = match direction {
-            SearchDirection::Forward => (limit, self.grapheme_count),
+            SearchDirection::Forward => (limit, self.len()),
             SearchDirection::Backward => (0, limit),
         };
 
-        let substring: String = self
-            .content
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-            .collect();
+        let offset = self.byte_index(start);
+        let substring = &self.content[offset..self.byte_index(end)];
 
         let target_byte_idx = match direction {
-            SearchDirection::Forward => query.find(&substring)?.start(),
-            SearchDirection::Backward => query.find_iter(&substring).last()?.start(),
+            SearchDirection::Forward => query.find(substring)?.start(),
+            SearchDirection::Backward => query.find_iter(substring).last()?.start(),
         };
 
-        substring
-            .grapheme_indices(true)
-            .enumerate()
-            .find_map(|(i, (byte_idx, _grapheme))| {
-                if byte_idx == target_byte_idx {
-                    // grapheme_idx indexes substring: add substring offset
-                    Some(i + start)
-                } else {
-                    None
-                }
-            })
+        // Matches starting in the middle of a grapheme don't count
+        self.boundaries
+            .binary_search(&(offset + target_byte_idx))
+            .ok()
+    }
+
+    /// Returns the grapheme ranges of every non-overlapping match of `query`.
+    #[must_use]
+    pub fn find_all(&self, query: &Regex) -> Vec<Range<usize>> {
+        // Matches may end in the middle of a grapheme: round up to the next one
+        let to_grapheme_idx = |byte_idx: usize| {
+            self.boundaries
+                .partition_point(|&boundary| boundary < byte_idx)
+        };
+
+        query
+            .find_iter(&self.content)
+            .filter(|m| !m.is_empty())
+            .map(|m| to_grapheme_idx(m.start())..to_grapheme_idx(m.end()))
+            .collect()
     }
 
     #[must_use]
     /// The length of the Row, in graphemes (as defined by Unicode).
     pub fn len(&self) -> usize {
-        self.grapheme_count
+        self.boundaries.len()
     }
 
     #[must_use]
@@ -104,57 +155,170 @@ impl Row {
 
     /// Inserts character at index `idx` or appends if `idx` >= `len()`.
     pub fn insert_or_append(&mut self, idx: usize, c: char) {
-        if idx >= self.len() {
-            self.content.push(c);
-        } else {
-            // Handle graphemes
-            let mut result: String = self.content.graphemes(true).take(idx).collect();
-            let remainder: String = self.content.graphemes(true).skip(idx).collect();
-            result.push(c);
-            result.push_str(&remainder);
-            self.content = result;
-        }
-
-        self.update_grapheme_count()
+        let byte_idx = self.byte_index(idx);
+        self.splice(byte_idx..byte_idx, c.encode_utf8(&mut [0; 4]));
     }
 
     pub fn push(&mut self, other: Self) {
-        self.content.push_str(&other.content);
-        self.update_grapheme_count();
+        let end = self.content.len();
+        self.splice(end..end, &other.content);
     }
 
     /// Noop if `idx` >= `len()`.
     pub fn delete(&mut self, idx: usize) {
-        if idx >= self.len() {
-            return;
-        } else {
-            // Handle graphemes
-            let mut result: String = self.content.graphemes(true).take(idx).collect();
-            // Skip over grapheme to delete
-            let remainder: String = self.content.graphemes(true).skip(idx + 1).collect();
-            result.push_str(&remainder);
-            self.content = result;
+        if idx < self.len() {
+            self.splice(self.byte_index(idx)..self.byte_index(idx + 1), "");
         }
+    }
 
-        self.update_grapheme_count()
+    /// Removes trailing whitespace, returns whether there was any.
+    pub fn trim_end(&mut self) -> bool {
+        let len = self.content.trim_end().len();
+        if len == self.content.len() {
+            return false;
+        }
+        self.splice(len..self.content.len(), "");
+        true
     }
 
     /// Returns empty Row if `idx` >= `len()`.
     pub fn split(&mut self, idx: usize) -> Self {
-        // Handle graphemes
-        let before: String = self.content.graphemes(true).take(idx).collect();
-        let after: String = self.content.graphemes(true).skip(idx).collect();
+        let byte_idx = self.byte_index(idx);
+        let after = self.content.split_off(byte_idx);
+        // Both halves start at a boundary, so neither needs segmenting again
+        let boundaries = self
+            .boundaries
+            .split_off(cmp::min(idx, self.len()))
+            .into_iter()
+            .map(|boundary| boundary - byte_idx)
+            .collect();
+        self.revision = next_revision();
+        Self {
+            content: after,
+            boundaries,
+            revision: next_revision(),
+        }
+    }
+
+    /// Returns the byte index at which the grapheme at `idx` starts, or `len_bytes()` if `idx` is
+    /// past the end.
+    #[must_use]
+    pub fn byte_index(&self, idx: usize) -> usize {
+        self.boundaries
+            .get(idx)
+            .copied()
+            .unwrap_or(self.content.len())
+    }
+
+    /// Index of the first grapheme starting at or after byte `byte`, the inverse of `byte_index`.
+    #[must_use]
+    pub fn grapheme_index(&self, byte: usize) -> usize {
+        self.boundaries.partition_point(|&start| start < byte)
+    }
+
+    #[must_use]
+    pub fn grapheme(&self, idx: usize) -> Option<&str> {
+        let start = *self.boundaries.get(idx)?;
+        Some(&self.content[start..self.byte_index(idx + 1)])
+    }
+
+    fn graphemes(&self) -> impl Iterator<Item = &str> {
+        (0..self.len()).filter_map(|idx| self.grapheme(idx))
+    }
 
-        self.content = before;
-        self.update_grapheme_count();
-        Self::from(after)
+    /// Changes whenever the row is edited, for caches of what is derived from its content.
+    #[must_use]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.content
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         self.content.as_bytes()
     }
 
-    fn update_grapheme_count(&mut self) {
-        self.grapheme_count = self.content.graphemes(true).count()
+    /// Checks that the cached grapheme boundaries are right and that the row holds a single line.
+    ///
+    /// # Errors
+    /// Describing the first broken invariant.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        if !self
+            .boundaries
+            .iter()
+            .copied()
+            .eq(boundaries(&self.content, 0))
+        {
+            return Err(format!(
+                "{:?} has graphemes at {:?}, not {:?}",
+                self.content,
+                boundaries(&self.content, 0).collect::<Vec<_>>(),
+                self.boundaries
+            ));
+        }
+        if self.content.contains('\n') {
+            return Err(format!("{:?} contains a newline", self.content));
+        }
+        Ok(())
     }
+
+    /// Replaces the bytes in `range` with `text`, segmenting again only around the edit: from
+    /// the grapheme before it, which a combining mark could join, until the boundaries match the
+    /// old ones again.
+    fn splice(&mut self, range: Range<usize>, text: &str) {
+        let first = self
+            .boundaries
+            .partition_point(|&boundary| boundary <= range.start)
+            .saturating_sub(2);
+        let start = self.byte_index(first);
+        let edit_end = range.start + text.len();
+        let old_end = range.end;
+        self.content.replace_range(range, text);
+        self.revision = next_revision();
+
+        // Old boundaries after the edit, in new byte indices
+        let old_tail = self
+            .boundaries
+            .partition_point(|&boundary| boundary < old_end);
+        let shift = |boundary: usize| boundary + edit_end - old_end;
+
+        let mut updated = self.boundaries[..first].to_vec();
+        let mut resync = None;
+        for boundary in boundaries(&self.content[start..], start) {
+            if boundary >= edit_end {
+                let old =
+                    self.boundaries[old_tail..].binary_search_by(|&old| shift(old).cmp(&boundary));
+                if let Ok(idx) = old {
+                    resync = Some(old_tail + idx);
+                    break;
+                }
+            }
+            updated.push(boundary);
+        }
+        if let Some(idx) = resync {
+            updated.extend(self.boundaries[idx..].iter().map(|&old| shift(old)));
+        }
+        self.boundaries = updated;
+    }
+}
+
+/// A revision nothing had before, for rows and documents.
+#[must_use]
+pub fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Byte indices of the graphemes of `text`, offset by `offset`.
+fn boundaries(text: &str, offset: usize) -> impl Iterator<Item = usize> + '_ {
+    text.grapheme_indices(true)
+        .map(move |(byte_idx, _)| offset + byte_idx)
+}
+
+/// Number of columns a tab starting at `col` spans.
+pub fn tab_stop_width(col: usize, tab_width: usize) -> usize {
+    let tab_width = cmp::max(tab_width, 1);
+    tab_width - col % tab_width
 }
@@ -0,0 +1,104 @@
+//! Scripts extending the editor, made of command lines:
+//!
+//! ```text
+//! # Comments start with `#`
+//! set tabwidth=2
+//! command dup copy_line | insert \n
+//! map <C-d> dup
+//! map <Leader>s write
+//! hook before_save set noexpandtab
+//! ```
+//!
+//! `command NAME BODY` defines `:NAME`, running each `|`-separated command line of `BODY`.
+//! `map KEYS LINE` binds a key sequence to a command line, and `hook HOOK LINE` runs it whenever `HOOK`
+//! fires. Anything else runs when the script is sourced.
+//!
+//! Scripts reuse the command line rather than embedding a language such as Rhai or Lua, so
+//! anything that can be typed after `:` can be scripted, with no second API to keep stable.
+
+use crate::hooks::Hook;
+use crate::input::{self, Chord};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Statement {
+    Command { name: String, body: Vec<String> },
+    Map { keys: Vec<Chord>, line: String },
+    Hook { hook: Hook, line: String },
+    Run(String),
+}
+
+/// # Errors
+/// On the first invalid statement, with its 1-based line number.
+pub fn parse(text: &str, leader: Chord) -> Result<Vec<Statement>, String> {
+    let mut statements = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}", idx + 1);
+
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        statements.push(match keyword {
+            "command" => {
+                let (name, body) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| error("usage: command NAME BODY"))?;
+                if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    return Err(error(
+                        "command names can only contain letters, digits and `_`",
+                    ));
+                }
+                Statement::Command {
+                    name: name.into(),
+                    body: split_body(body),
+                }
+            }
+            "map" => {
+                let (keys, line) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| error("usage: map KEYS COMMAND"))?;
+                Statement::Map {
+                    keys: input::parse_sequence(keys, leader).map_err(|e| error(&e))?,
+                    line: line.trim().into(),
+                }
+            }
+            "hook" => {
+                let (hook, line) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| error("usage: hook HOOK COMMAND"))?;
+                Statement::Hook {
+                    hook: hook.parse().map_err(|e: String| error(&e))?,
+                    line: line.trim().into(),
+                }
+            }
+            _ => Statement::Run(line.into()),
+        });
+    }
+    Ok(statements)
+}
+
+/// Splits on `|`, except when escaped as `\|`.
+fn split_body(body: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with('|') => {
+                chars.next();
+                current.push('|');
+            }
+            '|' => lines.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    lines.push(current);
+
+    lines
+        .into_iter()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
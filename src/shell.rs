@@ -0,0 +1,45 @@
+//! Running commands through the user's shell.
+
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+/// `command` run by `$SHELL -c`, falling back to `sh`.
+#[must_use]
+pub fn command(command: &str) -> Command {
+    let shell = env::var_os("SHELL").unwrap_or_else(|| "sh".into());
+    let mut shell = Command::new(shell);
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Runs `command` with `input` as its standard input, capturing its output.
+///
+/// # Errors
+/// If the shell can't be started.
+pub fn pipe_through(command: &str, input: String) -> Result<Output, io::Error> {
+    let mut child = self::command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Written from another thread, or both sides could block on full pipes
+    let mut stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        if let Some(stdin) = &mut stdin {
+            // The command may not read all of its input, which isn't an error
+            let _ = stdin.write_all(input.as_bytes());
+        }
+    });
+    let output = child.wait_with_output();
+    let _ = writer.join();
+    output
+}
+
+/// Quotes `arg` so that the shell passes it as a single argument, as is.
+#[must_use]
+pub fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
@@ -0,0 +1,175 @@
+//! Spell checking against a word list with one word per line, like `/usr/share/dict/words`.
+
+use std::cmp;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Word list used when the config doesn't name one.
+pub const DEFAULT_DICTIONARY: &str = "/usr/share/dict/words";
+
+/// Suggestions are at most this many edits away from the misspelled word.
+const MAX_DISTANCE: usize = 2;
+
+#[derive(Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl<S: Into<String>> FromIterator<S> for Dictionary {
+    fn from_iter<I: IntoIterator<Item = S>>(words: I) -> Self {
+        Self {
+            words: words.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Dictionary {
+    /// # Errors
+    /// If the word list can't be read.
+    pub fn load(path: &Path) -> Result<Self, io::Error> {
+        let text = fs::read_to_string(path)?;
+        Ok(text
+            .lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .collect())
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Whether `word` is spelled correctly. Capitalized and all caps forms of known words are
+    /// accepted, but not the other way around: `paris` is wrong if only `Paris` is known.
+    #[must_use]
+    pub fn check(&self, word: &str) -> bool {
+        self.words.contains(word) || self.words.contains(&word.to_lowercase())
+    }
+
+    /// Grapheme ranges of the misspelled words in `text`.
+    ///
+    /// Words with digits or underscores, and mixed case words like `camelCase`, are taken to be
+    /// identifiers and ignored.
+    #[must_use]
+    pub fn misspelled(&self, text: &str) -> Vec<Range<usize>> {
+        words(text)
+            .into_iter()
+            .filter(|(_, word)| is_checkable(word) && !self.check(word))
+            .map(|(range, _)| range)
+            .collect()
+    }
+
+    /// Known words close to `word`, closest first, with its capitalization.
+    #[must_use]
+    pub fn suggestions(&self, word: &str, max: usize) -> Vec<String> {
+        let lower = word.to_lowercase();
+        let target: Vec<char> = lower.chars().collect();
+
+        let mut found: Vec<(usize, &str)> = self
+            .words
+            .iter()
+            .filter(|candidate| candidate.chars().count().abs_diff(target.len()) <= MAX_DISTANCE)
+            .filter_map(|candidate| {
+                let chars: Vec<char> = candidate.to_lowercase().chars().collect();
+                let distance = distance(&target, &chars);
+                (distance <= MAX_DISTANCE).then_some((distance, candidate.as_str()))
+            })
+            .collect();
+        found.sort_unstable();
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for (_, candidate) in found {
+            let suggestion = match_case(word, candidate);
+            if suggestion != word && !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+            if suggestions.len() == max {
+                break;
+            }
+        }
+        suggestions
+    }
+}
+
+/// Grapheme ranges of the words of `text`, along with them. Apostrophes are part of words when
+/// between letters, as in `don't`.
+#[must_use]
+pub fn words(text: &str) -> Vec<(Range<usize>, String)> {
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    let is_word = |g: &str| g.chars().all(|c| c.is_alphanumeric() || c == '_');
+
+    let mut words = Vec::new();
+    let mut idx = 0;
+    while idx < graphemes.len() {
+        if !is_word(graphemes[idx]) {
+            idx += 1;
+            continue;
+        }
+        let start = idx;
+        while idx < graphemes.len()
+            && (is_word(graphemes[idx])
+                || (graphemes[idx] == "'"
+                    && graphemes.get(idx + 1).is_some_and(|&g| is_word(g))
+                    && idx > start))
+        {
+            idx += 1;
+        }
+        words.push((start..idx, graphemes[start..idx].concat()));
+    }
+    words
+}
+
+fn is_checkable(word: &str) -> bool {
+    let mut chars = word.chars();
+    let rest_upper = chars.clone().skip(1).any(char::is_uppercase);
+    let all_upper = !word.chars().any(char::is_lowercase);
+    word.chars().count() > 1
+        && chars.all(|c| c.is_alphabetic() || c == '\'')
+        && (!rest_upper || all_upper)
+}
+
+/// Gives `suggestion` the capitalization of `word`.
+fn match_case(word: &str, suggestion: &str) -> String {
+    if word.chars().count() > 1 && !word.chars().any(char::is_lowercase) {
+        return suggestion.to_uppercase();
+    }
+    match (word.chars().next(), suggestion.chars().next()) {
+        (Some(first), Some(c)) if first.is_uppercase() => {
+            c.to_uppercase().chain(suggestion.chars().skip(1)).collect()
+        }
+        _ => suggestion.into(),
+    }
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and transpositions
+/// of adjacent characters.
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = cmp::min(
+                cmp::min(rows[i - 1][j] + 1, rows[i][j - 1] + 1),
+                rows[i - 1][j - 1] + cost,
+            );
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = cmp::min(best, rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = best;
+        }
+    }
+    rows[a.len()][b.len()]
+}
@@ -0,0 +1,79 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Default layout of the status bar, see `render`.
+pub const DEFAULT_FORMAT: &str =
+    "{file}{modified}{mode}{keys}{>}{jobs}{branch}{filetype} | {encoding} | {eol}  {percent} [{line:>4}:{col:<2}]";
+
+/// Evaluates a status bar `format` string.
+///
+/// `{name}` placeholders are replaced by `field(name)`, optionally padded with a `{name:>N}`
+/// (right-aligned) or `{name:<N}` (left-aligned) width. Unknown fields are kept verbatim, `{{` and
+/// `}}` are literal braces. Everything after `{>}` is right-aligned, and the result is padded or
+/// truncated to exactly `width` graphemes.
+#[must_use]
+pub fn render<F>(format: &str, width: usize, field: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut left = String::new();
+    let mut right = String::new();
+    let mut out = &mut left;
+
+    let mut rest = format;
+    while let Some(idx) = rest.find(['{', '}']) {
+        out.push_str(&rest[..idx]);
+        let tail = &rest[idx..];
+
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+
+        let Some(end) = tail.find('}').filter(|_| tail.starts_with('{')) else {
+            // Stray closing brace
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+            continue;
+        };
+
+        let placeholder = &tail[1..end];
+        rest = &tail[end + 1..];
+
+        if placeholder == ">" {
+            out = &mut right;
+            continue;
+        }
+
+        let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+        match field(name) {
+            Some(value) => out.push_str(&pad(&value, spec)),
+            None => out.push_str(&tail[..=end]),
+        }
+    }
+    out.push_str(rest);
+
+    let used = left.graphemes(true).count() + right.graphemes(true).count();
+    let mut line = format!("{left}{}{right}", " ".repeat(width.saturating_sub(used)));
+    if let Some((idx, _)) = line.grapheme_indices(true).nth(width) {
+        line.truncate(idx);
+    }
+    line
+}
+
+/// Pads `value` according to a `>N` or `<N` spec.
+fn pad(value: &str, spec: &str) -> String {
+    let (right_align, width) = match spec.chars().next() {
+        Some('>') => (true, &spec[1..]),
+        Some('<') => (false, &spec[1..]),
+        _ => (false, spec),
+    };
+    let width: usize = width.parse().unwrap_or(0);
+    let padding = " ".repeat(width.saturating_sub(value.graphemes(true).count()));
+
+    if right_align {
+        format!("{padding}{value}")
+    } else {
+        format!("{value}{padding}")
+    }
+}
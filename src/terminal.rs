@@ -1,9 +1,15 @@
+use crate::event::{Event, Mouse};
+use crate::frame::Line;
+use crate::input::{self, Decoder, Input};
 use crate::Position;
+use std::fmt::Display;
 use std::io::{self, Write};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
 
-use termion::event::Key;
-use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{AlternateScreen, IntoAlternateScreen, ToAlternateScreen, ToMainScreen};
 
 #[derive(Clone, Copy)]
 pub struct Size {
@@ -11,79 +17,408 @@ pub struct Size {
     pub height: u16,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct RgbColor(pub u8, pub u8, pub u8);
 
-pub struct Terminal {
-    _stdout: RawTerminal<io::Stdout>,
+impl RgbColor {
+    /// Parses `#rrggbb` or `#rgb` notation.
+    #[must_use]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let digits = hex.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize, len: usize| {
+            let value = u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).ok()?;
+            // `#abc` is short for `#aabbcc`
+            Some(if len == 1 { value * 17 } else { value })
+        };
+        let len = match digits.len() {
+            6 => 2,
+            3 => 1,
+            _ => return None,
+        };
+        Some(Self(channel(0, len)?, channel(1, len)?, channel(2, len)?))
+    }
+}
+
+/// Most terminals silently drop OSC 52 sequences above ~100kB of payload.
+pub const CLIPBOARD_MAX_ENCODED_LEN: usize = 100_000;
+/// GNU screen limits the length of a passthrough string.
+const SCREEN_CHUNK_LEN: usize = 76;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+/// Where the editor draws and gets its input from.
+///
+/// Output may be buffered until `flush`.
+pub trait Terminal {
+    /// Size available for the document, leaving room for the status and message bars.
+    fn size(&self) -> Size;
+
+    /// Re-reads the terminal size, when it was resized.
+    /// # Errors
+    /// If the size can't be queried.
+    fn update_size(&mut self) -> Result<Size, io::Error>;
+
+    /// Starts delivering input as `Event::Key`s.
+    fn start_input(&mut self, events: Sender<Event>);
+
+    fn clear_screen(&mut self);
+
+    fn clear_current_line(&mut self);
+
+    /// 0-based coords
+    fn cursor_position(&mut self, pos: Position);
+
+    /// Prints plain text at the cursor.
+    fn print(&mut self, text: &str);
+
+    /// Prints `line` at the cursor, resetting colors afterwards.
+    fn print_line(&mut self, line: &Line);
+
+    fn set_cursor_shape(&mut self, shape: CursorShape);
+
+    /// Restores the terminal's default cursor shape.
+    fn reset_cursor_shape(&mut self);
+
+    /// Copies `text` to the system clipboard. Returns `false` without copying if it is too
+    /// large.
+    fn copy_to_clipboard(&mut self, text: &str) -> bool;
+
+    /// Leaves the alternate screen and raw mode, so that another program can use the terminal.
+    /// # Errors
+    /// If the terminal mode can't be changed.
+    fn pause(&mut self) -> Result<(), io::Error>;
+
+    /// Undoes `pause`. The screen needs a full redraw afterwards.
+    /// # Errors
+    /// If the terminal mode can't be changed.
+    fn resume(&mut self) -> Result<(), io::Error>;
+
+    /// Gives the terminal back to the shell and stops the process, as Ctrl-Z would in cooked mode.
+    /// Returns once the process is resumed.
+    /// # Errors
+    /// If the terminal mode can't be changed.
+    fn suspend(&mut self) -> Result<(), io::Error>;
+
+    /// # Errors
+    /// If the output can't be written.
+    fn flush(&mut self) -> Result<(), io::Error>;
+}
+
+/// A real terminal, driven by termion escape sequences on stdout.
+///
+/// Output is queued in an internal frame buffer, and only written to stdout on `flush`.
+pub struct TermionTerminal {
+    stdout: AlternateScreen<RawTerminal<io::Stdout>>,
     size: Size,
+    buffer: Vec<u8>,
+    /// Whether the input thread must leave stdin alone, held by that thread while it reads.
+    paused: Arc<(Mutex<bool>, Condvar)>,
+    /// Whether mouse events are reported, see `EditorConfig::mouse`.
+    mouse: bool,
 }
 
-impl Terminal {
-    pub fn init() -> Result<Self, io::Error> {
+/// How long the input thread waits for input before letting `pause` take stdin.
+const INPUT_POLL_MS: libc::c_int = 50;
+
+impl TermionTerminal {
+    /// # Errors
+    /// If stdout isn't a terminal.
+    pub fn init(mouse: bool) -> Result<Self, io::Error> {
+        let mut terminal = Self {
+            stdout: io::stdout().into_raw_mode()?.into_alternate_screen()?,
+            size: Self::query_size()?,
+            buffer: Vec::new(),
+            paused: Arc::default(),
+            mouse,
+        };
+        terminal.write(input::ENABLE_EXTENDED_KEYS);
+        terminal.write(input::ENABLE_FOCUS_EVENTS);
+        if mouse {
+            terminal.write(input::ENABLE_MOUSE_EVENTS);
+        }
+        terminal.flush()?;
+        Ok(terminal)
+    }
+
+    fn query_size() -> Result<Size, io::Error> {
         let size = termion::terminal_size()?;
-        Ok(Self {
-            _stdout: io::stdout().into_raw_mode()?,
-            size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(2),
-            },
+        Ok(Size {
+            width: size.0,
+            height: size.1.saturating_sub(2),
         })
     }
 
-    pub fn size(&self) -> Size {
+    /// Queues `content` to be written on the next `flush`.
+    fn write(&mut self, content: impl Display) {
+        // Writing to a Vec can't fail
+        let _ = write!(self.buffer, "{content}");
+    }
+
+    fn set_bg_color(&mut self, color: RgbColor) {
+        self.write(termion::color::Bg(termion::color::Rgb(
+            color.0, color.1, color.2,
+        )));
+    }
+
+    fn reset_bg_color(&mut self) {
+        self.write(termion::color::Bg(termion::color::Reset));
+    }
+
+    fn set_fg_color(&mut self, color: RgbColor) {
+        self.write(termion::color::Fg(termion::color::Rgb(
+            color.0, color.1, color.2,
+        )));
+    }
+
+    fn reset_fg_color(&mut self) {
+        self.write(termion::color::Fg(termion::color::Reset));
+    }
+}
+
+impl Terminal for TermionTerminal {
+    fn size(&self) -> Size {
         self.size
     }
 
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+    fn update_size(&mut self) -> Result<Size, io::Error> {
+        self.size = Self::query_size()?;
+        Ok(self.size)
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    /// Spawns a thread decoding stdin, except while paused.
+    fn start_input(&mut self, events: Sender<Event>) {
+        let paused = Arc::clone(&self.paused);
+        thread::spawn(move || {
+            let mut decoder = Decoder::default();
+            let mut buf = [0; 1024];
+            loop {
+                let (lock, resumed) = &*paused;
+                let guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+                let guard = resumed
+                    .wait_while(guard, |paused| *paused)
+                    .unwrap_or_else(PoisonError::into_inner);
+                let read = read_stdin(&mut buf);
+                drop(guard);
+                match read {
+                    Ok(0) => return,
+                    Ok(len) => decoder.feed(&buf[..len]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return,
+                }
+                while let Some(input) = decoder.next_input() {
+                    let event = match input {
+                        Input::Key(chord) => Event::Key(chord),
+                        Input::Focus(true) => Event::FocusGained,
+                        Input::Focus(false) => continue,
+                        Input::Wheel(up) => Event::Mouse(Mouse::Wheel { up }),
+                    };
+                    if events.send(event).is_err() {
+                        // The editor is gone
+                        return;
+                    }
+                }
+            }
+        });
     }
 
-    /// 0-based coords
-    pub fn cursor_position(pos: Position) {
-        print!(
-            "{}",
-            termion::cursor::Goto(
-                pos.x.saturating_add(1) as u16,
-                pos.y.saturating_add(1) as u16
-            )
-        );
+    fn clear_screen(&mut self) {
+        self.write(termion::clear::All);
+    }
+
+    fn clear_current_line(&mut self) {
+        self.write(termion::clear::CurrentLine);
+    }
+
+    fn cursor_position(&mut self, pos: Position) {
+        self.write(termion::cursor::Goto(
+            pos.x.saturating_add(1) as u16,
+            pos.y.saturating_add(1) as u16,
+        ));
     }
 
-    pub fn set_bg_color(color: RgbColor) {
-        print!(
-            "{}",
-            termion::color::Bg(termion::color::Rgb(color.0, color.1, color.2))
-        );
+    fn print(&mut self, text: &str) {
+        self.write(text);
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", termion::color::Bg(termion::color::Reset));
+    fn print_line(&mut self, line: &Line) {
+        for (style, text) in line.spans() {
+            match style.fg {
+                Some(color) => self.set_fg_color(color),
+                None => self.reset_fg_color(),
+            }
+            match style.bg {
+                Some(color) => self.set_bg_color(color),
+                None => self.reset_bg_color(),
+            }
+            if style.bold {
+                self.write(termion::style::Bold);
+            }
+            if style.underline {
+                self.write(termion::style::Underline);
+            }
+            self.write(text);
+            if style.underline {
+                self.write(termion::style::NoUnderline);
+            }
+            if style.bold {
+                // Normal intensity: `NoBold` means double underline to most terminals
+                self.write(termion::style::NoFaint);
+            }
+        }
+        self.reset_fg_color();
+        self.reset_bg_color();
     }
 
-    pub fn set_fg_color(color: RgbColor) {
-        print!(
-            "{}",
-            termion::color::Fg(termion::color::Rgb(color.0, color.1, color.2))
-        );
+    /// Uses the DECSCUSR escape sequence.
+    fn set_cursor_shape(&mut self, shape: CursorShape) {
+        match shape {
+            CursorShape::Block => self.write(termion::cursor::SteadyBlock),
+            CursorShape::Bar => self.write(termion::cursor::SteadyBar),
+            CursorShape::Underline => self.write(termion::cursor::SteadyUnderline),
+        }
     }
 
-    pub fn reset_fg_color() {
-        print!("{}", termion::color::Fg(termion::color::Reset));
+    fn reset_cursor_shape(&mut self) {
+        self.write("\x1b[0 q");
     }
 
-    pub fn flush() -> Result<(), io::Error> {
-        io::stdout().flush()
+    /// Uses the OSC 52 escape sequence, which also works over SSH. The payload is limited to
+    /// `CLIPBOARD_MAX_ENCODED_LEN`.
+    fn copy_to_clipboard(&mut self, text: &str) -> bool {
+        let encoded = base64(text.as_bytes());
+        if encoded.len() > CLIPBOARD_MAX_ENCODED_LEN {
+            return false;
+        }
+
+        let sequence = format!("\x1b]52;c;{encoded}\x07");
+        let term = std::env::var("TERM").unwrap_or_default();
+        if std::env::var_os("TMUX").is_some() {
+            // tmux passthrough: escape characters must be doubled
+            self.write(format_args!(
+                "\x1bPtmux;{}\x1b\\",
+                sequence.replace('\x1b', "\x1b\x1b")
+            ));
+        } else if term.starts_with("screen") {
+            // GNU screen passthrough, split into chunks it accepts
+            for chunk in sequence.as_bytes().chunks(SCREEN_CHUNK_LEN) {
+                self.write("\x1bP");
+                self.buffer.extend_from_slice(chunk);
+                self.write("\x1b\\");
+            }
+        } else {
+            self.write(sequence);
+        }
+        true
     }
 
-    pub fn read_key() -> Result<Key, io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+    /// Also stops reading stdin, for a child process to get all keys.
+    fn pause(&mut self) -> Result<(), io::Error> {
+        // Taking the lock waits for the input thread to be done reading
+        *self.paused.0.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.reset_cursor_shape();
+        self.write(input::DISABLE_EXTENDED_KEYS);
+        self.write(input::DISABLE_FOCUS_EVENTS);
+        if self.mouse {
+            self.write(input::DISABLE_MOUSE_EVENTS);
+        }
+        self.write(ToMainScreen);
+        self.flush()?;
+        self.stdout.suspend_raw_mode()
+    }
+
+    fn resume(&mut self) -> Result<(), io::Error> {
+        self.stdout.activate_raw_mode()?;
+        self.write(ToAlternateScreen);
+        self.write(input::ENABLE_EXTENDED_KEYS);
+        self.write(input::ENABLE_FOCUS_EVENTS);
+        if self.mouse {
+            self.write(input::ENABLE_MOUSE_EVENTS);
+        }
+        let (lock, resumed) = &*self.paused;
+        *lock.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        resumed.notify_all();
+        self.flush()
+    }
+
+    fn suspend(&mut self) -> Result<(), io::Error> {
+        self.pause()?;
+        // SAFETY: raising a signal has no memory safety implications.
+        unsafe {
+            libc::raise(libc::SIGTSTP);
+        }
+        self.resume()
+    }
+
+    /// Writes the whole frame buffer at once.
+    fn flush(&mut self) -> Result<(), io::Error> {
+        self.stdout.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.stdout.flush()
+    }
+}
+
+/// Leaves the terminal as it was found, on both normal exits and errors or panics: attributes and
+/// cursor are reset, then the alternate screen and raw mode are exited by the wrapped `stdout`.
+impl Drop for TermionTerminal {
+    fn drop(&mut self) {
+        self.write(termion::style::Reset);
+        self.reset_cursor_shape();
+        self.write(termion::cursor::Show);
+        self.write(input::DISABLE_EXTENDED_KEYS);
+        self.write(input::DISABLE_FOCUS_EVENTS);
+        if self.mouse {
+            self.write(input::DISABLE_MOUSE_EVENTS);
+        }
+        let _ = self.flush();
+    }
+}
+
+/// Reads what stdin has to offer, waiting at most `INPUT_POLL_MS` for it. Reads the file
+/// descriptor directly, as `io::Stdin` could buffer input past a `pause`.
+/// # Errors
+/// `Interrupted` if there's nothing to read yet.
+fn read_stdin(buf: &mut [u8]) -> Result<usize, io::Error> {
+    let mut fd = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `fd` is valid for the duration of the call.
+    if unsafe { libc::poll(&mut fd, 1, INPUT_POLL_MS) } <= 0 {
+        return Err(io::ErrorKind::Interrupted.into());
+    }
+    // SAFETY: `buf` is valid for writes of its length.
+    let len = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+    usize::try_from(len).map_err(|_| io::Error::last_os_error())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                result.push('=');
             }
         }
     }
+    result
 }
@@ -0,0 +1,249 @@
+//! Text objects of modal editing: the words, lines, quoted strings and bracketed blocks that
+//! `iw`, `a(`, `i"`... select around the cursor.
+
+use crate::vim::TextObject;
+use crate::{Document, Position, Row};
+
+/// Text covered by a text object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    /// Exclusive, or the last row if `linewise`.
+    pub end: Position,
+    /// Covers whole rows, as the inside of a block whose delimiters are on lines of their own.
+    pub linewise: bool,
+}
+
+impl Span {
+    fn chars(start: Position, end: Position) -> Self {
+        Self {
+            start,
+            end,
+            linewise: false,
+        }
+    }
+}
+
+/// Word class of a grapheme: 0 for whitespace, 1 for word characters, 2 for punctuation.
+#[must_use]
+pub fn word_class(grapheme: &str) -> u8 {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => 0,
+        Some(c) if c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+/// The text `object` covers at `pos`, with its delimiters or surrounding whitespace if
+/// `around`. `None` if the cursor isn't in one.
+#[must_use]
+pub fn find(doc: &Document, pos: Position, object: TextObject, around: bool) -> Option<Span> {
+    let row = doc.get(pos.y)?;
+    match object {
+        TextObject::Word => word(row, pos, around),
+        TextObject::Line if around => Some(Span {
+            start: Position { x: 0, y: pos.y },
+            end: pos,
+            linewise: true,
+        }),
+        TextObject::Line => {
+            let start = row.indent_len();
+            let mut end = row.len();
+            while end > start && row.grapheme(end - 1).is_some_and(|g| g.trim().is_empty()) {
+                end -= 1;
+            }
+            Some(Span::chars(
+                Position { x: start, y: pos.y },
+                Position { x: end, y: pos.y },
+            ))
+        }
+        TextObject::Quote(quote) => quoted(row, pos, quote, around),
+        TextObject::Pair(open, close) => {
+            let (open_pos, close_pos) = enclosing_pair(doc, pos, open, close)?;
+            let after_close = Position {
+                x: close_pos.x + 1,
+                ..close_pos
+            };
+            if around {
+                return Some(Span::chars(open_pos, after_close));
+            }
+            Some(inside_pair(doc, open_pos, close_pos))
+        }
+    }
+}
+
+/// `iw`: the run of graphemes of the same class as the one at `pos`. `aw` adds the whitespace
+/// after it, or before it if there is none after.
+fn word(row: &Row, pos: Position, around: bool) -> Option<Span> {
+    let class = |x: usize| row.grapheme(x).map(word_class);
+    let current = class(pos.x)?;
+    let (mut start, mut end) = (pos.x, pos.x + 1);
+    while start > 0 && class(start - 1) == Some(current) {
+        start -= 1;
+    }
+    while class(end) == Some(current) {
+        end += 1;
+    }
+
+    if around {
+        if current == 0 {
+            // Whitespace, then the word after it
+            if let Some(next) = class(end) {
+                while class(end) == Some(next) {
+                    end += 1;
+                }
+            }
+        } else if class(end) == Some(0) {
+            while class(end) == Some(0) {
+                end += 1;
+            }
+        } else {
+            while start > 0 && class(start - 1) == Some(0) {
+                start -= 1;
+            }
+        }
+    }
+    Some(Span::chars(
+        Position { x: start, y: pos.y },
+        Position { x: end, y: pos.y },
+    ))
+}
+
+/// The quoted string of the row containing `pos`, or the next one after it. Quotes pair up from
+/// the start of the row, escaped ones excepted.
+fn quoted(row: &Row, pos: Position, quote: char, around: bool) -> Option<Span> {
+    let mut quote_buf = [0; 4];
+    let quote: &str = quote.encode_utf8(&mut quote_buf);
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for x in 0..row.len() {
+        let grapheme = row.grapheme(x)?;
+        if grapheme == quote && !escaped {
+            quotes.push(x);
+        }
+        escaped = grapheme == "\\" && !escaped;
+    }
+
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| close >= pos.x)?;
+    let (start, end) = if around {
+        (open, close + 1)
+    } else {
+        (open + 1, close)
+    };
+    Some(Span::chars(
+        Position { x: start, y: pos.y },
+        Position { x: end, y: pos.y },
+    ))
+}
+
+/// Positions of the `open` and `close` graphemes of the innermost pair around `pos`, counting
+/// nested pairs.
+fn enclosing_pair(
+    doc: &Document,
+    pos: Position,
+    open: char,
+    close: char,
+) -> Option<(Position, Position)> {
+    let is = |pos: Position, c: char| {
+        doc.get(pos.y)
+            .and_then(|row| row.grapheme(pos.x))
+            .is_some_and(|g| g.chars().eq([c]))
+    };
+
+    let mut open_pos = if is(pos, open) { Some(pos) } else { None };
+    let mut depth = 0;
+    // On a closing delimiter, its own pair
+    let mut scan = if is(pos, close) {
+        step_backward(doc, pos)
+    } else {
+        Some(pos)
+    };
+    while open_pos.is_none() {
+        let current = scan?;
+        if is(current, close) {
+            depth += 1;
+        } else if is(current, open) && depth > 0 {
+            depth -= 1;
+        } else if is(current, open) {
+            open_pos = Some(current);
+        }
+        scan = step_backward(doc, current);
+    }
+    let open_pos = open_pos?;
+
+    let mut depth = 0;
+    let mut scan = step_forward(doc, open_pos);
+    while let Some(current) = scan {
+        if is(current, open) {
+            depth += 1;
+        } else if is(current, close) && depth > 0 {
+            depth -= 1;
+        } else if is(current, close) {
+            return Some((open_pos, current));
+        }
+        scan = step_forward(doc, current);
+    }
+    None
+}
+
+/// Between the delimiters at `open` and `close`. When they end and start their lines, only the
+/// lines in between are covered, as whole rows.
+fn inside_pair(doc: &Document, open: Position, close: Position) -> Span {
+    let start = Position {
+        x: open.x + 1,
+        ..open
+    };
+    let row_len = |y: usize| doc.get(y).map_or(0, Row::len);
+    let opens_line = start.x == row_len(open.y);
+    let closes_line = doc
+        .get(close.y)
+        .is_some_and(|row| row.indent_len() == close.x);
+    if opens_line && closes_line && close.y > open.y + 1 {
+        return Span {
+            start: Position {
+                x: 0,
+                y: open.y + 1,
+            },
+            end: Position {
+                x: 0,
+                y: close.y - 1,
+            },
+            linewise: true,
+        };
+    }
+    Span::chars(start, close)
+}
+
+/// The next grapheme, going to the next row at the end of a row.
+fn step_forward(doc: &Document, pos: Position) -> Option<Position> {
+    if pos.x + 1 < doc.get(pos.y)?.len() {
+        Some(Position {
+            x: pos.x + 1,
+            ..pos
+        })
+    } else {
+        (pos.y + 1..doc.len())
+            .find(|&y| doc.get(y).is_some_and(|row| !row.is_empty()))
+            .map(|y| Position { x: 0, y })
+    }
+}
+
+/// The previous grapheme, going to the previous row at the start of a row.
+fn step_backward(doc: &Document, pos: Position) -> Option<Position> {
+    if pos.x > 0 {
+        Some(Position {
+            x: pos.x - 1,
+            ..pos
+        })
+    } else {
+        (0..pos.y).rev().find_map(|y| {
+            Some(Position {
+                x: doc.get(y)?.len().checked_sub(1)?,
+                y,
+            })
+        })
+    }
+}
@@ -0,0 +1,164 @@
+use crate::terminal::RgbColor;
+
+/// Colors used to draw the editor's interface.
+pub struct Theme {
+    /// One of `NAMES`.
+    pub name: &'static str,
+    pub status_bg: RgbColor,
+    /// Text of the status bar, `None` for the terminal's default.
+    pub status_fg: Option<RgbColor>,
+    pub line_num_bg: RgbColor,
+    pub line_num_fg: RgbColor,
+    /// Background of the row containing the cursor, `None` to disable current line highlighting.
+    pub current_line_bg: Option<RgbColor>,
+    pub search_match_bg: RgbColor,
+    pub search_match_fg: RgbColor,
+    pub diff_added_fg: RgbColor,
+    pub diff_modified_fg: RgbColor,
+    pub diff_deleted_fg: RgbColor,
+    pub scrollbar_fg: RgbColor,
+    pub indent_guide_fg: RgbColor,
+    /// Background of the column at the text width.
+    pub ruler_bg: RgbColor,
+    pub selection_bg: RgbColor,
+    /// Misspelled words, which are also underlined.
+    pub spell_error_fg: RgbColor,
+    pub diagnostic_error_fg: RgbColor,
+    pub diagnostic_warning_fg: RgbColor,
+    pub markdown_heading_fg: RgbColor,
+    pub markdown_code_fg: RgbColor,
+    pub markdown_link_fg: RgbColor,
+}
+
+impl Theme {
+    /// Names accepted by `by_name`.
+    pub const NAMES: &'static [&'static str] = &["default", "light"];
+
+    /// Names accepted by `set_color`.
+    pub const COLORS: &'static [&'static str] = &[
+        "status_bg",
+        "status_fg",
+        "line_num_bg",
+        "line_num_fg",
+        "current_line_bg",
+        "search_match_bg",
+        "search_match_fg",
+        "diff_added_fg",
+        "diff_modified_fg",
+        "diff_deleted_fg",
+        "scrollbar_fg",
+        "indent_guide_fg",
+        "ruler_bg",
+        "selection_bg",
+        "spell_error_fg",
+        "diagnostic_error_fg",
+        "diagnostic_warning_fg",
+        "markdown_heading_fg",
+        "markdown_code_fg",
+        "markdown_link_fg",
+    ];
+
+    /// Looks up a built-in theme.
+    #[must_use]
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "light" => Some(Self {
+                name: "light",
+                status_bg: RgbColor(170, 210, 230),
+                status_fg: None,
+                line_num_bg: RgbColor(230, 230, 230),
+                line_num_fg: RgbColor(96, 96, 96),
+                current_line_bg: Some(RgbColor(240, 240, 220)),
+                search_match_bg: RgbColor(255, 230, 120),
+                search_match_fg: RgbColor(0, 0, 0),
+                diff_added_fg: RgbColor(0, 130, 0),
+                diff_modified_fg: RgbColor(170, 100, 0),
+                diff_deleted_fg: RgbColor(190, 0, 0),
+                scrollbar_fg: RgbColor(160, 160, 160),
+                indent_guide_fg: RgbColor(210, 210, 210),
+                ruler_bg: RgbColor(240, 225, 225),
+                selection_bg: RgbColor(190, 210, 250),
+                spell_error_fg: RgbColor(200, 0, 0),
+                diagnostic_error_fg: RgbColor(200, 0, 0),
+                diagnostic_warning_fg: RgbColor(170, 110, 0),
+                markdown_heading_fg: RgbColor(0, 90, 160),
+                markdown_code_fg: RgbColor(140, 60, 20),
+                markdown_link_fg: RgbColor(20, 80, 200),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Overrides one of the `COLORS`. `None` means the terminal's default, which only
+    /// `status_fg` and `current_line_bg` accept.
+    ///
+    /// # Errors
+    /// If there is no such color, or it can't be left to the terminal.
+    pub fn set_color(&mut self, name: &str, color: Option<RgbColor>) -> Result<(), String> {
+        let field = match name {
+            "status_fg" => {
+                self.status_fg = color;
+                return Ok(());
+            }
+            "current_line_bg" => {
+                self.current_line_bg = color;
+                return Ok(());
+            }
+            "status_bg" => &mut self.status_bg,
+            "line_num_bg" => &mut self.line_num_bg,
+            "line_num_fg" => &mut self.line_num_fg,
+            "search_match_bg" => &mut self.search_match_bg,
+            "search_match_fg" => &mut self.search_match_fg,
+            "diff_added_fg" => &mut self.diff_added_fg,
+            "diff_modified_fg" => &mut self.diff_modified_fg,
+            "diff_deleted_fg" => &mut self.diff_deleted_fg,
+            "scrollbar_fg" => &mut self.scrollbar_fg,
+            "indent_guide_fg" => &mut self.indent_guide_fg,
+            "ruler_bg" => &mut self.ruler_bg,
+            "selection_bg" => &mut self.selection_bg,
+            "spell_error_fg" => &mut self.spell_error_fg,
+            "diagnostic_error_fg" => &mut self.diagnostic_error_fg,
+            "diagnostic_warning_fg" => &mut self.diagnostic_warning_fg,
+            "markdown_heading_fg" => &mut self.markdown_heading_fg,
+            "markdown_code_fg" => &mut self.markdown_code_fg,
+            "markdown_link_fg" => &mut self.markdown_link_fg,
+            _ => {
+                return Err(format!(
+                    "unknown color `{name}` (available: {})",
+                    Self::COLORS.join(", ")
+                ))
+            }
+        };
+        *field = color.ok_or_else(|| format!("`{name}` can't be \"none\""))?;
+        Ok(())
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "default",
+            status_bg: RgbColor(0, 128, 128),
+            status_fg: None,
+            line_num_bg: RgbColor(255, 255, 255),
+            line_num_fg: RgbColor(0, 0, 0),
+            current_line_bg: Some(RgbColor(48, 48, 48)),
+            search_match_bg: RgbColor(255, 200, 0),
+            search_match_fg: RgbColor(0, 0, 0),
+            diff_added_fg: RgbColor(0, 160, 0),
+            diff_modified_fg: RgbColor(200, 120, 0),
+            diff_deleted_fg: RgbColor(200, 0, 0),
+            scrollbar_fg: RgbColor(128, 128, 128),
+            indent_guide_fg: RgbColor(80, 80, 80),
+            ruler_bg: RgbColor(64, 40, 40),
+            selection_bg: RgbColor(60, 80, 130),
+            spell_error_fg: RgbColor(255, 100, 100),
+            diagnostic_error_fg: RgbColor(230, 0, 0),
+            diagnostic_warning_fg: RgbColor(220, 160, 0),
+            markdown_heading_fg: RgbColor(100, 180, 255),
+            markdown_code_fg: RgbColor(230, 170, 100),
+            markdown_link_fg: RgbColor(120, 160, 255),
+        }
+    }
+}
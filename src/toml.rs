@@ -0,0 +1,451 @@
+//! A parser for the subset of TOML used by configuration files: tables, dotted keys, strings,
+//! integers, floats, booleans and (possibly multi-line) arrays. Inline tables, arrays of tables
+//! and dates aren't supported.
+//!
+//! Hand-written rather than using the `toml` crate, which would bring `serde` along for a
+//! single, small file.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    #[must_use]
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "a string",
+            Self::Integer(_) => "an integer",
+            Self::Float(_) => "a float",
+            Self::Boolean(_) => "a boolean",
+            Self::Array(_) => "an array",
+            Self::Table(_) => "a table",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// # Errors
+/// On the first syntax error, or if a key is defined twice.
+pub fn parse(input: &str) -> Result<Table, ParseError> {
+    let mut root = Table::new();
+    // Path of the current [table]
+    let mut current: Vec<String> = Vec::new();
+
+    let mut lines = input.lines().enumerate();
+    while let Some((idx, line)) = lines.next() {
+        let error = |message: String| ParseError {
+            line: idx + 1,
+            message,
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let mut parser = Parser::new(header);
+            let path = parser.key().map_err(error)?;
+            parser.expect(']').map_err(error)?;
+            parser.end().map_err(error)?;
+
+            table_at(&mut root, &path).map_err(error)?;
+            current = path;
+            continue;
+        }
+
+        // Multi-line arrays: keep appending lines until brackets are balanced
+        let mut statement = line.to_string();
+        while bracket_depth(&statement) > 0 {
+            let Some((_, next)) = lines.next() else {
+                return Err(error("unterminated array".into()));
+            };
+            statement.push('\n');
+            statement.push_str(next);
+        }
+
+        let mut parser = Parser::new(&statement);
+        let mut path = current.clone();
+        path.extend(parser.key().map_err(error)?);
+        parser.expect('=').map_err(error)?;
+        let value = parser.value().map_err(error)?;
+        parser.end().map_err(error)?;
+
+        let (key, parents) = path.split_last().expect("keys are never empty");
+        let table = table_at(&mut root, parents).map_err(error)?;
+        if table.contains_key(key) {
+            return Err(error(format!("duplicate key `{key}`")));
+        }
+        table.insert(key.clone(), value);
+    }
+
+    Ok(root)
+}
+
+/// Returns the table at `path`, creating intermediate tables as needed.
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let mut table = root;
+    for key in path {
+        match table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(t) => table = t,
+            _ => return Err(format!("`{key}` is not a table")),
+        }
+    }
+    Ok(table)
+}
+
+/// Unclosed `[` count outside of strings and comments.
+fn bracket_depth(s: &str) -> isize {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut comment = false;
+    for c in s.chars() {
+        if comment {
+            comment = c != '\n';
+            continue;
+        }
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                '#' => comment = true,
+                _ => (),
+            },
+        }
+    }
+    depth
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { rest: input }
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            self.rest = self.rest.trim_start();
+            // Comments are only allowed where whitespace is, up to the end of the line
+            if self.rest.starts_with('#') {
+                self.rest = self.rest.find('\n').map_or("", |idx| &self.rest[idx..]);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest.chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.rest = &self.rest[c.len_utf8()..];
+                Ok(())
+            }
+            Some(found) => Err(format!("expected `{c}`, found `{found}`")),
+            None => Err(format!("expected `{c}`")),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        match self.peek() {
+            None => Ok(()),
+            Some(c) => Err(format!("unexpected `{c}`")),
+        }
+    }
+
+    /// A possibly dotted and quoted key.
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut path = Vec::new();
+        loop {
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let len = self
+                        .rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                        .unwrap_or(self.rest.len());
+                    if len == 0 {
+                        return Err("expected a key".into());
+                    }
+                    let (part, rest) = self.rest.split_at(len);
+                    self.rest = rest;
+                    part.into()
+                }
+            };
+            path.push(part);
+
+            if self.peek() == Some('.') {
+                self.rest = &self.rest[1..];
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.rest = &self.rest[1..];
+                let mut items = Vec::new();
+                loop {
+                    if self.peek() == Some(']') {
+                        self.rest = &self.rest[1..];
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(',') => self.rest = &self.rest[1..],
+                        Some(']') => (),
+                        _ => return Err("expected `,` or `]` in array".into()),
+                    }
+                }
+            }
+            Some(_) => {
+                let len = self
+                    .rest
+                    .find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+                    .unwrap_or(self.rest.len());
+                let (token, rest) = self.rest.split_at(len);
+                self.rest = rest;
+                scalar(token)
+            }
+            None => Err("expected a value".into()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut chars = self.rest[1..].char_indices();
+        let mut result = String::new();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[idx + 2..];
+                    return Ok(result);
+                }
+                '\\' => {
+                    let escaped = match chars.next().map(|(_, c)| c) {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some(u @ ('u' | 'U')) => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or(format!("invalid unicode escape `\\{u}{hex}`"))?
+                        }
+                        Some(c) => return Err(format!("invalid escape `\\{c}`")),
+                        None => break,
+                    };
+                    result.push(escaped);
+                }
+                '\n' => break,
+                c => result.push(c),
+            }
+        }
+        Err("unterminated string".into())
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let body = &self.rest[1..];
+        match body.find(['\'', '\n']) {
+            Some(end) if body[end..].starts_with('\'') => {
+                let result = body[..end].to_string();
+                self.rest = &body[end + 1..];
+                Ok(result)
+            }
+            _ => Err("unterminated string".into()),
+        }
+    }
+}
+
+fn scalar(token: &str) -> Result<Value, String> {
+    match token {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => (),
+    }
+
+    let digits = token.replace('_', "");
+    if let Ok(n) = digits.parse() {
+        return Ok(Value::Integer(n));
+    }
+    if let Some(hex) = digits.strip_prefix("0x") {
+        if let Ok(n) = i64::from_str_radix(hex, 16) {
+            return Ok(Value::Integer(n));
+        }
+    }
+    if token.contains(['.', 'e', 'E']) {
+        if let Ok(f) = digits.parse() {
+            return Ok(Value::Float(f));
+        }
+    }
+
+    Err(format!("invalid value `{token}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> (usize, String) {
+        let e = parse(input).unwrap_err();
+        (e.line, e.message)
+    }
+
+    #[test]
+    fn values() {
+        let table = parse(
+            r#"
+# A comment
+name = "a \"quoted\"\t\u00e9\U0001F600 string" # trailing comment
+path = 'C:\no\escapes'
+count = 1_000
+mask = 0xff
+negative = -3
+ratio = 0.5
+big = 1e3
+on = true
+off = false
+list = [1, "two", [3]]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            table["name"],
+            Value::String("a \"quoted\"\té\u{1F600} string".into())
+        );
+        assert_eq!(table["path"], Value::String(r"C:\no\escapes".into()));
+        assert_eq!(table["count"], Value::Integer(1000));
+        assert_eq!(table["mask"], Value::Integer(255));
+        assert_eq!(table["negative"], Value::Integer(-3));
+        assert_eq!(table["ratio"], Value::Float(0.5));
+        assert_eq!(table["big"], Value::Float(1000.0));
+        assert_eq!(table["on"], Value::Boolean(true));
+        assert_eq!(table["off"], Value::Boolean(false));
+        assert_eq!(
+            table["list"],
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::String("two".into()),
+                Value::Array(vec![Value::Integer(3)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn tables_and_dotted_keys() {
+        let table = parse(
+            r#"
+a.b = 1
+[c.d]
+e = 2
+"f.g" = 3
+[a]
+h = 4
+"#,
+        )
+        .unwrap();
+        let Value::Table(a) = &table["a"] else {
+            panic!("`a` is not a table");
+        };
+        assert_eq!(a["b"], Value::Integer(1));
+        assert_eq!(a["h"], Value::Integer(4));
+        let Value::Table(c) = &table["c"] else {
+            panic!("`c` is not a table");
+        };
+        let Value::Table(d) = &c["d"] else {
+            panic!("`c.d` is not a table");
+        };
+        assert_eq!(d["e"], Value::Integer(2));
+        assert_eq!(d["f.g"], Value::Integer(3));
+    }
+
+    #[test]
+    fn multi_line_arrays() {
+        let table = parse("list = [\n  \"]\", # a ] in a comment\n  '[',\n]\nnext = 1").unwrap();
+        assert_eq!(
+            table["list"],
+            Value::Array(vec![Value::String("]".into()), Value::String("[".into())])
+        );
+        assert_eq!(table["next"], Value::Integer(1));
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(error("a = 1\nb = [1,\n2"), (2, "unterminated array".into()));
+        assert_eq!(error("\na 1"), (2, "expected `=`, found `1`".into()));
+        assert_eq!(error("[a"), (1, "expected `]`".into()));
+        assert_eq!(error("a = 1 2"), (1, "unexpected `2`".into()));
+        assert_eq!(error("= 1"), (1, "expected a key".into()));
+        assert_eq!(error("a ="), (1, "expected a value".into()));
+        assert_eq!(
+            error("a = [1 2]"),
+            (1, "expected `,` or `]` in array".into())
+        );
+        assert_eq!(error("a = \"b"), (1, "unterminated string".into()));
+        assert_eq!(error("a = 'b"), (1, "unterminated string".into()));
+        assert_eq!(error(r#"a = "\q""#), (1, r"invalid escape `\q`".into()));
+        assert_eq!(
+            error(r#"a = "\uzzzz""#),
+            (1, r"invalid unicode escape `\uzzzz`".into())
+        );
+        assert_eq!(error("a = yes"), (1, "invalid value `yes`".into()));
+        assert_eq!(error("a = 1\n\na = 2"), (3, "duplicate key `a`".into()));
+        assert_eq!(error("a = 1\n[a.b]"), (2, "`a` is not a table".into()));
+        assert_eq!(
+            parse("[a\nb = 1").unwrap_err().to_string(),
+            "line 1: expected `]`"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Finding URLs in text and opening them in the system browser.
+
+use std::process::Command;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Schemes worth opening; bare domains like `example.com` are too ambiguous.
+const URL_PATTERN: &str = r#"(?:https?|ftp|file)://[^\s<>"'`]+|mailto:[^\s<>"'`]+"#;
+
+/// The URL spanning byte `idx` of `line`, without trailing punctuation that most likely ends
+/// the sentence around it.
+#[must_use]
+pub fn at(line: &str, idx: usize) -> Option<&str> {
+    static PATTERN: OnceLock<Option<Regex>> = OnceLock::new();
+    // The pattern is valid
+    let pattern = PATTERN
+        .get_or_init(|| Regex::new(URL_PATTERN).ok())
+        .as_ref()?;
+    let url = pattern
+        .find_iter(line)
+        .map(|m| (m.start(), trim_end(m.as_str())))
+        .find(|&(start, url)| (start..start + url.len()).contains(&idx))
+        .map(|(_, url)| url);
+    url
+}
+
+/// Strips trailing punctuation, and closing brackets that don't belong to the URL, as in
+/// `(see https://example.com)` but not `https://en.wikipedia.org/wiki/Rust_(language)`.
+fn trim_end(mut url: &str) -> &str {
+    loop {
+        let Some(last) = url.chars().last() else {
+            return url;
+        };
+        let open = match last {
+            '.' | ',' | ';' | ':' | '!' | '?' => {
+                url = &url[..url.len() - 1];
+                continue;
+            }
+            ')' => '(',
+            ']' => '[',
+            '}' => '{',
+            _ => return url,
+        };
+        if url.matches(open).count() >= url.matches(last).count() {
+            return url;
+        }
+        url = &url[..url.len() - 1];
+    }
+}
+
+/// The platform's command to open `url` with the default application.
+#[must_use]
+pub fn opener(url: &str) -> Command {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let mut command = Command::new(program);
+    command.arg(url);
+    command
+}
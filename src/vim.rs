@@ -0,0 +1,386 @@
+//! Key parsing for the optional modal (vim-style) editing: counts, operators and motions.
+
+use crate::input::Chord;
+
+use std::cmp;
+use termion::event::Key;
+
+/// Largest count, for a mistyped one not to hang the editor repeating a command.
+pub const MAX_COUNT: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w`
+    WordForward,
+    /// `b`
+    WordBackward,
+    /// `e`
+    WordEnd,
+    /// `0`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `gg`, or the line given by the count.
+    FirstLine,
+    /// `G`, or the line given by the count.
+    LastLine,
+}
+
+impl Motion {
+    /// Whether operators apply to whole lines.
+    #[must_use]
+    pub fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Self::Up | Self::Down | Self::FirstLine | Self::LastLine
+        )
+    }
+
+    /// Whether operators include the character the motion lands on.
+    #[must_use]
+    pub fn is_inclusive(self) -> bool {
+        matches!(self, Self::WordEnd)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// What `i` and `a` select after an operator or in visual mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextObject {
+    /// `w`
+    Word,
+    /// `l`
+    Line,
+    /// `"`, `'` or `` ` ``
+    Quote(char),
+    /// Opening and closing delimiters: `(` or `b`, `[`, `{` or `B`, `<`.
+    Pair(char, char),
+}
+
+impl TextObject {
+    fn from_key(c: char) -> Option<Self> {
+        Some(match c {
+            'w' => Self::Word,
+            'l' => Self::Line,
+            '"' | '\'' | '`' => Self::Quote(c),
+            '(' | ')' | 'b' => Self::Pair('(', ')'),
+            '[' | ']' => Self::Pair('[', ']'),
+            '{' | '}' | 'B' => Self::Pair('{', '}'),
+            '<' | '>' => Self::Pair('<', '>'),
+            _ => return None,
+        })
+    }
+}
+
+/// Where `i`, `a`, `I`, `A`, `o` and `O` start inserting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertAt {
+    Cursor,
+    AfterCursor,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+/// Where `zt`, `zz` and `zb` put the cursor line on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Move, with the count if one was typed.
+    Move(Motion, Option<usize>),
+    /// Apply the operator from the cursor to where the motion (repeated `count` times) lands.
+    Apply(Operator, Motion, Option<usize>),
+    /// `dd`, `cc` and `yy` on `count` lines.
+    ApplyLines(Operator, usize),
+    /// In visual mode, apply the operator to the selection.
+    ApplySelection(Operator),
+    /// Apply the operator to a text object, with its delimiters or whitespace if `around`.
+    ApplyObject(Operator, TextObject, bool),
+    /// In visual mode, select a text object.
+    SelectObject(TextObject, bool),
+    Insert(InsertAt),
+    /// `x`
+    DeleteChars(usize),
+    /// `p` (after) or `P`.
+    Paste {
+        after: bool,
+        count: usize,
+    },
+    /// `v` or `V`.
+    Visual {
+        linewise: bool,
+    },
+    /// Scroll for the cursor line to be at the top, center or bottom of the screen.
+    Align(ViewAlign),
+    /// `:`
+    CommandLine,
+    /// `u`, undoing `count` changes.
+    Undo(usize),
+    /// Esc, or an invalid sequence.
+    Cancel,
+    /// Not a modal key, handled like in insert mode (e.g. Ctrl-S), `count` times.
+    Other(Chord, usize),
+}
+
+/// Accumulates keys until they form an action.
+#[derive(Default)]
+pub struct Parser {
+    count: Option<usize>,
+    operator: Option<(Operator, Option<usize>)>,
+    /// Whether `g` was typed.
+    g: bool,
+    /// Whether `z` was typed.
+    z: bool,
+    /// After an operator or in visual mode, whether `a` (`true`) or `i` was typed.
+    around: Option<bool>,
+    /// Keys of the pending sequence, for display.
+    typed: String,
+}
+
+impl Parser {
+    /// Feeds a key, returning the completed action if any. `visual` is whether a selection is
+    /// active.
+    pub fn feed(&mut self, chord: Chord, visual: bool) -> Option<Action> {
+        let action = self.parse(chord, visual);
+        match action {
+            Some(_) => *self = Self::default(),
+            None => self.typed.push_str(&chord.to_string()),
+        }
+        action
+    }
+
+    /// Keys typed so far of an incomplete sequence, e.g. `2d3`.
+    #[must_use]
+    pub fn typed(&self) -> &str {
+        &self.typed
+    }
+
+    fn parse(&mut self, chord: Chord, visual: bool) -> Option<Action> {
+        let count = self.count.unwrap_or(1);
+        if !chord.modifiers.is_empty() {
+            return Some(Action::Other(chord, count));
+        }
+        let c = match chord.key {
+            Key::Char(c) => c,
+            Key::Esc => return Some(Action::Cancel),
+            Key::Left => 'h',
+            Key::Down => 'j',
+            Key::Up => 'k',
+            Key::Right => 'l',
+            Key::Home => '0',
+            Key::End => '$',
+            _ => return Some(Action::Other(chord, count)),
+        };
+
+        if self.g {
+            return Some(match c {
+                'g' => self.motion(Motion::FirstLine),
+                _ => Action::Cancel,
+            });
+        }
+        if let Some(around) = self.around {
+            let Some(object) = TextObject::from_key(c) else {
+                return Some(Action::Cancel);
+            };
+            return Some(match self.operator {
+                Some((operator, _)) => Action::ApplyObject(operator, object, around),
+                None => Action::SelectObject(object, around),
+            });
+        }
+        if self.z {
+            return Some(match c {
+                't' => Action::Align(ViewAlign::Top),
+                'z' => Action::Align(ViewAlign::Center),
+                'b' => Action::Align(ViewAlign::Bottom),
+                _ => Action::Cancel,
+            });
+        }
+
+        match c {
+            '1'..='9' | '0' if c != '0' || self.count.is_some() => {
+                self.count = Some(append_digit(self.count, c));
+                return None;
+            }
+            'g' => {
+                self.g = true;
+                return None;
+            }
+            'i' | 'a' if self.operator.is_some() || visual => {
+                self.around = Some(c == 'a');
+                return None;
+            }
+            'z' if self.operator.is_none() => {
+                self.z = true;
+                return None;
+            }
+            _ => (),
+        }
+
+        let motion = match c {
+            'h' => Some(Motion::Left),
+            'j' => Some(Motion::Down),
+            'k' => Some(Motion::Up),
+            'l' | ' ' => Some(Motion::Right),
+            'w' => Some(Motion::WordForward),
+            'b' => Some(Motion::WordBackward),
+            'e' => Some(Motion::WordEnd),
+            '0' => Some(Motion::LineStart),
+            '$' => Some(Motion::LineEnd),
+            'G' => Some(Motion::LastLine),
+            _ => None,
+        };
+        if let Some(motion) = motion {
+            return Some(self.motion(motion));
+        }
+
+        let operator = match c {
+            'd' => Some(Operator::Delete),
+            'c' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            if visual {
+                return Some(Action::ApplySelection(operator));
+            }
+            return match self.operator {
+                // `dd`, `cc`, `yy`, counts multiply as in `2d3d`
+                Some((pending, op_count)) if pending == operator => Some(Action::ApplyLines(
+                    operator,
+                    multiply(op_count.unwrap_or(1), count),
+                )),
+                Some(_) => Some(Action::Cancel),
+                None => {
+                    self.operator = Some((operator, self.count.take()));
+                    None
+                }
+            };
+        }
+        if self.operator.is_some() {
+            return Some(Action::Cancel);
+        }
+
+        Some(match c {
+            'x' if visual => Action::ApplySelection(Operator::Delete),
+            'x' => Action::DeleteChars(count),
+            'i' => Action::Insert(InsertAt::Cursor),
+            'a' => Action::Insert(InsertAt::AfterCursor),
+            'I' => Action::Insert(InsertAt::LineStart),
+            'A' => Action::Insert(InsertAt::LineEnd),
+            'o' => Action::Insert(InsertAt::LineBelow),
+            'O' => Action::Insert(InsertAt::LineAbove),
+            'p' => Action::Paste { after: true, count },
+            'P' => Action::Paste {
+                after: false,
+                count,
+            },
+            'v' => Action::Visual { linewise: false },
+            'V' => Action::Visual { linewise: true },
+            ':' => Action::CommandLine,
+            'u' => Action::Undo(count),
+            _ => Action::Cancel,
+        })
+    }
+
+    fn motion(&mut self, motion: Motion) -> Action {
+        match self.operator {
+            Some((operator, op_count)) => {
+                let count = match (op_count, self.count) {
+                    (None, None) => None,
+                    (a, b) => Some(multiply(a.unwrap_or(1), b.unwrap_or(1))),
+                };
+                Action::Apply(operator, motion, count)
+            }
+            None => Action::Move(motion, self.count),
+        }
+    }
+}
+
+/// `count` followed by the digit `c`, at most `MAX_COUNT`.
+#[must_use]
+pub fn append_digit(count: Option<usize>, c: char) -> usize {
+    let digit = c.to_digit(10).unwrap_or_default() as usize;
+    cmp::min(
+        count.unwrap_or(0).saturating_mul(10).saturating_add(digit),
+        MAX_COUNT,
+    )
+}
+
+/// Count of an operator and motion both given one, as in `2d3w`, at most `MAX_COUNT`.
+fn multiply(a: usize, b: usize) -> usize {
+    cmp::min(a.saturating_mul(b), MAX_COUNT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The action `keys` complete, if any.
+    fn parse(keys: &str) -> Option<Action> {
+        let mut parser = Parser::default();
+        keys.chars()
+            .map(|c| parser.feed(Key::Char(c).into(), false))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn counts_apply_to_motions_and_operators() {
+        assert_eq!(parse("w"), Some(Action::Move(Motion::WordForward, None)));
+        assert_eq!(parse("12j"), Some(Action::Move(Motion::Down, Some(12))));
+        assert_eq!(parse("0"), Some(Action::Move(Motion::LineStart, None)));
+        assert_eq!(parse("10x"), Some(Action::DeleteChars(10)));
+        assert_eq!(
+            parse("2d3w"),
+            Some(Action::Apply(
+                Operator::Delete,
+                Motion::WordForward,
+                Some(6)
+            ))
+        );
+        assert_eq!(parse("2y3y"), Some(Action::ApplyLines(Operator::Yank, 6)));
+        assert_eq!(parse("dd"), Some(Action::ApplyLines(Operator::Delete, 1)));
+        assert_eq!(parse("dy"), Some(Action::Cancel));
+        assert_eq!(parse("2d"), None);
+    }
+
+    #[test]
+    fn counts_are_capped() {
+        assert_eq!(
+            parse("999999999999999999999u"),
+            Some(Action::Undo(MAX_COUNT))
+        );
+        assert_eq!(
+            parse("9999p"),
+            Some(Action::Paste {
+                after: true,
+                count: 9999
+            })
+        );
+        assert_eq!(
+            parse("5000d5000j"),
+            Some(Action::Apply(
+                Operator::Delete,
+                Motion::Down,
+                Some(MAX_COUNT)
+            ))
+        );
+    }
+}
@@ -0,0 +1,36 @@
+//! Counting lines, words, graphemes and bytes, as `wc` does.
+
+use std::fmt;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub lines: usize,
+    /// Runs of non-whitespace.
+    pub words: usize,
+    pub graphemes: usize,
+    pub bytes: usize,
+}
+
+impl Counts {
+    #[must_use]
+    pub fn of(text: &str) -> Self {
+        Self {
+            lines: text.lines().count(),
+            words: text.split_whitespace().count(),
+            graphemes: text.graphemes(true).count(),
+            bytes: text.len(),
+        }
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} lines, {} words, {} characters, {} bytes",
+            self.lines, self.words, self.graphemes, self.bytes
+        )
+    }
+}
@@ -0,0 +1,111 @@
+//! Driving an `Editor` on a scratch file and an in-memory terminal.
+
+#![allow(dead_code)]
+
+use hecto::memory_terminal::{MemoryTerminal, Screen};
+use hecto::terminal::Size;
+use hecto::{Editor, EditorConfig, Position};
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use termion::event::Key;
+
+/// An editor on a scratch file, fed keys one at a time.
+pub struct Harness {
+    pub editor: Editor,
+    pub path: PathBuf,
+    screen: Rc<RefCell<Screen>>,
+}
+
+impl Harness {
+    /// Opens a scratch file containing `text`, on a 40x10 terminal.
+    pub fn new(text: &str) -> Self {
+        Self::with_config(text, 40, 10, EditorConfig::default())
+    }
+
+    /// Opens a scratch file containing `text`, on a terminal of the given size.
+    pub fn with_config(text: &str, width: u16, height: u16, config: EditorConfig) -> Self {
+        Self::with_input(text, width, height, config, &[])
+    }
+
+    /// Like `with_config`, with `input` read by `Editor::run` and prompts.
+    pub fn with_input(
+        text: &str,
+        width: u16,
+        height: u16,
+        config: EditorConfig,
+        input: &[Key],
+    ) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        // A directory per harness, for the file name shown in the status bar to be stable
+        let dir = std::env::temp_dir().join(format!(
+            "hecto-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, text).unwrap();
+
+        let input = input.iter().map(|&key| key.into()).collect();
+        let terminal = MemoryTerminal::new(Size { width, height }, input);
+        let screen = terminal.screen();
+        let editor = Editor::with_terminal(Box::new(terminal), Some(path.clone()), config).unwrap();
+        Self {
+            editor,
+            path,
+            screen,
+        }
+    }
+
+    /// Frames drawn so far.
+    pub fn frames(&self) -> usize {
+        self.screen.borrow().flushes
+    }
+
+    pub fn keys(&mut self, keys: &[Key]) -> &mut Self {
+        for &key in keys {
+            self.editor.feed_key(key.into()).unwrap();
+        }
+        self
+    }
+
+    pub fn type_str(&mut self, text: &str) -> &mut Self {
+        for c in text.chars() {
+            self.editor.feed_key(Key::Char(c).into()).unwrap();
+        }
+        self
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        self.editor
+            .document()
+            .iter()
+            .map(|row| row.as_str())
+            .collect()
+    }
+
+    /// `(x, y)`, 0-based.
+    pub fn cursor(&self) -> (usize, usize) {
+        let Position { x, y } = self.editor.cursor_position();
+        (x, y)
+    }
+
+    /// Refreshes the screen and returns it as text.
+    pub fn screen(&mut self) -> String {
+        self.editor.refresh_screen().unwrap();
+        self.screen.borrow().to_string()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! Random edit sequences, checking `Document::check_invariants` after every step.
+//!
+//! Failures report the seed and step, for the sequence to be replayed. Sequences come from a
+//! seeded generator rather than `proptest`, which would shrink failures but isn't a dependency.
+
+mod common;
+
+use common::Harness;
+
+use hecto::{Document, Position};
+
+use regex::Regex;
+use termion::event::Key;
+
+const SEEDS: u64 = 200;
+const STEPS: usize = 100;
+
+/// Characters worth typing: combining marks, emoji joined by ZWJ and pairs of regional
+/// indicators make up multi-char graphemes, `\r` and `\n` can pair up into one.
+const CHARS: &[char] = &[
+    'a',
+    'e',
+    ' ',
+    '\t',
+    '\u{301}',
+    '\u{1F600}',
+    '\u{200D}',
+    '\u{1F1EB}',
+    '\r',
+    '\n',
+];
+
+/// xorshift64*, enough to spread edits around without a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// In `0..n`, `n` > 0.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+
+    /// A position in `doc`, sometimes past the end of its row or of the document.
+    fn position(&mut self, doc: &Document) -> Position {
+        let y = self.below(doc.len() + 2);
+        let len = doc.get(y).map_or(0, |row| row.len());
+        Position {
+            x: self.below(len + 2),
+            y,
+        }
+    }
+}
+
+#[track_caller]
+fn check(doc: &Document, seed: u64, step: usize, edit: &str) {
+    if let Err(e) = doc.check_invariants() {
+        panic!("seed {seed}, step {step} ({edit}): {e}");
+    }
+}
+
+#[test]
+fn document_edits_keep_invariants() {
+    let pattern = Regex::new("e\u{301}|a+").unwrap();
+
+    for seed in 0..SEEDS {
+        let mut rng = Rng::new(seed);
+        let mut doc = Document::default();
+        check(&doc, seed, 0, "new");
+
+        for step in 1..=STEPS {
+            let edit = match rng.below(10) {
+                0..=2 => {
+                    let c = rng.pick(CHARS);
+                    doc.insert_or_append(rng.position(&doc), c);
+                    format!("insert {c:?}")
+                }
+                3 => {
+                    let c = rng.pick(CHARS);
+                    doc.replace(rng.position(&doc), c);
+                    format!("replace {c:?}")
+                }
+                4 => {
+                    doc.delete(rng.position(&doc));
+                    "delete".into()
+                }
+                5 => {
+                    let (a, b) = (rng.position(&doc), rng.position(&doc));
+                    let (start, end) = if (a.y, a.x) <= (b.y, b.x) {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    doc.delete_range(start, end);
+                    "delete range".into()
+                }
+                6 => {
+                    let start = rng.below(doc.len() + 1);
+                    let lines = doc.remove_rows(start..start + rng.below(3));
+                    doc.insert_rows(rng.below(doc.len() + 1), &lines);
+                    let start = rng.below(doc.len() + 1);
+                    doc.reverse(start..start + rng.below(4));
+                    "move and reverse rows".into()
+                }
+                7 => {
+                    let all = rng.below(2) == 0;
+                    doc.substitute(0..doc.len(), &pattern, "\u{301}\u{1F1EB}", all);
+                    "substitute".into()
+                }
+                8 => {
+                    let text: String = (0..rng.below(6)).map(|_| rng.pick(CHARS)).collect();
+                    doc.insert_str(rng.position(&doc), &text);
+                    format!("insert {text:?}")
+                }
+                _ => {
+                    doc.trim_trailing_whitespace();
+                    doc.dedup(0..doc.len(), rng.below(2) == 0, true);
+                    doc.sort(rng.below(2) == 0);
+                    "trim, dedup and sort".into()
+                }
+            };
+            check(&doc, seed, step, &edit);
+        }
+    }
+}
+
+#[test]
+fn newlines_in_rows_are_caught() {
+    let mut doc = Document::default();
+    doc.insert_rows(0, &["one\ntwo".into()]);
+    assert!(doc.check_invariants().is_err());
+}
+
+/// Keys for `Harness::keys`, with undo and redo.
+const KEYS: &[Key] = &[
+    Key::Char('a'),
+    Key::Char('\u{301}'),
+    Key::Char('\n'),
+    Key::Char('\t'),
+    Key::Backspace,
+    Key::Delete,
+    Key::Left,
+    Key::Right,
+    Key::Up,
+    Key::Down,
+    Key::Home,
+    Key::End,
+    Key::Insert,
+    Key::Alt('u'),
+    Key::Ctrl('r'),
+];
+
+#[test]
+fn editing_keys_keep_invariants() {
+    for seed in 0..SEEDS / 4 {
+        let mut rng = Rng::new(seed);
+        let mut h = Harness::new("first\nse\u{301}cond\n\nlast");
+
+        for step in 1..=STEPS {
+            let key = rng.pick(KEYS);
+            h.keys(&[key]);
+            check(h.editor.document(), seed, step, &format!("{key:?}"));
+
+            let (x, y) = h.cursor();
+            let doc = h.editor.document();
+            let len = doc.get(y).map_or(0, |row| row.len());
+            assert!(
+                y <= doc.len() && x <= len,
+                "seed {seed}, step {step} ({key:?}): cursor out of the document at {x}:{y}"
+            );
+        }
+    }
+}
+
+#[test]
+fn undoing_every_edit_restores_the_text() {
+    for seed in 0..SEEDS / 4 {
+        let mut rng = Rng::new(seed);
+        let mut h = Harness::new("one\ntwo\n");
+        let original: Vec<String> = h.lines().iter().map(|&line| line.into()).collect();
+
+        for _ in 0..STEPS / 4 {
+            let key = rng.pick(&KEYS[..KEYS.len() - 2]);
+            h.keys(&[key]);
+        }
+        let edited: Vec<String> = h.lines().iter().map(|&line| line.into()).collect();
+
+        h.keys(&[Key::Alt('u'); STEPS]);
+        assert_eq!(h.lines(), original, "seed {seed}: undo");
+        h.keys(&[Key::Ctrl('r'); STEPS]);
+        assert_eq!(h.lines(), edited, "seed {seed}: redo");
+        check(h.editor.document(), seed, STEPS, "redo");
+    }
+}
+
+#[test]
+fn saving_clears_the_dirty_flag() {
+    for seed in 0..SEEDS / 20 {
+        let mut rng = Rng::new(seed);
+        let mut h = Harness::new("text\n");
+
+        for step in 1..=STEPS / 4 {
+            h.keys(&[rng.pick(KEYS), Key::Ctrl('s')]);
+            let doc = h.editor.document();
+            assert!(
+                !doc.is_dirty(),
+                "seed {seed}, step {step}: dirty after save"
+            );
+            check(doc, seed, step, "save");
+        }
+    }
+}
@@ -0,0 +1,1037 @@
+//! Editing behavior, driven by key sequences through the public `Editor` API.
+
+mod common;
+
+use common::Harness;
+
+use hecto::config::FileTypeConfig;
+use hecto::document::TimedOut;
+use hecto::input::Modifiers;
+use hecto::{Chord, Command, Document, EditorConfig, Position, SearchDirection};
+
+use regex::Regex;
+use std::fs;
+use std::time::Duration;
+
+use termion::event::Key;
+
+#[test]
+fn typing_inserts_at_the_cursor() {
+    let mut h = Harness::new("world\n");
+    h.type_str("hello ");
+    assert_eq!(h.lines(), ["hello world"]);
+    assert_eq!(h.cursor(), (6, 0));
+    assert!(h.editor.document().is_dirty());
+}
+
+#[test]
+fn enter_splits_the_row() {
+    let mut h = Harness::new("abcd\n");
+    h.keys(&[Key::Right, Key::Right]).type_str("\n");
+    assert_eq!(h.lines(), ["ab", "cd"]);
+    assert_eq!(h.cursor(), (0, 1));
+}
+
+#[test]
+fn backspace_at_line_start_joins_rows() {
+    let mut h = Harness::new("ab\ncd\n");
+    h.keys(&[Key::Down, Key::Backspace]);
+    assert_eq!(h.lines(), ["abcd"]);
+    assert_eq!(h.cursor(), (2, 0));
+}
+
+#[test]
+fn delete_at_line_end_joins_rows() {
+    let mut h = Harness::new("ab\ncd\n");
+    h.keys(&[Key::End, Key::Delete]);
+    assert_eq!(h.lines(), ["abcd"]);
+    assert_eq!(h.cursor(), (2, 0));
+}
+
+#[test]
+fn backspace_at_document_start_does_nothing() {
+    let mut h = Harness::new("ab\n");
+    h.keys(&[Key::Backspace]);
+    assert_eq!(h.lines(), ["ab"]);
+    assert_eq!(h.cursor(), (0, 0));
+    assert!(!h.editor.document().is_dirty());
+}
+
+#[test]
+fn moving_wraps_across_rows() {
+    let mut h = Harness::new("ab\ncd\n");
+    h.keys(&[Key::End, Key::Right]);
+    assert_eq!(h.cursor(), (0, 1));
+    h.keys(&[Key::Left]);
+    assert_eq!(h.cursor(), (2, 0));
+}
+
+#[test]
+fn vertical_moves_clamp_to_the_row() {
+    let mut h = Harness::new("abcdef\nab\nabcdef\n");
+    h.keys(&[Key::End, Key::Down]);
+    assert_eq!(h.cursor(), (2, 1));
+    h.keys(&[Key::Down, Key::Down, Key::Down]);
+    assert_eq!(h.cursor(), (2, 2));
+}
+
+#[test]
+fn graphemes_are_moved_over_whole() {
+    let mut h = Harness::new("e\u{301}x\n");
+    h.keys(&[Key::Right, Key::Delete]);
+    assert_eq!(h.lines(), ["e\u{301}"]);
+}
+
+#[test]
+fn overwrite_mode_replaces_characters() {
+    let mut h = Harness::new("abc\n");
+    h.keys(&[Key::Insert]).type_str("xy");
+    assert_eq!(h.lines(), ["xyc"]);
+    h.keys(&[Key::Insert]).type_str("z");
+    assert_eq!(h.lines(), ["xyzc"]);
+}
+
+#[test]
+fn typing_is_undone_at_once() {
+    let mut h = Harness::new("\n");
+    h.type_str("one two");
+    h.keys(&[Key::Alt('u')]);
+    assert_eq!(h.lines(), [""]);
+    assert_eq!(h.cursor(), (0, 0));
+    h.keys(&[Key::Ctrl('r')]);
+    assert_eq!(h.lines(), ["one two"]);
+}
+
+#[test]
+fn saving_writes_the_file() {
+    let mut h = Harness::new("a\n");
+    h.type_str("b").keys(&[Key::Ctrl('s')]);
+    assert!(!h.editor.document().is_dirty());
+    assert_eq!(fs::read_to_string(&h.path).unwrap(), "ba\n");
+}
+
+#[test]
+fn message_log_shows_past_messages() {
+    let mut h = Harness::new("text\n");
+    h.editor.run_command("goto x").unwrap();
+    h.screen();
+    h.editor.run_command("messages").unwrap();
+    assert_eq!(h.lines().last(), Some(&"Not a line number: x"));
+    h.type_str("a");
+    assert!(!h.editor.document().is_dirty());
+
+    h.editor.run_command("q").unwrap();
+    assert!(!h.editor.should_quit());
+    assert_eq!(h.lines(), ["text"]);
+}
+
+#[test]
+fn goto_takes_columns_and_percentages() {
+    let mut h = Harness::new("one\ntwo\nthree\nfour\n");
+    h.editor.run_command("goto 3:2").unwrap();
+    assert_eq!(h.cursor(), (1, 2));
+    h.editor.run_command("goto 50%").unwrap();
+    assert_eq!(h.cursor(), (0, 1));
+    h.editor.run_command("goto 9:9").unwrap();
+    assert_eq!(h.cursor(), (4, 3));
+}
+
+#[test]
+fn ctrl_home_and_end_reach_the_ends_of_the_document() {
+    let ctrl = Modifiers {
+        ctrl: true,
+        ..Modifiers::default()
+    };
+    let mut h = Harness::new("one\ntwo\nthree\n");
+    h.editor.feed_key(Chord::new(Key::End, ctrl)).unwrap();
+    assert_eq!(h.cursor(), (5, 2));
+    h.editor.feed_key(Chord::new(Key::Home, ctrl)).unwrap();
+    assert_eq!(h.cursor(), (0, 0));
+}
+
+#[test]
+fn scrolling_the_view_keeps_the_cursor_until_it_leaves() {
+    let text: String = (1..=30).map(|n| format!("{n}\n")).collect();
+    let mut h = Harness::new(&text);
+    h.keys(&[Key::Down; 5]);
+    h.keys(&[Key::Ctrl('e')]);
+    assert_eq!(h.cursor(), (0, 5));
+    h.keys(&[Key::Ctrl('e'); 5]);
+    assert!(h.cursor().1 > 5);
+    let y = h.cursor().1;
+    h.keys(&[Key::Ctrl('y')]);
+    assert_eq!(h.cursor().1, y);
+    h.keys(&[Key::Ctrl('y'); 10]);
+    assert!(h.screen().starts_with("  1 1 "));
+}
+
+#[test]
+fn half_pages_and_alignment_move_the_view() {
+    let text: String = (1..=30).map(|n| format!("{n}\n")).collect();
+    let config = EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config(&text, 40, 10, config);
+    // 8 rows of text, scrolling by 4, the cursor kept 3 rows from the edges
+    h.keys(&[Key::Ctrl('d')]);
+    assert_eq!(h.cursor(), (0, 7));
+    assert!(h.screen().starts_with("  5 5 "));
+    h.keys(&[Key::Ctrl('d'), Key::Ctrl('d'), Key::Ctrl('u')]);
+    assert_eq!(h.cursor(), (0, 11));
+    assert!(h.screen().starts_with("  9 9 "));
+
+    h.type_str("zt");
+    assert!(h.screen().starts_with("  9 9 "));
+    h.type_str("zb");
+    assert!(h.screen().starts_with("  8 8 "));
+    h.keys(&[Key::Up]).type_str("zz");
+    assert!(h.screen().starts_with("  7 7 "));
+}
+
+#[test]
+fn subword_motion_stops_inside_identifiers() {
+    let config = EditorConfig {
+        modal: true,
+        subword_motion: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("parseHTMLBody foo_bar\n", 40, 10, config);
+    let mut stops = Vec::new();
+    for _ in 0..5 {
+        h.type_str("w");
+        stops.push(h.cursor().0);
+    }
+    assert_eq!(stops, [5, 9, 14, 18, 20]);
+    h.type_str("b");
+    assert_eq!(h.cursor().0, 18);
+    h.type_str("0e");
+    assert_eq!(h.cursor().0, 4);
+
+    h.editor.run_command("set nosubword").unwrap();
+    h.type_str("0w");
+    assert_eq!(h.cursor().0, 14);
+}
+
+#[test]
+fn counts_repeat_commands() {
+    let mut h = Harness::new("one\ntwo\nthree\nfour\n");
+    h.keys(&[Key::Alt('1'), Key::Alt('2')]);
+    assert!(h.screen().contains("notes.txt 12"));
+    h.keys(&[Key::Char('-')]);
+    assert_eq!(h.lines()[0], "------------one");
+    h.keys(&[Key::Alt('2'), Key::Down]);
+    assert_eq!(h.cursor().1, 2);
+
+    let config = EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("one\ntwo\nthree\nfour\n", 40, 10, config);
+    h.type_str("3");
+    assert!(h.screen().contains("[NOR] 3"));
+    h.keys(&[Key::Down]);
+    assert_eq!(h.cursor(), (0, 3));
+    h.type_str("2dd2u");
+    assert_eq!(h.lines().len(), 4);
+}
+
+#[test]
+fn text_objects_work_with_operators() {
+    let config = || EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("call(a, (b), \"c d\") end\n", 40, 10, config());
+    h.type_str("6ldi(");
+    assert_eq!(h.lines(), ["call() end"]);
+    h.keys(&[Key::Alt('u')]).type_str("0ci\"x");
+    h.keys(&[Key::Esc]);
+    assert_eq!(h.lines(), ["call(a, (b), \"x\") end"]);
+    h.type_str("$daw");
+    assert_eq!(h.lines(), ["call(a, (b), \"x\")"]);
+    h.type_str("0yilP");
+    assert_eq!(h.lines(), ["call(a, (b), \"x\")call(a, (b), \"x\")"]);
+
+    let mut h = Harness::with_config("fn f() {\n    a;\n    b;\n}\n", 40, 10, config());
+    h.type_str("jdi{");
+    assert_eq!(h.lines(), ["fn f() {", "}"]);
+    h.type_str("va{d");
+    assert_eq!(h.lines(), ["fn f() "]);
+}
+
+#[test]
+fn increment_keeps_the_number_format() {
+    let mut h = Harness::new("width: 0x0fF, item-09 and -1\n");
+    h.keys(&[Key::Ctrl('a')]);
+    assert_eq!(h.lines()[0], "width: 0x100, item-09 and -1");
+    assert_eq!(h.cursor(), (11, 0));
+    h.keys(&[Key::Right, Key::Alt('1'), Key::Alt('1'), Key::Ctrl('a')]);
+    assert_eq!(h.lines()[0], "width: 0x100, item-20 and -1");
+    h.keys(&[Key::Right, Key::Ctrl('a'), Key::Ctrl('a')]);
+    assert_eq!(h.lines()[0], "width: 0x100, item-20 and 1");
+    h.keys(&[Key::Alt('u')]);
+    assert_eq!(h.lines()[0], "width: 0x100, item-20 and 0");
+    // Past the last number, nothing changes and there is nothing more to undo
+    h.keys(&[Key::End, Key::Ctrl('a'), Key::Alt('u')]);
+    assert_eq!(h.lines()[0], "width: 0x100, item-20 and -1");
+}
+
+#[test]
+fn reflow_keeps_comment_prefixes() {
+    let text_type = FileTypeConfig {
+        comment: Some("//".into()),
+        ..FileTypeConfig::default()
+    };
+    let config = EditorConfig {
+        text_width: Some(21),
+        file_types: [("text".to_string(), text_type)].into_iter().collect(),
+        ..EditorConfig::default()
+    };
+    let text = "fn f() {}\n\n    // One two three four five\n    // six.\n    //\n    // Seven.\n";
+    let mut h = Harness::with_config(text, 40, 10, config);
+    h.keys(&[Key::Down, Key::Down, Key::Alt('q')]);
+    assert_eq!(
+        h.lines(),
+        [
+            "fn f() {}",
+            "",
+            "    // One two three",
+            "    // four five six.",
+            "    //",
+            "    // Seven.",
+        ]
+    );
+    assert_eq!(h.cursor(), (7, 2));
+}
+
+#[test]
+fn auto_wrap_breaks_lines_while_typing() {
+    let config = EditorConfig {
+        text_width: Some(12),
+        auto_wrap: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("  \n", 40, 10, config);
+    h.keys(&[Key::End]);
+    h.type_str("one two three four");
+    assert_eq!(h.lines(), ["  one two", "  three four"]);
+    assert_eq!(h.cursor(), (12, 1));
+
+    h.editor.run_command("set noautowrap").unwrap();
+    h.type_str(" five six");
+    assert_eq!(h.lines()[1], "  three four five six");
+}
+
+#[test]
+fn dates_and_snippets_are_inserted_at_the_cursor() {
+    let config = EditorConfig {
+        snippets: [
+            ("sig".to_string(), "Regards,\nMe".to_string()),
+            ("none".to_string(), String::new()),
+        ]
+        .into_iter()
+        .collect(),
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("\n", 40, 10, config);
+    h.editor.run_command("date %%Y").unwrap();
+    h.editor.run_command("snippet sig").unwrap();
+    assert_eq!(h.lines(), ["%YRegards,", "Me"]);
+    assert_eq!(h.cursor(), (2, 1));
+    h.editor.run_command("snippet nope").unwrap();
+    assert_eq!(h.editor.status_message(), "No snippet named nope");
+    // Neither is a change to undo, nor is inserting nothing
+    h.editor.run_command("snippet none").unwrap();
+    h.editor.execute(Command::Undo).unwrap();
+    assert_eq!(h.lines(), ["%Y"]);
+}
+
+#[test]
+fn read_inserts_a_file_below_the_cursor() {
+    let mut h = Harness::new("one\ntwo\n");
+    let other = h.path.with_file_name("other.txt");
+    fs::write(&other, "\u{feff}a\r\nb\r\n").unwrap();
+    h.editor
+        .run_command(&format!("read {}", other.display()))
+        .unwrap();
+    assert_eq!(h.lines(), ["one", "a", "b", "two"]);
+    assert_eq!(h.cursor(), (0, 1));
+    h.keys(&[Key::Alt('u')]);
+    assert_eq!(h.lines(), ["one", "two"]);
+}
+
+#[test]
+fn word_count_covers_the_file_or_the_selection() {
+    let config = EditorConfig {
+        modal: true,
+        status_format: "{words}".into(),
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("héllo wörld\nbye\n", 40, 10, config);
+    h.editor.execute(Command::WordCount).unwrap();
+    assert_eq!(
+        h.editor.status_message(),
+        "File: 2 lines, 3 words, 16 characters, 18 bytes"
+    );
+    assert!(h.screen().contains("3 words"));
+    h.type_str("A again");
+    h.keys(&[Key::Esc]);
+    assert!(h.screen().contains("4 words"));
+    h.type_str("dd");
+    assert!(h.screen().contains("1 words"));
+    h.type_str("u");
+    assert!(h.screen().contains("4 words"));
+    h.type_str("gg0vw");
+    h.editor.execute(Command::WordCount).unwrap();
+    assert_eq!(
+        h.editor.status_message(),
+        "Selection: 1 lines, 2 words, 7 characters, 8 bytes"
+    );
+}
+
+#[test]
+fn substitutions_can_be_confirmed_one_by_one() {
+    let input = [
+        Key::Char('y'),
+        Key::Char('n'),
+        Key::Char('a'),
+        Key::Char('n'),
+        Key::Char('n'),
+        Key::Char('n'),
+    ];
+    let mut h = Harness::with_input("a a\nb a\na\n", 40, 10, EditorConfig::default(), &input);
+    h.editor.run_command("%s/a/X/gc").unwrap();
+    assert_eq!(h.lines(), ["X a", "b X", "X"]);
+    assert_eq!(h.editor.status_message(), "3 substitutions");
+
+    // Declining every match, or not finding any, leaves nothing to undo
+    h.editor.run_command("%s/X/Y/gc").unwrap();
+    assert_eq!(h.editor.status_message(), "0 substitutions");
+    h.editor.run_command("%s/nowhere/Y/g").unwrap();
+    assert_eq!(h.editor.status_message(), "Pattern not found: nowhere");
+    h.editor.execute(Command::Undo).unwrap();
+    assert_eq!(h.lines(), ["a a", "b a", "a"]);
+}
+
+#[test]
+fn substitute_takes_ranges_flags_and_groups() {
+    let mut h = Harness::new("Ann Lee\nann lee\nBob Ray\nann lee\n");
+    h.editor.run_command(r"2,3s/(\w+) (\w+)/\2, \1/").unwrap();
+    assert_eq!(h.lines(), ["Ann Lee", "lee, ann", "Ray, Bob", "ann lee"]);
+    // `$` is literal, `&` the whole match
+    h.editor.run_command(r"1s/Ann/$1 &/").unwrap();
+    assert_eq!(h.lines()[0], "$1 Ann Lee");
+    h.editor.run_command("undo").unwrap();
+    h.editor.run_command("%s/ANN/Jo/gi").unwrap();
+    assert_eq!(h.lines(), ["Jo Lee", "lee, Jo", "Ray, Bob", "Jo lee"]);
+    h.editor.run_command("$d").unwrap();
+    assert_eq!(h.lines(), ["Jo Lee", "lee, Jo", "Ray, Bob"]);
+    h.editor.run_command("1,2sort").unwrap();
+    assert_eq!(
+        h.editor.status_message(),
+        "This command doesn't take a line range"
+    );
+}
+
+#[test]
+fn global_runs_a_command_on_matching_lines() {
+    let mut h = Harness::new("keep 1\ndrop\nkeep 2\ndrop\nkeep 3\n");
+    h.editor.run_command("g/drop/d").unwrap();
+    assert_eq!(h.lines(), ["keep 1", "keep 2", "keep 3"]);
+    h.editor.run_command("v/2/s/keep/kept/").unwrap();
+    assert_eq!(h.lines(), ["kept 1", "keep 2", "kept 3"]);
+    h.editor.run_command("1,2g/e/indent").unwrap();
+    assert_eq!(h.lines(), ["\tkept 1", "\tkeep 2", "kept 3"]);
+    assert_eq!(h.editor.status_message(), "Ran indent on 2 lines");
+    // Not an edit to undo
+    h.editor.run_command("g/nowhere/d").unwrap();
+    h.editor.execute(Command::Undo).unwrap();
+    h.editor.execute(Command::Undo).unwrap();
+    assert_eq!(h.lines(), ["keep 1", "keep 2", "keep 3"]);
+
+    // Marked lines deleted by the command are skipped
+    let mut h = Harness::new("x 1\nx 2\ny\nx 3\nz\nw\n");
+    h.editor.run_command("g/x/d 2").unwrap();
+    assert_eq!(h.lines(), ["y", "w"]);
+}
+
+#[test]
+fn lines_can_be_deleted_or_kept_by_pattern() {
+    let mut h = Harness::new("INFO a\nDEBUG b\nWARN c\nDEBUG d\nINFO e\n");
+    h.editor.run_command("delete_matching ^DEBUG").unwrap();
+    assert_eq!(h.lines(), ["INFO a", "WARN c", "INFO e"]);
+    assert_eq!(h.editor.status_message(), "Deleted 2 of 5 lines");
+    h.editor.run_command("2,$keep_matching INFO").unwrap();
+    assert_eq!(h.lines(), ["INFO a", "INFO e"]);
+}
+
+#[test]
+fn uniq_removes_repeated_lines() {
+    let mut h = Harness::new("a\na\nb\n  B\nb\na\n");
+    h.editor.run_command("uniq").unwrap();
+    assert_eq!(h.lines(), ["a", "b", "  B", "b", "a"]);
+    assert_eq!(h.editor.status_message(), "Removed 1 line");
+    h.editor.run_command("uniq iw").unwrap();
+    assert_eq!(h.lines(), ["a", "b", "a"]);
+    // Removing nothing isn't a change to undo
+    h.editor.run_command("%uniq").unwrap();
+    assert_eq!(h.editor.status_message(), "Removed 0 lines");
+    h.editor.execute(Command::Undo).unwrap();
+    assert_eq!(h.lines(), ["a", "b", "  B", "b", "a"]);
+}
+
+#[test]
+fn reverse_flips_the_selected_lines() {
+    let config = EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("1\n2\n3\n4\n", 40, 10, config);
+    h.type_str("jVj");
+    h.editor.run_command("reverse").unwrap();
+    assert_eq!(h.lines(), ["1", "3", "2", "4"]);
+    h.editor.run_command("%reverse").unwrap();
+    assert_eq!(h.lines(), ["4", "2", "3", "1"]);
+    h.type_str("u");
+    assert_eq!(h.lines(), ["1", "3", "2", "4"]);
+}
+
+#[test]
+fn retab_converts_indentation() {
+    let mut h = Harness::new("\t  a\tb\n      c\n");
+    h.editor.run_command("set tabwidth=4").unwrap();
+    h.editor.run_command("retab").unwrap();
+    assert_eq!(h.lines(), ["\t  a\tb", "\t  c"]);
+    assert_eq!(h.editor.status_message(), "Retabbed 1 line");
+    h.editor.run_command("set expandtab").unwrap();
+    h.editor.run_command("retab!").unwrap();
+    assert_eq!(h.lines(), ["      a b", "      c"]);
+}
+
+#[test]
+fn line_endings_can_be_converted() {
+    let mut h = Harness::new("one\r\ntwo\r\n");
+    assert!(h.screen().contains("CRLF"));
+    h.editor.run_command("set ff=unix").unwrap();
+    assert!(h.editor.document().is_dirty());
+    assert!(h.screen().contains("| LF"));
+    h.editor.run_command("write").unwrap();
+    assert_eq!(fs::read_to_string(&h.path).unwrap(), "one\ntwo\n");
+    h.editor.run_command("set fileformat?").unwrap();
+    assert_eq!(h.editor.status_message(), "fileformat=lf");
+}
+
+#[test]
+fn files_can_be_saved_in_another_encoding() {
+    let mut h = Harness::new("hé\n");
+    h.editor.run_command("set fenc=utf-16le").unwrap();
+    h.editor.run_command("write").unwrap();
+    assert_eq!(
+        fs::read(&h.path).unwrap(),
+        [0xFF, 0xFE, b'h', 0, 0xE9, 0, b'\n', 0]
+    );
+    let reopened = Document::open(h.path.clone()).unwrap();
+    assert_eq!(reopened.encoding().to_string(), "utf-16le");
+
+    h.editor.run_command("set fenc=latin1").unwrap();
+    h.editor.run_command("write").unwrap();
+    assert_eq!(fs::read(&h.path).unwrap(), [b'h', 0xE9, b'\n']);
+    // Read back in the encoding it was saved in
+    h.editor.run_command("e!").unwrap();
+    assert_eq!(h.lines(), ["hé"]);
+    assert!(!h.editor.document().is_dirty());
+
+    h.keys(&[Key::End]).type_str("→");
+    h.editor.run_command("write").unwrap();
+    assert!(h
+        .editor
+        .status_message()
+        .contains("1 characters written as ?"));
+    assert_eq!(fs::read(&h.path).unwrap(), [b'h', 0xE9, b'?', b'\n']);
+}
+
+#[test]
+fn other_files_can_be_opened() {
+    let dir = std::env::temp_dir().join(format!("hecto-open-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let other = dir.join("other.txt");
+    fs::write(&other, "other\n").unwrap();
+    let other = other.to_str().unwrap();
+
+    let mut input = vec![Key::Char('x'), Key::Ctrl('o')];
+    input.extend(other.chars().map(Key::Char));
+    input.extend([Key::Char('\n'), Key::Ctrl('q'), Key::Ctrl('q')]);
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    h.editor.run().unwrap();
+    assert_eq!(h.lines(), ["other"]);
+    h.keys(&[Key::Alt(',')]);
+    assert_eq!(h.lines(), ["xfirst"]);
+    assert!(h.editor.document().is_dirty());
+
+    h.editor.run_command(&format!("e {other}")).unwrap();
+    assert_eq!(h.lines(), ["other"]);
+    h.type_str("y");
+    h.editor.run_command("e").unwrap();
+    assert!(h
+        .editor
+        .status_message()
+        .starts_with("No write since last change"));
+    h.editor.run_command("e!").unwrap();
+    assert_eq!(h.lines(), ["other"]);
+    h.editor.run_command("e new.txt").unwrap();
+    assert_eq!(h.editor.status_message(), r#""new.txt" [New]"#);
+    assert!(h.lines().is_empty());
+}
+
+#[test]
+fn closing_a_buffer_shows_the_next_one() {
+    let input = [Key::Char('n')];
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    let other = h.path.with_file_name("other.txt");
+    fs::write(&other, "other\n").unwrap();
+    h.type_str("x");
+    h.editor
+        .run_command(&format!("e {}", other.display()))
+        .unwrap();
+    h.editor.run_command("q").unwrap();
+    assert!(!h.editor.should_quit());
+    assert_eq!(
+        h.editor.status_message(),
+        format!(
+            "No write since last change to {} (add ! to override)",
+            h.path.display()
+        )
+    );
+
+    h.editor.run_command("bd").unwrap();
+    assert_eq!(h.lines(), ["xfirst"]);
+    h.keys(&[Key::Alt('w')]);
+    assert_eq!(h.editor.status_message(), "Close aborted");
+    h.editor.run_command("bd!").unwrap();
+    assert!(h.lines().is_empty());
+    assert!(!h.editor.document().has_path());
+}
+
+#[test]
+fn buffers_can_be_picked_by_name() {
+    let input = [
+        Key::Alt('l'),
+        Key::Char('n'),
+        Key::Char('o'),
+        Key::Char('t'),
+        Key::Char('\n'),
+        Key::Char('x'),
+        Key::Alt('l'),
+        Key::Down,
+        Key::Char('\n'),
+        Key::Ctrl('q'),
+        Key::Ctrl('q'),
+    ];
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    for name in ["one.txt", "two.txt"] {
+        let path = h.path.with_file_name(name);
+        fs::write(&path, format!("{name}\n")).unwrap();
+        h.editor
+            .run_command(&format!("e {}", path.display()))
+            .unwrap();
+    }
+    h.editor.run().unwrap();
+    // The second pick lists notes.txt, one.txt then two.txt
+    assert_eq!(h.lines(), ["one.txt"]);
+    h.editor
+        .run_command(&format!("e {}", h.path.display()))
+        .unwrap();
+    assert_eq!(h.lines(), ["xfirst"]);
+}
+
+#[test]
+fn ctrl_caret_flips_between_two_buffers() {
+    let mut h = Harness::new("first\n");
+    h.keys(&[Key::Ctrl('6')]);
+    assert_eq!(h.editor.status_message(), "No alternate file");
+    for name in ["one.txt", "two.txt"] {
+        let path = h.path.with_file_name(name);
+        fs::write(&path, format!("{name}\n")).unwrap();
+        h.editor
+            .run_command(&format!("e {}", path.display()))
+            .unwrap();
+    }
+    h.keys(&[Key::Ctrl('6')]);
+    assert_eq!(h.lines(), ["one.txt"]);
+    h.keys(&[Key::Ctrl('6')]);
+    assert_eq!(h.lines(), ["two.txt"]);
+    h.editor
+        .run_command(&format!("e {}", h.path.display()))
+        .unwrap();
+    h.keys(&[Key::Ctrl('6')]);
+    assert_eq!(h.lines(), ["two.txt"]);
+}
+
+#[test]
+fn invalid_utf8_is_replaced_and_confirmed_before_saving() {
+    let input = [Key::Char('n'), Key::Char('y')];
+    let mut h = Harness::with_input("", 40, 10, EditorConfig::default(), &input);
+    fs::write(&h.path, b"ok\n\xffbad\n").unwrap();
+    h.editor.run_command("e!").unwrap();
+    assert_eq!(h.lines(), ["ok", "\u{fffd}bad"]);
+    assert_eq!(
+        h.editor.status_message(),
+        "Invalid UTF-8 replaced with \u{fffd} on line 2"
+    );
+
+    h.keys(&[Key::Ctrl('s')]);
+    assert_eq!(h.editor.status_message(), "Save aborted");
+    assert_eq!(fs::read(&h.path).unwrap(), b"ok\n\xffbad\n");
+    h.keys(&[Key::Ctrl('s')]);
+    assert_eq!(fs::read_to_string(&h.path).unwrap(), "ok\n\u{fffd}bad\n");
+}
+
+#[test]
+fn large_files_can_be_opened_read_only() {
+    let config = EditorConfig {
+        large_file_size: Some(100),
+        ..EditorConfig::default()
+    };
+    let input = [Key::Char('c'), Key::Char('r')];
+    let mut h = Harness::with_input("first\n", 40, 10, config, &input);
+    let path = h.path.with_file_name("big.txt");
+    fs::write(&path, "0123456789\n".repeat(20)).unwrap();
+    let open = format!("e {}", path.display());
+
+    h.editor.run_command(&open).unwrap();
+    assert_eq!(h.editor.status_message(), "Open cancelled");
+    assert_eq!(h.lines(), ["first"]);
+
+    h.editor.run_command(&open).unwrap();
+    // The first 100 bytes, without the line they cut
+    assert!(h
+        .editor
+        .status_message()
+        .ends_with("first 9L, read-only without highlighting, git changes nor spell checking"));
+    h.type_str("x");
+    assert_eq!(h.lines()[0], "0123456789");
+    h.editor.run_command("w").unwrap();
+    assert_eq!(
+        h.editor.status_message(),
+        "Only the start of the file was read, save it under another name"
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap().len(), 220);
+}
+
+#[test]
+fn keys_typed_while_loading_apply_to_the_loaded_file() {
+    let input = [Key::End, Key::Ctrl('q')];
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    let path = h.path.with_file_name("big.txt");
+    // Large enough to be read in the background
+    fs::write(&path, "0123456789\n".repeat(200_000)).unwrap();
+    h.editor
+        .run_command(&format!("e {}", path.display()))
+        .unwrap();
+    assert_eq!(h.editor.document().len(), 200_000);
+
+    h.editor.run().unwrap();
+    assert_eq!(h.cursor(), (10, 0));
+}
+
+#[test]
+fn opening_a_directory_picks_one_of_its_files() {
+    let input: Vec<Key> = "two\n".chars().map(Key::Char).collect();
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    let dir = h.path.with_file_name("sub");
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["one.txt", "two.txt"] {
+        fs::write(dir.join(name), format!("{name}\n")).unwrap();
+    }
+    h.editor
+        .run_command(&format!("e {}", dir.display()))
+        .unwrap();
+    assert_eq!(h.lines(), ["two.txt"]);
+    assert_eq!(
+        h.editor.document().path(),
+        Some(dir.join("two.txt").as_path())
+    );
+}
+
+#[test]
+fn pickers_find_files_and_run_commands() {
+    let mut input: Vec<Key> = "fuzzy\n".chars().map(Key::Char).collect();
+    input.extend([Key::Alt('r'), Key::Char('\n'), Key::Alt('x')]);
+    input.extend("pick_theme".chars().map(Key::Char));
+    input.extend([
+        Key::Char('\n'),
+        Key::Char('l'),
+        Key::Char('\n'),
+        Key::Alt('x'),
+    ]);
+    // Commands taking arguments are completed on the command line
+    input.extend("goto".chars().map(Key::Char));
+    input.extend("\n3\n".chars().map(Key::Char));
+    input.push(Key::Ctrl('q'));
+    let mut h = Harness::with_input(
+        "first\nsecond\nthird\n",
+        40,
+        10,
+        EditorConfig::default(),
+        &input,
+    );
+    let dir = h.path.with_file_name("tree");
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/fuzzy.rs"), "/// Scores how well\n").unwrap();
+    h.editor
+        .run_command(&format!("e {}", dir.display()))
+        .unwrap();
+    assert!(h.lines()[0].starts_with("/// Scores how well"));
+
+    h.editor.run().unwrap();
+    // Back to the first file, the most recent one
+    assert_eq!(h.lines()[0], "first");
+    assert_eq!(h.cursor(), (0, 2));
+    h.editor.run_command("set theme?").unwrap();
+    assert_eq!(h.editor.status_message(), "theme=light");
+}
+
+#[test]
+fn file_pickers_skip_hidden_and_ignored_files() {
+    let input: Vec<Key> = "rs\n".chars().map(Key::Char).collect();
+    let mut h = Harness::with_input("first\n", 40, 10, EditorConfig::default(), &input);
+    let dir = h.path.with_file_name("repo");
+    for sub in ["src", "target", ".cache"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+        fs::write(dir.join(sub).join("a.rs"), format!("{sub}\n")).unwrap();
+    }
+    fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+    let status = std::process::Command::new("git")
+        .arg("init")
+        .arg("-q")
+        .arg(&dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    h.editor
+        .run_command(&format!("e {}", dir.display()))
+        .unwrap();
+    assert_eq!(h.lines(), ["src"]);
+}
+
+#[test]
+fn completion_menu_goes_through_words_and_snippets() {
+    let config = EditorConfig {
+        snippets: [("sig".to_string(), "Me".to_string())].into(),
+        ..EditorConfig::default()
+    };
+    let input = [
+        Key::End,
+        Key::Ctrl('n'),
+        Key::Down,
+        Key::Down,
+        Key::Char(' '),
+        Key::Char('a'),
+        Key::Char('l'),
+        Key::Ctrl('n'),
+        Key::Esc,
+        Key::Char('\n'),
+        Key::Char('s'),
+        Key::Char('i'),
+        Key::Ctrl('n'),
+        Key::Ctrl('q'),
+        Key::Ctrl('q'),
+    ];
+    let mut h = Harness::with_input("al\nalpha\nalpine\n", 40, 10, config, &input);
+    h.editor.run().unwrap();
+    assert_eq!(h.lines(), ["alpine al", "Me", "alpha", "alpine"]);
+}
+
+#[test]
+fn escape_cancels_a_long_substitution_at_a_checkpoint() {
+    let text = "a\n".repeat(25_000);
+    let mut h = Harness::with_input(&text, 40, 10, EditorConfig::default(), &[Key::Esc]);
+    h.editor.run_command("%s/a/b/").unwrap();
+    let lines = h.lines();
+    assert_eq!(lines.iter().filter(|&&line| line == "b").count(), 10_000);
+    assert_eq!(lines[10_000], "a");
+    assert_eq!(
+        h.editor.status_message(),
+        "Substitute cancelled after line 10000, 10000 substitution(s) made"
+    );
+}
+
+#[test]
+fn huge_regexes_are_refused_and_searches_can_time_out() {
+    let mut h = Harness::new("abc\n");
+    h.editor.run_command(r"%s/\w{100}{100}/x/").unwrap();
+    assert!(h.editor.status_message().contains("size limit"));
+    assert_eq!(h.lines(), ["abc"]);
+
+    let mut lines = vec!["a".to_string(); 1000];
+    lines.push("needle".into());
+    let doc = Document::scratch(&lines);
+    let needle = Regex::new("needle").unwrap();
+    let start = Position::default();
+    let forward = SearchDirection::Forward;
+    assert_eq!(
+        doc.find_within(&needle, start, forward, Duration::ZERO),
+        Err(TimedOut)
+    );
+    assert_eq!(
+        doc.find_within(&needle, start, forward, Duration::from_secs(60)),
+        Ok(Some(Position { x: 0, y: 1000 }))
+    );
+}
+
+#[test]
+fn modelines_set_the_buffer_options_unless_disabled() {
+    let text = "fn main() {}\n\n// vim: set ts=2 et ft=python ro:\n";
+    let mut h = Harness::new(text);
+    h.editor.run_command("set tabwidth? expandtab?").unwrap();
+    assert_eq!(h.editor.status_message(), "tabwidth=2  expandtab");
+    assert_eq!(h.editor.document().file_type().name(), "python");
+    h.type_str("x");
+    assert_eq!(h.lines()[0], "fn main() {}");
+
+    let config = EditorConfig {
+        modelines: false,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("# -*- mode: rust; tab-width: 8 -*-\n", 80, 24, config);
+    h.editor.run_command("set tabwidth?").unwrap();
+    assert_eq!(h.editor.status_message(), "tabwidth=4");
+    assert_eq!(h.editor.document().file_type().name(), "text");
+}
+
+#[test]
+fn indentation_is_detected_from_the_content() {
+    let mut h = Harness::new("fn main() {\n  if x {\n    y();\n  }\n}\n");
+    h.editor.run_command("set tabwidth? expandtab?").unwrap();
+    assert_eq!(h.editor.status_message(), "tabwidth=2  expandtab");
+
+    let config = EditorConfig {
+        expand_tab: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("a {\n\tb;\n\tc;\n}\n", 80, 24, config);
+    h.editor.run_command("set expandtab?").unwrap();
+    assert_eq!(h.editor.status_message(), "noexpandtab");
+
+    // `--tab-width` wins
+    let config = EditorConfig {
+        forced_tab_width: Some(8),
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("fn main() {\n  if x {\n    y();\n  }\n}\n", 80, 24, config);
+    h.editor.run_command("set tabwidth? expandtab?").unwrap();
+    assert_eq!(h.editor.status_message(), "tabwidth=8  expandtab");
+}
+
+#[test]
+fn edits_changing_nothing_take_no_undo_step() {
+    let config = EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("ab\n\n", 40, 10, config);
+    h.type_str("x");
+    assert_eq!(h.lines(), ["b", ""]);
+    // On an empty line, unindented, and at the end of the buffer
+    h.type_str("jx<<dw");
+    assert_eq!(h.lines(), ["b", ""]);
+    h.type_str("u");
+    assert_eq!(h.lines(), ["ab", ""]);
+    // Nor do they lose what can be redone
+    h.type_str("jx");
+    h.keys(&[Key::Ctrl('r')]);
+    assert_eq!(h.lines(), ["b", ""]);
+}
+
+#[test]
+fn huge_paste_and_undo_counts_are_bounded() {
+    let config = EditorConfig {
+        modal: true,
+        ..EditorConfig::default()
+    };
+    let mut h = Harness::with_config("ab\n", 40, 10, config);
+    h.type_str("yy999999999p");
+    assert_eq!(h.lines().len(), 10_001);
+    h.type_str("999999999u");
+    assert_eq!(h.lines(), ["ab"]);
+}
+
+#[test]
+fn writing_to_another_path_asks_before_overwriting() {
+    let mut h = Harness::with_input("mine\n", 40, 10, EditorConfig::default(), &[Key::Char('n')]);
+    let other = h.path.with_file_name("other.txt");
+    fs::write(&other, "theirs\n").unwrap();
+    h.editor
+        .run_command(&format!("w {}", other.display()))
+        .unwrap();
+    assert_eq!(h.editor.status_message(), "Save aborted");
+    assert_eq!(fs::read_to_string(&other).unwrap(), "theirs\n");
+    assert_eq!(h.editor.document().path(), Some(h.path.as_path()));
+
+    // The buffer stays on its file when the write fails
+    let missing = h.path.with_file_name("missing").join("new.txt");
+    h.editor
+        .run_command(&format!("w {}", missing.display()))
+        .unwrap();
+    assert!(h.editor.status_message().contains("Error writing to file"));
+    assert_eq!(h.editor.document().path(), Some(h.path.as_path()));
+
+    let new = h.path.with_file_name("new.txt");
+    h.editor
+        .run_command(&format!("w {}", new.display()))
+        .unwrap();
+    assert_eq!(fs::read_to_string(&new).unwrap(), "mine\n");
+    assert_eq!(h.editor.document().path(), Some(new.as_path()));
+}
+
+#[test]
+fn write_quit_waits_for_large_files_to_be_saved() {
+    let text = "0123456789\n".repeat(200_000);
+    let mut h = Harness::new(&text);
+    h.type_str("X");
+    h.editor.run_command("wq").unwrap();
+    assert!(h.editor.should_quit());
+    assert!(!h.editor.document().is_dirty());
+    assert!(fs::read_to_string(&h.path).unwrap().starts_with("X0123"));
+}
+
+#[test]
+fn quitting_with_unsaved_buffers_asks_to_quit_again() {
+    let mut h = Harness::new("first\n");
+    let other = h.path.with_file_name("other.txt");
+    fs::write(&other, "other\n").unwrap();
+    h.type_str("x");
+    h.editor
+        .run_command(&format!("e {}", other.display()))
+        .unwrap();
+    h.keys(&[Key::Ctrl('q')]);
+    assert!(!h.editor.should_quit());
+    assert_eq!(
+        h.editor.status_message(),
+        format!(
+            "No write since last change to {}, quit again to discard it",
+            h.path.display()
+        )
+    );
+    h.keys(&[Key::Ctrl('q')]);
+    assert!(h.editor.should_quit());
+}
+
+#[test]
+fn editorconfig_line_endings_only_apply_to_new_files() {
+    let mut h = Harness::new("");
+    let dir = h.path.parent().unwrap().to_path_buf();
+    fs::write(dir.join(".editorconfig"), "[*]\nend_of_line = crlf\n").unwrap();
+    fs::write(&h.path, "one\n").unwrap();
+    h.editor.run_command("e!").unwrap();
+    assert!(!h.editor.document().is_dirty());
+    assert_eq!(h.editor.document().line_ending().to_string(), "LF");
+
+    let new = dir.join("new.txt");
+    h.editor
+        .run_command(&format!("e {}", new.display()))
+        .unwrap();
+    assert_eq!(h.editor.document().line_ending().to_string(), "CRLF");
+}
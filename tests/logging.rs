@@ -0,0 +1,34 @@
+//! The debug log, which is process-wide, hence its own test binary.
+
+mod common;
+
+use common::Harness;
+
+use hecto::logging;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use termion::event::Key;
+
+#[test]
+fn opening_and_saving_are_logged() {
+    let path = std::env::temp_dir().join(format!("hecto-test-{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+    logging::init(Some(&path), Some("trace")).unwrap();
+
+    let mut h = Harness::new("one\n");
+    h.type_str("x").keys(&[Key::Ctrl('s')]);
+    h.screen();
+
+    let log = fs::read_to_string(&path).unwrap();
+    assert!(log.contains("INFO  hecto"), "{log}");
+    assert!(log.contains("DEBUG open "), "{log}");
+    assert!(log.contains("DEBUG save "), "{log}");
+    assert!(log.contains("TRACE render: "), "{log}");
+    // Only readable by the user
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(logging::init(None, Some("loud")).is_err());
+    let _ = fs::remove_file(&path);
+}
@@ -0,0 +1,224 @@
+//! Snapshots of the screen: gutter, status bar, truncation and scrolling.
+
+mod common;
+
+use common::Harness;
+
+use hecto::frame::{Line, Style};
+use hecto::popup::{Popup, Rect};
+use hecto::EditorConfig;
+
+use termion::event::Key;
+
+const HELP: &str = "<C-q>: quit (don't save); <C-s>: save; <C-S-s>/<C-";
+
+fn harness(text: &str, width: u16, height: u16) -> Harness {
+    Harness::with_config(text, width, height, EditorConfig::default())
+}
+
+#[track_caller]
+fn assert_screen(h: &mut Harness, expected: &[&str]) {
+    assert_eq!(h.screen(), expected.join("\n"));
+}
+
+#[test]
+fn short_document() {
+    let mut h = harness("fn main() {\n    println!(\"hi\");\n}\n", 50, 8);
+    assert_screen(
+        &mut h,
+        &[
+            " 1 fn main() {",
+            " 2 │   println!(\"hi\");",
+            " 3 }",
+            "~",
+            "~",
+            "~",
+            "notes.txt         text | utf-8 | LF  Top [   1:1 ]",
+            HELP,
+        ],
+    );
+}
+
+#[test]
+fn scrolling_follows_the_cursor() {
+    let text: String = (1..=30).map(|i| format!("line {i}\n")).collect();
+    let mut h = harness(&text, 30, 6);
+    assert_screen(
+        &mut h,
+        &[
+            "  1 line 1                   ┃",
+            "  2 line 2                   │",
+            "  3 line 3                   │",
+            "  4 line 4                   │",
+            "notes.txttext | utf-8 | LF  To",
+            "<C-q>: quit (don't save); <C-s",
+        ],
+    );
+
+    h.keys(&[Key::Down; 5]);
+    assert_screen(
+        &mut h,
+        &[
+            "  4 line 4                   ┃",
+            "  5 line 5                   │",
+            "  6 line 6                   │",
+            "  7 line 7                   │",
+            "notes.txttext | utf-8 | LF  17",
+            "<C-q>: quit (don't save); <C-s",
+        ],
+    );
+}
+
+#[test]
+fn long_lines_scroll_horizontally() {
+    let line: Vec<String> = (0..40).map(|i| format!("w{i}")).collect();
+    let mut h = harness(&format!("{}\n", line.join(" ")), 50, 5);
+
+    h.keys(&[Key::End]);
+    assert_screen(
+        &mut h,
+        &[
+            " 1 < w29 w30 w31 w32 w33 w34 w35 w36 w37 w38 w39",
+            "~",
+            "~",
+            "notes.txt        text | utf-8 | LF  Top [   1:150]",
+            HELP,
+        ],
+    );
+
+    h.keys(&[Key::Home]);
+    assert_screen(
+        &mut h,
+        &[
+            " 1 w0 w1 w2 w3 w4 w5 w6 w7 w8 w9 w10 w11 w12 w13>",
+            "~",
+            "~",
+            "notes.txt         text | utf-8 | LF  Top [   1:1 ]",
+            HELP,
+        ],
+    );
+}
+
+#[test]
+fn edited_rows_are_redrawn() {
+    let mut h = harness("one\ntwo\n", 30, 5);
+    assert_screen(
+        &mut h,
+        &[
+            " 1 one",
+            " 2 two",
+            "~",
+            "notes.txttext | utf-8 | LF  To",
+            "<C-q>: quit (don't save); <C-s",
+        ],
+    );
+
+    h.keys(&[Key::Down, Key::End]).type_str("\tx");
+    h.keys(&[Key::Up]).type_str("!");
+    assert_screen(
+        &mut h,
+        &[
+            " 1 one!",
+            " 2 two x",
+            "~",
+            "notes.txt [+]text | utf-8 | LF",
+            "<C-q>: quit (don't save); <C-s",
+        ],
+    );
+}
+
+#[test]
+fn narrow_status_bar_is_truncated() {
+    let mut h = harness("a\n", 20, 5);
+    h.type_str("b");
+    assert_screen(
+        &mut h,
+        &[
+            " 1 ba",
+            "~",
+            "~",
+            "notes.txt [+]text |",
+            "<C-q>: quit (don't s",
+        ],
+    );
+}
+
+#[test]
+fn render_matches_the_screen() {
+    let mut h = harness("one\ntwo\n", 30, 6);
+    let rendered: Vec<String> = h
+        .editor
+        .render()
+        .iter()
+        .map(|line| line.to_string().trim_end().to_string())
+        .collect();
+    assert_eq!(h.screen(), rendered.join("\n"));
+}
+
+#[test]
+fn pasted_text_is_drawn_in_few_frames() {
+    let mut input: Vec<Key> = "pasted text\n".repeat(50).chars().map(Key::Char).collect();
+    // Confirming to discard the pasted text
+    input.extend([Key::Ctrl('q'), Key::Ctrl('q')]);
+    let mut h = Harness::with_input("", 30, 6, EditorConfig::default(), &input);
+    h.editor.run().unwrap();
+    assert_eq!(h.lines().len(), 51);
+    assert!(h.frames() < 10, "{} frames", h.frames());
+}
+
+#[test]
+fn frame_timings_show_in_a_corner() {
+    let mut h = harness("one\ntwo\n", 40, 8);
+    h.keys(&[Key::F(12)]);
+    let screen = h.screen();
+    let lines: Vec<&str> = screen.lines().collect();
+    assert!(
+        lines[0].starts_with(" 1 one") && lines[0].ends_with("┐"),
+        "{screen}"
+    );
+    assert!(
+        lines[1].contains("│ frame ") && lines[1].ends_with("ms │"),
+        "{screen}"
+    );
+    assert!(lines[2].contains("│ rows "), "{screen}");
+    assert!(lines[3].contains("│ latency "), "{screen}");
+
+    h.keys(&[Key::F(12)]);
+    assert!(!h.screen().contains("latency"));
+}
+
+#[test]
+fn popups_cover_part_of_the_frame() {
+    let mut frame: Vec<Line> = ["0123456789", "abcdefghij", "ABCDEFGHIJ", "klmnopqrst", "x"]
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    let lines = ["one", "two", "three"]
+        .into_iter()
+        .map(Line::from)
+        .collect();
+    let mut popup = Popup::new("Hi", lines);
+    assert_eq!(popup.size(80, 4), (7, 4));
+    popup.scroll_to(2, 4);
+    popup.draw(
+        &mut frame,
+        Rect {
+            x: 1,
+            y: 1,
+            width: 9,
+            height: 4,
+        },
+        Style::default(),
+    );
+    let text: Vec<String> = frame.iter().map(ToString::to_string).collect();
+    assert_eq!(
+        text,
+        [
+            "0123456789",
+            "a┌ Hi ───┐",
+            "A│two    │",
+            "k│three  │",
+            "x└ 2-3/3 ┘",
+        ]
+    );
+}